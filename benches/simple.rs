@@ -10,13 +10,19 @@ use misosoup::index_builder::IndexBuilder;
 use misosoup::parser::Parser;
 use misosoup::query::QueryTree;
 use misosoup::query_parser::{QueryParser, QueryParserMode};
+use misosoup::testdata::{Corpus, DEFAULT_SEED};
+use std::sync::OnceLock;
 
-const INPUT: &str = include_str!("temp.json");
+/// A large record generated deterministically
+fn input() -> &'static str {
+    static INPUT: OnceLock<String> = OnceLock::new();
+    INPUT.get_or_init(|| Corpus::Companies.records(DEFAULT_SEED, 1).remove(0))
+}
 
 #[bench]
 fn bench_serde_json(b: &mut test::Bencher) {
     b.iter(|| {
-        let _: serde_json::Value = serde_json::from_str(INPUT).unwrap();
+        let _: serde_json::Value = serde_json::from_str(input()).unwrap();
     });
 }
 
@@ -26,10 +32,53 @@ fn bench_mison_fallback(b: &mut test::Bencher) {
     let parser = Parser::new(index_builder);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT).unwrap();
+        let _ = parser.parse(input()).unwrap();
+    });
+}
+
+#[bench]
+fn bench_mison_fallback_compact(b: &mut test::Bencher) {
+    let index_builder = IndexBuilder::new(FallbackBackend::default(), 3);
+    let parser = Parser::new(index_builder);
+
+    b.iter(|| {
+        let _ = parser.parse_compact(input()).unwrap();
+    });
+}
+
+fn bench_mison_fallback_corpus(b: &mut test::Bencher, corpus: Corpus) {
+    let records = corpus.records(DEFAULT_SEED, 100);
+    let index_builder = IndexBuilder::new(FallbackBackend::default(), 3);
+    let parser = Parser::new(index_builder);
+
+    b.bytes = records.iter().map(|r| r.len() as u64).sum();
+    b.iter(|| {
+        for record in &records {
+            let _ = parser.parse(record).unwrap();
+        }
     });
 }
 
+#[bench]
+fn bench_mison_fallback_tweets(b: &mut test::Bencher) {
+    bench_mison_fallback_corpus(b, Corpus::Tweets);
+}
+
+#[bench]
+fn bench_mison_fallback_nested_configs(b: &mut test::Bencher) {
+    bench_mison_fallback_corpus(b, Corpus::NestedConfigs);
+}
+
+#[bench]
+fn bench_mison_fallback_escaped_strings(b: &mut test::Bencher) {
+    bench_mison_fallback_corpus(b, Corpus::EscapedStrings);
+}
+
+#[bench]
+fn bench_mison_fallback_huge_arrays(b: &mut test::Bencher) {
+    bench_mison_fallback_corpus(b, Corpus::HugeArrays);
+}
+
 #[bench]
 #[cfg(feature = "avx-accel")]
 fn bench_mison_avx(b: &mut test::Bencher) {
@@ -37,7 +86,7 @@ fn bench_mison_avx(b: &mut test::Bencher) {
     let parser = Parser::new(index_builder);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT).unwrap();
+        let _ = parser.parse(input()).unwrap();
     });
 }
 
@@ -48,7 +97,7 @@ fn bench_mison_avx_2(b: &mut test::Bencher) {
     let parser = Parser::new(index_builder);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT).unwrap();
+        let _ = parser.parse(input()).unwrap();
     });
 }
 
@@ -59,7 +108,7 @@ fn bench_mison_avx_3(b: &mut test::Bencher) {
     let parser = Parser::new(index_builder);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT).unwrap();
+        let _ = parser.parse(input()).unwrap();
     });
 }
 
@@ -72,7 +121,7 @@ fn bench_mison_avx_basic_1(b: &mut test::Bencher) {
     let parser = QueryParser::new(index_builder, queries);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT, QueryParserMode::Basic).unwrap();
+        let _ = parser.parse(input(), QueryParserMode::Basic).unwrap();
     });
 }
 
@@ -86,7 +135,7 @@ fn bench_mison_avx_basic_2(b: &mut test::Bencher) {
     let parser = QueryParser::new(index_builder, queries);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT, QueryParserMode::Basic).unwrap();
+        let _ = parser.parse(input(), QueryParserMode::Basic).unwrap();
     });
 }
 
@@ -99,7 +148,7 @@ fn bench_mison_avx_basic_3(b: &mut test::Bencher) {
     let parser = QueryParser::new(index_builder, queries);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT, QueryParserMode::Basic).unwrap();
+        let _ = parser.parse(input(), QueryParserMode::Basic).unwrap();
     });
 }
 
@@ -113,11 +162,11 @@ fn bench_mison_avx_speculative_1(b: &mut test::Bencher) {
 
     // train
     parser.save_patterns(true);
-    let _ = parser.parse(INPUT, QueryParserMode::Basic).unwrap();
+    let _ = parser.parse(input(), QueryParserMode::Basic).unwrap();
     parser.save_patterns(false);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT, QueryParserMode::Speculative).unwrap();
+        let _ = parser.parse(input(), QueryParserMode::Speculative).unwrap();
     });
 }
 
@@ -132,11 +181,11 @@ fn bench_mison_avx_speculative_2(b: &mut test::Bencher) {
 
     // train
     parser.save_patterns(true);
-    let _ = parser.parse(INPUT, QueryParserMode::Basic).unwrap();
+    let _ = parser.parse(input(), QueryParserMode::Basic).unwrap();
     parser.save_patterns(false);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT, QueryParserMode::Speculative).unwrap();
+        let _ = parser.parse(input(), QueryParserMode::Speculative).unwrap();
     });
 }
 
@@ -150,11 +199,11 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 
     // train
     parser.save_patterns(true);
-    let _ = parser.parse(INPUT, QueryParserMode::Basic).unwrap();
+    let _ = parser.parse(input(), QueryParserMode::Basic).unwrap();
     parser.save_patterns(false);
 
     b.iter(|| {
-        let _ = parser.parse(INPUT, QueryParserMode::Speculative).unwrap();
+        let _ = parser.parse(input(), QueryParserMode::Speculative).unwrap();
     });
 }
 
@@ -164,7 +213,7 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 //     let mut pikkr = Pikkr::new(&["$._id.$oid"], ::std::usize::MAX).unwrap();
 
 //     b.iter(|| {
-//         let _ = pikkr.parse(INPUT).unwrap();
+//         let _ = pikkr.parse(input()).unwrap();
 //     });
 // }
 
@@ -174,7 +223,7 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 //     let mut pikkr = Pikkr::new(&["$._id.$oid", "$.partners"], ::std::usize::MAX).unwrap();
 
 //     b.iter(|| {
-//         let _ = pikkr.parse(INPUT).unwrap();
+//         let _ = pikkr.parse(input()).unwrap();
 //     });
 // }
 
@@ -184,7 +233,7 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 //     let mut pikkr = Pikkr::new(&["$.partners"], ::std::usize::MAX).unwrap();
 
 //     b.iter(|| {
-//         let _ = pikkr.parse(INPUT).unwrap();
+//         let _ = pikkr.parse(input()).unwrap();
 //     });
 // }
 
@@ -192,10 +241,10 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 // #[cfg(feature = "avx-accel")]
 // fn bench_pikkr_speculative_1(b: &mut test::Bencher) {
 //     let mut pikkr = Pikkr::new(&["$._id.$oid"], 1).unwrap();
-//     let _ = pikkr.parse(INPUT).unwrap();
+//     let _ = pikkr.parse(input()).unwrap();
 
 //     b.iter(|| {
-//         let _ = pikkr.parse(INPUT).unwrap();
+//         let _ = pikkr.parse(input()).unwrap();
 //     });
 // }
 
@@ -203,10 +252,10 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 // #[cfg(feature = "avx-accel")]
 // fn bench_pikkr_speculative_2(b: &mut test::Bencher) {
 //     let mut pikkr = Pikkr::new(&["$._id.$oid", "$.partners"], 1).unwrap();
-//     let _ = pikkr.parse(INPUT).unwrap();
+//     let _ = pikkr.parse(input()).unwrap();
 
 //     b.iter(|| {
-//         let _ = pikkr.parse(INPUT).unwrap();
+//         let _ = pikkr.parse(input()).unwrap();
 //     });
 // }
 
@@ -214,10 +263,10 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 // #[cfg(feature = "avx-accel")]
 // fn bench_pikkr_speculative_3(b: &mut test::Bencher) {
 //     let mut pikkr = Pikkr::new(&["$.partners"], 1).unwrap();
-//     let _ = pikkr.parse(INPUT).unwrap();
+//     let _ = pikkr.parse(input()).unwrap();
 
 //     b.iter(|| {
-//         let _ = pikkr.parse(INPUT).unwrap();
+//         let _ = pikkr.parse(input()).unwrap();
 //     });
 // }
 
@@ -228,7 +277,7 @@ fn bench_mison_avx_speculative_3(b: &mut test::Bencher) {
 //     let mut index_builder = IndexBuilder::new(3);
 //     b.iter(|| {
 //         index_builder
-//             .build_structural_indices(INPUT.as_bytes())
+//             .build_structural_indices(input().as_bytes())
 //             .unwrap();
 //     });
 // }
@@ -238,7 +287,7 @@ fn bench_mison_index_builder_fallback(b: &mut test::Bencher) {
     let index_builder = IndexBuilder::new(FallbackBackend::default(), 3);
 
     b.iter(|| {
-        let _ = index_builder.build(INPUT).unwrap();
+        let _ = index_builder.build(input()).unwrap();
     });
 }
 
@@ -248,7 +297,7 @@ fn bench_mison_index_builder_sse2(b: &mut test::Bencher) {
     let index_builder = IndexBuilder::new(Sse2Backend::default(), 3);
 
     b.iter(|| {
-        let _ = index_builder.build(INPUT).unwrap();
+        let _ = index_builder.build(input()).unwrap();
     });
 }
 
@@ -258,7 +307,7 @@ fn bench_mison_index_builder_avx(b: &mut test::Bencher) {
     let index_builder = IndexBuilder::new(AvxBackend::default(), 3);
 
     b.iter(|| {
-        let _ = index_builder.build(INPUT).unwrap();
+        let _ = index_builder.build(input()).unwrap();
     });
 }
 
@@ -268,7 +317,7 @@ fn bench_mison_index_builder_avx_2(b: &mut test::Bencher) {
     let index_builder = IndexBuilder::new(AvxBackend::default(), 1);
 
     b.iter(|| {
-        let _ = index_builder.build(INPUT).unwrap();
+        let _ = index_builder.build(input()).unwrap();
     });
 }
 
@@ -278,6 +327,6 @@ fn bench_mison_index_builder_avx_3(b: &mut test::Bencher) {
     let index_builder = IndexBuilder::new(AvxBackend::default(), 25);
 
     b.iter(|| {
-        let _ = index_builder.build(INPUT).unwrap();
+        let _ = index_builder.build(input()).unwrap();
     });
 }
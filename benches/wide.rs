@@ -0,0 +1,90 @@
+#![feature(test)]
+extern crate test;
+
+use misosoup::index_builder::backend::FallbackBackend;
+use misosoup::index_builder::IndexBuilder;
+use misosoup::query::QueryTree;
+use misosoup::query_parser::{QueryParser, QueryParserMode};
+
+const NUM_FIELDS: usize = 10_000;
+const NUM_QUERIES: usize = 100;
+
+/// An object with many fields, whose values contain many quotes and colons.
+fn wide_record() -> String {
+    let fields: Vec<String> = (0..NUM_FIELDS)
+        .map(|i| format!(r#""field_{}": {{"s": "a:b:c", "t": ["x", "y"]}}"#, i))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn paths() -> Vec<String> {
+    (0..NUM_QUERIES)
+        .map(|i| format!("$.field_{}.s", i * (NUM_FIELDS / NUM_QUERIES)))
+        .collect()
+}
+
+fn bench_wide(b: &mut test::Bencher, mode: QueryParserMode, rank_select: bool) {
+    let record = wide_record();
+    let paths = paths();
+    let mut query_tree = QueryTree::default();
+    for path in &paths {
+        query_tree.add_path(path).unwrap();
+    }
+    let mut index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+    index_builder.rank_select(rank_select);
+    let mut parser = QueryParser::new(index_builder, query_tree);
+    parser.save_patterns(true);
+    parser.parse(&record, QueryParserMode::Basic).unwrap();
+
+    b.bytes = record.len() as u64;
+    b.iter(|| {
+        let _ = parser.parse(&record, mode).unwrap();
+    });
+}
+
+/// Enumerate the colons of the root object, or of a nested object in the middle of it.
+fn bench_colon_positions(b: &mut test::Bencher, nested: bool) {
+    let record = wide_record();
+    let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+    let index = index_builder.build(&record).unwrap();
+    let (begin, end, level) = if nested {
+        let begin = record.find(&format!("\"field_{}\"", NUM_FIELDS / 2)).unwrap();
+        let begin = begin + record[begin..].find('{').unwrap();
+        let end = begin + record[begin..].find('}').unwrap() + 1;
+        (begin, end, 1)
+    } else {
+        (0, record.len(), 0)
+    };
+    let mut cp = Vec::new();
+
+    b.bytes = (end - begin) as u64;
+    b.iter(|| {
+        index.colon_positions(begin, end, level, &mut cp);
+        test::black_box(cp.len())
+    });
+}
+
+#[bench]
+fn bench_colon_positions_root(b: &mut test::Bencher) {
+    bench_colon_positions(b, false);
+}
+
+#[bench]
+fn bench_colon_positions_nested(b: &mut test::Bencher) {
+    bench_colon_positions(b, true);
+}
+
+#[bench]
+fn bench_wide_basic(b: &mut test::Bencher) {
+    bench_wide(b, QueryParserMode::Basic, false);
+}
+
+#[bench]
+fn bench_wide_speculative(b: &mut test::Bencher) {
+    bench_wide(b, QueryParserMode::Speculative, false);
+}
+
+#[bench]
+fn bench_wide_speculative_rank_select(b: &mut test::Bencher) {
+    bench_wide(b, QueryParserMode::Speculative, true);
+}
@@ -0,0 +1,64 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+
+use misosoup::index_builder::IndexBuilder;
+use misosoup::query::QueryTree;
+use misosoup::query_parser::{QueryParser, QueryParserMode};
+use misosoup::shard::shard;
+
+/// Count the values of the query paths in a file of newline-delimited records on all
+/// cores, e.g. `cargo run --release --example shard -- tweets.json '$.user.name'`.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: shard <file> <path>...");
+        process::exit(1);
+    }
+    let input = fs::read_to_string(&args[0]).unwrap();
+
+    let mut tree = QueryTree::default();
+    for path in &args[1..] {
+        tree.add_path(path).unwrap();
+    }
+    let index_builder = IndexBuilder::with_best_backend(tree.max_level());
+    let mut parser = QueryParser::new(index_builder, tree);
+    parser.save_patterns(true);
+    // train the patterns on a few records before freezing them.
+    for record in input.lines().take(100) {
+        let _ = parser.parse(record, QueryParserMode::Basic);
+    }
+    let parser = parser.freeze();
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let results = shard(
+        &parser,
+        &input,
+        QueryParserMode::Speculative,
+        cores,
+        |_, results| results.iter().map(Option::is_some).collect::<Vec<_>>(),
+    );
+
+    let mut counts = vec![0; args.len() - 1];
+    let mut errors = 0;
+    for result in &results {
+        match result {
+            Ok(found) => {
+                for (count, &found) in counts.iter_mut().zip(found) {
+                    *count += found as usize;
+                }
+            }
+            Err(_) => errors += 1,
+        }
+    }
+    println!(
+        "{} records on {} cores, {} errors",
+        results.len(),
+        cores,
+        errors
+    );
+    for (path, count) in args[1..].iter().zip(counts) {
+        println!("{}: {}", path, count);
+    }
+}
@@ -0,0 +1,262 @@
+//! Run many varied records through a single `QueryParser` and check that it does not
+//! degrade over time:
+//!
+//! * no record fails to be parsed,
+//! * the buffers of the workspaces, including the internal ones of the parser and its
+//!   index builder, do not keep growing,
+//! * nested and concurrent parses neither panic nor deadlock, including those from
+//!   the prefilter and the failure sink while the outer parse holds the internal
+//!   workspace, and those from the transforms and the worker threads,
+//! * the trained pattern trees stay bounded while the patterns decay.
+//!
+//! ```text
+//! cargo run --release --example soak -- [count] [seed] [workers]
+//! ```
+//!
+//! A shorter run is also made by `cargo test -- --ignored` (see `tests/soak.rs`).
+
+use std::env;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+use std::thread;
+
+use misosoup::index_builder::backend::DynBackend;
+use misosoup::index_builder::{BufferPolicy, IndexBuilder};
+use misosoup::query::QueryTree;
+use misosoup::query_parser::{QueryParser, QueryParserMode, Workspace};
+use misosoup::testdata::{Corpus, Rng, DEFAULT_SEED};
+
+const PATHS: &[&str] = &[
+    "$._id",
+    "$.id",
+    "$.name",
+    "$.text",
+    "$.user",
+    "$.user.name",
+    "$.entities.hashtags",
+    "$.offices",
+    "$.values",
+];
+
+/// The number of patterns trained for a node which is considered a bloat
+const MAX_PATTERNS: usize = 4096;
+
+type Parser = QueryParser<'static, DynBackend>;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    records: usize,
+    errors: usize,
+    peak_memory: usize,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let count: usize = args.first().map_or(1_000_000, |s| s.parse().unwrap());
+    let seed: u64 = args.get(1).map_or(DEFAULT_SEED, |s| s.parse().unwrap());
+    let workers: usize = args.get(2).map_or(4, |s| s.parse().unwrap());
+
+    let failures = soak(count, seed, workers);
+    for failure in &failures {
+        eprintln!("{}", failure);
+    }
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Run `count` records through a parser in `workers` threads, and return the reasons
+/// why the run failed.
+pub fn soak(count: usize, seed: u64, workers: usize) -> Vec<String> {
+    let parser = parser();
+    let num_nodes = parser.query_tree().num_nodes();
+
+    // the statistics of the first and the second halves of the records
+    let mut halves = [Stats::default(); 2];
+    let mut peak_patterns = [0; 2];
+    let mut parser_memory = [0; 2];
+    for (half, stats) in halves.iter_mut().enumerate() {
+        let results: Vec<Stats> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|worker| {
+                    let parser = &*parser;
+                    let seed = seed ^ ((half * workers + worker) as u64) << 32;
+                    scope.spawn(move || run(parser, seed, count / 2 / workers))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for r in results {
+            stats.records += r.records;
+            stats.errors += r.errors;
+            stats.peak_memory = stats.peak_memory.max(r.peak_memory);
+        }
+        peak_patterns[half] = (0..num_nodes)
+            .map(|node_id| parser.pattern_tree(node_id).patterns().count())
+            .max()
+            .unwrap_or(0);
+        // the workers have finished, so the parser is no longer in use.
+        parser_memory[half] = parser.memory_usage().unwrap();
+        eprintln!(
+            "half {}: {} records, {} errors, peak workspace {} bytes, parser {} bytes, peak patterns {}",
+            half,
+            stats.records,
+            stats.errors,
+            stats.peak_memory,
+            parser_memory[half],
+            peak_patterns[half],
+        );
+    }
+
+    let mut failures = vec![];
+    let errors = halves[0].errors + halves[1].errors;
+    if errors > 0 {
+        failures.push(format!("{} records failed to be parsed", errors));
+    }
+    let nested_errors = NESTED_ERRORS.load(Ordering::Relaxed);
+    if nested_errors > 0 {
+        failures.push(format!("{} nested parses failed", nested_errors));
+    }
+    if halves[1].peak_memory > 2 * halves[0].peak_memory {
+        failures.push("the workspaces keep growing".to_owned());
+    }
+    // the internal buffers are measured only between the halves, and may be held for
+    // a large record at that time.
+    if parser_memory[1] > 2 * parser_memory[0].max(halves[0].peak_memory) {
+        failures.push("the internal buffers of the parser keep growing".to_owned());
+    }
+    if peak_patterns[1] > MAX_PATTERNS {
+        failures.push("the pattern trees keep growing".to_owned());
+    }
+    for (name, nested) in &[
+        ("transform", &NESTED),
+        ("prefilter", &NESTED_PREFILTER),
+        ("failure sink", &NESTED_SINK),
+    ] {
+        if nested.load(Ordering::Relaxed) == 0 {
+            failures.push(format!("no nested parses are made from the {}", name));
+        }
+    }
+    failures
+}
+
+/// The numbers of the parses made by the transform, the prefilter and the failure sink
+static NESTED: AtomicUsize = AtomicUsize::new(0);
+static NESTED_PREFILTER: AtomicUsize = AtomicUsize::new(0);
+static NESTED_SINK: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of the nested parses which failed
+static NESTED_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// The record parsed from the prefilter and the failure sink
+const NESTED_RECORD: &str = r#"{"id": 1, "user": {"name": "nested"}}"#;
+
+/// Parse `NESTED_RECORD` with `parser` from within a callback of the outer parse.
+fn parse_nested(parser: &Parser, count: &AtomicUsize) {
+    count.fetch_add(1, Ordering::Relaxed);
+    match parser.parse(NESTED_RECORD, QueryParserMode::Speculative) {
+        Ok(ref results) if results[1] == Some("1") => {}
+        _ => {
+            NESTED_ERRORS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn parser() -> Arc<Parser> {
+    let mut tree = QueryTree::default();
+    for path in PATHS {
+        tree.add_path(path).unwrap();
+    }
+    let index_builder = IndexBuilder::with_best_backend(tree.max_level()).into_dyn();
+    let mut parser = QueryParser::new(index_builder, tree);
+    parser.save_patterns(true);
+    parser.decay_patterns(Some(10_000));
+    parser.buffer_policy(BufferPolicy::ShrinkAfter(1000));
+
+    // parse the objects of the users again with the same parser, after the outer
+    // parse has released the internal workspace.
+    let this: Arc<OnceLock<Weak<Parser>>> = Arc::new(OnceLock::new());
+    let user = PATHS.iter().position(|&p| p == "$.user").unwrap();
+    parser.map(user, {
+        let this = this.clone();
+        move |value| {
+            if let Some(parser) = this.get().and_then(Weak::upgrade) {
+                if value.starts_with('{') {
+                    NESTED.fetch_add(1, Ordering::Relaxed);
+                    if parser.parse(value, QueryParserMode::Speculative).is_err() {
+                        NESTED_ERRORS.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            value.to_owned()
+        }
+    });
+
+    // parse another record from the prefilter and the failure sink, which are called
+    // while the outer parse holds the internal workspace.
+    let calls = AtomicUsize::new(0);
+    parser.with_prefilter({
+        let this = this.clone();
+        move |record| {
+            if record != NESTED_RECORD && calls.fetch_add(1, Ordering::Relaxed).is_multiple_of(64) {
+                if let Some(parser) = this.get().and_then(Weak::upgrade) {
+                    parse_nested(&parser, &NESTED_PREFILTER);
+                }
+            }
+            true
+        }
+    });
+    parser.failure_sink(256, {
+        let this = this.clone();
+        move |_| {
+            if let Some(parser) = this.get().and_then(Weak::upgrade) {
+                parse_nested(&parser, &NESTED_SINK);
+            }
+        }
+    });
+
+    let parser = Arc::new(parser);
+    this.set(Arc::downgrade(&parser)).unwrap();
+    parser
+}
+
+fn run(parser: &Parser, seed: u64, count: usize) -> Stats {
+    let mut rng = Rng::new(seed);
+    let mut workspace = Workspace::new();
+    let mut stats = Stats::default();
+    for i in 0..count {
+        // the huge arrays are rare, but inflate the buffers.
+        let corpus = match rng.below(100) {
+            0 => Corpus::HugeArrays,
+            n => Corpus::ALL[n as usize % 4],
+        };
+        let record = corpus.record(&mut rng);
+        // the invalid records are passed to the failure sink, and must be rejected.
+        if rng.below(64) == 0 {
+            let invalid = format!("[{}]", record);
+            if parser.parse(&invalid, QueryParserMode::Basic).is_ok() {
+                stats.errors += 1;
+            }
+        }
+        let mode = match rng.below(2) {
+            0 => QueryParserMode::Basic,
+            _ => QueryParserMode::Speculative,
+        };
+
+        // the internal workspace is shared with the other workers and the nested
+        // parses, and the own one is measured.
+        let result = if i % 2 == 0 {
+            parser.parse_cow(&record, mode).map(drop)
+        } else {
+            let mut results = vec![];
+            parser.parse_with_workspace(&record, mode, &mut workspace, &mut results)
+        };
+        if result.is_err() {
+            stats.errors += 1;
+        }
+        stats.records += 1;
+        stats.peak_memory = stats.peak_memory.max(workspace.memory_usage());
+    }
+    stats
+}
@@ -0,0 +1,25 @@
+use std::env;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use misosoup::testdata::{Corpus, DEFAULT_SEED};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let corpus = match args.first().and_then(|name| Corpus::from_name(name)) {
+        Some(corpus) => corpus,
+        None => {
+            let names: Vec<_> = Corpus::ALL.iter().map(|c| c.name()).collect();
+            eprintln!("usage: testdata <{}> [count] [seed]", names.join("|"));
+            process::exit(1);
+        }
+    };
+    let count = args.get(1).map_or(1000, |s| s.parse().unwrap());
+    let seed = args.get(2).map_or(DEFAULT_SEED, |s| s.parse().unwrap());
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for record in corpus.records(seed, count) {
+        writeln!(out, "{}", record).unwrap();
+    }
+}
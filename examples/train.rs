@@ -17,14 +17,21 @@ mod imp {
         tree.add_path("$.total_money_raised").unwrap();
 
         let index_builder = IndexBuilder::new(AvxBackend::default(), tree.max_level());
-        let parser = QueryParser::new(index_builder, tree);
+        let mut parser = QueryParser::new(index_builder, tree);
+        parser.save_patterns(true);
 
         let path = env::args().nth(1).unwrap();
         let f = BufReader::new(File::open(path).unwrap());
         for input in f.lines().filter_map(Result::ok) {
             let _ = parser.parse(&input, QueryParserMode::Basic).unwrap();
         }
-        println!("{:#?}", parser);
+        // the graphs can be rendered by e.g. `dot -Tsvg -O`.
+        print!("{}", parser.query_tree().to_dot());
+        parser.query_tree().visit(|_, node| {
+            if !node.is_leaf() {
+                print!("{}", parser.pattern_tree(node.node_id()).to_dot());
+            }
+        });
     }
 }
 
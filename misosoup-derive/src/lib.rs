@@ -0,0 +1,135 @@
+//! Custom derive for `misosoup::record::FromRecord`.
+//!
+//! Every field has to be annotated with the query path of its value. The attribute is
+//! namespaced as `#[record(path = "...")]`, since `#[path]` is reserved by the compiler.
+//!
+//! ```ignore
+//! #[derive(FromRecord)]
+//! struct Click<'s> {
+//!     #[record(path = "$.user.id")]
+//!     user_id: u64,
+//!     #[record(path = "$.url")]
+//!     url: Option<&'s str>,
+//! }
+//! ```
+//!
+//! The order of the fields determines the order of the paths.
+
+#![deny(missing_docs)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, GenericParam, Lifetime, LifetimeParam,
+    LitStr, Result,
+};
+
+#[allow(missing_docs)]
+#[proc_macro_derive(FromRecord, attributes(record))]
+pub fn derive_from_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    input,
+                    "FromRecord can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "FromRecord can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut names = Vec::with_capacity(fields.len());
+    let mut paths = Vec::with_capacity(fields.len());
+    for field in fields {
+        names.push(field.ident.clone().unwrap());
+        paths.push(field_path(field)?);
+    }
+
+    if let Some(param) = input.generics.type_params().next() {
+        return Err(Error::new_spanned(
+            param,
+            "FromRecord cannot be derived for generic structs",
+        ));
+    }
+
+    // The lifetime of the record is the first lifetime parameter of the struct, if any.
+    let ident = &input.ident;
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut generics = input.generics.clone();
+    let lifetime = match input.generics.lifetimes().next() {
+        Some(param) => param.lifetime.clone(),
+        None => {
+            let lifetime = Lifetime::new("'__s", Span::call_site());
+            generics.params.insert(
+                0,
+                GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+            );
+            lifetime
+        }
+    };
+    let (impl_generics, _, _) = generics.split_for_impl();
+
+    let indices = 0..names.len();
+    let field_names = names.iter().map(|name| name.to_string());
+    Ok(quote! {
+        impl #impl_generics ::misosoup::record::FromRecord<#lifetime> for #ident #ty_generics
+        #where_clause
+        {
+            fn paths() -> &'static [&'static str] {
+                &[#(#paths),*]
+            }
+
+            fn from_results(
+                results: &[::std::option::Option<&#lifetime str>],
+            ) -> ::misosoup::errors::Result<Self> {
+                ::std::result::Result::Ok(#ident {
+                    #(
+                        #names: ::misosoup::errors::ResultExt::chain_err(
+                            ::misosoup::record::FromSlice::from_slice(results[#indices]),
+                            || format!("field {:?} at {:?}", #field_names, #paths),
+                        )?,
+                    )*
+                })
+            }
+        }
+    })
+}
+
+fn field_path(field: &syn::Field) -> Result<String> {
+    let mut path = None;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("record") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("path") {
+                return Err(meta.error("unsupported attribute"));
+            }
+            if path.is_some() {
+                return Err(meta.error("duplicated `path`"));
+            }
+            let value: LitStr = meta.value()?.parse()?;
+            path = Some(value.value());
+            Ok(())
+        })?;
+    }
+    path.ok_or_else(|| Error::new_spanned(field, "missing #[record(path = \"...\")] attribute"))
+}
@@ -0,0 +1,54 @@
+use misosoup::index_builder::backend::FallbackBackend;
+use misosoup::index_builder::IndexBuilder;
+use misosoup::query_parser::QueryParserMode;
+use misosoup::record::{FromRecord, RecordParser};
+
+#[derive(Debug, PartialEq, FromRecord)]
+struct Click<'s> {
+    #[record(path = "$.user.id")]
+    user_id: u64,
+    #[record(path = "$.url")]
+    url: Option<&'s str>,
+    #[record(path = "$.user.name")]
+    name: String,
+}
+
+#[derive(Debug, PartialEq, FromRecord)]
+struct Status {
+    #[record(path = "$.ok")]
+    ok: bool,
+}
+
+#[test]
+fn derive_from_record() {
+    assert_eq!(Click::paths(), &["$.user.id", "$.url", "$.user.name"]);
+
+    let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+    let parser = RecordParser::<Click<'_>, _>::new(index_builder).unwrap();
+    let record = r#"{ "user": { "id": 42, "name": "A" }, "url": "/" }"#;
+    assert_eq!(
+        parser.parse(record, QueryParserMode::Basic).unwrap(),
+        Click {
+            user_id: 42,
+            url: Some(r#""/""#),
+            name: "A".into(),
+        }
+    );
+
+    let err = parser
+        .parse(r#"{ "user": { "id": 1 } }"#, QueryParserMode::Basic)
+        .unwrap_err();
+    assert!(err.to_string().contains("\"name\""), "{}", err);
+}
+
+#[test]
+fn derive_without_lifetime() {
+    let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+    let parser = RecordParser::<Status, _>::new(index_builder).unwrap();
+    assert_eq!(
+        parser
+            .parse(r#"{ "ok": true }"#, QueryParserMode::Basic)
+            .unwrap(),
+        Status { ok: true }
+    );
+}
@@ -0,0 +1,302 @@
+//! Group-by aggregation over query results
+
+use crate::errors::Result;
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+use fnv::FnvHashMap;
+
+/// Statistics of the numeric values of a path within a group
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// number of numeric values
+    pub count: u64,
+    /// sum of numeric values
+    pub sum: f64,
+    /// minimum of numeric values
+    pub min: f64,
+    /// maximum of numeric values
+    pub max: f64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Stats {
+    #[inline]
+    fn add(&mut self, n: f64) {
+        self.count += 1;
+        self.sum += n;
+        self.min = self.min.min(n);
+        self.max = self.max.max(n);
+    }
+}
+
+/// Aggregated results of the records sharing a key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// number of records in this group
+    pub count: u64,
+    /// statistics for each value path, in the order of registration
+    pub stats: Vec<Stats>,
+}
+
+/// An aggregator which groups records by the value of a key path.
+///
+/// Keys are compared by their raw slices (e.g. `"foo"` including quotes), and only
+/// the values which are JSON numbers contribute to the statistics. Numbers are
+/// parsed directly from the extracted slices, without building `Value`s.
+/// Records without the key path are skipped.
+#[derive(Debug)]
+pub struct Aggregator<'q, B: Backend> {
+    parser: QueryParser<'q, B>,
+    num_values: usize,
+    groups: FnvHashMap<String, Group>,
+}
+
+impl<'q, B: Backend> Aggregator<'q, B> {
+    /// Create a new aggregator grouping by `key_path` and summarizing `value_paths`.
+    ///
+    /// The level of `index_builder` should be large enough to cover all paths.
+    pub fn new(
+        index_builder: IndexBuilder<B>,
+        key_path: &'q str,
+        value_paths: &[&'q str],
+    ) -> Result<Self> {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path(key_path)?;
+        for path in value_paths {
+            query_tree.add_path(path)?;
+        }
+        Ok(Self {
+            parser: QueryParser::new(index_builder, query_tree),
+            num_values: value_paths.len(),
+            groups: FnvHashMap::default(),
+        })
+    }
+
+    /// Add a record to the aggregation.
+    pub fn consume(&mut self, record: &str) -> Result<()> {
+        let results = self.parser.parse(record, QueryParserMode::Basic)?;
+        let key = match results[0] {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        if !self.groups.contains_key(key) {
+            let group = Group {
+                count: 0,
+                stats: vec![Stats::default(); self.num_values],
+            };
+            self.groups.insert(key.to_owned(), group);
+        }
+        let group = self.groups.get_mut(key).unwrap();
+
+        group.count += 1;
+        for (stats, value) in group.stats.iter_mut().zip(&results[1..]) {
+            if let Some(n) = value.and_then(|v| v.parse::<f64>().ok()) {
+                stats.add(n);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add all records in `records` to the aggregation.
+    pub fn consume_all<'s, I>(&mut self, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'s str>,
+    {
+        for record in records {
+            self.consume(record)?;
+        }
+        Ok(())
+    }
+
+    /// Return the aggregated result of the group with the raw key `key`.
+    pub fn get(&self, key: &str) -> Option<&Group> {
+        self.groups.get(key)
+    }
+
+    /// Iterate over all groups, in arbitrary order.
+    pub fn groups(&self) -> impl Iterator<Item = (&str, &Group)> {
+        self.groups.iter().map(|(k, g)| (k.as_str(), g))
+    }
+}
+
+/// An estimated frequency of a value reported by `TopK`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frequency<'a> {
+    /// raw slice of the value
+    pub value: &'a str,
+    /// estimated number of occurrences, which never underestimates the true count
+    pub count: u64,
+    /// maximal overestimation of `count`
+    pub error: u64,
+}
+
+/// A collector of the most frequent values of a path, based on the space-saving
+/// algorithm (Metwally et al., 2005).
+///
+/// At most `k` counters are kept, so the memory usage does not depend on the number
+/// of distinct values. Every value whose true frequency exceeds `n / k` (where `n` is
+/// the number of consumed values) is guaranteed to be reported.
+#[derive(Debug)]
+pub struct TopK<'q, B: Backend> {
+    parser: QueryParser<'q, B>,
+    k: usize,
+    counters: FnvHashMap<String, (u64, u64)>,
+}
+
+impl<'q, B: Backend> TopK<'q, B> {
+    #[allow(missing_docs)]
+    pub fn new(index_builder: IndexBuilder<B>, path: &'q str, k: usize) -> Result<Self> {
+        assert!(k > 0, "k must be positive");
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path(path)?;
+        Ok(Self {
+            parser: QueryParser::new(index_builder, query_tree),
+            k,
+            counters: FnvHashMap::default(),
+        })
+    }
+
+    /// Add a record to the collector. Records without the path are skipped.
+    pub fn consume(&mut self, record: &str) -> Result<()> {
+        let results = self.parser.parse(record, QueryParserMode::Basic)?;
+        let value = match results[0] {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+
+        if let Some(counter) = self.counters.get_mut(value) {
+            counter.0 += 1;
+        } else if self.counters.len() < self.k {
+            self.counters.insert(value.to_owned(), (1, 0));
+        } else {
+            // replace the counter with the minimal count.
+            let (min_value, (min_count, _)) = self
+                .counters
+                .iter()
+                .min_by_key(|(_, &(count, _))| count)
+                .map(|(v, &c)| (v.clone(), c))
+                .unwrap();
+            self.counters.remove(&min_value);
+            self.counters
+                .insert(value.to_owned(), (min_count + 1, min_count));
+        }
+        Ok(())
+    }
+
+    /// Add all records in `records` to the collector.
+    pub fn consume_all<'s, I>(&mut self, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'s str>,
+    {
+        for record in records {
+            self.consume(record)?;
+        }
+        Ok(())
+    }
+
+    /// Return the monitored values in descending order of their estimated counts.
+    pub fn top(&self) -> Vec<Frequency<'_>> {
+        let mut result: Vec<_> = self
+            .counters
+            .iter()
+            .map(|(value, &(count, error))| Frequency {
+                value,
+                count,
+                error,
+            })
+            .collect();
+        result.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(b.value)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::stream::StreamParser;
+
+    #[test]
+    fn group_by() {
+        let input = r#"
+            { "user": "a", "bytes": 10, "latency": { "ms": 1.5 } }
+            { "user": "b", "bytes": 20 }
+            { "user": "a", "bytes": 30, "latency": { "ms": 0.5 } }
+            { "user": "a", "bytes": "n/a" }
+            { "bytes": 100 }
+        "#;
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let mut aggregator =
+            Aggregator::new(index_builder, "$.user", &["$.bytes", "$.latency.ms"]).unwrap();
+        aggregator.consume_all(StreamParser::new(input)).unwrap();
+
+        assert_eq!(aggregator.groups().count(), 2);
+        assert_eq!(
+            aggregator.get(r#""a""#),
+            Some(&Group {
+                count: 3,
+                stats: vec![
+                    Stats {
+                        count: 2,
+                        sum: 40.0,
+                        min: 10.0,
+                        max: 30.0,
+                    },
+                    Stats {
+                        count: 2,
+                        sum: 2.0,
+                        min: 0.5,
+                        max: 1.5,
+                    },
+                ],
+            })
+        );
+        let b = aggregator.get(r#""b""#).unwrap();
+        assert_eq!(b.count, 1);
+        assert_eq!(b.stats[0].sum, 20.0);
+        assert_eq!(b.stats[1].count, 0);
+    }
+
+    #[test]
+    fn top_k() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let mut top_k = TopK::new(index_builder, "$.status", 2).unwrap();
+
+        let statuses = [200, 200, 404, 200, 500, 200, 404, 404, 200];
+        let records: Vec<_> = statuses
+            .iter()
+            .map(|s| format!(r#"{{ "status": {} }}"#, s))
+            .collect();
+        top_k
+            .consume_all(records.iter().map(|r| r.as_str()))
+            .unwrap();
+        top_k.consume(r#"{ "path": "/" }"#).unwrap();
+
+        let top = top_k.top();
+        assert_eq!(top.len(), 2);
+        assert_eq!(
+            top[0],
+            Frequency {
+                value: "200",
+                count: 5,
+                error: 0,
+            }
+        );
+        assert_eq!(top[1].value, "404");
+        assert!(top[1].count - top[1].error <= 3 && 3 <= top[1].count);
+    }
+}
@@ -0,0 +1,68 @@
+//! Decoding of Base64 strings (RFC 4648)
+//!
+//! Both the standard and the URL-safe alphabets are accepted, with or without the
+//! padding, since the producers of the encoded payloads in records differ.
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+
+/// Decode a Base64 string into bytes.
+pub fn decode(s: &str) -> Result<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let body = bytes
+        .strip_suffix(b"==")
+        .or_else(|| bytes.strip_suffix(b"="))
+        .unwrap_or(bytes);
+    if body.len() < bytes.len() && bytes.len() & 3 != 0 || body.len() & 3 == 1 {
+        return Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("invalid length of Base64 string: {}", bytes.len()));
+    }
+
+    let mut out = Vec::with_capacity(body.len() / 4 * 3 + 2);
+    let (mut acc, mut bits) = (0u32, 0);
+    for (i, &c) in body.iter().enumerate() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| format!("invalid Base64 character at {}", i));
+            }
+        };
+        acc = acc << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let cases: &[(&str, &[u8])] = &[
+            ("", b""),
+            ("Zg==", b"f"),
+            ("Zm8=", b"fo"),
+            ("Zm9v", b"foo"),
+            ("Zm9vYg", b"foob"),
+            ("Zm9vYmE=", b"fooba"),
+            ("+/8=", &[0xfb, 0xff]),
+            ("-_8", &[0xfb, 0xff]),
+        ];
+        for &(s, expected) in cases {
+            assert_eq!(decode(s).unwrap(), expected, "{}", s);
+        }
+        for s in &["Z", "Zg=", "Zm9v=", "Zm 9v", "Zm9v\n"] {
+            assert!(decode(s).is_err(), "{}", s);
+        }
+    }
+}
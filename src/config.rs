@@ -0,0 +1,177 @@
+//! Declarative extraction configs
+//!
+//! A config lists the fields to extract, with their query paths, output names and
+//! types, so that extractions can be changed without recompiling:
+//!
+//! ```toml
+//! mode = "speculative"
+//!
+//! [[fields]]
+//! name = "user_id"
+//! path = "$.user.id"
+//! type = "i64"
+//!
+//! [[fields]]
+//! path = "$.url"
+//! ```
+//!
+//! `ExtractionConfig` implements `Deserialize`, so it can be loaded from other formats
+//! (e.g. YAML) with the corresponding serde crates.
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+use crate::typed::{FieldType, Schema, TypedQueryParser};
+use serde::Deserialize;
+
+/// A set of fields to extract from records
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractionConfig {
+    /// parsing mode, `"basic"` (default) or `"speculative"`
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[allow(missing_docs)]
+    pub fields: Vec<FieldConfig>,
+}
+
+/// A field of `ExtractionConfig`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldConfig {
+    /// output name of the field, or the path itself if omitted
+    #[serde(default)]
+    pub name: Option<String>,
+    /// query path, like `"$.user.id"`
+    pub path: String,
+    /// type of the field (see `FieldType`), or `"raw"` if omitted
+    #[serde(default, rename = "type")]
+    pub ty: Option<String>,
+}
+
+impl ExtractionConfig {
+    /// Load a config from a TOML document.
+    pub fn from_toml(s: &str) -> Result<Self> {
+        toml::from_str(s)
+            .map_err(|e| Error::from(ErrorKind::InvalidQuery).chain_err(|| e.to_string()))
+            .chain_err(|| "failed to load the extraction config")
+    }
+
+    #[allow(missing_docs)]
+    pub fn mode(&self) -> Result<QueryParserMode> {
+        match self.mode.as_deref() {
+            None | Some("basic") => Ok(QueryParserMode::Basic),
+            Some("speculative") => Ok(QueryParserMode::Speculative),
+            Some(mode) => Err(Error::from(ErrorKind::InvalidQuery))
+                .chain_err(|| format!("unknown parsing mode {:?}", mode)),
+        }
+    }
+
+    /// Build a query tree with the named paths of the fields, in order.
+    pub fn query_tree(&self) -> Result<QueryTree<'_>> {
+        let mut query_tree = QueryTree::default();
+        for field in &self.fields {
+            match field.name {
+                Some(ref name) => query_tree.add_named(name, &field.path),
+                None => query_tree.add_path(&field.path),
+            }
+            .chain_err(|| format!("invalid field {:?}", field.path))?;
+        }
+        Ok(query_tree)
+    }
+
+    /// Build a schema with the types of the fields, in order.
+    pub fn schema(&self) -> Result<Schema<'_>> {
+        let mut schema = Schema::default();
+        for field in &self.fields {
+            let ty = match field.ty {
+                Some(ref ty) => ty
+                    .parse()
+                    .chain_err(|| format!("invalid field {:?}", field.path))?,
+                None => FieldType::Raw,
+            };
+            schema.add(&field.path, ty);
+        }
+        Ok(schema)
+    }
+
+    /// Create a query parser whose level covers all the paths.
+    pub fn query_parser<B: Backend>(&self, backend: B) -> Result<QueryParser<'_, B>> {
+        let query_tree = self.query_tree()?;
+        let index_builder = IndexBuilder::new(backend, query_tree.max_level());
+        Ok(QueryParser::new(index_builder, query_tree))
+    }
+
+    /// Create a typed query parser whose level covers all the paths.
+    pub fn typed_parser<B: Backend>(&self, backend: B) -> Result<TypedQueryParser<'_, B>> {
+        // validate the names as well as the paths.
+        let level = self.query_tree()?.max_level();
+        TypedQueryParser::new(IndexBuilder::new(backend, level), &self.schema()?)
+    }
+
+    /// Return the output names of the fields, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields
+            .iter()
+            .map(|field| field.name.as_ref().unwrap_or(&field.path).as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::typed::TypedValue;
+
+    const CONFIG: &str = r#"
+        mode = "speculative"
+
+        [[fields]]
+        name = "user_id"
+        path = "$.user.id"
+        type = "i64"
+
+        [[fields]]
+        path = "$.url"
+    "#;
+
+    #[test]
+    fn load_config() {
+        let config = ExtractionConfig::from_toml(CONFIG).unwrap();
+        assert_eq!(config.mode().unwrap(), QueryParserMode::Speculative);
+        assert_eq!(config.names().collect::<Vec<_>>(), ["user_id", "$.url"]);
+
+        let record = r#"{ "user": { "id": 42 }, "url": "/" }"#;
+        let parser = config.query_parser(FallbackBackend::default()).unwrap();
+        let results = parser.parse_named(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(results["user_id"], Some("42"));
+        assert_eq!(results["$.url"], Some(r#""/""#));
+
+        let parser = config.typed_parser(FallbackBackend::default()).unwrap();
+        let results = parser
+            .parse(record, QueryParserMode::Basic)
+            .unwrap()
+            .into_fields();
+        assert_eq!(results[0].as_ref().unwrap(), &Some(TypedValue::I64(42)));
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &Some(TypedValue::Raw(r#""/""#))
+        );
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(ExtractionConfig::from_toml("fields = 1").is_err());
+        assert!(ExtractionConfig::from_toml("[[fields]]\npath = \"$.a\"\nfoo = 1").is_err());
+
+        let config =
+            ExtractionConfig::from_toml("[[fields]]\npath = \"$.a\"\ntype = \"u8\"").unwrap();
+        assert!(config.schema().is_err());
+        let config = ExtractionConfig::from_toml("[[fields]]\npath = \"a\"").unwrap();
+        assert!(config.query_tree().is_err());
+        let config = ExtractionConfig::from_toml("mode = \"fast\"\nfields = []").unwrap();
+        assert!(config.mode().is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Editing of records preserving their original bytes
+//!
+//! A `Document` keeps the record along with the spans of its values, and serializing
+//! it reproduces the record byte for byte (whitespace, key order and the text of
+//! numbers and strings), except for the values replaced by `Document::replace`.
+
+use crate::errors::{Error, ErrorKind, Result};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::{IndexBuffers, IndexBuilder, StructuralIndex};
+use crate::parser::RawPolicy;
+use crate::value::pointer::{is_named, parse_index, tokens};
+use crate::value::Value;
+use std::fmt;
+
+/// A parsed record which can be serialized into its original bytes
+#[derive(Debug, Clone)]
+pub struct Document<'s> {
+    record: &'s str,
+    value: Value<'s>,
+    span: Span,
+    /// the spans of the replaced values, with their JSON pointers
+    edits: Vec<(usize, usize, String)>,
+}
+
+/// The span of a value and of its elements or fields, in the order of `Value`
+#[derive(Debug, Clone)]
+pub(crate) struct Span {
+    begin: usize,
+    end: usize,
+    children: Vec<Span>,
+    replaced: bool,
+}
+
+impl<'s> Document<'s> {
+    pub(crate) fn new(record: &'s str, value: Value<'s>, span: Span) -> Self {
+        Self {
+            record,
+            value,
+            span,
+            edits: vec![],
+        }
+    }
+
+    #[allow(missing_docs)]
+    pub fn value(&self) -> &Value<'s> {
+        &self.value
+    }
+
+    /// Return whether any value has been replaced.
+    pub fn is_modified(&self) -> bool {
+        !self.edits.is_empty()
+    }
+
+    /// Replace the value at a JSON Pointer (RFC 6901).
+    ///
+    /// Only the text of the replaced value changes when serialized, which is written
+    /// compactly in the order of its fields. Fields and elements cannot be added or
+    /// removed.
+    pub fn replace(&mut self, pointer: &str, value: Value<'s>) -> Result<()> {
+        let not_found = || {
+            Error::from(ErrorKind::InvalidPatch).chain_err(|| format!("{:?} not found", pointer))
+        };
+        let tokens = tokens(pointer).ok_or_else(not_found)?;
+
+        let mut cur = &self.value;
+        let mut span = Some(&mut self.span);
+        for token in &tokens {
+            let i = match *cur {
+                Value::Object(ref obj) => obj.iter().position(|(k, _)| is_named(k, token)),
+                Value::Array(ref arr) => parse_index(token).filter(|&i| i < arr.len()),
+                _ => None,
+            }
+            .ok_or_else(not_found)?;
+            cur = match *cur {
+                Value::Object(ref obj) => &obj[i].1,
+                Value::Array(ref arr) => &arr[i],
+                _ => unreachable!(),
+            };
+            // the spans within a replaced value are no longer tracked.
+            span = span
+                .filter(|span| !span.replaced)
+                .map(|span| &mut span.children[i]);
+        }
+
+        if let Some(span) = span.filter(|span| !span.replaced) {
+            span.replaced = true;
+            span.children.clear();
+            self.edits.push((span.begin, span.end, pointer.to_owned()));
+            self.edits.sort_by_key(|&(begin, ..)| begin);
+            // drop the edits within the replaced value.
+            let (begin, end) = (span.begin, span.end);
+            self.edits
+                .retain(|&(b, e, _)| (b, e) == (begin, end) || b < begin || end <= b);
+        }
+        *self.value.pointer_mut(pointer).ok_or_else(not_found)? = value;
+        Ok(())
+    }
+
+    /// Serialize the document into a string.
+    pub fn write(&self, out: &mut String) -> Result<()> {
+        let mut pos = 0;
+        for &(begin, end, ref pointer) in &self.edits {
+            out.push_str(&self.record[pos..begin]);
+            let value = self.value.pointer(pointer).ok_or_else(|| {
+                Error::from(ErrorKind::InvalidPatch)
+                    .chain_err(|| format!("{:?} not found", pointer))
+            })?;
+            value.write_json(out)?;
+            pos = end;
+        }
+        out.push_str(&self.record[pos..]);
+        Ok(())
+    }
+}
+
+/// Same as `Document::write`, but fails with `fmt::Error` if a replaced value cannot be
+/// serialized (e.g. a non-finite number).
+impl<'s> fmt::Display for Document<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::with_capacity(self.record.len());
+        self.write(&mut out).map_err(|_| fmt::Error)?;
+        f.write_str(&out)
+    }
+}
+
+/// Calculate the spans of the value between `begin` and `end` of `index`, whose record
+/// starts at `base` in the whole record.
+pub(crate) fn spans<B: Backend>(
+    index_builder: &IndexBuilder<B>,
+    index: &StructuralIndex<'_, '_>,
+    begin: usize,
+    end: usize,
+    level: usize,
+    base: usize,
+    policy: RawPolicy,
+) -> Result<Span> {
+    let mut span = Span {
+        begin: base + begin,
+        end: base + end,
+        children: vec![],
+        replaced: false,
+    };
+    let s = index.substr(begin, end);
+    let mut cp = vec![];
+    let indexed = match s.as_bytes().first() {
+        Some(b'[') => index.comma_positions(begin, end, level, &mut cp),
+        Some(b'{') => index.colon_positions(begin, end, level, &mut cp),
+        _ => return Ok(span),
+    };
+    if !indexed {
+        if policy != RawPolicy::ParseRecursively {
+            return Ok(span);
+        }
+        let mut buffers = IndexBuffers::default();
+        let index = index_builder.build_all_levels(s, &mut buffers)?;
+        return spans(index_builder, &index, 0, s.len(), 0, base + begin, policy);
+    }
+
+    if s.starts_with('[') {
+        cp.push(end - 1); // dummy
+        for i in 0..cp.len() {
+            let (vsi, vei) =
+                index.find_array_value(if i == 0 { begin + 1 } else { cp[i - 1] + 1 }, cp[i]);
+            if i == 0 && vsi == vei {
+                break; // an empty array
+            }
+            span.children.push(spans(
+                index_builder,
+                index,
+                vsi,
+                vei,
+                level + 1,
+                base,
+                policy,
+            )?);
+        }
+    } else {
+        for field in index.object_fields_at(begin, end, &cp) {
+            let field = field?;
+            span.children.push(spans(
+                index_builder,
+                index,
+                field.vsi,
+                field.vei,
+                level + 1,
+                base,
+                policy,
+            )?);
+        }
+    }
+    Ok(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::parser::Parser;
+    use crate::value::ValueExt;
+
+    const RECORD: &str =
+        "{\n  \"a\" : 1.50,\n  \"b\": [ \"x\\u0041\", {\"c\": 1e3} ],\n  \"d\":{}\n}";
+
+    #[test]
+    fn round_trip() {
+        let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        let mut doc = parser.parse_document(RECORD).unwrap();
+        assert!(!doc.is_modified());
+        assert_eq!(doc.to_string(), RECORD);
+
+        doc.replace("/a", Value::Number(2.0)).unwrap();
+        doc.replace("/b/0", Value::from("y")).unwrap();
+        assert!(doc.is_modified());
+        assert_eq!(
+            doc.to_string(),
+            "{\n  \"a\" : 2,\n  \"b\": [ \"y\", {\"c\": 1e3} ],\n  \"d\":{}\n}"
+        );
+        assert_eq!(doc.value().path("a"), Some(&Value::Number(2.0)));
+
+        // a value beyond the level is replaced as a whole.
+        assert!(doc.replace("/b/1/c", Value::Null).is_err());
+        doc.replace("/d", Value::Array(vec![Value::Null])).unwrap();
+        assert!(doc.to_string().ends_with("\"d\":[null]\n}"));
+        assert!(doc.replace("/e", Value::Null).is_err());
+        assert!(doc.replace("/b/2", Value::Null).is_err());
+
+        parser.raw_policy(RawPolicy::ParseRecursively);
+        let mut doc = parser.parse_document(RECORD).unwrap();
+        doc.replace("/b/1/c", Value::Boolean(false)).unwrap();
+        doc.replace("/b", Value::Array(vec![])).unwrap();
+        doc.replace("/b", Value::Array(vec![Value::Boolean(true)]))
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "{\n  \"a\" : 1.50,\n  \"b\": [true],\n  \"d\":{}\n}"
+        );
+
+        let mut doc = parser.parse_document(" [1, 2]\n").unwrap();
+        doc.replace("/1", Value::Null).unwrap();
+        assert_eq!(doc.to_string(), " [1, null]\n");
+        doc.replace("", Value::Null).unwrap();
+        assert_eq!(doc.to_string(), " null\n");
+    }
+
+    #[test]
+    fn escaped_keys() {
+        let parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        let mut doc = parser
+            .parse_document(r#"{"a\\b": 1, "c\u0064": 2}"#)
+            .unwrap();
+        // the reference tokens are matched against the decoded names.
+        assert!(doc.replace(r"/a\\b", Value::Null).is_err());
+        doc.replace(r"/a\b", Value::Boolean(true)).unwrap();
+        doc.replace("/cd", Value::Number(3.0)).unwrap();
+        assert_eq!(doc.to_string(), r#"{"a\\b": true, "c\u0064": 3}"#);
+    }
+}
@@ -0,0 +1,311 @@
+//! Re-encoding of parsed values and query results into compact binary formats
+
+use crate::errors::{Error, ErrorKind, Result};
+use crate::index_builder::backend::Backend;
+use crate::parser::Parser;
+use crate::value::Value;
+
+/// Binary formats supported by the encoder
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// MessagePack (https://msgpack.org/)
+    MessagePack,
+    /// CBOR (RFC 7049)
+    Cbor,
+}
+
+/// Encode a value into `buf` with the specified format.
+///
+/// Numbers without fractional part are encoded as integers, and unparsed `Raw`
+/// subtrees are encoded as strings containing their original JSON text.
+pub fn encode_value(value: &Value<'_>, format: Format, buf: &mut Vec<u8>) -> Result<()> {
+    match format {
+        Format::MessagePack => write_value(&mut MessagePack(buf), value),
+        Format::Cbor => write_value(&mut Cbor(buf), value),
+    }
+}
+
+/// Encode the results of `QueryParser` into `buf` as an array.
+///
+/// Each matched slice is parsed by `parser`, and missing fields are encoded as `nil`.
+pub fn encode_results<B: Backend>(
+    parser: &Parser<B>,
+    results: &[Option<&str>],
+    format: Format,
+    buf: &mut Vec<u8>,
+) -> Result<()> {
+    match format {
+        Format::MessagePack => write_results(&mut MessagePack(buf), parser, results),
+        Format::Cbor => write_results(&mut Cbor(buf), parser, results),
+    }
+}
+
+trait Encoder {
+    fn null(&mut self);
+    fn boolean(&mut self, b: bool);
+    fn int(&mut self, n: i64);
+    fn float(&mut self, n: f64);
+    fn string(&mut self, s: &str) -> Result<()>;
+    fn array_header(&mut self, len: usize) -> Result<()>;
+    fn map_header(&mut self, len: usize) -> Result<()>;
+}
+
+fn write_results<E: Encoder, B: Backend>(
+    e: &mut E,
+    parser: &Parser<B>,
+    results: &[Option<&str>],
+) -> Result<()> {
+    e.array_header(results.len())?;
+    for result in results {
+        match *result {
+            Some(s) => write_value(e, &parser.parse(s)?)?,
+            None => e.null(),
+        }
+    }
+    Ok(())
+}
+
+fn write_value<E: Encoder>(e: &mut E, value: &Value<'_>) -> Result<()> {
+    match *value {
+        Value::Null => e.null(),
+        Value::Boolean(b) => e.boolean(b),
+        Value::Number(n) => {
+            if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+                e.int(n as i64)
+            } else {
+                e.float(n)
+            }
+        }
+        Value::String(ref s) => e.string(&s.unescape()?)?,
+        Value::Array(ref arr) => {
+            e.array_header(arr.len())?;
+            for v in arr {
+                write_value(e, v)?;
+            }
+        }
+        Value::Object(ref obj) => {
+            e.map_header(obj.len())?;
+            for (k, v) in obj {
+                e.string(&k.unescape()?)?;
+                write_value(e, v)?;
+            }
+        }
+        Value::Raw(ref s) => e.string(s)?,
+    }
+    Ok(())
+}
+
+fn too_long(len: usize) -> Error {
+    Error::from(ErrorKind::InvalidRecord).chain_err(|| format!("too long to encode: {}", len))
+}
+
+struct MessagePack<'b>(&'b mut Vec<u8>);
+
+impl<'b> MessagePack<'b> {
+    fn header(
+        &mut self,
+        len: usize,
+        fix: (u8, usize),
+        m8: Option<u8>,
+        m16: u8,
+        m32: u8,
+    ) -> Result<()> {
+        match len {
+            n if n < fix.1 => self.0.push(fix.0 | n as u8),
+            n if n <= u8::MAX as usize && m8.is_some() => {
+                self.0.extend_from_slice(&[m8.unwrap(), n as u8])
+            }
+            n if n <= u16::MAX as usize => {
+                self.0.push(m16);
+                self.0.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n if n <= u32::MAX as usize => {
+                self.0.push(m32);
+                self.0.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            n => return Err(too_long(n)),
+        }
+        Ok(())
+    }
+}
+
+impl<'b> Encoder for MessagePack<'b> {
+    fn null(&mut self) {
+        self.0.push(0xc0);
+    }
+
+    fn boolean(&mut self, b: bool) {
+        self.0.push(if b { 0xc3 } else { 0xc2 });
+    }
+
+    fn int(&mut self, n: i64) {
+        match n {
+            0..=0x7f => self.0.push(n as u8),
+            -32..=-1 => self.0.push(n as i8 as u8),
+            0x80..=0xff => self.0.extend_from_slice(&[0xcc, n as u8]),
+            -0x80..=-33 => self.0.extend_from_slice(&[0xd0, n as i8 as u8]),
+            0x100..=0xffff => {
+                self.0.push(0xcd);
+                self.0.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            -0x8000..=-0x81 => {
+                self.0.push(0xd1);
+                self.0.extend_from_slice(&(n as i16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.0.push(0xce);
+                self.0.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            -0x8000_0000..=-0x8001 => {
+                self.0.push(0xd2);
+                self.0.extend_from_slice(&(n as i32).to_be_bytes());
+            }
+            0x1_0000_0000.. => {
+                self.0.push(0xcf);
+                self.0.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+            _ => {
+                self.0.push(0xd3);
+                self.0.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+    }
+
+    fn float(&mut self, n: f64) {
+        self.0.push(0xcb);
+        self.0.extend_from_slice(&n.to_bits().to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) -> Result<()> {
+        self.header(s.len(), (0xa0, 32), Some(0xd9), 0xda, 0xdb)?;
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    fn array_header(&mut self, len: usize) -> Result<()> {
+        self.header(len, (0x90, 16), None, 0xdc, 0xdd)
+    }
+
+    fn map_header(&mut self, len: usize) -> Result<()> {
+        self.header(len, (0x80, 16), None, 0xde, 0xdf)
+    }
+}
+
+struct Cbor<'b>(&'b mut Vec<u8>);
+
+impl<'b> Cbor<'b> {
+    fn header(&mut self, major: u8, n: u64) {
+        let major = major << 5;
+        match n {
+            0..=23 => self.0.push(major | n as u8),
+            24..=0xff => self.0.extend_from_slice(&[major | 24, n as u8]),
+            0x100..=0xffff => {
+                self.0.push(major | 25);
+                self.0.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.0.push(major | 26);
+                self.0.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            _ => {
+                self.0.push(major | 27);
+                self.0.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+    }
+}
+
+impl<'b> Encoder for Cbor<'b> {
+    fn null(&mut self) {
+        self.0.push(0xf6);
+    }
+
+    fn boolean(&mut self, b: bool) {
+        self.0.push(if b { 0xf5 } else { 0xf4 });
+    }
+
+    fn int(&mut self, n: i64) {
+        if n >= 0 {
+            self.header(0, n as u64);
+        } else {
+            self.header(1, !(n as u64));
+        }
+    }
+
+    fn float(&mut self, n: f64) {
+        self.0.push(0xfb);
+        self.0.extend_from_slice(&n.to_bits().to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) -> Result<()> {
+        self.header(3, s.len() as u64);
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    fn array_header(&mut self, len: usize) -> Result<()> {
+        self.header(4, len as u64);
+        Ok(())
+    }
+
+    fn map_header(&mut self, len: usize) -> Result<()> {
+        self.header(5, len as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::{array, object};
+
+    #[test]
+    fn encode_msgpack() {
+        let value = object! {
+            "a" => array![true, Value::Null, 1.0, -1.0, 300.0, 1.5,],
+            "b" => r#"x\"y"#,
+        };
+        let mut buf = vec![];
+        encode_value(&value, Format::MessagePack, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            &[
+                0x82, 0xa1, b'a', 0x96, 0xc3, 0xc0, 0x01, 0xff, 0xcd, 0x01, 0x2c, 0xcb, 0x3f, 0xf8,
+                0, 0, 0, 0, 0, 0, 0xa1, b'b', 0xa3, b'x', b'"', b'y',
+            ][..]
+        );
+    }
+
+    #[test]
+    fn encode_cbor() {
+        let value = object! {
+            "a" => array![true, Value::Null, 1.0, -1.0, 300.0, 1.5,],
+            "b" => r#"x\"y"#,
+        };
+        let mut buf = vec![];
+        encode_value(&value, Format::Cbor, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            &[
+                0xa2, 0x61, b'a', 0x86, 0xf5, 0xf6, 0x01, 0x20, 0x19, 0x01, 0x2c, 0xfb, 0x3f, 0xf8,
+                0, 0, 0, 0, 0, 0, 0x61, b'b', 0x63, b'x', b'"', b'y',
+            ][..]
+        );
+    }
+
+    #[test]
+    fn encode_query_results() {
+        let parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 1));
+        let results = &[Some("true"), None, Some(r#"[ "10" ]"#)];
+
+        let mut buf = vec![];
+        encode_results(&parser, results, Format::MessagePack, &mut buf).unwrap();
+        assert_eq!(buf, &[0x93, 0xc3, 0xc0, 0x91, 0xa2, b'1', b'0'][..]);
+
+        let mut buf = vec![];
+        encode_results(&parser, results, Format::Cbor, &mut buf).unwrap();
+        assert_eq!(buf, &[0x83, 0xf5, 0xf6, 0x81, 0x62, b'1', b'0'][..]);
+    }
+}
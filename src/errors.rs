@@ -16,6 +16,26 @@ error_chain::error_chain! {
             display("invalid record")
         }
 
+        InvalidType(expected: &'static str, found: &'static str) {
+            description("invalid type")
+            display("invalid type: expected {}, found {}", expected, found)
+        }
+
+        TypeMismatch(path: String, expected: &'static str, found: &'static str) {
+            description("type mismatch")
+            display("type mismatch at {}: expected {}, found {}", path, expected, found)
+        }
+
+        InvalidPatch {
+            description("invalid patch")
+            display("invalid patch")
+        }
+
+        DepthLimitExceeded(limit: usize) {
+            description("nesting depth limit exceeded")
+            display("nesting depth limit exceeded: {}", limit)
+        }
+
         FailedSpeculativeParse {
             description("failed to parse in speculative parsing mode")
             display("failed to parse in speculative parsing mode")
@@ -0,0 +1,191 @@
+//! Event-driven parsing driven by the structural index
+//!
+//! `EventParser` reports the structure of a record to a `Handler` in the order of the
+//! record, without building a `Value` tree, so that consumers can construct their own
+//! data structures.
+
+use crate::errors::Result;
+use crate::index_builder::backend::Backend;
+use crate::index_builder::{IndexBuilder, StructuralIndex};
+use crate::value::{self, EscapedStr, Value, ValueType};
+
+/// Callbacks of `EventParser`
+///
+/// All callbacks do nothing by default. An error returned from a callback aborts
+/// the parsing.
+pub trait Handler<'s> {
+    #[allow(missing_docs)]
+    fn on_object_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[allow(missing_docs)]
+    fn on_object_end(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[allow(missing_docs)]
+    fn on_array_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[allow(missing_docs)]
+    fn on_array_end(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the (escaped) name of a field, followed by the events of its value.
+    fn on_key(&mut self, _key: EscapedStr<'s>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with an atomic value, or a `Value::Raw` for the arrays and objects
+    /// nested deeper than the level of the index builder.
+    fn on_value(&mut self, _value: Value<'s>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A parser which reports the events of records to a `Handler`
+#[derive(Debug)]
+pub struct EventParser<B: Backend> {
+    index_builder: IndexBuilder<B>,
+}
+
+impl<B: Backend> EventParser<B> {
+    #[allow(missing_docs)]
+    pub fn new(index_builder: IndexBuilder<B>) -> Self {
+        Self { index_builder }
+    }
+
+    /// Parse a record and report its events to `handler`.
+    pub fn parse<'s, H: Handler<'s>>(&self, record: &'s str, handler: &mut H) -> Result<()> {
+        let record = record.trim();
+        let index = self.index_builder.build(record)?;
+        walk(&index, 0, record.len(), 0, handler)
+    }
+}
+
+fn walk<'s, H: Handler<'s>>(
+    index: &StructuralIndex<'_, 's>,
+    begin: usize,
+    end: usize,
+    level: usize,
+    handler: &mut H,
+) -> Result<()> {
+    match value::parse(index.substr(begin, end))? {
+        ValueType::Atomic(v) => handler.on_value(v),
+        ValueType::Array => walk_array(index, begin, end, level, handler),
+        ValueType::Object => walk_object(index, begin, end, level, handler),
+    }
+}
+
+fn walk_array<'s, H: Handler<'s>>(
+    index: &StructuralIndex<'_, 's>,
+    begin: usize,
+    end: usize,
+    level: usize,
+    handler: &mut H,
+) -> Result<()> {
+    let mut cp = Vec::new();
+    if !index.comma_positions(begin, end, level, &mut cp) {
+        return handler.on_value(Value::raw(index.substr(begin, end)));
+    }
+    cp.push(end - 1); // dummy
+
+    handler.on_array_start()?;
+    for i in 0..cp.len() {
+        let (vsi, vei) =
+            index.find_array_value(if i == 0 { begin + 1 } else { cp[i - 1] + 1 }, cp[i]);
+        if i == 0 && vsi == vei && cp.len() == 1 {
+            break; // an empty array
+        }
+        walk(index, vsi, vei, level + 1, handler)?;
+    }
+    handler.on_array_end()
+}
+
+fn walk_object<'s, H: Handler<'s>>(
+    index: &StructuralIndex<'_, 's>,
+    begin: usize,
+    end: usize,
+    level: usize,
+    handler: &mut H,
+) -> Result<()> {
+    let fields = match index.object_fields_strict(begin, end, level) {
+        Some(fields) => fields,
+        None => return handler.on_value(Value::raw(index.substr(begin, end))),
+    };
+
+    handler.on_object_start()?;
+    for field in fields {
+        let field = field?;
+        handler.on_key(field.name)?;
+        walk(index, field.vsi, field.vei, level + 1, handler)?;
+    }
+    handler.on_object_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{Error, ErrorKind};
+    use crate::index_builder::backend::FallbackBackend;
+
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    impl<'s> Handler<'s> for Trace {
+        fn on_object_start(&mut self) -> Result<()> {
+            self.0.push("{".into());
+            Ok(())
+        }
+
+        fn on_object_end(&mut self) -> Result<()> {
+            self.0.push("}".into());
+            Ok(())
+        }
+
+        fn on_array_start(&mut self) -> Result<()> {
+            self.0.push("[".into());
+            Ok(())
+        }
+
+        fn on_array_end(&mut self) -> Result<()> {
+            self.0.push("]".into());
+            Ok(())
+        }
+
+        fn on_key(&mut self, key: EscapedStr<'s>) -> Result<()> {
+            self.0.push(format!("{}:", key.as_raw_str()));
+            Ok(())
+        }
+
+        fn on_value(&mut self, value: Value<'s>) -> Result<()> {
+            if value == Value::Number(0.0) {
+                return Err(ErrorKind::InvalidRecord.into());
+            }
+            self.0.push(format!("{:?}", value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 3);
+        let parser = EventParser::new(index_builder);
+
+        let mut trace = Trace::default();
+        let record = r#"{ "a": [1, "x", {}], "b": { "c": null, "d": [[true]] }, "e": [] }"#;
+        parser.parse(record, &mut trace).unwrap();
+        assert_eq!(
+            trace.0.join(" "),
+            r#"{ a: [ 1 "x" { } ] b: { c: null d: [ Raw("[true]") ] } e: [ ] }"#
+        );
+
+        let mut trace = Trace::default();
+        let err: Error = parser.parse(r#"[1, 0, 2]"#, &mut trace).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidRecord));
+        assert_eq!(trace.0, ["[", "1"]);
+    }
+}
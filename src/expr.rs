@@ -0,0 +1,377 @@
+//! Simple boolean expressions over query results
+//!
+//! An expression such as `len($.items) > 0 && $.status == "ok"` is compiled against a
+//! `QueryTree`, which gets the paths referenced by the expression, and is evaluated on
+//! the results of `QueryParser::parse` for each record.
+//!
+//! The grammar is:
+//!
+//! ```text
+//! expr    := and ("||" and)*
+//! and     := not ("&&" not)*
+//! not     := "!" not | cmp
+//! cmp     := operand (("==" | "!=" | "<" | "<=" | ">" | ">=") operand)?
+//! operand := path | JSON scalar | "len(" operand ")" | "exists(" path ")" | "(" expr ")"
+//! ```
+//!
+//! Missing values are treated as `null`. In a boolean context, `false` and `null` are
+//! false and all other values are true.
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::query::QueryTree;
+use crate::scan;
+use crate::value::{self, EscapedStr, Value, ValueType};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// A compiled expression
+#[derive(Debug, Clone)]
+pub struct Expr<'a> {
+    node: Node<'a>,
+}
+
+#[derive(Debug, Clone)]
+enum Node<'a> {
+    Path(usize),
+    Literal(&'a str),
+    Len(Box<Node<'a>>),
+    Exists(usize),
+    Not(Box<Node<'a>>),
+    And(Box<Node<'a>>, Box<Node<'a>>),
+    Or(Box<Node<'a>>, Box<Node<'a>>),
+    Cmp(Op, Box<Node<'a>>, Box<Node<'a>>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// The value of an operand while evaluating
+#[derive(Debug, Clone, PartialEq)]
+enum Val<'v> {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(Cow<'v, str>),
+    /// the raw text of an array or object
+    Raw(&'v str),
+}
+
+impl<'a> Expr<'a> {
+    /// Compile an expression, adding the paths it references to `query_tree` unless
+    /// they are already queried.
+    pub fn compile(src: &'a str, query_tree: &mut QueryTree<'a>) -> Result<Self> {
+        let mut p = ExprParser {
+            src,
+            pos: 0,
+            query_tree,
+        };
+        let node = p.or()?;
+        p.skip_whitespace();
+        if p.pos < src.len() {
+            return Err(p.error("unexpected characters"));
+        }
+        Ok(Self { node })
+    }
+
+    /// Evaluate the expression on the results of `QueryParser::parse`, in which the
+    /// string values include their quotes (the default of `StringQuotes`).
+    pub fn eval(&self, results: &[Option<&str>]) -> Result<bool> {
+        eval(&self.node, results).map(|v| truthy(&v))
+    }
+}
+
+fn eval<'v>(node: &Node<'v>, results: &[Option<&'v str>]) -> Result<Val<'v>> {
+    match *node {
+        Node::Path(id) => match results.get(id).cloned().flatten() {
+            Some(raw) => parse_val(raw.trim()),
+            None => Ok(Val::Null),
+        },
+        Node::Literal(raw) => parse_val(raw),
+        Node::Len(ref node) => {
+            let n = match eval(node, results)? {
+                Val::String(s) => s.chars().count(),
+                Val::Raw(raw) => {
+                    let mut members = vec![];
+                    scan::members(raw, 0, raw.len(), &mut members)?;
+                    members.len()
+                }
+                _ => return Ok(Val::Null),
+            };
+            Ok(Val::Number(n as f64))
+        }
+        Node::Exists(id) => Ok(Val::Boolean(results.get(id).cloned().flatten().is_some())),
+        Node::Not(ref node) => Ok(Val::Boolean(!truthy(&eval(node, results)?))),
+        Node::And(ref lhs, ref rhs) => Ok(Val::Boolean(
+            truthy(&eval(lhs, results)?) && truthy(&eval(rhs, results)?),
+        )),
+        Node::Or(ref lhs, ref rhs) => Ok(Val::Boolean(
+            truthy(&eval(lhs, results)?) || truthy(&eval(rhs, results)?),
+        )),
+        Node::Cmp(op, ref lhs, ref rhs) => {
+            let (lhs, rhs) = (eval(lhs, results)?, eval(rhs, results)?);
+            let ord = match (&lhs, &rhs) {
+                (Val::Number(a), Val::Number(b)) => a.partial_cmp(b),
+                (Val::String(a), Val::String(b)) => Some(a.cmp(b)),
+                _ if lhs == rhs => Some(Ordering::Equal),
+                _ => None,
+            };
+            Ok(Val::Boolean(match op {
+                Op::Eq => ord == Some(Ordering::Equal),
+                Op::Ne => ord != Some(Ordering::Equal),
+                Op::Lt => ord == Some(Ordering::Less),
+                Op::Le => matches!(ord, Some(Ordering::Less) | Some(Ordering::Equal)),
+                Op::Gt => ord == Some(Ordering::Greater),
+                Op::Ge => matches!(ord, Some(Ordering::Greater) | Some(Ordering::Equal)),
+            }))
+        }
+    }
+}
+
+fn truthy(v: &Val<'_>) -> bool {
+    !matches!(*v, Val::Null | Val::Boolean(false))
+}
+
+fn parse_val(raw: &str) -> Result<Val<'_>> {
+    Ok(match value::parse_with(raw, false)? {
+        ValueType::Atomic(Value::Null) => Val::Null,
+        ValueType::Atomic(Value::Boolean(b)) => Val::Boolean(b),
+        ValueType::Atomic(Value::Number(n)) => Val::Number(n),
+        ValueType::Atomic(_) => {
+            Val::String(EscapedStr::from(&raw[1..raw.len() - 1]).into_unescaped()?)
+        }
+        ValueType::Array | ValueType::Object => Val::Raw(raw),
+    })
+}
+
+struct ExprParser<'a, 't> {
+    src: &'a str,
+    pos: usize,
+    query_tree: &'t mut QueryTree<'a>,
+}
+
+impl<'a, 't> ExprParser<'a, 't> {
+    fn error(&self, msg: &str) -> Error {
+        Error::from(ErrorKind::InvalidQuery).chain_err(|| format!("{} at {}", msg, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume `token` if the input continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.src[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Node<'a>> {
+        let mut node = self.and()?;
+        while self.eat("||") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node<'a>> {
+        let mut node = self.not()?;
+        while self.eat("&&") {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node<'a>> {
+        if !self.src[self.pos..].trim_start().starts_with("!=") && self.eat("!") {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Node<'a>> {
+        let lhs = self.operand()?;
+        // the longer operators first
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        for &(token, op) in &ops {
+            if self.eat(token) {
+                return Ok(Node::Cmp(op, Box::new(lhs), Box::new(self.operand()?)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Node<'a>> {
+        self.skip_whitespace();
+        let rest = &self.src[self.pos..];
+        match rest.as_bytes().first() {
+            Some(b'(') => {
+                self.pos += 1;
+                let node = self.or()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(b'$') => self.path().map(Node::Path),
+            Some(b'"') => {
+                let mut escaped = false;
+                let close = rest.bytes().enumerate().skip(1).find(|&(_, c)| match c {
+                    _ if escaped => {
+                        escaped = false;
+                        false
+                    }
+                    b'\\' => {
+                        escaped = true;
+                        false
+                    }
+                    c => c == b'"',
+                });
+                let (end, _) = close.ok_or_else(|| self.error("unterminated string"))?;
+                self.literal(end + 1)
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "+-._".contains(c)))
+                    .unwrap_or(rest.len());
+                match &rest[..len] {
+                    "len" | "exists" if rest[len..].trim_start().starts_with('(') => {
+                        self.pos += len;
+                        self.expect("(")?;
+                        let node = if &rest[..len] == "len" {
+                            Node::Len(Box::new(self.operand()?))
+                        } else {
+                            self.skip_whitespace();
+                            Node::Exists(self.path()?)
+                        };
+                        self.expect(")")?;
+                        Ok(node)
+                    }
+                    "" => Err(self.error("expected an operand")),
+                    _ => self.literal(len),
+                }
+            }
+        }
+    }
+
+    /// Consume the scalar of `len` bytes.
+    fn literal(&mut self, len: usize) -> Result<Node<'a>> {
+        let raw = &self.src[self.pos..self.pos + len];
+        match value::parse_with(raw, false) {
+            Ok(ValueType::Atomic(..)) => {}
+            _ => return Err(self.error("invalid literal")),
+        }
+        self.pos += len;
+        Ok(Node::Literal(raw))
+    }
+
+    /// Consume a query path, and return its identifier in the query tree.
+    fn path(&mut self) -> Result<usize> {
+        let rest = &self.src[self.pos..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || "()=!<>&|,".contains(c))
+            .unwrap_or(rest.len());
+        let path = &rest[..len];
+        let path_id = match self.query_tree.path_id_of(path) {
+            Some(path_id) => path_id,
+            None => {
+                self.query_tree
+                    .add_path(path)
+                    .chain_err(|| format!("invalid path {:?} at {}", path, self.pos))?;
+                self.query_tree.num_paths() - 1
+            }
+        };
+        self.pos += len;
+        Ok(path_id)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if !self.eat(token) {
+            return Err(self.error(&format!("expected {:?}", token)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::query_parser::{QueryParser, QueryParserMode};
+
+    #[test]
+    fn routing_rules() {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.status").unwrap();
+        let rules = [
+            r#"len($.items) > 0 && $.status == "ok""#,
+            r#"!($.status == "ok") || $.user.age >= 20.5"#,
+            r#"exists($.user.name) && len($.user.name) != 3"#,
+            "$.user.admin",
+            r#"$.status < "p" && $.missing == null && $.items != 1"#,
+        ];
+        let exprs: Vec<Expr<'_>> = rules
+            .iter()
+            .map(|rule| Expr::compile(rule, &mut query_tree).unwrap())
+            .collect();
+        assert_eq!(query_tree.path_id_of("$.status"), Some(0));
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        let records = [
+            (
+                r#"{ "status": "ok", "items": [1, 2], "user": { "age": 20, "name": "café" } }"#,
+                [true, false, true, false, true],
+            ),
+            (
+                r#"{ "status": "ok", "items": [], "user": { "admin": true, "name": "bob" } }"#,
+                [false, false, false, true, true],
+            ),
+            (
+                r#"{ "status": "error", "user": { "age": 30, "admin": false } }"#,
+                [false, true, false, false, true],
+            ),
+        ];
+        for &(record, expected) in &records {
+            let results = parser.parse(record, QueryParserMode::Basic).unwrap();
+            let evaluated: Vec<bool> = exprs.iter().map(|e| e.eval(&results).unwrap()).collect();
+            assert_eq!(evaluated, expected, "{}", record);
+        }
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        let cases = [
+            "",
+            "$.a ==",
+            "($.a",
+            "$.a $.b",
+            r#"$.a == "x"#,
+            "$.a == nil",
+            "len $.a",
+            "exists(1)",
+            "$..a",
+        ];
+        for case in &cases {
+            let mut query_tree = QueryTree::default();
+            assert!(Expr::compile(case, &mut query_tree).is_err(), "{:?}", case);
+        }
+    }
+}
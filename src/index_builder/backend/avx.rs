@@ -2,7 +2,7 @@ use super::{Backend, Bitmap};
 use packed_simd::u8x32;
 
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AvxBackend {
     backslash: u8x32,
     quote: u8x32,
@@ -12,6 +12,7 @@ pub struct AvxBackend {
     right_brace: u8x32,
     left_bracket: u8x32,
     right_bracket: u8x32,
+    newline: u8x32,
 }
 
 impl Default for AvxBackend {
@@ -25,6 +26,7 @@ impl Default for AvxBackend {
             right_brace: u8x32::splat(b'}'),
             left_bracket: u8x32::splat(b'['),
             right_bracket: u8x32::splat(b']'),
+            newline: u8x32::splat(b'\n'),
         }
     }
 }
@@ -43,6 +45,7 @@ impl Backend for AvxBackend {
             right_brace: cmp2(self.right_brace, b0, b1),
             left_bracket: cmp2(self.left_bracket, b0, b1),
             right_bracket: cmp2(self.right_bracket, b0, b1),
+            newline: cmp2(self.newline, b0, b1),
         }
     }
 
@@ -60,6 +63,7 @@ impl Backend for AvxBackend {
                     right_brace: cmp1(self.right_brace, b0),
                     left_bracket: cmp1(self.left_bracket, b0),
                     right_bracket: cmp1(self.right_bracket, b0),
+                    newline: cmp1(self.newline, b0),
                 }
             }
             32 => {
@@ -73,6 +77,7 @@ impl Backend for AvxBackend {
                     right_brace: cmp1(self.right_brace, b0),
                     left_bracket: cmp1(self.left_bracket, b0),
                     right_bracket: cmp1(self.right_bracket, b0),
+                    newline: cmp1(self.newline, b0),
                 }
             }
             _ => {
@@ -87,6 +92,7 @@ impl Backend for AvxBackend {
                     right_brace: cmp2(self.right_brace, b0, b1),
                     left_bracket: cmp2(self.left_bracket, b0, b1),
                     right_bracket: cmp2(self.right_bracket, b0, b1),
+                    newline: cmp2(self.newline, b0, b1),
                 }
             }
         }
@@ -1,8 +1,7 @@
 use super::{Backend, Bitmap};
-use std::u64;
 
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FallbackBackend {
     backslash: m256i,
     quote: m256i,
@@ -12,6 +11,7 @@ pub struct FallbackBackend {
     right_brace: m256i,
     left_bracket: m256i,
     right_bracket: m256i,
+    newline: m256i,
 }
 
 impl Default for FallbackBackend {
@@ -25,6 +25,7 @@ impl Default for FallbackBackend {
             right_brace: m256i::splat(b'}'),
             left_bracket: m256i::splat(b'['),
             right_bracket: m256i::splat(b']'),
+            newline: m256i::splat(b'\n'),
         }
     }
 }
@@ -42,6 +43,7 @@ impl Backend for FallbackBackend {
             right_brace: cmp2(self.right_brace, b0, b1),
             left_bracket: cmp2(self.left_bracket, b0, b1),
             right_bracket: cmp2(self.right_bracket, b0, b1),
+            newline: cmp2(self.newline, b0, b1),
         }
     }
 
@@ -58,6 +60,7 @@ impl Backend for FallbackBackend {
                     right_brace: cmp1(self.right_brace, b0),
                     left_bracket: cmp1(self.left_bracket, b0),
                     right_bracket: cmp1(self.right_bracket, b0),
+                    newline: cmp1(self.newline, b0),
                 }
             }
             32 => {
@@ -71,6 +74,7 @@ impl Backend for FallbackBackend {
                     right_brace: cmp1(self.right_brace, b0),
                     left_bracket: cmp1(self.left_bracket, b0),
                     right_bracket: cmp1(self.right_bracket, b0),
+                    newline: cmp1(self.newline, b0),
                 }
             }
             _ => {
@@ -85,10 +89,28 @@ impl Backend for FallbackBackend {
                     right_brace: cmp2(self.right_brace, b0, b1),
                     left_bracket: cmp2(self.left_bracket, b0, b1),
                     right_bracket: cmp2(self.right_bracket, b0, b1),
+                    newline: cmp2(self.newline, b0, b1),
                 }
             }
         }
     }
+
+    fn create_newline_bitmap(&self, s: &[u8], offset: usize) -> u64 {
+        match s.len() - offset {
+            x if x < 32 => cmp1(self.newline, m256i::load_partial(s, offset)),
+            32 => cmp1(self.newline, m256i::load(s, offset)),
+            x if x < 64 => cmp2(
+                self.newline,
+                m256i::load(s, offset),
+                m256i::load_partial(s, offset + 32),
+            ),
+            _ => cmp2(
+                self.newline,
+                m256i::load(s, offset),
+                m256i::load(s, offset + 32),
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -134,10 +156,10 @@ impl m256i {
     #[inline]
     fn move_mask(self) -> u64 {
         let f = 0x_8040_2010_0804_0201_u64;
-        ((self.0[0].wrapping_mul(f) >> 56) & 0x0000_00FF_u64
+        (self.0[0].wrapping_mul(f) >> 56) & 0x0000_00FF_u64
             | (self.0[1].wrapping_mul(f) >> 48) & 0x0000_FF00_u64
             | (self.0[2].wrapping_mul(f) >> 40) & 0x00FF_0000_u64
-            | (self.0[3].wrapping_mul(f) >> 32) & 0xFF00_0000_u64)
+            | (self.0[3].wrapping_mul(f) >> 32) & 0xFF00_0000_u64
     }
 }
 
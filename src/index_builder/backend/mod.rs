@@ -6,6 +6,8 @@ mod fallback;
 #[cfg(feature = "simd-accel")]
 mod sse2;
 
+use std::sync::Arc;
+
 pub use self::fallback::FallbackBackend;
 
 #[cfg(feature = "simd-accel")]
@@ -14,6 +16,30 @@ pub use self::sse2::Sse2Backend;
 #[cfg(all(feature = "avx-accel", target_arch = "x86_64"))]
 pub use self::avx::AvxBackend;
 
+/// The fastest backend available with the enabled features and the target architecture
+#[cfg(all(feature = "avx-accel", target_arch = "x86_64"))]
+pub type DefaultBackend = AvxBackend;
+
+/// The fastest backend available with the enabled features and the target architecture
+#[cfg(all(
+    feature = "simd-accel",
+    not(all(feature = "avx-accel", target_arch = "x86_64"))
+))]
+pub type DefaultBackend = Sse2Backend;
+
+/// The fastest backend available with the enabled features and the target architecture
+#[cfg(not(any(
+    feature = "simd-accel",
+    all(feature = "avx-accel", target_arch = "x86_64")
+)))]
+pub type DefaultBackend = FallbackBackend;
+
+/// A type-erased backend, chosen at runtime
+///
+/// Parsers over `DynBackend` can be stored together regardless of their backends.
+/// The overhead is one virtual call per 64 bytes of the record.
+pub type DynBackend = Box<dyn Backend + Send + Sync>;
+
 /// Structural character bitmaps
 #[allow(missing_docs)]
 #[derive(Debug, PartialEq, Default)]
@@ -26,6 +52,44 @@ pub struct Bitmap {
     pub right_brace: u64,
     pub left_bracket: u64,
     pub right_bracket: u64,
+    pub newline: u64,
+}
+
+impl Bitmap {
+    /// Create the bitmaps of the structural characters in `s`, up to 64 bytes.
+    ///
+    /// The other characters leave their bits clear, so a fixture can be written as the
+    /// record itself, or with placeholders (e.g. `.`) for the irrelevant bytes.
+    pub fn from_marked_str(s: &str) -> Self {
+        assert!(s.len() <= 64, "a bitmap covers at most 64 bytes");
+        let mut bitmap = Self::default();
+        for (i, c) in s.bytes().enumerate() {
+            let bits = match c {
+                b'\\' => &mut bitmap.backslash,
+                b'"' => &mut bitmap.quote,
+                b':' => &mut bitmap.colon,
+                b',' => &mut bitmap.comma,
+                b'{' => &mut bitmap.left_brace,
+                b'}' => &mut bitmap.right_brace,
+                b'[' => &mut bitmap.left_bracket,
+                b']' => &mut bitmap.right_bracket,
+                b'\n' => &mut bitmap.newline,
+                _ => continue,
+            };
+            *bits |= 1 << i;
+        }
+        bitmap
+    }
+
+    /// Return the bits marked by `^` in `s`, one character per byte from the least
+    /// significant bit, e.g. `Bitmap::marks("^..^") == 0b1001`.
+    pub fn marks(s: &str) -> u64 {
+        assert!(s.len() <= 64, "a bitmap covers at most 64 bytes");
+        s.bytes()
+            .enumerate()
+            .filter(|&(_, c)| c == b'^')
+            .fold(0, |bits, (i, _)| bits | 1 << i)
+    }
 }
 
 /// Represents the backend of `IndexBuilder` to create character bitmaps
@@ -35,4 +99,73 @@ pub trait Backend {
 
     /// Create a new bitmap from slice of bytes, whose length may be less than 64.
     fn create_partial_bitmap(&self, s: &[u8], offset: usize) -> Bitmap;
+
+    /// Create a bitmap of newlines from slice of bytes, whose length may be less than 64.
+    fn create_newline_bitmap(&self, s: &[u8], offset: usize) -> u64 {
+        if s.len() - offset >= 64 {
+            self.create_full_bitmap(s, offset).newline
+        } else {
+            self.create_partial_bitmap(s, offset).newline
+        }
+    }
+
+    /// Create a bitmap of control characters (`U+0000` to `U+001F`) from slice of bytes,
+    /// whose length may be less than 64.
+    fn create_control_bitmap(&self, s: &[u8], offset: usize) -> u64 {
+        s[offset..]
+            .iter()
+            .take(64)
+            .enumerate()
+            .filter(|&(_, &c)| c < 0x20)
+            .fold(0, |bits, (i, _)| bits | 1 << i)
+    }
+}
+
+macro_rules! impl_backend_for_pointers {
+    ($($t:ty,)*) => {$(
+        impl<B: Backend + ?Sized> Backend for $t {
+            #[inline]
+            fn create_full_bitmap(&self, s: &[u8], offset: usize) -> Bitmap {
+                (**self).create_full_bitmap(s, offset)
+            }
+
+            #[inline]
+            fn create_partial_bitmap(&self, s: &[u8], offset: usize) -> Bitmap {
+                (**self).create_partial_bitmap(s, offset)
+            }
+
+            #[inline]
+            fn create_newline_bitmap(&self, s: &[u8], offset: usize) -> u64 {
+                (**self).create_newline_bitmap(s, offset)
+            }
+
+            #[inline]
+            fn create_control_bitmap(&self, s: &[u8], offset: usize) -> u64 {
+                (**self).create_control_bitmap(s, offset)
+            }
+        }
+    )*};
+}
+
+impl_backend_for_pointers!(&B, Box<B>, Arc<B>,);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marked_bitmaps() {
+        let record = r#"{"a\"":[1,{}]}"#;
+        assert_eq!(
+            Bitmap::from_marked_str(record),
+            FallbackBackend::default().create_partial_bitmap(record.as_bytes(), 0)
+        );
+        let bitmap = Bitmap::from_marked_str("..:..,\n");
+        assert_eq!(bitmap.colon, Bitmap::marks("  ^"));
+        assert_eq!(bitmap.comma, 0b10_0000);
+        assert_eq!(bitmap.newline, 0b100_0000);
+        assert_eq!(bitmap.quote, 0);
+        assert_eq!(Bitmap::marks("^..^"), 0b1001);
+        assert_eq!(Bitmap::marks(&"^".repeat(64)), !0);
+    }
 }
@@ -2,7 +2,7 @@ use super::{Backend, Bitmap};
 use packed_simd::u8x16;
 
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sse2Backend {
     backslash: u8x16,
     quote: u8x16,
@@ -12,6 +12,7 @@ pub struct Sse2Backend {
     right_brace: u8x16,
     left_bracket: u8x16,
     right_bracket: u8x16,
+    newline: u8x16,
 }
 
 impl Default for Sse2Backend {
@@ -25,6 +26,7 @@ impl Default for Sse2Backend {
             right_brace: u8x16::splat(b'}'),
             left_bracket: u8x16::splat(b'['),
             right_bracket: u8x16::splat(b']'),
+            newline: u8x16::splat(b'\n'),
         }
     }
 }
@@ -44,6 +46,7 @@ impl Backend for Sse2Backend {
             right_brace: cmp4(self.right_brace, b0, b1, b2, b3),
             left_bracket: cmp4(self.left_bracket, b0, b1, b2, b3),
             right_bracket: cmp4(self.right_bracket, b0, b1, b2, b3),
+            newline: cmp4(self.newline, b0, b1, b2, b3),
         }
     }
 
@@ -60,6 +63,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp1(self.right_brace, b0),
                     left_bracket: cmp1(self.left_bracket, b0),
                     right_bracket: cmp1(self.right_bracket, b0),
+                    newline: cmp1(self.newline, b0),
                 }
             }
             16 => {
@@ -73,6 +77,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp1(self.right_brace, b0),
                     left_bracket: cmp1(self.left_bracket, b0),
                     right_bracket: cmp1(self.right_bracket, b0),
+                    newline: cmp1(self.newline, b0),
                 }
             }
             x if x < 32 => {
@@ -87,6 +92,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp2(self.right_brace, b0, b1),
                     left_bracket: cmp2(self.left_bracket, b0, b1),
                     right_bracket: cmp2(self.right_bracket, b0, b1),
+                    newline: cmp2(self.newline, b0, b1),
                 }
             }
             32 => {
@@ -101,6 +107,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp2(self.right_brace, b0, b1),
                     left_bracket: cmp2(self.left_bracket, b0, b1),
                     right_bracket: cmp2(self.right_bracket, b0, b1),
+                    newline: cmp2(self.newline, b0, b1),
                 }
             }
             x if x < 48 => {
@@ -116,6 +123,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp3(self.right_brace, b0, b1, b2),
                     left_bracket: cmp3(self.left_bracket, b0, b1, b2),
                     right_bracket: cmp3(self.right_bracket, b0, b1, b2),
+                    newline: cmp3(self.newline, b0, b1, b2),
                 }
             }
             48 => {
@@ -131,6 +139,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp3(self.right_brace, b0, b1, b2),
                     left_bracket: cmp3(self.left_bracket, b0, b1, b2),
                     right_bracket: cmp3(self.right_bracket, b0, b1, b2),
+                    newline: cmp3(self.newline, b0, b1, b2),
                 }
             }
             _ => {
@@ -147,6 +156,7 @@ impl Backend for Sse2Backend {
                     right_brace: cmp4(self.right_brace, b0, b1, b2, b3),
                     left_bracket: cmp4(self.left_bracket, b0, b1, b2, b3),
                     right_bracket: cmp4(self.right_bracket, b0, b1, b2, b3),
+                    newline: cmp4(self.newline, b0, b1, b2, b3),
                 }
             }
         }
@@ -1,77 +1,361 @@
 use crate::bit;
 use crate::errors::{Error, ErrorKind, Result, ResultExt};
 use num::Integer;
-use std::cell::RefCell;
+use std::cmp;
+use std::mem;
+use std::sync::{Arc, Mutex, TryLockError};
 
-use super::backend::{Backend, Bitmap};
-use super::index::StructuralIndex;
+use super::backend::{Backend, Bitmap, DefaultBackend, DynBackend};
+use super::index::{location, InnerRef, StructuralIndex};
 
 /// A index builder
+///
+/// The builder owns a set of bitmaps used by `build`, which can be used by only one
+/// index at a time (the other indices get their own bitmaps). `build_with` takes the
+/// bitmaps from the caller instead, so that a builder can be shared among threads
+/// without allocating them for every record.
 #[derive(Debug, Default)]
 pub struct IndexBuilder<B: Backend> {
-    backend: B,
-    inner: RefCell<Inner>,
+    /// the backend, shared with the builders created by `with_level`
+    backend: Arc<B>,
+    level: usize,
+    options: Options,
+    /// the number of threads and the function to build the bitmaps in parallel
+    parallel: Option<(usize, ParallelFn<B>)>,
+    inner: Mutex<Inner>,
+}
+
+type ParallelFn<B> = fn(&B, &[u8], usize, &mut Vec<Bitmap>);
+
+/// The minimum number of bytes of the record given to each thread by
+/// `IndexBuilder::threads`
+pub const PARALLEL_CHUNK_LEN: usize = 64 * 1024;
+
+/// The default limit of the nesting depth of brackets and braces
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+#[derive(Debug, Copy, Clone)]
+struct Options {
+    rank_select: bool,
+    max_depth: usize,
+    control_bitmap: bool,
+    buffer_policy: BufferPolicy,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            rank_select: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            control_bitmap: false,
+            buffer_policy: BufferPolicy::KeepPeak,
+        }
+    }
 }
 
 impl<B: Backend> IndexBuilder<B> {
     #[allow(missing_docs)]
     pub fn new(backend: B, level: usize) -> Self {
         Self {
-            backend,
-            inner: RefCell::new(Inner::new(level)),
+            backend: Arc::new(backend),
+            level,
+            options: Options::default(),
+            parallel: None,
+            inner: Mutex::new(Inner::new(level)),
+        }
+    }
+
+    /// Erase the type of the backend, so that builders with different backends can be
+    /// stored together.
+    pub fn into_dyn(self) -> IndexBuilder<DynBackend>
+    where
+        B: Send + Sync + 'static,
+    {
+        IndexBuilder {
+            backend: Arc::new(match Arc::try_unwrap(self.backend) {
+                Ok(backend) => Box::new(backend),
+                Err(backend) => Box::new(backend),
+            }),
+            level: self.level,
+            options: self.options,
+            parallel: self.parallel.map(|(threads, _)| {
+                (
+                    threads,
+                    build_bitmaps_parallel::<DynBackend> as ParallelFn<DynBackend>,
+                )
+            }),
+            inner: Mutex::new(
+                self.inner
+                    .into_inner()
+                    .unwrap_or_else(|err| err.into_inner()),
+            ),
+        }
+    }
+
+    /// Return the number of levels of the leveled bitmaps.
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Create a builder of `level` levels with the same backend and options as this
+    /// one, and its own bitmaps.
+    pub(crate) fn with_level(&self, level: usize) -> Self {
+        Self {
+            backend: Arc::clone(&self.backend),
+            level,
+            options: self.options,
+            parallel: self.parallel,
+            inner: Mutex::new(Inner::new(level)),
+        }
+    }
+
+    /// Precompute the prefix sums of popcounts of the leveled bitmaps, so that the
+    /// `n`-th colon or comma can be located without scanning the preceding words.
+    ///
+    /// This pays off for large objects and arrays accessed by position.
+    pub fn rank_select(&mut self, v: bool) {
+        self.options.rank_select = v;
+    }
+
+    /// Set the limit of the nesting depth of brackets and braces (`DEFAULT_MAX_DEPTH`
+    /// by default). Deeper records are rejected with `ErrorKind::DepthLimitExceeded`.
+    pub fn max_depth(&mut self, depth: usize) {
+        self.options.max_depth = depth;
+    }
+
+    /// Return the limit of the nesting depth set by `max_depth`.
+    pub(crate) fn depth_limit(&self) -> usize {
+        self.options.max_depth
+    }
+
+    /// Also build the bitmap of the control characters within strings, so that
+    /// `StructuralIndex::contains_control` checks the values without scanning them.
+    pub fn control_bitmap(&mut self, v: bool) {
+        self.options.control_bitmap = v;
+    }
+
+    /// Set when the buffers are shrunk to the size of the next record, so that the
+    /// buffers grown by an unusually large record are released afterwards
+    /// (`BufferPolicy::KeepPeak` by default).
+    ///
+    /// This applies to the internal buffers and to those given to `build_with`.
+    pub fn buffer_policy(&mut self, policy: BufferPolicy) {
+        self.options.buffer_policy = policy;
+    }
+
+    /// Return the bytes held by the internal buffers, or `None` if they are in use
+    /// (i.e. the previously built index is still alive).
+    pub fn memory_usage(&self) -> Option<MemoryUsage> {
+        match self.inner.try_lock() {
+            Ok(inner) => Some(inner.memory_usage()),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner().memory_usage()),
+            Err(TryLockError::WouldBlock) => None,
         }
     }
 
     /// Build a structural index from a slice of bytes.
+    ///
+    /// The index holds the internal bitmaps while it is alive. If the previously
+    /// built index is still alive (e.g. when `build` is called again from within a
+    /// callback, or from another thread), the index is built into new bitmaps instead,
+    /// which are dropped along with it.
     pub fn build<'a, 's>(&'a self, record: &'s str) -> Result<StructuralIndex<'a, 's>> {
-        {
-            let mut inner = self.inner.borrow_mut();
-
-            trait VecExt<T> {
-                fn init(&mut self, len: usize);
-            }
-            impl<T> VecExt<T> for Vec<T> {
-                #[inline]
-                fn init(&mut self, len: usize) {
-                    self.clear();
-                    self.reserve_exact(len);
-                }
+        let inner = match self.inner.try_lock() {
+            Ok(inner) => Ok(inner),
+            Err(TryLockError::Poisoned(err)) => Ok(err.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(Box::new(Inner::new(self.level))),
+        };
+        let inner = match inner {
+            Ok(mut inner) => {
+                inner.build(record, &*self.backend, self.options, self.parallel)?;
+                InnerRef::Guard(inner)
             }
-            let b_len = (record.len() + 63) / 64;
-            inner.bitmaps.init(b_len);
-            for c in &mut inner.b_colon {
-                c.init(b_len);
+            Err(mut inner) => {
+                inner.build(record, &*self.backend, self.options, self.parallel)?;
+                InnerRef::Owned(inner)
             }
-            for c in &mut inner.b_comma {
-                c.init(b_len);
-            }
-
-            // Step 1
-            inner.build_structural_character_bitmaps(record.as_bytes(), &self.backend);
-
-            // Step 2
-            inner.remove_unstructural_quotes();
+        };
+        Ok(StructuralIndex { record, inner })
+    }
 
-            // Step 3
-            inner.remove_unstructural_characters()?;
+    /// Build a structural index using the bitmaps in `buffers` instead of the internal ones.
+    pub fn build_with<'a, 's>(
+        &self,
+        record: &'s str,
+        buffers: &'a mut IndexBuffers,
+    ) -> Result<StructuralIndex<'a, 's>> {
+        self.build_levels_with(record, buffers, self.level)
+    }
 
-            // Step 4
-            inner.build_leveled_bitmaps()?;
+    /// Build a structural index with as many levels as the nesting depth of the record
+    /// if it is deeper than `level`, so that the values beyond the level are parsed
+    /// from the same index instead of indexing each of them again.
+    ///
+    /// The deeper index is built into `buffers`, after the internal one finds the depth.
+    pub(crate) fn build_all_levels<'a, 's>(
+        &'a self,
+        record: &'s str,
+        buffers: &'a mut IndexBuffers,
+    ) -> Result<StructuralIndex<'a, 's>> {
+        let index = self.build(record)?;
+        let depth = index.depth();
+        if depth <= self.level {
+            return Ok(index);
         }
+        drop(index);
+        self.build_levels_with(record, buffers, depth)
+    }
 
+    fn build_levels_with<'a, 's>(
+        &self,
+        record: &'s str,
+        buffers: &'a mut IndexBuffers,
+        level: usize,
+    ) -> Result<StructuralIndex<'a, 's>> {
+        if buffers.inner.level != level {
+            buffers.inner = Inner::new(level);
+        }
+        buffers
+            .inner
+            .build(record, &*self.backend, self.options, self.parallel)?;
         Ok(StructuralIndex {
             record,
-            inner: self.inner.borrow(),
+            inner: InnerRef::Borrowed(&buffers.inner),
         })
     }
 }
 
+/// A clone has its own bitmaps, and does not share them with the original builder.
+impl<B: Backend + Clone> Clone for IndexBuilder<B> {
+    fn clone(&self) -> Self {
+        self.with_level(self.level)
+    }
+}
+
+impl<B: Backend + Sync> IndexBuilder<B> {
+    /// Build the character bitmaps of large records on up to `n` threads (`1` by
+    /// default), each of which is given at least `PARALLEL_CHUNK_LEN` bytes.
+    ///
+    /// The following steps (removing the characters within strings and leveling)
+    /// are cheap and run sequentially.
+    pub fn threads(&mut self, n: usize) {
+        self.parallel = if n > 1 {
+            Some((n, build_bitmaps_parallel::<B> as ParallelFn<B>))
+        } else {
+            None
+        };
+    }
+}
+
+/// Build the bitmaps of `record` into `bitmaps`, splitting the words among `threads`.
+fn build_bitmaps_parallel<B: Backend + Sync>(
+    backend: &B,
+    record: &[u8],
+    threads: usize,
+    bitmaps: &mut Vec<Bitmap>,
+) {
+    let num_words = record.len().div_ceil(64);
+    let words_per_thread = num_words.div_ceil(threads);
+    bitmaps.resize_with(num_words, Bitmap::default);
+    std::thread::scope(|scope| {
+        for (i, chunk) in bitmaps.chunks_mut(words_per_thread).enumerate() {
+            scope.spawn(move || {
+                for (j, bitmap) in chunk.iter_mut().enumerate() {
+                    let offset = (i * words_per_thread + j) * 64;
+                    *bitmap = if record.len() - offset >= 64 {
+                        backend.create_full_bitmap(record, offset)
+                    } else {
+                        backend.create_partial_bitmap(record, offset)
+                    };
+                }
+            });
+        }
+    });
+}
+
+impl IndexBuilder<DefaultBackend> {
+    /// Create a new builder with the fastest backend available (see `DefaultBackend`).
+    pub fn with_best_backend(level: usize) -> Self {
+        Self::new(DefaultBackend::default(), level)
+    }
+}
+
+/// Reusable bitmaps for `IndexBuilder::build_with`
+///
+/// The buffers are resized on the first use, and reused afterwards.
+#[derive(Debug, Default)]
+pub struct IndexBuffers {
+    inner: Inner,
+}
+
+impl IndexBuffers {
+    /// Return the bytes held by the buffers.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.inner.memory_usage()
+    }
+}
+
+/// The retention policy of the buffers reused among parses
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum BufferPolicy {
+    /// The buffers keep the size of the largest record seen so far.
+    #[default]
+    KeepPeak,
+    /// The buffers are shrunk every `n` parses.
+    ShrinkAfter(usize),
+    /// The buffers are shrunk when they hold more than the bytes, before the next
+    /// parse. A larger record still grows them while it is parsed.
+    HardCap(usize),
+}
+
+impl BufferPolicy {
+    /// Return whether the buffers holding `bytes` should be shrunk before the parse
+    /// following `parses`.
+    pub(crate) fn shrinks(self, parses: usize, bytes: impl FnOnce() -> usize) -> bool {
+        match self {
+            BufferPolicy::KeepPeak => false,
+            BufferPolicy::ShrinkAfter(n) => n > 0 && parses > 0 && parses.is_multiple_of(n),
+            BufferPolicy::HardCap(limit) => bytes() > limit,
+        }
+    }
+}
+
+/// The bytes held by the buffers of an index, by their capacities
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// the bitmaps of the structural characters and of the control characters
+    pub bitmaps: usize,
+    /// the leveled bitmaps of colons and commas with their prefix sums, for each level
+    pub levels: Vec<usize>,
+    /// the stack of the open brackets and braces used while leveling
+    pub stack: usize,
+}
+
+impl MemoryUsage {
+    /// Return the total bytes.
+    pub fn total(&self) -> usize {
+        self.bitmaps + self.levels.iter().sum::<usize>() + self.stack
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct Inner {
     pub(crate) bitmaps: Vec<Bitmap>,
     pub(crate) b_colon: Vec<Vec<u64>>,
     pub(crate) b_comma: Vec<Vec<u64>>,
+    /// prefix sums of popcounts of `b_colon`, or empty if disabled
+    pub(crate) r_colon: Vec<Vec<u32>>,
+    /// prefix sums of popcounts of `b_comma`, or empty if disabled
+    pub(crate) r_comma: Vec<Vec<u32>>,
+    /// control characters within strings, or empty if disabled
+    pub(crate) control: Vec<u64>,
+    /// maximal nesting depth of the record
+    pub(crate) depth: usize,
+    /// stack of the open brackets and braces, reused among builds
+    stack: Vec<(usize, u64, bool)>,
     level: usize,
+    /// the number of builds so far
+    builds: usize,
 }
 
 impl Inner {
@@ -81,17 +365,127 @@ impl Inner {
             bitmaps: vec![],
             b_colon: vec![vec![]; level],
             b_comma: vec![vec![]; level],
+            r_colon: vec![vec![]; level],
+            r_comma: vec![vec![]; level],
+            control: vec![],
+            depth: 0,
+            stack: vec![],
             level,
+            builds: 0,
         }
     }
 
+    fn memory_usage(&self) -> MemoryUsage {
+        fn bytes<T>(v: &Vec<T>) -> usize {
+            v.capacity() * mem::size_of::<T>()
+        }
+        MemoryUsage {
+            bitmaps: bytes(&self.bitmaps) + bytes(&self.control),
+            levels: (0..self.level)
+                .map(|i| {
+                    bytes(&self.b_colon[i])
+                        + bytes(&self.b_comma[i])
+                        + bytes(&self.r_colon[i])
+                        + bytes(&self.r_comma[i])
+                })
+                .collect(),
+            stack: bytes(&self.stack),
+        }
+    }
+
+    /// Clear the buffers, and shrink them to `b_len` words.
+    fn shrink(&mut self, b_len: usize) {
+        fn shrink<T>(v: &mut Vec<T>, len: usize) {
+            v.clear();
+            v.shrink_to(len);
+        }
+        shrink(&mut self.bitmaps, b_len);
+        shrink(&mut self.control, b_len);
+        for v in self.b_colon.iter_mut().chain(&mut self.b_comma) {
+            shrink(v, b_len);
+        }
+        for v in self.r_colon.iter_mut().chain(&mut self.r_comma) {
+            shrink(v, b_len + 1);
+        }
+        self.stack.clear();
+        self.stack.shrink_to_fit();
+    }
+
+    fn build<B: Backend>(
+        &mut self,
+        record: &str,
+        backend: &B,
+        options: Options,
+        parallel: Option<(usize, ParallelFn<B>)>,
+    ) -> Result<()> {
+        trait VecExt<T> {
+            fn init(&mut self, len: usize);
+        }
+        impl<T> VecExt<T> for Vec<T> {
+            #[inline]
+            fn init(&mut self, len: usize) {
+                self.clear();
+                self.reserve_exact(len);
+            }
+        }
+        let b_len = record.len().div_ceil(64);
+        let builds = self.builds;
+        self.builds = self.builds.wrapping_add(1);
+        if options
+            .buffer_policy
+            .shrinks(builds, || self.memory_usage().total())
+        {
+            self.shrink(b_len);
+        }
+        self.bitmaps.init(b_len);
+        for c in &mut self.b_colon {
+            c.init(b_len);
+        }
+        for c in &mut self.b_comma {
+            c.init(b_len);
+        }
+
+        // Step 1
+        match parallel {
+            Some((threads, f)) if record.len() >= 2 * PARALLEL_CHUNK_LEN => {
+                let threads = cmp::min(threads, record.len() / PARALLEL_CHUNK_LEN);
+                f(backend, record.as_bytes(), threads, &mut self.bitmaps);
+            }
+            _ => self.build_structural_character_bitmaps(record.as_bytes(), backend),
+        }
+        self.control.clear();
+        if options.control_bitmap {
+            self.control.extend(
+                (0..b_len).map(|i| backend.create_control_bitmap(record.as_bytes(), i * 64)),
+            );
+        }
+
+        // Step 2
+        self.remove_unstructural_quotes();
+
+        // Step 3
+        self.remove_unstructural_characters()?;
+
+        // Step 4
+        self.build_leveled_bitmaps(options.max_depth)?;
+
+        for (b, r) in self.b_colon.iter().zip(&mut self.r_colon) {
+            build_prefix_sums(b, r, options.rank_select);
+        }
+        for (b, r) in self.b_comma.iter().zip(&mut self.r_comma) {
+            build_prefix_sums(b, r, options.rank_select);
+        }
+
+        Ok(())
+    }
+
     fn build_structural_character_bitmaps<B: Backend>(&mut self, record: &[u8], backend: &B) {
         for i in 0..(record.len() / 64) {
             self.bitmaps
                 .push(backend.create_full_bitmap(record, i * 64));
         }
 
-        if record.len() % 64 != 0 {
+        if !record.len().is_multiple_of(64) {
             self.bitmaps
                 .push(backend.create_partial_bitmap(record, (record.len() / 64) * 64));
         }
@@ -132,7 +526,7 @@ impl Inner {
         // The number of quotes in structural quote bitmap
         let mut n = 0;
 
-        for b in &mut self.bitmaps {
+        for (i, b) in self.bitmaps.iter_mut().enumerate() {
             let mut m_quote = b.quote;
             let mut m_string = 0u64;
             while m_quote != 0 {
@@ -147,6 +541,9 @@ impl Inner {
                 m_string ^= !0u64;
             }
 
+            if let Some(control) = self.control.get_mut(i) {
+                *control &= m_string;
+            }
             b.colon &= !m_string;
             b.comma &= !m_string;
             b.left_brace &= !m_string;
@@ -162,13 +559,27 @@ impl Inner {
         Ok(())
     }
 
-    fn build_leveled_bitmaps(&mut self) -> Result<()> {
+    fn build_leveled_bitmaps(&mut self, max_depth: usize) -> Result<()> {
         for i in 0..self.level {
             self.b_colon[i].extend(self.bitmaps.iter().map(|b| b.colon));
             self.b_comma[i].extend(self.bitmaps.iter().map(|b| b.comma));
         }
 
-        let mut s = Vec::new();
+        // The stack is moved out while leveling, and keeps its capacity for the
+        // records of similar depths.
+        let mut s = std::mem::take(&mut self.stack);
+        s.clear();
+        self.depth = 0;
+        let result = self.build_leveled_bitmaps_with(&mut s, max_depth);
+        self.stack = s;
+        result
+    }
+
+    fn build_leveled_bitmaps_with(
+        &mut self,
+        s: &mut Vec<(usize, u64, bool)>,
+        max_depth: usize,
+    ) -> Result<()> {
         for (i, b) in self.bitmaps.iter().enumerate() {
             let mut m_left = b.left_brace | b.left_bracket;
             let mut m_right = b.right_brace | b.right_bracket;
@@ -177,24 +588,38 @@ impl Inner {
                 let m_rightbit = bit::E(m_right);
                 let mut m_leftbit = bit::E(m_left);
                 while m_leftbit != 0 && (m_rightbit == 0 || m_leftbit < m_rightbit) {
+                    if s.len() == max_depth {
+                        return Err(ErrorKind::DepthLimitExceeded(max_depth).into());
+                    }
                     let t = m_leftbit & b.left_brace != 0;
                     s.push((i, m_leftbit, t));
+                    self.depth = cmp::max(self.depth, s.len());
                     m_left = bit::R(m_left);
                     m_leftbit = bit::E(m_left);
                 }
 
                 if m_rightbit != 0 {
+                    let offset = i * 64 + m_rightbit.trailing_zeros() as usize;
                     let (j, mlb, t) = s
                         .pop()
                         .ok_or_else(|| Error::from(ErrorKind::InvalidRecord))
-                        .chain_err(|| "s.pop()")?;
+                        .chain_err(|| {
+                            format!(
+                                "unmatched bracket/brace at {}",
+                                location(&self.bitmaps, offset)
+                            )
+                        })?;
                     if t != (m_rightbit & b.right_brace != 0) {
-                        return Err(Error::from(ErrorKind::InvalidRecord))
-                            .chain_err(|| "invalid bracket/brace");
+                        return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| {
+                            format!(
+                                "invalid bracket/brace at {}",
+                                location(&self.bitmaps, offset)
+                            )
+                        });
                     }
                     m_leftbit = mlb;
 
-                    if s.len() > 0 && s.len() - 1 < self.level {
+                    if !s.is_empty() && s.len() - 1 < self.level {
                         let b_colon = &mut self.b_colon[s.len() - 1];
                         let b_comma = &mut self.b_comma[s.len() - 1];
 
@@ -231,6 +656,22 @@ impl Inner {
     }
 }
 
+/// Store the number of set bits in `bitmap[..i]` into `ranks[i]`, or clear `ranks`
+/// if disabled.
+#[inline]
+fn build_prefix_sums(bitmap: &[u64], ranks: &mut Vec<u32>, enabled: bool) {
+    ranks.clear();
+    if enabled {
+        ranks.reserve(bitmap.len() + 1);
+        let mut sum = 0;
+        ranks.push(sum);
+        for bits in bitmap {
+            sum += bits.count_ones();
+            ranks.push(sum);
+        }
+    }
+}
+
 /// Compute the length of the consecutive ones in the backslash bitmap starting at `pos`
 #[inline]
 fn consecutive_ones(b: &[Bitmap], pos: u32) -> u32 {
@@ -251,8 +692,8 @@ fn consecutive_ones(b: &[Bitmap], pos: u32) -> u32 {
 
 #[cfg(test)]
 mod tests {
-    use super::super::backend::{Bitmap, FallbackBackend};
-    use super::IndexBuilder;
+    use super::super::backend::{Bitmap, DynBackend, FallbackBackend};
+    use super::{BufferPolicy, IndexBuffers, IndexBuilder, PARALLEL_CHUNK_LEN};
 
     #[test]
     fn test_structural_character_bitmaps() {
@@ -276,6 +717,7 @@ mod tests {
                     right_brace: 0b0000_0010,
                     left_bracket: 0,
                     right_bracket: 0,
+                    newline: 0,
                 }],
                 b_colon: vec![vec![0]],
                 b_comma: vec![vec![0]],
@@ -292,6 +734,7 @@ mod tests {
                     right_brace: 0b_0001_0000_0000_0000,
                     left_bracket: 0,
                     right_bracket: 0,
+                    newline: 0,
                 }],
                 b_colon: vec![vec![0b_0000_0010_0000_0000]],
                 b_comma: vec![vec![0b_0000_0000_0000_0000]],
@@ -308,6 +751,7 @@ mod tests {
                     right_brace: 0b_0010_0000_0000_0000_0000_1000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000,
                     left_bracket: 0,
                     right_bracket: 0,
+                    newline: 0,
                 }],
                 b_colon: vec![
                     vec![0b_0000_0000_0000_0100_0000_0000_0000_0000_0000_0000_0000_0001_0000_0000_0100_0000],
@@ -330,6 +774,7 @@ mod tests {
                     right_brace: 11274289152,
                     left_bracket: 0,
                     right_bracket: 0,
+                    newline: 0,
                 }],
                 b_colon: vec![vec![64], vec![16448], vec![4210752]],
                 b_comma: vec![vec![0], vec![0], vec![0]],
@@ -346,10 +791,10 @@ mod tests {
                     right_brace: 131072,
                     left_bracket: 128,
                     right_bracket: 32768,
+                    newline: 0,
                 }],
-                //    }_ ]2_, 1_,0 [_:" a"_{
-                b_colon: vec![vec![0b_0000_0000_0000_0010_0000], vec![0b_0000_0000_0000_0010_0000]],
-                b_comma: vec![vec![0b_0000_0000_0000_0000_0000], vec![0b_0000_0001_0010_0000_0000]],
+                b_colon: vec![vec![Bitmap::marks("     ^")], vec![Bitmap::marks("     ^")]],
+                b_comma: vec![vec![0], vec![Bitmap::marks("         ^  ^")]],
             },
         ];
 
@@ -361,4 +806,178 @@ mod tests {
             assert_eq!(t.b_comma, actual.inner.b_comma);
         }
     }
+
+    #[test]
+    fn error_location() {
+        let index_builder = IndexBuilder::<FallbackBackend>::new(Default::default(), 1);
+        let err = index_builder.build("{\n  \"a\": [1, 2}\n}").unwrap_err();
+        assert!(
+            err.iter()
+                .any(|e| e.to_string().ends_with("at line 2, column 13")),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn best_backend() {
+        let index_builder = IndexBuilder::with_best_backend(1);
+        let index = index_builder.build(r#"{"a": 1, "b": 2}"#).unwrap();
+        let mut cp = vec![];
+        assert!(index.colon_positions(0, 16, 0, &mut cp));
+        assert_eq!(cp, [4, 12]);
+    }
+
+    #[test]
+    fn dyn_backend() {
+        let record = r#"{"a": 1, "b": 2}"#;
+        let backend = FallbackBackend::default();
+        let builders: Vec<IndexBuilder<DynBackend>> = vec![
+            IndexBuilder::new(FallbackBackend::default(), 1).into_dyn(),
+            IndexBuilder::new(Box::new(backend.clone()), 1),
+        ];
+        let expected = IndexBuilder::new(&backend, 1);
+        let expected = expected.build(record).unwrap();
+        for builder in &builders {
+            let index = builder.build(record).unwrap();
+            let mut cp = vec![];
+            assert!(index.colon_positions(0, record.len(), 0, &mut cp));
+            let mut expected_cp = vec![];
+            expected.colon_positions(0, record.len(), 0, &mut expected_cp);
+            assert_eq!(cp, expected_cp);
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        use crate::errors::ErrorKind;
+
+        // a million unclosed brackets are rejected at the default depth limit.
+        let bomb = "[".repeat(1 << 20);
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        match index_builder.build(&bomb) {
+            Err(ref e) if matches!(e.kind(), ErrorKind::DepthLimitExceeded(1024)) => {}
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+
+        let mut index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let record = r#"{"a": [[1]]}"#;
+        index_builder.max_depth(3);
+        assert!(index_builder.build(record).is_ok());
+        index_builder.max_depth(2);
+        assert!(index_builder.build(record).is_err());
+    }
+
+    #[test]
+    fn memory_usage() {
+        let mut index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        assert_eq!(index_builder.memory_usage().unwrap().total(), 0);
+        let large = format!("[{}]", "[1],".repeat(10_000) + "1");
+        drop(index_builder.build(&large).unwrap());
+        let usage = index_builder.memory_usage().unwrap();
+        assert_eq!(usage.levels.len(), 2);
+        assert!(usage.levels[0] >= 2 * 8 * large.len() / 64);
+        assert!(usage.bitmaps >= large.len() / 64 * std::mem::size_of::<Bitmap>());
+
+        // the buffers are kept for the small records unless shrunk.
+        let small = r#"{"a": [1]}"#;
+        drop(index_builder.build(small).unwrap());
+        assert_eq!(index_builder.memory_usage().unwrap(), usage);
+        index_builder.buffer_policy(BufferPolicy::HardCap(1024));
+        drop(index_builder.build(small).unwrap());
+        assert!(index_builder.memory_usage().unwrap().total() <= 1024);
+        drop(index_builder.build(&large).unwrap());
+        assert!(index_builder.memory_usage().unwrap().total() > 1024);
+
+        // 4 builds so far, and shrunk before the 7th one.
+        index_builder.buffer_policy(BufferPolicy::ShrinkAfter(3));
+        for _ in 0..2 {
+            drop(index_builder.build(small).unwrap());
+            assert!(index_builder.memory_usage().unwrap().total() > 1024);
+        }
+        drop(index_builder.build(small).unwrap());
+        assert!(index_builder.memory_usage().unwrap().total() <= 1024);
+    }
+
+    #[test]
+    fn nested_builds() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let outer = index_builder.build(r#"{"a": 1, "b": 2}"#).unwrap();
+        let inner = index_builder.build(r#"{"c": [3]}"#).unwrap();
+        let mut cp = vec![];
+        inner.colon_positions(0, 10, 0, &mut cp);
+        assert_eq!(cp, [4]);
+        drop(inner);
+        outer.colon_positions(0, 16, 0, &mut cp);
+        assert_eq!(cp, [4, 12]);
+        assert_eq!(index_builder.memory_usage(), None);
+        drop(outer);
+        assert!(index_builder.memory_usage().unwrap().total() > 0);
+    }
+
+    #[test]
+    fn depth() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        assert_eq!(
+            index_builder.build(r#"{"a": [[1], {}]}"#).unwrap().depth(),
+            3
+        );
+        assert_eq!(index_builder.build(r#"{"a": 1}"#).unwrap().depth(), 1);
+        assert_eq!(index_builder.build("[]").unwrap().depth(), 1);
+    }
+
+    #[test]
+    fn all_levels() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let mut buffers = IndexBuffers::default();
+        let record = r#"{"a": [{"b": 1, "c": 2}]}"#;
+        let index = index_builder
+            .build_all_levels(record, &mut buffers)
+            .unwrap();
+        let mut cp = vec![];
+        assert!(index.colon_positions(7, 23, 2, &mut cp));
+        assert_eq!(cp, [11, 19]);
+        drop(index);
+        assert!(buffers.memory_usage().total() > 0);
+
+        // the internal index is returned if it covers all levels.
+        let mut buffers = IndexBuffers::default();
+        let index = index_builder
+            .build_all_levels(r#"{"a": 1}"#, &mut buffers)
+            .unwrap();
+        assert_eq!(index_builder.memory_usage(), None);
+        drop(index);
+        assert_eq!(buffers.memory_usage().total(), 0);
+    }
+
+    #[test]
+    fn parallel_bitmaps() {
+        // strings with escaped quotes across the boundaries of the chunks
+        let fields: Vec<String> = (0..20000)
+            .map(|i| format!(r#""f{}": {{"s": "a\"b:{}", "t": [{}, "\\"]}}"#, i, i, i))
+            .collect();
+        let record = format!("{{{}}}", fields.join(","));
+        assert!(record.len() > 4 * PARALLEL_CHUNK_LEN);
+
+        let serial = IndexBuilder::new(FallbackBackend::default(), 2);
+        let serial = serial.build(&record).unwrap();
+        let mut builders = vec![];
+        for &threads in &[2, 3, 8] {
+            let mut builder = IndexBuilder::new(FallbackBackend::default(), 2);
+            builder.threads(threads);
+            builders.push(builder.into_dyn());
+        }
+        for builder in &builders {
+            let index = builder.build(&record).unwrap();
+            assert_eq!(index.inner.bitmaps, serial.inner.bitmaps);
+            assert_eq!(index.inner.b_colon, serial.inner.b_colon);
+            assert_eq!(index.inner.b_comma, serial.inner.b_comma);
+        }
+
+        // small records are indexed serially.
+        let mut builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        builder.threads(4);
+        let index = builder.build(r#"{"a": 1}"#).unwrap();
+        assert_eq!(index.inner.bitmaps.len(), 1);
+    }
 }
@@ -1,14 +1,457 @@
+use super::backend::Bitmap;
 use super::builder::Inner;
 use crate::bit;
-use crate::errors::{ErrorKind, Result};
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::typed::JsonType;
 use crate::value::EscapedStr;
-use std::cell::Ref;
+use std::cmp;
+use std::fmt::{self, Write};
+use std::iter;
+use std::ops::Deref;
+use std::slice;
+use std::sync::MutexGuard;
+
+/// Strategy to determine the boundaries of field values
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ValueTrimming {
+    /// Strip the whitespaces and a delimiter by scanning the bytes backward.
+    #[default]
+    Scan,
+    /// Locate the delimiter from the leveled comma bitmaps, and reject the values
+    /// surrounded by unexpected characters (e.g. trailing commas).
+    Strict,
+}
+
+/// A position in a record, numbered from 1
+///
+/// The column is counted in bytes from the beginning of the line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Location {
+    #[allow(missing_docs)]
+    pub line: usize,
+    #[allow(missing_docs)]
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Convert a byte offset into its location, by counting the newlines in the bitmaps.
+pub(crate) fn location(bitmaps: &[Bitmap], offset: usize) -> Location {
+    let mut line = 1;
+    let mut line_start = 0;
+    for (i, b) in bitmaps.iter().enumerate().take(offset / 64 + 1) {
+        let mut newline = b.newline;
+        if i == offset / 64 {
+            newline &= (1u64 << (offset % 64)).wrapping_sub(1);
+        }
+        if newline != 0 {
+            line += newline.count_ones() as usize;
+            line_start = i * 64 + (64 - newline.leading_zeros() as usize);
+        }
+    }
+    Location {
+        line,
+        column: offset - line_start + 1,
+    }
+}
+
+/// A token of a record, with its byte offsets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Token {
+    #[allow(missing_docs)]
+    ObjectStart(usize),
+    #[allow(missing_docs)]
+    ObjectEnd(usize),
+    #[allow(missing_docs)]
+    ArrayStart(usize),
+    #[allow(missing_docs)]
+    ArrayEnd(usize),
+    #[allow(missing_docs)]
+    Colon(usize),
+    #[allow(missing_docs)]
+    Comma(usize),
+    /// The (escaped) name of a field, without the quotes
+    Key(usize, usize),
+    /// The raw text of an atomic value, including the quotes of a string
+    Value(usize, usize),
+}
+
+/// An iterator over the tokens of a range of a record
+///
+/// Created by `StructuralIndex::tokens`.
+#[derive(Debug)]
+pub struct Tokens<'i, 'a, 's> {
+    index: &'i StructuralIndex<'a, 's>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'i, 'a, 's> Iterator for Tokens<'i, 'a, 's> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let s = self.index.record.as_bytes();
+        while self.pos < self.end && is_whitespace(s[self.pos]) {
+            self.pos += 1;
+        }
+        if self.pos >= self.end {
+            return None;
+        }
+
+        let pos = self.pos;
+        let token = match s[pos] {
+            b'{' => Token::ObjectStart(pos),
+            b'}' => Token::ObjectEnd(pos),
+            b'[' => Token::ArrayStart(pos),
+            b']' => Token::ArrayEnd(pos),
+            b':' => Token::Colon(pos),
+            b',' => Token::Comma(pos),
+            b'"' => {
+                let bitmaps = &self.index.inner.bitmaps;
+                let ei = next_position(bitmaps, pos + 1, self.end, |b| b.quote);
+                let Some(ei) = ei else {
+                    // the string continues beyond `end`.
+                    self.pos = self.end;
+                    return Some(Token::Value(pos, self.end));
+                };
+                self.pos = ei + 1;
+                let next = s[ei + 1..self.end].iter().find(|&&c| !is_whitespace(c));
+                return Some(if next == Some(&b':') {
+                    Token::Key(pos + 1, ei)
+                } else {
+                    Token::Value(pos, ei + 1)
+                });
+            }
+            _ => {
+                let bitmaps = &self.index.inner.bitmaps;
+                let next = next_position(bitmaps, pos + 1, self.end, |b| {
+                    b.quote
+                        | b.colon
+                        | b.comma
+                        | b.left_brace
+                        | b.right_brace
+                        | b.left_bracket
+                        | b.right_bracket
+                })
+                .unwrap_or(self.end);
+                self.pos = next;
+                let (vsi, vei, _) = find_array_value(s, pos, next);
+                return Some(Token::Value(vsi, vei));
+            }
+        };
+        self.pos += 1;
+        Some(token)
+    }
+}
+
+/// An iterator over the ranges of the consecutive elements of an array
+///
+/// Created by `StructuralIndex::array_chunks`.
+#[derive(Debug)]
+pub struct ArrayChunks<'i, 'a, 's> {
+    index: &'i StructuralIndex<'a, 's>,
+    level: usize,
+    chunk_len: usize,
+    /// the beginning of the next chunk, or `None` after the last one
+    pos: Option<usize>,
+    /// the position of the closing bracket
+    end: usize,
+    first: bool,
+}
+
+impl<'i, 'a, 's> Iterator for ArrayChunks<'i, 'a, 's> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let pos = self.pos?;
+        let s = self.index.record.as_bytes();
+        let first = std::mem::replace(&mut self.first, false);
+        // the level has been checked by `array_chunks`.
+        let comma = self
+            .index
+            .nth_comma(pos, self.end, self.level, self.chunk_len - 1)
+            .ok()
+            .flatten();
+        let range = match comma {
+            Some(ci) => {
+                self.pos = Some(ci + 1);
+                let (vsi, vei, _) = find_array_value(s, pos, ci);
+                (vsi, vei)
+            }
+            None => {
+                self.pos = None;
+                let (vsi, vei, _) = find_array_value(s, pos, self.end);
+                if first && vsi >= vei {
+                    return None; // an empty array
+                }
+                (vsi, vei)
+            }
+        };
+        Some(range)
+    }
+}
+
+/// A field of an object, yielded by `ObjectFields`
+#[derive(Debug, Clone)]
+pub(crate) struct ObjectField<'s> {
+    /// the (escaped) name of the field
+    pub name: EscapedStr<'s>,
+    /// the beginning of the name, just after its opening quote
+    pub fsi: usize,
+    /// the position of the colon after the name
+    pub ci: usize,
+    /// the boundary of the value, trimmed by `ValueTrimming::Scan`
+    pub vsi: usize,
+    pub vei: usize,
+    /// the type of the value guessed from its first byte
+    pub ty: Option<JsonType>,
+    /// the end of the range of the value, i.e. just before the opening quote of the
+    /// next field, or the end of the object
+    pub vend: usize,
+    pub is_last: bool,
+}
+
+impl<'s> ObjectField<'s> {
+    /// Return the end of the name, just before its closing quote.
+    #[inline]
+    pub fn fei(&self) -> usize {
+        self.fsi + self.name.as_raw_str().len()
+    }
+}
+
+/// An iterator over the fields of an object, from the colons of its level
+///
+/// The value of each field ends before the opening quote of the next field, whose
+/// name is found once and carried over to the next iteration. The fields can also be
+/// taken from the back if the colons can.
+///
+/// Created by `StructuralIndex::object_fields` and `object_fields_at`.
+#[derive(Debug)]
+pub(crate) struct ObjectFields<'i, 'a, 's, C> {
+    index: &'i StructuralIndex<'a, 's>,
+    colons: C,
+    /// the beginning of the object, at its opening brace
+    begin: usize,
+    /// the colon of the last field taken from the front, or the beginning of the object
+    front_bound: usize,
+    /// the next field from the front, whose colon has been taken from `colons`
+    front_next: Option<(usize, EscapedStr<'s>, usize)>,
+    /// the end of the value of the next field from the back
+    back_end: usize,
+    /// the colon preceding the last field taken from the back
+    back_peek: Option<usize>,
+    back_started: bool,
+    /// the level of the object, if the values are trimmed by `ValueTrimming::Strict`
+    strict: Option<usize>,
+}
+
+impl<'i, 'a, 's, C: Iterator<Item = usize>> ObjectFields<'i, 'a, 's, C> {
+    fn new(index: &'i StructuralIndex<'a, 's>, begin: usize, end: usize, colons: C) -> Self {
+        Self {
+            index,
+            colons,
+            begin,
+            front_bound: begin,
+            front_next: None,
+            back_end: end,
+            back_peek: None,
+            back_started: false,
+            strict: None,
+        }
+    }
+
+    #[inline]
+    fn field(
+        &self,
+        (ci, name, fsi): (usize, EscapedStr<'s>, usize),
+        vend: usize,
+        is_last: bool,
+    ) -> Result<ObjectField<'s>> {
+        let (vsi, vei, ty) = match self.strict {
+            Some(level) => self
+                .index
+                .find_object_value_strict(ci + 1, vend, level, is_last)?,
+            None => self.index.find_object_value_typed(ci + 1, vend, is_last),
+        };
+        Ok(ObjectField {
+            name,
+            fsi,
+            ci,
+            vsi,
+            vei,
+            ty,
+            vend,
+            is_last,
+        })
+    }
+
+    /// Find the name of the field before the colon `ci`, after `bound`. In strict mode,
+    /// nothing but whitespace may precede the first field nor follow the name.
+    #[inline]
+    fn find_field(&self, bound: usize, ci: usize) -> Result<(EscapedStr<'s>, usize)> {
+        let (name, fsi) = self.index.find_object_field(bound, ci)?;
+        if self.strict.is_some() {
+            let s = self.index.record.as_bytes();
+            let fei = fsi + name.as_raw_str().len() + 1;
+            let unexpected = if bound == self.begin {
+                s[bound + 1..fsi - 1]
+                    .iter()
+                    .position(|&c| !is_whitespace(c))
+                    .map(|i| bound + 1 + i)
+            } else {
+                None
+            }
+            .or_else(|| {
+                s[fei..ci]
+                    .iter()
+                    .position(|&c| !is_whitespace(c))
+                    .map(|i| fei + i)
+            });
+            if let Some(i) = unexpected {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| format!("unexpected character at {}", self.index.location(i)));
+            }
+        }
+        Ok((name, fsi))
+    }
+
+    /// Check that the object is empty if it has no fields at all, i.e. no colons, in
+    /// strict mode.
+    fn check_empty(&self) -> Result<()> {
+        if self.strict.is_none() || self.front_bound != self.begin || self.back_started {
+            return Ok(());
+        }
+        // the braces of the object are excluded.
+        let s = self.index.record.as_bytes();
+        if s[self.begin + 1..self.back_end - 1]
+            .iter()
+            .all(|&c| is_whitespace(c))
+        {
+            return Ok(());
+        }
+        Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| {
+            format!(
+                "missing colon in the object at {}",
+                self.index.location(self.begin)
+            )
+        })
+    }
+
+    #[inline]
+    fn next_field(&mut self) -> Result<Option<ObjectField<'s>>> {
+        let cur = match self.front_next.take() {
+            Some(cur) => cur,
+            None => match self.colons.next().or_else(|| self.back_peek.take()) {
+                Some(ci) => {
+                    let (name, fsi) = self.find_field(self.front_bound, ci)?;
+                    (ci, name, fsi)
+                }
+                None => return self.check_empty().map(|()| None),
+            },
+        };
+        self.front_bound = cur.0;
+        let field = match self.colons.next().or_else(|| self.back_peek.take()) {
+            Some(nci) => {
+                let (name, nfsi) = self.find_field(cur.0, nci)?;
+                self.front_next = Some((nci, name, nfsi));
+                self.field(cur, nfsi - 1, false)?
+            }
+            None => self.field(cur, self.back_end, !self.back_started)?,
+        };
+        Ok(Some(field))
+    }
+}
+
+impl<'i, 'a, 's, C: Iterator<Item = usize>> Iterator for ObjectFields<'i, 'a, 's, C> {
+    type Item = Result<ObjectField<'s>>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_field().transpose()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.colons.size_hint();
+        let carried = self.front_next.is_some() as usize + self.back_peek.is_some() as usize;
+        (lower + carried, upper.map(|upper| upper + carried))
+    }
+}
+
+impl<'i, 'a, 's, C: DoubleEndedIterator<Item = usize>> ObjectFields<'i, 'a, 's, C> {
+    #[inline]
+    fn next_field_back(&mut self) -> Result<Option<ObjectField<'s>>> {
+        let ci = match self.back_peek.take().or_else(|| self.colons.next_back()) {
+            Some(ci) => ci,
+            None => {
+                // the last field has been found from the front.
+                return match self.front_next.take() {
+                    Some(cur) => {
+                        self.front_bound = cur.0;
+                        self.field(cur, self.back_end, !self.back_started).map(Some)
+                    }
+                    None => self.check_empty().map(|()| None),
+                };
+            }
+        };
+        self.back_peek = self.colons.next_back();
+        let bound = match (self.back_peek, &self.front_next) {
+            (Some(prev), _) => prev,
+            (None, &Some((prev, ..))) => prev,
+            (None, None) => self.front_bound,
+        };
+        let (name, fsi) = self.find_field(bound, ci)?;
+        let field = self.field((ci, name, fsi), self.back_end, !self.back_started)?;
+        self.back_started = true;
+        self.back_end = fsi - 1;
+        Ok(Some(field))
+    }
+}
+
+impl<'i, 'a, 's, C: DoubleEndedIterator<Item = usize>> DoubleEndedIterator
+    for ObjectFields<'i, 'a, 's, C>
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_field_back().transpose()
+    }
+}
+
+impl<'i, 'a, 's, C: ExactSizeIterator<Item = usize>> ExactSizeIterator
+    for ObjectFields<'i, 'a, 's, C>
+{
+}
+
+/// Bitmaps owned by either `IndexBuilder` or `IndexBuffers`, or by the index itself
+/// when the builder is already in use
+#[derive(Debug)]
+pub(super) enum InnerRef<'a> {
+    Guard(MutexGuard<'a, Inner>),
+    Borrowed(&'a Inner),
+    Owned(Box<Inner>),
+}
+
+impl<'a> Deref for InnerRef<'a> {
+    type Target = Inner;
+
+    #[inline]
+    fn deref(&self) -> &Inner {
+        match *self {
+            InnerRef::Guard(ref inner) => inner,
+            InnerRef::Borrowed(inner) => inner,
+            InnerRef::Owned(ref inner) => inner,
+        }
+    }
+}
 
 /// Structural index of a slice of bytes
 #[derive(Debug)]
 pub struct StructuralIndex<'a, 's> {
     pub(super) record: &'s str,
-    pub(super) inner: Ref<'a, Inner>,
+    pub(super) inner: InnerRef<'a>,
 }
 
 impl<'a, 's> StructuralIndex<'a, 's> {
@@ -29,6 +472,126 @@ impl<'a, 's> StructuralIndex<'a, 's> {
         }
     }
 
+    /// Find the position of the `n`-th (0-origin) colon at `level`, between from `begin`
+    /// to `end`, without enumerating the preceding colons.
+    pub fn nth_colon(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+        n: usize,
+    ) -> Result<Option<usize>> {
+        nth_position(
+            &self.inner.b_colon,
+            &self.inner.r_colon,
+            begin,
+            end,
+            level,
+            n,
+        )
+    }
+
+    /// Find the position of the `n`-th (0-origin) comma at `level`, between from `begin`
+    /// to `end`, without enumerating the preceding commas.
+    pub fn nth_comma(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+        n: usize,
+    ) -> Result<Option<usize>> {
+        nth_position(
+            &self.inner.b_comma,
+            &self.inner.r_comma,
+            begin,
+            end,
+            level,
+            n,
+        )
+    }
+
+    /// Count the colons at `level` between `begin` and `end`, i.e. the fields of the
+    /// object at `level`, from the popcounts of the words without enumerating them.
+    ///
+    /// Returns `None` if the level is not covered by the index.
+    pub fn field_count(&self, begin: usize, end: usize, level: usize) -> Option<usize> {
+        let b_colon = self.inner.b_colon.get(level)?;
+        Some(
+            masked_words(b_colon, begin, end)
+                .map(|(_, bits)| bits.count_ones() as usize)
+                .sum(),
+        )
+    }
+
+    /// Same as `colon_positions`, but the positions are enumerated lazily.
+    pub(crate) fn colons(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+    ) -> Option<Positions<impl Iterator<Item = (usize, u64)> + '_>> {
+        let b_colon = self.inner.b_colon.get(level)?;
+        Some(Positions {
+            words: masked_words(b_colon, begin, end),
+            word: (0, 0),
+        })
+    }
+
+    /// Return an iterator over the fields of the object `begin..end` at `level`, or
+    /// `None` if the level is not covered by the index.
+    pub(crate) fn object_fields(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+    ) -> Option<ObjectFields<'_, 'a, 's, impl Iterator<Item = usize> + '_>> {
+        let colons = self.colons(begin, end, level)?;
+        Some(ObjectFields::new(self, begin, end, colons))
+    }
+
+    /// Same as `object_fields`, but the values are trimmed by `ValueTrimming::Strict`,
+    /// and an object without colons must be empty, so that the missing colons and
+    /// commas between the fields are detected.
+    pub(crate) fn object_fields_strict(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+    ) -> Option<ObjectFields<'_, 'a, 's, impl Iterator<Item = usize> + '_>> {
+        let mut fields = self.object_fields(begin, end, level)?;
+        fields.strict = Some(level);
+        Some(fields)
+    }
+
+    /// Same as `object_fields`, but with the colon positions `cp` of the object
+    /// calculated beforehand (e.g. by `colon_positions`), from which the fields can
+    /// also be taken in reverse.
+    pub(crate) fn object_fields_at<'c>(
+        &self,
+        begin: usize,
+        end: usize,
+        cp: &'c [usize],
+    ) -> ObjectFields<'_, 'a, 's, iter::Copied<slice::Iter<'c, usize>>> {
+        ObjectFields::new(self, begin, end, cp.iter().copied())
+    }
+
+    /// Return the boundary of the value of `field` at `level`, using the given
+    /// strategy. See `find_object_value_with`.
+    #[inline]
+    pub(crate) fn field_value_with(
+        &self,
+        field: &ObjectField<'_>,
+        level: usize,
+        trimming: ValueTrimming,
+    ) -> Result<(usize, usize)> {
+        match trimming {
+            ValueTrimming::Scan => Ok((field.vsi, field.vei)),
+            ValueTrimming::Strict => self
+                .find_object_value_strict(field.ci + 1, field.vend, level, field.is_last)
+                .map(|(vsi, vei, _)| (vsi, vei)),
+        }
+    }
+
     /// Calculate the position of colons at `level`, between from `begin` to `end`
     pub fn comma_positions(
         &self,
@@ -46,28 +609,191 @@ impl<'a, 's> StructuralIndex<'a, 's> {
         }
     }
 
-    #[allow(missing_docs)]
+    /// Return an iterator over the ranges of the array `begin..end` at `level`, each of
+    /// which covers `chunk_len` elements (except the last one) without the surrounding
+    /// whitespace and commas.
+    ///
+    /// The commas are located one chunk at a time, so that enormous arrays can be
+    /// processed incrementally, e.g. by parsing `[` + chunk + `]` separately.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_len` is 0.
+    pub fn array_chunks(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+        chunk_len: usize,
+    ) -> Result<ArrayChunks<'_, 'a, 's>> {
+        assert!(chunk_len != 0, "chunk_len must be non-zero");
+        let s = self.record.as_bytes();
+        if begin + 2 > end || s[begin] != b'[' || s[end - 1] != b']' {
+            return Err(Error::from(ErrorKind::InvalidRecord))
+                .chain_err(|| format!("not an array at {}", self.location(begin)));
+        }
+        if level >= self.inner.b_comma.len() {
+            return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "mismatched level");
+        }
+        Ok(ArrayChunks {
+            index: self,
+            level,
+            chunk_len,
+            pos: Some(begin + 1),
+            end: end - 1,
+            first: true,
+        })
+    }
+
+    /// Return an iterator over the tokens between `begin` and `end`.
+    ///
+    /// The structural characters are found from the bitmaps, so the bytes within
+    /// strings and atomic values are not inspected. `begin` must not be within a string.
+    pub fn tokens(&self, begin: usize, end: usize) -> Tokens<'_, 'a, 's> {
+        Tokens {
+            index: self,
+            pos: begin,
+            end: cmp::min(end, self.record.len()),
+        }
+    }
+
+    /// Return whether the name of the field whose colon at `level` is at `ci` spans from
+    /// `fsi` to `fei` (the closing quote), checked only from the bits around them.
+    pub(crate) fn is_field_at(&self, fsi: usize, fei: usize, ci: usize, level: usize) -> bool {
+        let bitmaps = &self.inner.bitmaps;
+        let bit =
+            |bits: &[u64], pos: usize| bits.get(pos / 64).is_some_and(|w| w >> (pos % 64) & 1 == 1);
+        let quote = |pos: usize| {
+            bitmaps
+                .get(pos / 64)
+                .is_some_and(|b| b.quote >> (pos % 64) & 1 == 1)
+        };
+        0 < fsi
+            && fsi <= fei
+            && fei < ci
+            && self.inner.b_colon.get(level).is_some_and(|b| bit(b, ci))
+            && quote(fsi - 1)
+            && quote(fei)
+            && next_position(bitmaps, fsi, fei, |b| b.quote).is_none()
+            && self.record.as_bytes()[fei + 1..ci]
+                .iter()
+                .all(|&c| is_whitespace(c))
+    }
+
+    /// Return whether any colon at `level` is between `begin` and `end`.
+    pub(crate) fn has_colon(&self, begin: usize, end: usize, level: usize) -> bool {
+        match self.inner.b_colon.get(level) {
+            Some(b_colon) => {
+                let end = cmp::min(end, b_colon.len() * 64);
+                begin < end && last_position(b_colon, begin, end).is_some()
+            }
+            None => false,
+        }
+    }
+
+    /// Return whether any control character within strings is between `begin` and `end`,
+    /// or `None` if the bitmap is not built (see `IndexBuilder::control_bitmap`).
+    pub fn contains_control(&self, begin: usize, end: usize) -> Option<bool> {
+        let control = &self.inner.control;
+        if control.is_empty() && !self.record.is_empty() {
+            return None;
+        }
+        Some(begin < end && last_position(control, begin, end).is_some())
+    }
+
+    /// Return whether any backslash is between `begin` and `end`.
+    pub fn contains_backslash(&self, begin: usize, end: usize) -> bool {
+        next_position(&self.inner.bitmaps, begin, end, |b| b.backslash).is_some()
+    }
+
+    /// Return the maximal nesting depth of brackets and braces in the record.
+    pub fn depth(&self) -> usize {
+        self.inner.depth
+    }
+
+    /// Render the record with the colons and commas of each level marked below it,
+    /// e.g. for debugging the leveled bitmaps. Each level marks only the characters
+    /// which are not of the outer levels.
+    ///
+    /// The record is split into the words of the bitmaps (64 bytes), each labeled
+    /// with its offset. The bytes other than the printable ASCII characters are
+    /// rendered as `.`, so that the markers stay aligned.
+    ///
+    /// ```text
+    ///        0 | {"a": {"b": 1, "c": 2}}
+    ///  level 0 |     :
+    ///  level 1 |           :  ,    :
+    /// ```
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        let bytes = self.record.as_bytes();
+        for (word, chunk) in bytes.chunks(64).enumerate() {
+            let line: String = chunk
+                .iter()
+                .map(|&c| match c {
+                    b' '..=b'~' => c as char,
+                    _ => '.',
+                })
+                .collect();
+            let _ = writeln!(out, "{:>8} | {}", word * 64, line);
+            for level in 0..self.inner.b_colon.len() {
+                // the bitmaps of a level include the characters of the outer levels.
+                let bit = |bits: &Vec<Vec<u64>>, i: usize| {
+                    let is_set =
+                        |level: usize| bits[level].get(word).is_some_and(|w| w >> i & 1 == 1);
+                    is_set(level) && (level == 0 || !is_set(level - 1))
+                };
+                let markers: String = (0..chunk.len())
+                    .map(|i| match () {
+                        _ if bit(&self.inner.b_colon, i) => ':',
+                        _ if bit(&self.inner.b_comma, i) => ',',
+                        _ => ' ',
+                    })
+                    .collect();
+                let _ = writeln!(out, " level {} | {}", level, markers.trim_end());
+            }
+        }
+        out
+    }
+
+    /// Convert a byte offset in the record into its line and column.
+    pub fn location(&self, offset: usize) -> Location {
+        location(&self.inner.bitmaps, offset)
+    }
+
+    /// Find the name of the field whose colon is at `end`, after `begin`.
+    ///
+    /// Only the words of the quote bitmap between `begin` and `end` are scanned (in
+    /// practice, the words just before the colon), so the cost does not depend on the
+    /// number of fields in the object.
     #[inline]
     pub fn find_object_field(&self, begin: usize, end: usize) -> Result<(EscapedStr<'s>, usize)> {
         let mut ei = None;
 
-        for i in (begin / 64..(end + 1 + 63) / 64).rev() {
-            let mut m_quote = self.inner.bitmaps[i].quote;
-            while m_quote != 0 {
-                let offset = (i + 1) * 64 - (m_quote.leading_zeros() as usize) - 1;
-                if offset < end {
+        if begin < end {
+            let (first, last) = (begin / 64, (end - 1) / 64);
+            for i in (first..=last).rev() {
+                let mut m_quote = self.inner.bitmaps[i].quote;
+                if i == last && !end.is_multiple_of(64) {
+                    m_quote &= (1 << (end % 64)) - 1;
+                }
+                if i == first {
+                    m_quote &= !0 << (begin % 64);
+                }
+                while m_quote != 0 {
+                    let offset = i * 64 + 63 - (m_quote.leading_zeros() as usize);
                     if let Some(ei) = ei {
                         let si = offset + 1;
                         return Ok((EscapedStr::from(&self.record[si..ei]), si));
-                    } else {
-                        ei = Some(offset);
                     }
+                    ei = Some(offset);
+                    m_quote = bit::L(m_quote);
                 }
-                m_quote = bit::L(m_quote);
             }
         }
 
-        Err(ErrorKind::InvalidRecord.into())
+        Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("missing field name before {}", self.location(end)))
     }
 
     #[allow(missing_docs)]
@@ -78,18 +804,109 @@ impl<'a, 's> StructuralIndex<'a, 's> {
         end: usize,
         is_last_field: bool,
     ) -> (usize, usize) {
-        find_object_value(
-            self.record.as_bytes(),
-            begin,
-            end,
-            if is_last_field { b'}' } else { b',' },
-        )
+        let (vsi, vei, _) = self.find_object_value_typed(begin, end, is_last_field);
+        (vsi, vei)
+    }
+
+    /// Same as `find_object_value`, but also return the type of the value guessed from
+    /// its first byte (see `JsonType::of`), which is the byte the trimming stops on.
+    #[inline]
+    pub(crate) fn find_object_value_typed(
+        &self,
+        begin: usize,
+        end: usize,
+        is_last_field: bool,
+    ) -> (usize, usize, Option<JsonType>) {
+        let delim = if is_last_field { b'}' } else { b',' };
+        let (vsi, vei, first) = find_object_value(self.record.as_bytes(), begin, end, delim);
+        (vsi, vei, first.and_then(JsonType::of_byte))
+    }
+
+    /// Find the boundary of the value of a field at `level`, between the colon at
+    /// `begin - 1` and `end`, using the given strategy.
+    ///
+    /// For the last field, `end` must be just after the closing brace of the object.
+    #[inline]
+    pub fn find_object_value_with(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+        is_last_field: bool,
+        trimming: ValueTrimming,
+    ) -> Result<(usize, usize)> {
+        match trimming {
+            ValueTrimming::Scan => Ok(self.find_object_value(begin, end, is_last_field)),
+            ValueTrimming::Strict => self
+                .find_object_value_strict(begin, end, level, is_last_field)
+                .map(|(vsi, vei, _)| (vsi, vei)),
+        }
+    }
+
+    fn find_object_value_strict(
+        &self,
+        begin: usize,
+        end: usize,
+        level: usize,
+        is_last_field: bool,
+    ) -> Result<(usize, usize, Option<JsonType>)> {
+        let s = self.record.as_bytes();
+        let b_comma = self
+            .inner
+            .b_comma
+            .get(level)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| "mismatched level")?;
+        let comma = last_position(b_comma, begin, end);
+
+        let delim = if is_last_field {
+            if comma.is_some() || end == 0 || s[end - 1] != b'}' {
+                return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| {
+                    format!("unexpected end of the value at {}", self.location(end))
+                });
+            }
+            end - 1
+        } else {
+            match comma {
+                Some(delim) if s[delim + 1..end].iter().all(|&c| is_whitespace(c)) => delim,
+                _ => {
+                    return Err(Error::from(ErrorKind::InvalidRecord))
+                        .chain_err(|| format!("missing delimiter before {}", self.location(end)))
+                }
+            }
+        };
+
+        let (vsi, vei, first) = find_array_value(s, begin, delim);
+        if vsi == vei {
+            return Err(Error::from(ErrorKind::InvalidRecord))
+                .chain_err(|| format!("empty value at {}", self.location(begin)));
+        }
+        Ok((vsi, vei, first.and_then(JsonType::of_byte)))
     }
 
     #[allow(missing_docs)]
     #[inline]
     pub fn find_array_value(&self, begin: usize, end: usize) -> (usize, usize) {
-        find_array_value(self.record.as_bytes(), begin, end)
+        let (vsi, vei, _) = find_array_value(self.record.as_bytes(), begin, end);
+        (vsi, vei)
+    }
+
+    /// Same as `find_array_value`, but also return the type of the value. See
+    /// `find_object_value_typed`.
+    #[inline]
+    pub(crate) fn find_array_value_typed(
+        &self,
+        begin: usize,
+        end: usize,
+    ) -> (usize, usize, Option<JsonType>) {
+        let (vsi, vei, first) = find_array_value(self.record.as_bytes(), begin, end);
+        (vsi, vei, first.and_then(JsonType::of_byte))
+    }
+
+    /// Return the whole record of this index.
+    #[inline]
+    pub fn as_str(&self) -> &'s str {
+        self.record
     }
 
     #[allow(missing_docs)]
@@ -100,29 +917,214 @@ impl<'a, 's> StructuralIndex<'a, 's> {
     }
 }
 
+/// Return the words of `bitmap` between `begin` and `end` with their indices, whose
+/// bits outside the range are masked.
+#[inline]
+fn masked_words(
+    bitmap: &[u64],
+    begin: usize,
+    end: usize,
+) -> impl Iterator<Item = (usize, u64)> + '_ {
+    let words = if begin < end {
+        begin / 64..cmp::min((end - 1) / 64 + 1, bitmap.len())
+    } else {
+        0..0
+    };
+    words.map(move |i| {
+        let mut bits = bitmap[i];
+        if i == begin / 64 {
+            bits &= !0u64 << (begin % 64);
+        }
+        if i == (end - 1) / 64 {
+            bits &= !0u64 >> (63 - (end - 1) % 64);
+        }
+        (i, bits)
+    })
+}
+
+/// Push the positions of the set bits between `begin` and `end` into `cp`.
+///
+/// The first and the last words are masked up front, so that every bit visited is
+/// pushed, and the words without set bits are skipped at once.
 #[inline]
 fn generate_positions(bitmap: &[u64], begin: usize, end: usize, cp: &mut Vec<usize>) {
-    for i in begin / 64..(end - 1 + 63) / 64 {
-        let mut m_bits = bitmap[i];
-        while m_bits != 0 {
-            let m_bit = bit::E(m_bits);
-            let offset = i * 64 + (m_bit.trailing_zeros() as usize);
-            if begin <= offset && offset < end {
-                cp.push(offset);
+    for (i, mut bits) in masked_words(bitmap, begin, end) {
+        while bits != 0 {
+            cp.push(i * 64 + bits.trailing_zeros() as usize);
+            bits = bit::R(bits);
+        }
+    }
+}
+
+/// An iterator over the positions of the set bits in a range of a bitmap
+pub(crate) struct Positions<I> {
+    words: I,
+    word: (usize, u64),
+}
+
+impl<I: Iterator<Item = (usize, u64)>> Iterator for Positions<I> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        while self.word.1 == 0 {
+            self.word = self.words.next()?;
+        }
+        let (i, bits) = self.word;
+        self.word.1 = bit::R(bits);
+        Some(i * 64 + bits.trailing_zeros() as usize)
+    }
+}
+
+#[inline]
+fn nth_position(
+    bitmaps: &[Vec<u64>],
+    ranks: &[Vec<u32>],
+    begin: usize,
+    end: usize,
+    level: usize,
+    n: usize,
+) -> Result<Option<usize>> {
+    match (bitmaps.get(level), ranks.get(level)) {
+        (Some(bitmap), Some(rank)) if !rank.is_empty() => {
+            Ok(select_with_rank(bitmap, rank, begin, end, n))
+        }
+        (Some(bitmap), _) => Ok(select(bitmap, begin, end, n)),
+        (None, _) => Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "mismatched level"),
+    }
+}
+
+/// Same as `select`, but the word containing the bit is found by a binary search over
+/// the prefix sums of popcounts (`rank[i]` is the number of set bits in `bitmap[..i]`).
+#[inline]
+fn select_with_rank(
+    bitmap: &[u64],
+    rank: &[u32],
+    begin: usize,
+    end: usize,
+    n: usize,
+) -> Option<usize> {
+    let w = begin / 64;
+    if w >= bitmap.len() {
+        return None;
+    }
+    let below = bitmap[w] & ((1u64 << (begin % 64)) - 1);
+    let target = rank[w] as usize + below.count_ones() as usize + n;
+
+    // rank[0] == 0, so the partition point is at least 1.
+    let w = rank.partition_point(|&r| r as usize <= target) - 1;
+    if w >= bitmap.len() {
+        return None;
+    }
+    let mut bits = bitmap[w];
+    for _ in 0..target - rank[w] as usize {
+        bits = bit::R(bits);
+    }
+    let offset = w * 64 + bits.trailing_zeros() as usize;
+    if offset < end {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+/// Return the position of the `n`-th (0-origin) set bit in `bitmap` between `begin`
+/// and `end`, skipping the whole words by counting their bits.
+#[inline]
+fn select(bitmap: &[u64], begin: usize, end: usize, mut n: usize) -> Option<usize> {
+    for (i, &bits) in bitmap
+        .iter()
+        .enumerate()
+        .take(end.div_ceil(64))
+        .skip(begin / 64)
+    {
+        let mut bits = bits;
+        if i == begin / 64 {
+            bits &= !0u64 << (begin % 64);
+        }
+        let count = bits.count_ones() as usize;
+        if n < count {
+            for _ in 0..n {
+                bits = bit::R(bits);
             }
-            m_bits = bit::R(m_bits);
+            let offset = i * 64 + bits.trailing_zeros() as usize;
+            return if offset < end { Some(offset) } else { None };
         }
+        n -= count;
     }
+    None
 }
 
+/// Return the first position of the set bits in `f(bitmap)` between `begin` and `end`.
 #[inline]
-fn find_object_value(s: &[u8], mut begin: usize, mut end: usize, delim: u8) -> (usize, usize) {
+fn next_position<F>(bitmaps: &[Bitmap], begin: usize, end: usize, f: F) -> Option<usize>
+where
+    F: Fn(&Bitmap) -> u64,
+{
+    for (i, b) in bitmaps
+        .iter()
+        .enumerate()
+        .take(end.div_ceil(64))
+        .skip(begin / 64)
+    {
+        let mut bits = f(b);
+        if i == begin / 64 {
+            bits &= !0u64 << (begin % 64);
+        }
+        if bits != 0 {
+            let offset = i * 64 + bits.trailing_zeros() as usize;
+            return if offset < end { Some(offset) } else { None };
+        }
+    }
+    None
+}
+
+/// Return the last position of the set bits in `bitmap` between `begin` and `end`.
+#[inline]
+fn last_position(bitmap: &[u64], begin: usize, end: usize) -> Option<usize> {
+    for i in (begin / 64..end.div_ceil(64).min(bitmap.len())).rev() {
+        let mut bits = bitmap[i];
+        while bits != 0 {
+            let offset = i * 64 + 63 - bits.leading_zeros() as usize;
+            if offset < begin {
+                return None;
+            }
+            if offset < end {
+                return Some(offset);
+            }
+            bits &= !(1 << (offset % 64));
+        }
+    }
+    None
+}
+
+#[inline]
+fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+/// Skip the whitespace from `begin`, and return the position and the byte it stops on.
+#[inline]
+fn skip_whitespace(s: &[u8], mut begin: usize, end: usize) -> (usize, Option<u8>) {
     while begin < end {
         match s[begin] {
             b' ' | b'\t' | b'\r' | b'\n' => begin += 1,
-            _ => break,
+            c => return (begin, Some(c)),
         }
     }
+    (begin, None)
+}
+
+/// Trim the value `begin..end` followed by `delim`, and return it along with its first
+/// byte, or `None` if it is empty.
+#[inline]
+fn find_object_value(
+    s: &[u8],
+    begin: usize,
+    mut end: usize,
+    delim: u8,
+) -> (usize, usize, Option<u8>) {
+    let (begin, first) = skip_whitespace(s, begin, end);
 
     let mut seen_delim = false;
     while end > begin {
@@ -136,26 +1138,23 @@ fn find_object_value(s: &[u8], mut begin: usize, mut end: usize, delim: u8) -> (
         }
     }
 
-    (begin, end)
+    // the delimiter itself is the first byte of an empty value.
+    (begin, end, first.filter(|_| begin < end))
 }
 
+/// Same as `find_object_value`, but without a delimiter.
 #[inline]
-fn find_array_value(s: &[u8], mut begin: usize, mut end: usize) -> (usize, usize) {
-    while begin < end {
-        match s[begin] {
-            b' ' | b'\t' | b'\r' | b'\n' => begin += 1,
-            _ => break,
-        }
-    }
+fn find_array_value(s: &[u8], begin: usize, mut end: usize) -> (usize, usize, Option<u8>) {
+    let (begin, first) = skip_whitespace(s, begin, end);
 
-    while end >= begin {
+    while end > begin {
         match s[end - 1] {
             b' ' | b'\t' | b'\r' | b'\n' => end -= 1,
             _ => break,
         }
     }
 
-    (begin, end)
+    (begin, end, first)
 }
 
 #[cfg(test)]
@@ -169,7 +1168,7 @@ mod tests {
             begin: usize,
             end: usize,
             delim: u8,
-            expect: (usize, usize),
+            expect: (usize, usize, Option<u8>),
         }
         let tests = &[
             TestCase {
@@ -177,14 +1176,14 @@ mod tests {
                 begin: 6,
                 end: 10,
                 delim: b',',
-                expect: (7, 9),
+                expect: (7, 9, Some(b'{')),
             },
             TestCase {
                 input: br#"{ "a": {}, "b": [] } "#,
                 begin: 16,
                 end: 21,
                 delim: b'}',
-                expect: (16, 18),
+                expect: (16, 18, Some(b'[')),
             },
         ];
         for t in tests {
@@ -192,4 +1191,353 @@ mod tests {
             assert_eq!(actual, t.expect);
         }
     }
+
+    #[test]
+    fn object_fields() {
+        use crate::index_builder::backend::FallbackBackend;
+        use crate::index_builder::IndexBuilder;
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let record = r#"{"a": 1, "b" : [2, 3] ,"c":{"d": 4} }"#;
+        let index = index_builder.build(record).unwrap();
+        let fields = |fields: &mut dyn Iterator<Item = Result<ObjectField<'_>>>| {
+            fields
+                .map(|field| {
+                    let field = field.unwrap();
+                    let name = &record[field.fsi..field.fei()];
+                    (name, &record[field.vsi..field.vei], field.is_last)
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            ("a", "1", false),
+            ("b", "[2, 3]", false),
+            ("c", r#"{"d": 4}"#, true),
+        ];
+
+        let mut forward = index.object_fields(0, record.len(), 0).unwrap();
+        assert_eq!(fields(&mut forward), expected);
+
+        let mut cp = vec![];
+        index.colon_positions(0, record.len(), 0, &mut cp);
+        let mut backward = index.object_fields_at(0, record.len(), &cp).rev();
+        let mut reversed = expected.clone();
+        reversed.reverse();
+        assert_eq!(fields(&mut backward), reversed);
+
+        for front in 0..=cp.len() {
+            let mut both = index.object_fields_at(0, record.len(), &cp);
+            let mut actual = fields(&mut both.by_ref().take(front));
+            let mut back = fields(&mut both.rev());
+            back.reverse();
+            actual.extend(back);
+            assert_eq!(actual, expected, "{} fields from the front", front);
+        }
+        assert!(index.object_fields(0, record.len(), 2).is_none());
+    }
+
+    #[test]
+    fn typed_values() {
+        use crate::index_builder::backend::FallbackBackend;
+        use crate::index_builder::IndexBuilder;
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let record = r#"{"a": [ "x" , -1 ], "b":  null }"#;
+        let index = index_builder.build(record).unwrap();
+        assert_eq!(
+            index.find_object_value_typed(5, 18, false),
+            (6, 18, Some(JsonType::Array))
+        );
+        assert_eq!(
+            index.find_array_value_typed(7, 12),
+            (8, 11, Some(JsonType::String))
+        );
+        assert_eq!(
+            index.find_array_value_typed(13, 17),
+            (14, 16, Some(JsonType::Number))
+        );
+        assert_eq!(
+            index.find_object_value_typed(24, record.len(), true),
+            (26, 30, Some(JsonType::Null))
+        );
+        assert_eq!(index.find_object_value_typed(11, 13, false), (12, 12, None));
+        assert_eq!(index.find_array_value_typed(13, 14), (14, 14, None));
+    }
+
+    #[test]
+    fn test_select() {
+        let bitmap = &[0b1001, 1 << 63, 0b10];
+        assert_eq!(select(bitmap, 0, 192, 0), Some(0));
+        assert_eq!(select(bitmap, 0, 192, 1), Some(3));
+        assert_eq!(select(bitmap, 0, 192, 2), Some(127));
+        assert_eq!(select(bitmap, 0, 192, 3), Some(129));
+        assert_eq!(select(bitmap, 0, 192, 4), None);
+        assert_eq!(select(bitmap, 1, 192, 0), Some(3));
+        assert_eq!(select(bitmap, 4, 129, 1), None);
+    }
+
+    #[test]
+    fn test_select_with_rank() {
+        // a simple LCG to generate sparse bitmaps
+        let mut x = 12345u64;
+        let mut bitmap = vec![];
+        for _ in 0..16 {
+            let mut bits = 0u64;
+            for _ in 0..4 {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                bits |= 1 << (x >> 58);
+            }
+            bitmap.push(if bitmap.len() % 5 == 3 { 0 } else { bits });
+        }
+        let mut rank = vec![0u32];
+        for bits in &bitmap {
+            rank.push(rank.last().unwrap() + bits.count_ones());
+        }
+
+        let len = bitmap.len() * 64;
+        for &(begin, end) in &[(0, len), (1, len), (70, 500), (200, 201), (len - 3, len)] {
+            for n in 0..70 {
+                assert_eq!(
+                    select_with_rank(&bitmap, &rank, begin, end, n),
+                    select(&bitmap, begin, end, n),
+                    "begin={}, end={}, n={}",
+                    begin,
+                    end,
+                    n
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_last_position() {
+        let bitmap = &[0b1001, 1 << 63, 0b10];
+        assert_eq!(last_position(bitmap, 0, 192), Some(129));
+        assert_eq!(last_position(bitmap, 0, 129), Some(127));
+        assert_eq!(last_position(bitmap, 0, 127), Some(3));
+        assert_eq!(last_position(bitmap, 1, 3), None);
+        assert_eq!(last_position(bitmap, 0, 1), Some(0));
+    }
+
+    #[test]
+    fn test_location() {
+        use super::super::backend::{Backend, FallbackBackend};
+
+        let input = format!("{{\n  \"a\": 1,\n{}\n\"b\": 2 }}", " ".repeat(70));
+        let bytes = input.as_bytes();
+        let backend = FallbackBackend::default();
+        let bitmaps = vec![
+            backend.create_full_bitmap(bytes, 0),
+            backend.create_partial_bitmap(bytes, 64),
+        ];
+        let loc = |offset| {
+            let l = location(&bitmaps, offset);
+            (l.line, l.column)
+        };
+        assert_eq!(loc(0), (1, 1));
+        assert_eq!(loc(1), (1, 2));
+        assert_eq!(loc(2), (2, 1));
+        assert_eq!(loc(input.find('1').unwrap()), (2, 8));
+        assert_eq!(loc(input.find('b').unwrap()), (4, 2));
+        assert_eq!(loc(input.len() - 1), (4, 8));
+    }
+
+    #[test]
+    fn test_generate_positions() {
+        let bitmap = [0x8000_0000_0000_0001, 0, 0x0000_0001_0001_0000, !0];
+        let naive = |begin: usize, end: usize| -> Vec<usize> {
+            (begin..end)
+                .filter(|&i| bitmap.get(i / 64).is_some_and(|w| w >> (i % 64) & 1 == 1))
+                .collect()
+        };
+        let mut cp = vec![];
+        for &(begin, end) in &[
+            (0, 256),
+            (0, 64),
+            (1, 63),
+            (63, 65),
+            (64, 128),
+            (128, 145),
+            (144, 300),
+            (10, 10),
+            (20, 5),
+        ] {
+            cp.clear();
+            generate_positions(&bitmap, begin, end, &mut cp);
+            assert_eq!(cp, naive(begin, end), "{}..{}", begin, end);
+        }
+    }
+
+    #[test]
+    fn test_field_count() {
+        use super::super::backend::FallbackBackend;
+        use super::super::IndexBuilder;
+
+        let long = "x".repeat(100);
+        let record = format!(
+            r#"{{"a": "{}:", "b": {{"c": 1, "d": 2}}, "e": "{}"}}"#,
+            long, long
+        );
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let index = index_builder.build(&record).unwrap();
+        let mut cp = vec![];
+        for &(begin, end, level) in &[
+            (0, record.len(), 0),
+            (0, record.len(), 1),
+            (120, 140, 1),
+            (5, 5, 0),
+        ] {
+            index.colon_positions(begin, end, level, &mut cp);
+            assert_eq!(index.field_count(begin, end, level), Some(cp.len()));
+            assert_eq!(
+                index.colons(begin, end, level).unwrap().collect::<Vec<_>>(),
+                cp
+            );
+        }
+        assert_eq!(index.field_count(0, record.len(), 0), Some(3));
+        assert_eq!(index.field_count(0, record.len(), 2), None);
+    }
+
+    #[test]
+    fn test_find_object_field() {
+        use super::super::backend::FallbackBackend;
+        use super::super::IndexBuilder;
+
+        // field names across the boundaries of the words
+        let long = "x".repeat(100);
+        let record = format!(r#"{{"{}": "a:\"b", "c": 1, "{}": 2}}"#, long, long);
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let index = index_builder.build(&record).unwrap();
+        let mut cp = vec![];
+        index.colon_positions(0, record.len(), 0, &mut cp);
+        assert_eq!(cp.len(), 3);
+
+        let (field, si) = index.find_object_field(0, cp[0]).unwrap();
+        assert_eq!((field.as_raw_str(), si), (&*long, 2));
+        let (field, _) = index.find_object_field(cp[0], cp[1]).unwrap();
+        assert_eq!(field.as_raw_str(), "c");
+        let (field, si) = index.find_object_field(cp[1], cp[2]).unwrap();
+        assert_eq!((field.as_raw_str(), si), (&*long, cp[2] - 101));
+
+        // the quotes before `begin` are not used.
+        assert!(index.find_object_field(cp[1] - 2, cp[1]).is_err());
+        assert!(index.find_object_field(cp[1], cp[1]).is_err());
+    }
+
+    #[test]
+    fn test_tokens() {
+        use super::super::backend::FallbackBackend;
+        use super::super::IndexBuilder;
+
+        let record = format!(
+            r#"{{ "a": [1, "x:,}}", -2.5e3 ], "{}": {{}}, "c": true }}"#,
+            "b".repeat(70)
+        );
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let index = index_builder.build(&record).unwrap();
+        let text = |t| match t {
+            Token::Key(si, ei) => format!("key:{}", &record[si..ei]),
+            Token::Value(si, ei) => format!("value:{}", &record[si..ei]),
+            Token::ObjectStart(i)
+            | Token::ObjectEnd(i)
+            | Token::ArrayStart(i)
+            | Token::ArrayEnd(i)
+            | Token::Colon(i)
+            | Token::Comma(i) => record[i..i + 1].to_owned(),
+        };
+        let tokens: Vec<String> = index.tokens(0, record.len()).map(text).collect();
+        let b = format!("key:{}", "b".repeat(70));
+        assert_eq!(
+            tokens,
+            [
+                "{",
+                "key:a",
+                ":",
+                "[",
+                "value:1",
+                ",",
+                r#"value:"x:,}""#,
+                ",",
+                "value:-2.5e3",
+                "]",
+                ",",
+                &b,
+                ":",
+                "{",
+                "}",
+                ",",
+                "key:c",
+                ":",
+                "value:true",
+                "}",
+            ]
+        );
+
+        // a sub-range of the record
+        let begin = record.find('[').unwrap();
+        let tokens: Vec<String> = index.tokens(begin + 1, begin + 6).map(text).collect();
+        assert_eq!(tokens, ["value:1", ",", r#"value:"x"#]);
+    }
+
+    #[test]
+    fn test_dump() {
+        use super::super::backend::FallbackBackend;
+        use super::super::IndexBuilder;
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let index = index_builder
+            .build("{\"a\": {\"b\": 1, \"c\": 2},\n \"d\": \"\u{e9}:\"}")
+            .unwrap();
+        assert_eq!(
+            index.dump(),
+            concat!(
+                "       0 | {\"a\": {\"b\": 1, \"c\": 2},. \"d\": \"..:\"}\n",
+                " level 0 |     :                 ,     :\n",
+                " level 1 |           :  ,    :\n",
+            )
+        );
+        drop(index);
+
+        let record = format!("[{}]", "1,".repeat(40));
+        let index = index_builder.build(&record).unwrap();
+        let dump = index.dump();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[3], format!("      64 | ,{}]", "1,".repeat(8)));
+        assert_eq!(lines[4], format!(" level 0 | ,{}", " ,".repeat(8)));
+    }
+
+    #[test]
+    fn test_array_chunks() {
+        use super::super::backend::FallbackBackend;
+        use super::super::IndexBuilder;
+
+        let elements: Vec<String> = (0..100).map(|i| format!("[{}, \",\"]", i)).collect();
+        let record = format!(r#"{{"a": [ {} ], "b": [ ]}}"#, elements.join(" ,\n"));
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let index = index_builder.build(&record).unwrap();
+        let begin = record.find('[').unwrap();
+        let end = record.rfind("],").unwrap() + 1;
+
+        let chunks: Vec<&str> = index
+            .array_chunks(begin, end, 1, 30)
+            .unwrap()
+            .map(|(b, e)| &record[b..e])
+            .collect();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0], elements[..30].join(" ,\n"));
+        assert_eq!(chunks[3], elements[90..].join(" ,\n"));
+        let single = index.array_chunks(begin, end, 1, 1).unwrap();
+        assert_eq!(single.count(), 100);
+        let whole = index.array_chunks(begin, end, 1, 1000).unwrap();
+        assert_eq!(whole.count(), 1);
+
+        let begin = record.rfind('[').unwrap();
+        let mut empty = index.array_chunks(begin, begin + 3, 1, 10).unwrap();
+        assert!(empty.next().is_none());
+        assert!(index.array_chunks(0, record.len(), 0, 10).is_err());
+        assert!(index.array_chunks(begin, begin + 3, 2, 10).is_err());
+    }
 }
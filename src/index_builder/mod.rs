@@ -4,5 +4,7 @@ pub mod backend;
 mod builder;
 mod index;
 
-pub use self::builder::IndexBuilder;
-pub use self::index::StructuralIndex;
+pub use self::builder::{
+    BufferPolicy, IndexBuffers, IndexBuilder, MemoryUsage, DEFAULT_MAX_DEPTH, PARALLEL_CHUNK_LEN,
+};
+pub use self::index::{ArrayChunks, Location, StructuralIndex, Token, Tokens, ValueTrimming};
@@ -7,14 +7,37 @@
     unsafe_code,
 )]
 
-#[macro_use]
-extern crate error_chain;
-
+pub mod aggregate;
+#[cfg(feature = "base64")]
+pub mod base64;
 pub mod bit;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod document;
+#[cfg(feature = "encode")]
+pub mod encode;
 pub mod errors;
+pub mod events;
+pub mod expr;
 pub mod index_builder;
 pub mod parser;
+pub mod patch;
 pub mod pattern_tree;
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
+pub mod presence;
 pub mod query;
 pub mod query_parser;
+pub mod record;
+pub mod router;
+mod scan;
+pub mod shard;
+pub mod stream;
+pub mod tape;
+pub mod testdata;
+#[cfg(feature = "chrono")]
+pub mod timestamp;
+pub mod typed;
+pub mod unnest;
+pub mod validate;
 pub mod value;
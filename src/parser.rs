@@ -1,38 +1,263 @@
 #![allow(missing_docs)]
 
-use crate::errors::Result;
+use crate::document::{self, Document};
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
 use crate::index_builder::backend::Backend;
-use crate::index_builder::{IndexBuilder, StructuralIndex};
+use crate::index_builder::{IndexBuffers, IndexBuilder, StructuralIndex};
+use crate::scan;
+use crate::tape::{self, CompactValue};
+use crate::typed::JsonType;
+use crate::validate;
 use crate::value::{self, Value, ValueType};
 use std::ptr;
 
-#[derive(Debug)]
+/// How to parse the arrays and objects nested deeper than the level of the index
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RawPolicy {
+    /// return them as `Value::Raw`
+    #[default]
+    ReturnRaw,
+    /// parse them from an index with a level for each nesting level of the record
+    ParseRecursively,
+    /// fail with `ErrorKind::DepthLimitExceeded`
+    Error,
+}
+
+/// A length of records below which scanning the bytes directly is usually faster than
+/// building the index, for `small_record_len` of `Parser` and `QueryParser`
+///
+/// The fast path is disabled in both parsers by default. Neither the scan nor the index
+/// validates the contents of strings, and a string with an unescaped quote (e.g.
+/// `["x"\"y"]`) may be rejected by one and accepted by the other, so enabling it by
+/// default would make whether such a record is rejected depend on its length.
+pub const DEFAULT_SMALL_RECORD_LEN: usize = 256;
+
+#[derive(Debug, Clone)]
 pub struct Parser<B: Backend> {
     index_builder: IndexBuilder<B>,
+    raw_policy: RawPolicy,
+    lenient_numbers: bool,
+    small_record_len: usize,
+    strict: bool,
 }
 
 impl<B: Backend> Parser<B> {
     pub fn new(index_builder: IndexBuilder<B>) -> Self {
-        Self { index_builder }
+        Self {
+            index_builder,
+            raw_policy: RawPolicy::default(),
+            lenient_numbers: false,
+            small_record_len: 0,
+            strict: false,
+        }
+    }
+
+    /// Validate the whole record with `validate::validate` before parsing it, so that
+    /// the malformed records are rejected even if they are not looked at by the index
+    /// (including the values beyond the level of the index builder).
+    pub fn strict(&mut self, v: bool) {
+        self.strict = v;
+    }
+
+    fn check(&self, record: &str) -> Result<()> {
+        if self.strict {
+            validate::validate(record)?;
+        }
+        Ok(())
+    }
+
+    /// Set the length of records below which the bytes are scanned directly, without
+    /// building the structural index (e.g. `DEFAULT_SMALL_RECORD_LEN`). `0`, the
+    /// default, disables the fast path.
+    ///
+    /// The well-formed records are parsed into the same values in both ways, including
+    /// the values beyond the level of the index builder, and the nesting depth is limited
+    /// by `IndexBuilder::max_depth` in both. The malformed strings (see `parse`) are not
+    /// always rejected in the same way, so whether such a record is rejected depends on
+    /// its length while this is enabled. This is why the fast path is opt-in; use
+    /// `strict` to reject them regardless of the length.
+    pub fn small_record_len(&mut self, len: usize) {
+        self.small_record_len = len;
     }
 
+    /// Set how to parse the values nested deeper than the level of the index builder.
+    pub fn raw_policy(&mut self, policy: RawPolicy) {
+        self.raw_policy = policy;
+    }
+
+    /// Accept `NaN`, `Infinity` and hexadecimal integers as numbers, which some
+    /// producers emit. Disabled by default. See `value::parse_number`.
+    pub fn lenient_numbers(&mut self, v: bool) {
+        self.lenient_numbers = v;
+    }
+
+    /// Parse a record into a value.
+    ///
+    /// A scalar at the root (e.g. `"just a string"` or `42`) is parsed without building
+    /// the index, and must be a single value surrounded only by whitespace.
+    ///
+    /// The contents of strings (e.g. escape sequences, control characters and unescaped
+    /// quotes) are not validated until the strings are unescaped, so records such as
+    /// `["\x"]` are accepted unless `strict` is set.
     pub fn parse<'s>(&self, record: &'s str) -> Result<Value<'s>> {
+        self.check(record)?;
+        let record = record.trim();
+        if is_scalar(record) {
+            return parse_scalar(record, self.lenient_numbers);
+        }
+        if record.len() < self.small_record_len {
+            return self.parse_small(record, 0, record.len(), 0, 0, self.raw_policy);
+        }
+        let mut buffers = IndexBuffers::default();
+        let index = self.build_index(record, &mut buffers)?;
+        self.parse_impl(&index, 0, record.len(), 0, self.raw_policy)
+    }
+
+    /// Build the index of `record`, covering all its levels for
+    /// `RawPolicy::ParseRecursively` (into `buffers` if it is deeper than the levels of
+    /// the index builder).
+    fn build_index<'a, 's>(
+        &'a self,
+        record: &'s str,
+        buffers: &'a mut IndexBuffers,
+    ) -> Result<StructuralIndex<'a, 's>> {
+        match self.raw_policy {
+            RawPolicy::ParseRecursively => self.index_builder.build_all_levels(record, buffers),
+            _ => self.index_builder.build(record),
+        }
+    }
+
+    /// Parse the value `record[begin..end]` nested in `depth` arrays or objects by
+    /// scanning its bytes directly.
+    ///
+    /// The nesting depth is limited by `IndexBuilder::max_depth` as in the index.
+    fn parse_small<'s>(
+        &self,
+        record: &'s str,
+        begin: usize,
+        end: usize,
+        mut level: usize,
+        depth: usize,
+        policy: RawPolicy,
+    ) -> Result<Value<'s>> {
+        let ty = value::parse_with(&record[begin..end], self.lenient_numbers)?;
+        if let ValueType::Atomic(v) = ty {
+            return Ok(v);
+        }
+        let mut members = vec![];
+        let inner_depth = scan::members(record, begin, end, &mut members)?;
+        let max_depth = self.index_builder.depth_limit();
+        if depth + 1 + inner_depth > max_depth {
+            return Err(ErrorKind::DepthLimitExceeded(max_depth).into());
+        }
+
+        if level >= self.index_builder.level() {
+            match policy {
+                RawPolicy::ReturnRaw => return Ok(Value::raw(&record[begin..end])),
+                RawPolicy::Error => {
+                    return Err(ErrorKind::DepthLimitExceeded(self.index_builder.level()).into())
+                }
+                RawPolicy::ParseRecursively => level = 0,
+            }
+        }
+        match ty {
+            ValueType::Array => members
+                .into_iter()
+                .map(|(vsi, vei)| self.parse_small(record, vsi, vei, level + 1, depth + 1, policy))
+                .collect::<Result<_>>()
+                .map(Value::Array),
+            _ => members
+                .into_iter()
+                .map(|(fsi, fei)| {
+                    let (field, vsi) = scan::split_field(record, fsi, fei)?;
+                    let value = self.parse_small(record, vsi, fei, level + 1, depth + 1, policy)?;
+                    Ok((field, value))
+                })
+                .collect::<Result<_>>()
+                .map(Value::Object),
+        }
+    }
+
+    /// Same as `parse`, but the record is stored into a flat `CompactValue`.
+    pub fn parse_compact<'s>(&self, record: &'s str) -> Result<CompactValue<'s>> {
+        self.check(record)?;
         let record = record.trim();
-        let index = self.index_builder.build(record)?;
-        self.parse_impl(&index, 0, record.len(), 0)
+        if is_scalar(record) {
+            parse_scalar(record, self.lenient_numbers)?;
+        }
+        tape::build(
+            &self.index_builder,
+            record,
+            self.raw_policy,
+            self.lenient_numbers,
+        )
     }
 
-    #[allow(unsafe_code)]
+    /// Same as `parse`, but the spans of the values are recorded so that the record can
+    /// be edited and serialized without changing the other bytes.
+    pub fn parse_document<'s>(&self, record: &'s str) -> Result<Document<'s>> {
+        self.check(record)?;
+        let trimmed = record.trim();
+        let base = record.len() - record.trim_start().len();
+        let mut buffers = IndexBuffers::default();
+        let index = self.build_index(trimmed, &mut buffers)?;
+        let value = if is_scalar(trimmed) {
+            parse_scalar(trimmed, self.lenient_numbers)?
+        } else {
+            self.parse_impl(&index, 0, trimmed.len(), 0, self.raw_policy)?
+        };
+        let span = document::spans(
+            &self.index_builder,
+            &index,
+            0,
+            trimmed.len(),
+            0,
+            base,
+            self.raw_policy,
+        )?;
+        Ok(Document::new(record, value, span))
+    }
+
+    /// Parse the raw values in `value` fully, building a new index of each of them
+    /// (see `RawPolicy::ParseRecursively`).
+    ///
+    /// The other values are returned as they are, so this can be applied to (a part
+    /// of) the result of `parse` when the values beyond the level are occasionally
+    /// needed.
+    pub fn parse_value<'v>(&self, value: &'v Value<'_>) -> Result<Value<'v>> {
+        match *value {
+            Value::Raw(ref raw) => {
+                let raw = raw.trim();
+                let mut buffers = IndexBuffers::default();
+                let index = self.index_builder.build_all_levels(raw, &mut buffers)?;
+                self.parse_impl(&index, 0, raw.len(), 0, RawPolicy::ParseRecursively)
+            }
+            Value::Array(ref elems) => elems
+                .iter()
+                .map(|elem| self.parse_value(elem))
+                .collect::<Result<_>>()
+                .map(Value::Array),
+            Value::Object(ref fields) => fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), self.parse_value(value)?)))
+                .collect::<Result<_>>()
+                .map(Value::Object),
+            ref value => Ok(value.clone()),
+        }
+    }
+
+    #[allow(unsafe_code, clippy::uninit_vec)]
     fn parse_array<'a, 's>(
         &self,
         index: &StructuralIndex<'a, 's>,
         begin: usize,
         end: usize,
         level: usize,
+        policy: RawPolicy,
     ) -> Result<Value<'s>> {
         let mut cp = Vec::new();
         if !index.comma_positions(begin, end, level, &mut cp) {
-            return Ok(Value::raw(index.substr(begin, end)));
+            return self.parse_beyond_levels(index.substr(begin, end), policy);
         };
         cp.push(end - 1); // dummy
 
@@ -42,21 +267,20 @@ impl<B: Backend> Parser<B> {
         }
 
         for i in 0..cp.len() {
-            let (vsi, vei) =
-                index.find_array_value(if i == 0 { begin + 1 } else { cp[i - 1] + 1 }, cp[i]);
-            if i == 0 && vsi == vei {
+            let (vsi, vei, ty) =
+                index.find_array_value_typed(if i == 0 { begin + 1 } else { cp[i - 1] + 1 }, cp[i]);
+            if i == 0 && vsi == vei && cp.len() == 1 {
                 unsafe {
                     // ensure not to call destructors of `uninitialized` elements.
                     result.set_len(0);
                 }
                 return Ok(Value::Array(result));
             }
-            let value = self.parse_impl(index, vsi, vei, level + 1).map_err(|e| {
-                unsafe {
+            let value = self
+                .parse_typed(index, vsi, vei, ty, level + 1, policy)
+                .inspect_err(|_| unsafe {
                     result.set_len(i);
-                }
-                e
-            })?;
+                })?;
 
             unsafe {
                 ptr::write(result.get_unchecked_mut(i), value);
@@ -66,64 +290,46 @@ impl<B: Backend> Parser<B> {
         Ok(Value::Array(result))
     }
 
-    #[allow(unsafe_code)]
     fn parse_object<'a, 's>(
         &self,
         index: &StructuralIndex<'a, 's>,
         begin: usize,
-        mut end: usize,
+        end: usize,
         level: usize,
+        policy: RawPolicy,
     ) -> Result<Value<'s>> {
-        let mut cp = Vec::new();
-        if !index.colon_positions(begin, end, level, &mut cp) {
-            return Ok(Value::raw(index.substr(begin, end)));
-        }
+        let (count, fields) = match (
+            index.field_count(begin, end, level),
+            index.object_fields_strict(begin, end, level),
+        ) {
+            (Some(count), Some(fields)) => (count, fields),
+            _ => return self.parse_beyond_levels(index.substr(begin, end), policy),
+        };
 
-        let mut result = Vec::with_capacity(cp.len());
-        unsafe {
-            result.set_len(cp.len());
+        let mut result = Vec::with_capacity(count);
+        for field in fields {
+            let field = field?;
+            let value =
+                self.parse_typed(index, field.vsi, field.vei, field.ty, level + 1, policy)?;
+            result.push((field.name, value));
         }
 
-        let mut err = Ok(());
-        for i in (0..cp.len()).rev() {
-            let (field, fsi) =
-                match index.find_object_field(if i == 0 { begin } else { cp[i - 1] }, cp[i]) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        err = Err((i, e));
-                        break;
-                    }
-                };
-
-            let (vsi, vei) = index.find_object_value(cp[i] + 1, end, i == cp.len() - 1);
-            let value = match self.parse_impl(index, vsi, vei, level + 1) {
-                Ok(v) => v,
-                Err(e) => {
-                    err = Err((i, e));
-                    break;
-                }
-            };
-
-            unsafe {
-                ptr::write(result.get_unchecked_mut(i), (field, value));
-            }
-
-            end = fsi - 1;
-        }
+        Ok(Value::Object(result))
+    }
 
-        if let Err((i, e)) = err {
-            unsafe {
-                for j in i + 1..cp.len() {
-                    // call destructors of `initialized` elements.
-                    ptr::drop_in_place(result.get_unchecked_mut(j));
-                }
-                // ensure not to call destructors of `uninitialized` elements
-                result.set_len(0);
+    /// Parse an array or object which is not covered by the levels of `index`.
+    fn parse_beyond_levels<'s>(&self, value: &'s str, policy: RawPolicy) -> Result<Value<'s>> {
+        match policy {
+            RawPolicy::ReturnRaw => return Ok(Value::raw(value)),
+            RawPolicy::Error => {
+                return Err(ErrorKind::DepthLimitExceeded(self.index_builder.level()).into())
             }
-            return Err(e);
+            RawPolicy::ParseRecursively => {}
         }
-
-        Ok(Value::Object(result))
+        // Not reached from the indexes built by `build_index`, which cover all levels.
+        let mut buffers = IndexBuffers::default();
+        let index = self.index_builder.build_all_levels(value, &mut buffers)?;
+        self.parse_impl(&index, 0, value.len(), 0, policy)
     }
 
     #[inline]
@@ -133,19 +339,83 @@ impl<B: Backend> Parser<B> {
         begin: usize,
         end: usize,
         level: usize,
+        policy: RawPolicy,
+    ) -> Result<Value<'s>> {
+        let ty = JsonType::of(index.substr(begin, end));
+        self.parse_typed(index, begin, end, ty, level, policy)
+    }
+
+    /// Parse the trimmed value `begin..end` of the type `ty` found along with its
+    /// boundary, so that the value is not trimmed and classified again.
+    #[inline]
+    fn parse_typed<'a, 's>(
+        &self,
+        index: &StructuralIndex<'a, 's>,
+        begin: usize,
+        end: usize,
+        ty: Option<JsonType>,
+        level: usize,
+        policy: RawPolicy,
     ) -> Result<Value<'s>> {
-        match value::parse(&index.substr(begin, end))? {
+        match value::parse_typed(index.substr(begin, end), ty, self.lenient_numbers)? {
             ValueType::Atomic(v) => Ok(v),
-            ValueType::Array => self.parse_array(index, begin, end, level),
-            ValueType::Object => self.parse_object(index, begin, end, level),
+            ValueType::Array => self.parse_array(index, begin, end, level, policy),
+            ValueType::Object => self.parse_object(index, begin, end, level, policy),
+        }
+    }
+}
+
+/// Return whether the trimmed record is neither an array nor an object.
+fn is_scalar(record: &str) -> bool {
+    !matches!(record.as_bytes().first(), Some(b'[') | Some(b'{'))
+}
+
+/// Parse a scalar at the root of a record.
+///
+/// Unlike the values within arrays and objects, whose boundaries are found from the
+/// index, nothing but the record delimits a root string, so it is checked that the
+/// closing quote is the first unescaped one (e.g. `"a" "b"` is rejected).
+fn parse_scalar(record: &str, lenient: bool) -> Result<Value<'_>> {
+    if record.is_empty() {
+        return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "empty record");
+    }
+    if record.starts_with('"') {
+        let mut escaped = false;
+        let close = record.bytes().enumerate().skip(1).find(|&(_, c)| match c {
+            _ if escaped => {
+                escaped = false;
+                false
+            }
+            b'\\' => {
+                escaped = true;
+                false
+            }
+            c => c == b'"',
+        });
+        match close {
+            Some((i, _)) if i == record.len() - 1 => {}
+            Some((i, _)) => {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| format!("unexpected characters after the string at {}", i + 1))
+            }
+            None => {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| "unterminated string")
+            }
         }
     }
+    match value::parse_with(record, lenient)? {
+        ValueType::Atomic(v) => Ok(v),
+        _ => unreachable!("not a scalar"),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::index_builder::backend::FallbackBackend;
     use super::*;
+    use crate::index_builder::DEFAULT_MAX_DEPTH;
+    use crate::value::ValueExt;
 
     #[test]
     fn basic_parsing() {
@@ -212,4 +482,240 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn extended_levels() {
+        let record = r#"{ "f1": { "e1": [ { "c1": [1, {}] } ] }, "f2": [] }"#;
+
+        let backend = FallbackBackend::default();
+        let index_builder = IndexBuilder::new(backend, 1);
+        let mut parser = Parser::new(index_builder);
+        parser.raw_policy(RawPolicy::ParseRecursively);
+
+        let result = parser.parse(record).unwrap();
+        assert_eq!(
+            result,
+            crate::object! {
+                "f1" => crate::object!{
+                    "e1" => crate::array![
+                        crate::object!{
+                            "c1" => crate::array![1.0, Value::Object(vec![]),],
+                        },
+                    ],
+                },
+                "f2" => crate::array![],
+            }
+        );
+    }
+
+    #[test]
+    fn raw_policy_error() {
+        let backend = FallbackBackend::default();
+        let index_builder = IndexBuilder::new(backend, 1);
+        let mut parser = Parser::new(index_builder);
+        parser.raw_policy(RawPolicy::Error);
+
+        assert!(parser.parse(r#"{ "f1": 1, "f2": "{}" }"#).is_ok());
+        match parser.parse(r#"{ "f1": 1, "f2": {} }"#) {
+            Err(ref e) if matches!(e.kind(), ErrorKind::DepthLimitExceeded(1)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_raw_values() {
+        let record = r#"{ "f1": { "e1": [ { "c1": [1] } ] }, "f2": 2 }"#;
+
+        let backend = FallbackBackend::default();
+        let index_builder = IndexBuilder::new(backend, 1);
+        let parser = Parser::new(index_builder);
+
+        let result = parser.parse(record).unwrap();
+        assert_eq!(
+            result,
+            crate::object! {
+                "f1" => Value::raw(r#"{ "e1": [ { "c1": [1] } ] }"#),
+                "f2" => 2.0,
+            }
+        );
+        let expected = crate::object! {
+            "f1" => crate::object!{
+                "e1" => crate::array![
+                    crate::object!{
+                        "c1" => crate::array![1.0,],
+                    },
+                ],
+            },
+            "f2" => 2.0,
+        };
+        assert_eq!(parser.parse_value(&result).unwrap(), expected);
+
+        let owned = Value::raw(format!(" {} ", record));
+        assert_eq!(parser.parse_value(&owned).unwrap(), expected);
+        assert!(parser.parse_value(&Value::raw("{")).is_err());
+    }
+
+    #[test]
+    fn lenient_numbers() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let mut parser = Parser::new(index_builder);
+        let record = r#"{ "a": [NaN, -0x10, 1] }"#;
+        assert!(parser.parse(record).is_err());
+
+        parser.lenient_numbers(true);
+        let result = parser.parse(record).unwrap();
+        assert!(matches!(result.path("a[0]"), Some(&Value::Number(n)) if n.is_nan()));
+        assert_eq!(result.path("a[1]"), Some(&Value::Number(-16.0)));
+        let tape = parser.parse_compact(record).unwrap();
+        assert_eq!(
+            tape.root().get("a").unwrap().at(1).unwrap().as_f64(),
+            Some(-16.0)
+        );
+    }
+
+    #[test]
+    fn strict_parsing() {
+        let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 1));
+        let records = [r#"{"a": [1 2]}"#, "{\"a\": \"\t\"}", "\u{c}{}"];
+        for record in &records {
+            assert!(parser.parse(record).is_ok(), "{:?}", record);
+        }
+
+        parser.strict(true);
+        for record in &records {
+            assert!(parser.parse(record).is_err(), "{:?}", record);
+            assert!(parser.parse_compact(record).is_err(), "{:?}", record);
+        }
+        assert_eq!(
+            parser.parse(" {\"a\": [1, 2]}\n").unwrap(),
+            crate::object! { "a" => Value::raw("[1, 2]"), }
+        );
+        assert_eq!(parser.parse("-0").unwrap(), Value::Number(-0.0));
+    }
+
+    #[test]
+    fn scalar_roots() {
+        let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 1));
+        let long = format!("\"{}\"", "x".repeat(1000));
+        let cases = [
+            (" \"just a string\"\n", Value::from("just a string")),
+            (r#""a\"b\\""#, Value::String(r#"a\"b\\"#.into())),
+            ("42", Value::Number(42.0)),
+            ("\t-1.5e3 ", Value::Number(-1500.0)),
+            ("true", Value::Boolean(true)),
+            ("null", Value::Null),
+            (&long, Value::String(long[1..long.len() - 1].into())),
+        ];
+        for &(record, ref expected) in &cases {
+            assert_eq!(parser.parse(record).unwrap(), *expected, "{:?}", record);
+            assert_eq!(
+                parser.parse_document(record).unwrap().to_string(),
+                record,
+                "{:?}",
+                record
+            );
+            parser.parse_compact(record).unwrap();
+        }
+
+        let invalid = [
+            "",
+            " \n",
+            r#""a" "b""#,
+            r#""a\""#,
+            r#"""#,
+            "42 43",
+            "nul",
+            "NaN",
+        ];
+        for record in &invalid {
+            assert!(parser.parse(record).is_err(), "{:?}", record);
+            assert!(parser.parse_compact(record).is_err(), "{:?}", record);
+            assert!(parser.parse_document(record).is_err(), "{:?}", record);
+        }
+
+        parser.lenient_numbers(true);
+        assert!(matches!(parser.parse("NaN").unwrap(), Value::Number(n) if n.is_nan()));
+        parser.strict(true);
+        assert!(parser.parse("NaN").is_err());
+        assert!(parser.parse("\"\t\"").is_err());
+        assert_eq!(parser.parse(" 0 ").unwrap(), Value::Number(0.0));
+    }
+
+    #[test]
+    fn small_records() {
+        let records = [
+            r#"{ "f1": { "e1": [ { "c1": [1, {}] } ] }, "f2": [], "f3": "a\"}" }"#,
+            r#"[ {"a": 1}, [[]], "x", null ]"#,
+            r#" "str" "#,
+            r#"{ "a": [1, "#,
+            r#"{ "a": 1, }"#,
+            r#"{"a":1 "b":2}"#,
+            r#"{"a" 1}"#,
+            r#"{"a"b: 1}"#,
+            r#"{,"a": 1}"#,
+            r#"[,1]"#,
+        ];
+        for &policy in &[
+            RawPolicy::ReturnRaw,
+            RawPolicy::ParseRecursively,
+            RawPolicy::Error,
+        ] {
+            let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+            parser.raw_policy(policy);
+            let indexed = parser.clone();
+            parser.small_record_len(DEFAULT_SMALL_RECORD_LEN);
+            for record in &records {
+                match (parser.parse(record), indexed.parse(record)) {
+                    (Ok(v1), Ok(v2)) => assert_eq!(v1, v2),
+                    (r1, r2) => assert!(r1.is_err() && r2.is_err(), "{}", record),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn small_records_depth() {
+        let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        parser.raw_policy(RawPolicy::ParseRecursively);
+        let indexed = parser.clone();
+        parser.small_record_len(1 << 22);
+
+        let deep = format!("{}{}", "[".repeat(200_000), "]".repeat(200_000));
+        for parser in &[&parser, &indexed] {
+            match parser.parse(&deep).unwrap_err().kind() {
+                ErrorKind::DepthLimitExceeded(DEFAULT_MAX_DEPTH) => {}
+                kind => panic!("unexpected error: {}", kind),
+            }
+        }
+
+        let mut index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        index_builder.max_depth(64);
+        let mut parser = Parser::new(index_builder);
+        parser.raw_policy(RawPolicy::ParseRecursively);
+        let indexed = parser.clone();
+        parser.small_record_len(1 << 22);
+        let limit = format!("{}{}", "[".repeat(64), "]".repeat(64));
+        assert_eq!(
+            parser.parse(&limit).unwrap(),
+            indexed.parse(&limit).unwrap()
+        );
+        let over = format!("[{}]", limit);
+        for parser in &[&parser, &indexed] {
+            match parser.parse(&over).unwrap_err().kind() {
+                ErrorKind::DepthLimitExceeded(64) => {}
+                kind => panic!("unexpected error: {}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn empty_arrays() {
+        let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        parser.small_record_len(0);
+        assert_eq!(parser.parse("[ ]").unwrap(), Value::Array(vec![]));
+        assert_eq!(
+            parser.parse(r#"{"a": [ ] }"#).unwrap(),
+            Value::Object(vec![("a".into(), Value::Array(vec![]))])
+        );
+    }
 }
@@ -0,0 +1,274 @@
+//! Application of JSON Patch (RFC 6902) documents to parsed values
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::value::pointer::{parse_index, tokens};
+use crate::value::{EscapedStr, Value, ValueExt};
+
+/// A JSON Patch operation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation<'a> {
+    Add { path: String, value: Value<'a> },
+    Remove { path: String },
+    Replace { path: String, value: Value<'a> },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value<'a> },
+}
+
+impl<'a> Operation<'a> {
+    /// Convert an element of a patch document into an operation.
+    pub fn from_value(op: &Value<'a>) -> Result<Self> {
+        let string = |field: &str| -> Result<String> {
+            match op.path(field) {
+                Some(Value::String(ref s)) => Ok(s.unescape()?.into_owned()),
+                _ => Err(Error::from(ErrorKind::InvalidPatch))
+                    .chain_err(|| format!("missing string field {:?}", field)),
+            }
+        };
+        let value = || -> Result<Value<'a>> {
+            match *op {
+                Value::Object(ref obj) => obj
+                    .iter()
+                    .find(|(k, _)| k.as_raw_str() == "value")
+                    .map(|(_, v)| v.clone())
+                    .ok_or_else(|| Error::from(ErrorKind::InvalidPatch))
+                    .chain_err(|| "missing field \"value\""),
+                _ => Err(ErrorKind::InvalidPatch.into()),
+            }
+        };
+
+        let path = string("path")?;
+        match &*string("op")? {
+            "add" => Ok(Operation::Add {
+                path,
+                value: value()?,
+            }),
+            "remove" => Ok(Operation::Remove { path }),
+            "replace" => Ok(Operation::Replace {
+                path,
+                value: value()?,
+            }),
+            "move" => Ok(Operation::Move {
+                from: string("from")?,
+                path,
+            }),
+            "copy" => Ok(Operation::Copy {
+                from: string("from")?,
+                path,
+            }),
+            "test" => Ok(Operation::Test {
+                path,
+                value: value()?,
+            }),
+            op => Err(Error::from(ErrorKind::InvalidPatch))
+                .chain_err(|| format!("unknown operation {:?}", op)),
+        }
+    }
+}
+
+/// Apply a patch document (an array of operation objects) to `doc`.
+pub fn apply_value<'a>(doc: &mut Value<'a>, patch: &Value<'a>) -> Result<()> {
+    let ops = match *patch {
+        Value::Array(ref ops) => ops
+            .iter()
+            .map(Operation::from_value)
+            .collect::<Result<Vec<_>>>()?,
+        _ => {
+            return Err(Error::from(ErrorKind::InvalidPatch))
+                .chain_err(|| "patch document must be an array")
+        }
+    };
+    apply(doc, &ops)
+}
+
+/// Apply a sequence of operations to `doc`.
+///
+/// The operations are applied atomically: if any of them fails, `doc` is left unchanged.
+pub fn apply<'a>(doc: &mut Value<'a>, ops: &[Operation<'a>]) -> Result<()> {
+    let mut result = doc.clone();
+    for op in ops {
+        apply_one(&mut result, op)?;
+    }
+    *doc = result;
+    Ok(())
+}
+
+fn apply_one<'a>(doc: &mut Value<'a>, op: &Operation<'a>) -> Result<()> {
+    match *op {
+        Operation::Add {
+            ref path,
+            ref value,
+        } => add(doc, path, value.clone()),
+        Operation::Remove { ref path } => remove(doc, path).map(|_| ()),
+        Operation::Replace {
+            ref path,
+            ref value,
+        } => {
+            *lookup_mut(doc, path)? = value.clone();
+            Ok(())
+        }
+        Operation::Move { ref from, ref path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(Error::from(ErrorKind::InvalidPatch))
+                    .chain_err(|| format!("cannot move {:?} into its child {:?}", from, path));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        Operation::Copy { ref from, ref path } => {
+            let value = doc.pointer(from).cloned().ok_or_else(|| not_found(from))?;
+            add(doc, path, value)
+        }
+        Operation::Test {
+            ref path,
+            ref value,
+        } => {
+            if doc.pointer(path) == Some(value) {
+                Ok(())
+            } else {
+                Err(Error::from(ErrorKind::InvalidPatch))
+                    .chain_err(|| format!("test failed at {:?}", path))
+            }
+        }
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    Error::from(ErrorKind::InvalidPatch).chain_err(|| format!("path not found: {:?}", path))
+}
+
+fn lookup_mut<'v, 'a>(doc: &'v mut Value<'a>, path: &str) -> Result<&'v mut Value<'a>> {
+    doc.pointer_mut(path).ok_or_else(|| not_found(path))
+}
+
+/// Split `path` into the pointer of its parent and the last reference token.
+fn split_last(path: &str) -> Result<(&str, String)> {
+    let i = path.rfind('/').ok_or_else(|| not_found(path))?;
+    let last = tokens(&path[i..])
+        .and_then(|mut t| t.pop())
+        .ok_or_else(|| not_found(path))?;
+    Ok((&path[..i], last))
+}
+
+fn add<'a>(doc: &mut Value<'a>, path: &str, value: Value<'a>) -> Result<()> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent, last) = split_last(path)?;
+    match *lookup_mut(doc, parent)? {
+        Value::Object(ref mut obj) => {
+            let key = EscapedStr::unescaped(last);
+            match obj.iter_mut().find(|(k, _)| k.eq_unescaped(&key)) {
+                Some(entry) => entry.1 = value,
+                None => obj.push((key, value)),
+            }
+            Ok(())
+        }
+        Value::Array(ref mut arr) => {
+            let i = if last == "-" {
+                arr.len()
+            } else {
+                match parse_index(&last) {
+                    Some(i) if i <= arr.len() => i,
+                    _ => return Err(not_found(path)),
+                }
+            };
+            arr.insert(i, value);
+            Ok(())
+        }
+        _ => Err(not_found(path)),
+    }
+}
+
+fn remove<'a>(doc: &mut Value<'a>, path: &str) -> Result<Value<'a>> {
+    let (parent, last) = split_last(path)?;
+    match *lookup_mut(doc, parent)? {
+        Value::Object(ref mut obj) => {
+            let key = EscapedStr::unescaped(last);
+            let i = obj
+                .iter()
+                .position(|(k, _)| k.eq_unescaped(&key))
+                .ok_or_else(|| not_found(path))?;
+            Ok(obj.remove(i).1)
+        }
+        Value::Array(ref mut arr) => match parse_index(&last) {
+            Some(i) if i < arr.len() => Ok(arr.remove(i)),
+            _ => Err(not_found(path)),
+        },
+        _ => Err(not_found(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{array, object};
+
+    #[test]
+    fn apply_operations() {
+        let mut doc = object! {
+            "foo" => array!["bar", "baz",],
+            "qux" => object! { "a" => 1.0, },
+        };
+        let patch = array![
+            object! { "op" => "add", "path" => "/foo/1", "value" => "x", },
+            object! { "op" => "add", "path" => "/foo/-", "value" => "y", },
+            object! { "op" => "remove", "path" => "/foo/0", },
+            object! { "op" => "replace", "path" => "/qux/a", "value" => 2.0, },
+            object! { "op" => "move", "from" => "/qux/a", "path" => "/b", },
+            object! { "op" => "copy", "from" => "/b", "path" => "/qux/c", },
+            object! { "op" => "test", "path" => "/foo", "value" => array!["x", "baz", "y",], },
+        ];
+        apply_value(&mut doc, &patch).unwrap();
+        assert_eq!(
+            doc,
+            object! {
+                "foo" => array!["x", "baz", "y",],
+                "qux" => object! { "c" => 2.0, },
+                "b" => 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn escaped_keys() {
+        // the decoded reference tokens match the escaped names of the fields.
+        let mut doc = object! { r#"a\"b"# => 1.0, "c" => 2.0, };
+        let patch = array![
+            object! { "op" => "replace", "path" => r#"/a"b"#, "value" => 3.0, },
+            object! { "op" => "add", "path" => r#"/a"b"#, "value" => 4.0, },
+            object! { "op" => "remove", "path" => r"/\u0063", },
+        ];
+        apply_value(&mut doc, &patch).unwrap();
+        assert_eq!(doc, object! { r#"a\"b"# => 4.0, });
+    }
+
+    #[test]
+    fn failed_patch_is_atomic() {
+        let mut doc = object! { "foo" => "bar", };
+        let ops = &[
+            Operation::Remove {
+                path: "/foo".into(),
+            },
+            Operation::Test {
+                path: "/foo".into(),
+                value: "bar".into(),
+            },
+        ];
+        assert!(apply(&mut doc, ops).is_err());
+        assert_eq!(doc, object! { "foo" => "bar", });
+
+        let invalid: &[Value<'_>] = &[
+            object! { "op" => "remove", "path" => "/missing", },
+            object! { "op" => "add", "path" => "/a/b", "value" => 1.0, },
+            object! { "op" => "move", "from" => "/foo", "path" => "/foo/x", },
+            object! { "op" => "unknown", "path" => "/foo", },
+            object! { "op" => "add", "path" => "/foo", },
+        ];
+        for op in invalid {
+            let patch = Value::Array(vec![op.clone()]);
+            assert!(apply_value(&mut doc, &patch).is_err(), "{:?}", op);
+        }
+    }
+}
@@ -1,6 +1,9 @@
 #![allow(missing_docs)]
 
-#[derive(Debug)]
+use std::cmp;
+use std::fmt::Write;
+
+#[derive(Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct PatternNode {
     field: String,
@@ -31,6 +34,12 @@ impl PatternNode {
         self.pos
     }
 
+    /// Return the number of the patterns through this node, which is halved by decay.
+    #[inline]
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
     #[inline]
     pub fn children(&self) -> &[PatternNode] {
         self.children.as_slice()
@@ -47,9 +56,11 @@ impl PatternNode {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct PatternTree {
     root: PatternNode,
+    decay_interval: Option<usize>,
+    num_appended: usize,
 }
 
 impl PatternTree {
@@ -58,42 +69,115 @@ impl PatternTree {
     /// The pattern should be represented as a sequence of pairs of field and its appearance
     /// position.
     ///
-    /// ```{text,ignore}
+    /// ```text
     /// { "A": "", "B": "", "Z": "", "_dummy": { ... }, "Y": "" }
     /// ```
     ///
-    /// ```{text, ignore}
+    /// ```text
     /// ["$.A", "$.B", "$.Y", "$.Z"]
     /// ```
     ///
-    /// ```{text,ignore}
+    /// ```text
     /// [("A", 0), ("B", 1), ("Z", 2), ("Y", 4)]
     /// ```
-    pub fn append<'a, I>(&mut self, pattern: I)
+    pub fn append<I, S>(&mut self, pattern: I)
     where
-        I: IntoIterator<Item = (String, usize)>,
+        I: IntoIterator<Item = (S, usize)>,
+        S: AsRef<str> + Into<String>,
     {
         let mut cur = &mut self.root;
         cur.weight += 1;
         for (field, pos) in pattern {
             let cur1 = cur;
-            cur = match cur1
+            let mut i = match cur1
                 .children
                 .iter()
-                .position(|ch| ch.field == field && ch.pos == pos)
+                .position(|ch| ch.field == field.as_ref() && ch.pos == pos)
             {
-                Some(i) => &mut cur1.children[i],
+                Some(i) => i,
                 None => {
                     cur1.children.push(PatternNode {
-                        field,
+                        field: field.into(),
                         pos,
                         ..Default::default()
                     });
-                    cur1.children.last_mut().unwrap()
+                    cur1.children.len() - 1
                 }
             };
-            cur.weight += 1;
+            cur1.children[i].weight += 1;
+
+            // keep the children sorted in descending order of their weights, so that
+            // the speculative parsing probes the most frequent one first.
+            while i > 0 && cur1.children[i - 1].weight < cur1.children[i].weight {
+                cur1.children.swap(i - 1, i);
+                i -= 1;
+            }
+            cur = &mut cur1.children[i];
+        }
+
+        if let Some(interval) = self.decay_interval {
+            self.num_appended += 1;
+            if self.num_appended >= interval {
+                self.decay();
+            }
+        }
+    }
+
+    /// Halve the weights of all patterns every `interval` appended patterns, so that
+    /// the old patterns are forgotten when the order of fields changes.
+    ///
+    /// `None` disables the decay (default).
+    pub fn set_decay_interval(&mut self, interval: Option<usize>) {
+        assert!(interval != Some(0), "decay interval must be positive");
+        self.decay_interval = interval;
+        self.num_appended = 0;
+    }
+
+    /// Halve the weights of all patterns, and remove the ones whose weight becomes zero.
+    pub fn decay(&mut self) {
+        fn decay(node: &mut PatternNode) {
+            node.weight /= 2;
+            node.children.retain(|ch| ch.weight > 1);
+            for child in &mut node.children {
+                decay(child);
+            }
+        }
+        decay(&mut self.root);
+        self.num_appended = 0;
+    }
+
+    /// Merge the patterns in `other` into this tree, summing up the weights of the
+    /// patterns found in both trees.
+    pub fn merge(&mut self, other: PatternTree) {
+        fn merge(dst: &mut PatternNode, src: PatternNode) {
+            dst.weight += src.weight;
+            for child in src.children {
+                match dst
+                    .children
+                    .iter()
+                    .position(|ch| ch.field == child.field && ch.pos == child.pos)
+                {
+                    Some(i) => merge(&mut dst.children[i], child),
+                    None => dst.children.push(child),
+                }
+            }
+        }
+        merge(&mut self.root, other.root);
+        self.sort_by_weight();
+    }
+
+    /// Sort the children of all nodes in descending order of their weights.
+    ///
+    /// `append` keeps the order by itself, so this is needed only after the weights
+    /// have been modified otherwise.
+    pub fn sort_by_weight(&mut self) {
+        fn sort(node: &mut PatternNode) {
+            node.children.sort_by_key(|ch| cmp::Reverse(ch.weight));
+            for child in &mut node.children {
+                sort(child);
+            }
         }
+        sort(&mut self.root);
     }
 
     #[allow(missing_docs)]
@@ -101,6 +185,79 @@ impl PatternTree {
     pub fn root_node(&self) -> &PatternNode {
         &self.root
     }
+
+    /// Return an iterator over the learned patterns, as the fields and their positions
+    /// from the root to each leaf, with the weights of the leaves.
+    ///
+    /// The patterns are visited in depth-first order, in the order they are probed.
+    pub fn patterns(&self) -> Patterns<'_> {
+        Patterns {
+            stack: vec![(&self.root, 0)],
+            pattern: vec![],
+        }
+    }
+
+    /// Render the tree in the DOT language of Graphviz, e.g. to visualize the learned
+    /// orders of fields by `dot -Tsvg`.
+    ///
+    /// Each node is labeled with its field name, its position and its weight, and the
+    /// children are listed in the order they are probed.
+    pub fn to_dot(&self) -> String {
+        fn render(out: &mut String, node: &PatternNode, id: usize, next_id: &mut usize) {
+            let label = if node.is_root() {
+                format!("$\\nweight {}", node.weight)
+            } else {
+                let field = escape_dot(&node.field);
+                format!("{} @ {}\\nweight {}", field, node.pos, node.weight)
+            };
+            let _ = writeln!(out, "    n{} [label=\"{}\"];", id, label);
+            for child in &node.children {
+                *next_id += 1;
+                let child_id = *next_id;
+                let _ = writeln!(out, "    n{} -> n{};", id, child_id);
+                render(out, child, child_id, next_id);
+            }
+        }
+        let mut out = String::from("digraph PatternTree {\n");
+        render(&mut out, &self.root, 0, &mut 0);
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Escape `s` to be written between the quotes of an ID in the DOT language, in which
+/// only the quotes are escaped (and the backslashes, which Graphviz unescapes in labels).
+pub(crate) fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// An iterator over the patterns of a `PatternTree`, created by `PatternTree::patterns`
+#[derive(Debug)]
+pub struct Patterns<'t> {
+    /// the nodes to visit, with the lengths of the patterns of their parents
+    stack: Vec<(&'t PatternNode, usize)>,
+    /// the fields from the root to the current node
+    pattern: Vec<(&'t str, usize)>,
+}
+
+impl<'t> Iterator for Patterns<'t> {
+    type Item = (Vec<(&'t str, usize)>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, len)) = self.stack.pop() {
+            self.pattern.truncate(len);
+            if !node.is_root() {
+                self.pattern.push((&node.field, node.pos));
+            }
+            let len = self.pattern.len();
+            self.stack
+                .extend(node.children.iter().rev().map(|child| (child, len)));
+            if node.is_leaf() && !node.is_root() {
+                return Some((self.pattern.clone(), node.weight));
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -173,4 +330,133 @@ mod tests {
         };
         assert_eq!(tree.root, expected);
     }
+
+    #[test]
+    fn test_patterns() {
+        let mut tree = PatternTree::default();
+        assert_eq!(tree.patterns().count(), 0);
+        tree.append(vec![("foo", 0), ("bar", 1)]);
+        tree.append(vec![("bar", 0)]);
+        tree.append(vec![("foo", 0), ("baz", 2)]);
+        tree.append(vec![("foo", 0), ("baz", 2)]);
+        let patterns: Vec<_> = tree.patterns().collect();
+        assert_eq!(
+            patterns,
+            [
+                (vec![("foo", 0), ("baz", 2)], 2),
+                (vec![("foo", 0), ("bar", 1)], 1),
+                (vec![("bar", 0)], 1),
+            ]
+        );
+
+        // the patterns can be appended into another tree as they are.
+        let mut copied = PatternTree::default();
+        for (pattern, weight) in tree.patterns() {
+            for _ in 0..weight {
+                copied.append(pattern.iter().cloned());
+            }
+        }
+        assert_eq!(copied.root, tree.root);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut tree = PatternTree::default();
+        tree.append(vec![("foo", 0), ("bar", 1)]);
+        tree.append(vec![("bar", 0)]);
+        tree.append(vec![("foo", 0)]);
+        tree.append(vec![("\\\"\té", 2)]);
+        assert_eq!(
+            tree.to_dot(),
+            concat!(
+                "digraph PatternTree {\n",
+                "    n0 [label=\"$\\nweight 4\"];\n",
+                "    n0 -> n1;\n",
+                "    n1 [label=\"foo @ 0\\nweight 2\"];\n",
+                "    n1 -> n2;\n",
+                "    n2 [label=\"bar @ 1\\nweight 1\"];\n",
+                "    n0 -> n3;\n",
+                "    n3 [label=\"bar @ 0\\nweight 1\"];\n",
+                "    n0 -> n4;\n",
+                "    n4 [label=\"\\\\\\\"\té @ 2\\nweight 1\"];\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_weighted_order() {
+        let mut tree = PatternTree::default();
+        tree.append(vec![("foo", 0), ("bar", 1)]);
+        tree.append(vec![("bar", 0), ("foo", 1)]);
+        tree.append(vec![("baz", 0)]);
+        tree.append(vec![("baz", 0)]);
+        tree.append(vec![("bar", 0), ("foo", 2)]);
+        tree.append(vec![("bar", 0), ("foo", 2)]);
+
+        let root = tree.root_node();
+        let fields: Vec<_> = root.children().iter().map(|n| n.field()).collect();
+        assert_eq!(fields, ["bar", "baz", "foo"]);
+        let positions: Vec<_> = root.children()[0]
+            .children()
+            .iter()
+            .map(|n| n.position())
+            .collect();
+        assert_eq!(positions, [2, 1]);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut tree = PatternTree::default();
+        tree.set_decay_interval(Some(4));
+        for _ in 0..7 {
+            tree.append(vec![("foo", 0), ("bar", 1)]);
+        }
+        assert_eq!(tree.root.weight, 5);
+        assert_eq!(tree.root.children[0].weight, 5);
+
+        for _ in 0..4 {
+            tree.append(vec![("bar", 0), ("foo", 1)]);
+        }
+        let fields: Vec<_> = tree.root.children.iter().map(|n| n.field()).collect();
+        assert_eq!(fields, ["bar", "foo"]);
+
+        for _ in 0..8 {
+            tree.append(vec![("bar", 0), ("foo", 1)]);
+        }
+        assert_eq!(tree.root.children.len(), 1);
+        assert_eq!(tree.root.children[0].field(), "bar");
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut tree1 = PatternTree::default();
+        tree1.append(vec![("foo", 0), ("bar", 1)]);
+        tree1.append(vec![("bar", 0), ("foo", 1)]);
+
+        let mut tree2 = PatternTree::default();
+        tree2.append(vec![("bar", 0), ("foo", 1)]);
+        tree2.append(vec![("bar", 0), ("foo", 2)]);
+
+        let mut expected = PatternTree::default();
+        expected.append(vec![("bar", 0), ("foo", 1)]);
+        expected.append(vec![("bar", 0), ("foo", 1)]);
+        expected.append(vec![("bar", 0), ("foo", 2)]);
+        expected.append(vec![("foo", 0), ("bar", 1)]);
+
+        tree1.merge(tree2);
+        assert_eq!(tree1.root, expected.root);
+    }
+
+    #[test]
+    fn test_sort_by_weight() {
+        let mut tree = PatternTree::default();
+        tree.append(vec![("foo", 0), ("bar", 1)]);
+        tree.append(vec![("bar", 0), ("foo", 1)]);
+        tree.root.children[1].weight = 10;
+        tree.sort_by_weight();
+
+        let fields: Vec<_> = tree.root.children.iter().map(|n| n.field()).collect();
+        assert_eq!(fields, ["bar", "foo"]);
+    }
 }
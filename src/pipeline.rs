@@ -0,0 +1,206 @@
+//! Parallel extraction with bounded channels
+//!
+//! A `Pipeline` connects a reader of newline-delimited records, parser workers and a
+//! sink with bounded channels. When the sink is slower than the workers (or the
+//! workers are slower than the reader), the channels fill up and the previous stage
+//! blocks, so the memory usage is bounded by the capacity of the channels.
+
+use crate::errors::{Error, Result};
+use crate::index_builder::backend::Backend;
+use crate::query_parser::{QueryParser, QueryParserMode, Workspace};
+use std::io::BufRead;
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// The configuration of a pipeline
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    workers: usize,
+    capacity: usize,
+    mode: QueryParserMode,
+}
+
+impl Pipeline {
+    /// Create a new pipeline with `workers` parser threads.
+    pub fn new(workers: usize) -> Self {
+        assert!(workers > 0, "the number of workers must be positive");
+        Self {
+            workers,
+            capacity: 1024,
+            mode: QueryParserMode::Basic,
+        }
+    }
+
+    /// Set the number of the records (and of the results) buffered in each channel.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set the mode to parse the records in.
+    pub fn mode(mut self, mode: QueryParserMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Read the records from `reader` line by line, extract their values by `parser`
+    /// on the workers, convert them by `f` and pass them to `sink`. Blank lines are
+    /// skipped.
+    ///
+    /// `sink` runs on its own thread, and receives the sequence number of each record
+    /// along with the converted values or the error of parsing it. The results arrive
+    /// in arbitrary order. The pipeline stops at the first error returned by `sink`
+    /// or from `reader`, and returns the number of the records read otherwise.
+    pub fn run<B, R, T, F, S>(
+        &self,
+        parser: &QueryParser<'_, B>,
+        reader: R,
+        f: F,
+        mut sink: S,
+    ) -> Result<u64>
+    where
+        B: Backend + Send + Sync,
+        R: BufRead,
+        T: Send,
+        F: Fn(&str, &[Option<&str>]) -> T + Sync,
+        S: FnMut(u64, Result<T>) -> Result<()> + Send,
+    {
+        let (record_tx, record_rx) = sync_channel::<(u64, String)>(self.capacity);
+        let (result_tx, result_rx) = sync_channel::<(u64, Result<T>)>(self.capacity);
+        // the receiver is dropped when all workers stop, so that the reader does not
+        // block forever after the sink fails.
+        let record_rx = Arc::new(Mutex::new(record_rx));
+        let mode = self.mode;
+        let f = &f;
+
+        thread::scope(|scope| {
+            for _ in 0..self.workers {
+                let record_rx = record_rx.clone();
+                let result_tx = result_tx.clone();
+                scope.spawn(move || {
+                    let mut workspace = Workspace::new();
+                    loop {
+                        let received = record_rx
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .recv();
+                        let (seq, record) = match received {
+                            Ok(received) => received,
+                            Err(_) => break,
+                        };
+                        let mut results = vec![];
+                        let out = parser
+                            .parse_with_workspace(&record, mode, &mut workspace, &mut results)
+                            .map(|_| f(&record, &results));
+                        if result_tx.send((seq, out)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(record_rx);
+            drop(result_tx);
+
+            let sink = scope.spawn(move || -> Result<()> {
+                for (seq, out) in result_rx {
+                    sink(seq, out)?;
+                }
+                Ok(())
+            });
+
+            let mut count = 0;
+            let mut read_error = None;
+            for line in reader.lines() {
+                match line {
+                    Ok(line) if line.trim().is_empty() => {}
+                    Ok(line) => {
+                        if record_tx.send((count, line)).is_err() {
+                            break;
+                        }
+                        count += 1;
+                    }
+                    Err(err) => {
+                        read_error = Some(err);
+                        break;
+                    }
+                }
+            }
+            drop(record_tx);
+
+            sink.join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            match read_error {
+                Some(err) => Err(Error::with_chain(err, "failed to read a record")),
+                None => Ok(count),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::query::QueryTree;
+    use std::io::Cursor;
+
+    fn parser() -> QueryParser<'static, FallbackBackend> {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.n").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        QueryParser::new(index_builder, query_tree)
+    }
+
+    #[test]
+    fn parallel_extraction() {
+        let parser = parser();
+        let mut input: String = (0..1000).map(|n| format!("{{\"n\": {}}}\n", n)).collect();
+        input.push_str("\n[1]\n");
+
+        let mut received = vec![];
+        let mut errors = vec![];
+        let count = Pipeline::new(4)
+            .capacity(8)
+            .run(
+                &parser,
+                Cursor::new(&input),
+                |_, results| results[0].unwrap().parse::<u64>().unwrap(),
+                |seq, out| {
+                    match out {
+                        Ok(n) => received.push((seq, n)),
+                        Err(_) => errors.push(seq),
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(count, 1001);
+        received.sort();
+        assert_eq!(received, (0..1000).map(|n| (n, n)).collect::<Vec<_>>());
+        assert_eq!(errors, [1000]);
+    }
+
+    #[test]
+    fn failed_sink() {
+        let parser = parser();
+        let input: String = (0..1000).map(|n| format!("{{\"n\": {}}}\n", n)).collect();
+        let mut seen = 0;
+        let result = Pipeline::new(2).capacity(1).run(
+            &parser,
+            Cursor::new(&input),
+            |record, _| record.len(),
+            |_, _| {
+                seen += 1;
+                if seen == 10 {
+                    return Err(ErrorKind::InvalidRecord.into());
+                }
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(seen, 10);
+    }
+}
@@ -0,0 +1,158 @@
+//! Presence of top-level keys over batches of records
+//!
+//! Only the colons at the top level and the quotes before them are inspected, and no
+//! value is parsed, so that records can be classified by their shapes cheaply (e.g.
+//! to route them to different downstream topics).
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use fnv::FnvHashMap;
+
+/// A matrix of the presence of keys (columns) in each record (rows)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceMatrix {
+    num_keys: usize,
+    num_records: usize,
+    /// bits of each row, packed into `words_per_row()` words
+    bits: Vec<u64>,
+}
+
+impl PresenceMatrix {
+    fn new(num_keys: usize) -> Self {
+        Self {
+            num_keys,
+            num_records: 0,
+            bits: vec![],
+        }
+    }
+
+    #[allow(missing_docs)]
+    pub fn num_keys(&self) -> usize {
+        self.num_keys
+    }
+
+    #[allow(missing_docs)]
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
+
+    /// Return the number of words of a row, where the `j`-th key is the `j % 64`-th bit
+    /// of the `j / 64`-th word.
+    pub fn words_per_row(&self) -> usize {
+        self.num_keys.div_ceil(64)
+    }
+
+    /// Return the bits of the `i`-th record.
+    pub fn row(&self, i: usize) -> &[u64] {
+        let n = self.words_per_row();
+        &self.bits[i * n..(i + 1) * n]
+    }
+
+    /// Return whether the `i`-th record contains the `j`-th key.
+    pub fn contains(&self, i: usize, j: usize) -> bool {
+        assert!(j < self.num_keys, "key index out of range");
+        self.row(i)[j / 64] & (1 << (j % 64)) != 0
+    }
+}
+
+/// A checker of the presence of top-level keys
+#[derive(Debug)]
+pub struct KeyPresence<'k, B: Backend> {
+    index_builder: IndexBuilder<B>,
+    keys: FnvHashMap<&'k str, usize>,
+}
+
+impl<'k, B: Backend> KeyPresence<'k, B> {
+    /// Create a new checker of `keys`, which are compared with the raw (escaped) field
+    /// names of the records.
+    pub fn new(backend: B, keys: &[&'k str]) -> Result<Self> {
+        let mut map = FnvHashMap::default();
+        for (j, &key) in keys.iter().enumerate() {
+            if map.insert(key, j).is_some() {
+                return Err(Error::from(ErrorKind::InvalidQuery))
+                    .chain_err(|| format!("duplicated key {:?}", key));
+            }
+        }
+        Ok(Self {
+            index_builder: IndexBuilder::new(backend, 1),
+            keys: map,
+        })
+    }
+
+    /// Compute the presence of the keys in a batch of records.
+    pub fn batch<'r, I>(&self, records: I) -> Result<PresenceMatrix>
+    where
+        I: IntoIterator<Item = &'r str>,
+    {
+        let mut matrix = PresenceMatrix::new(self.keys.len());
+        let mut cp = vec![];
+        for record in records {
+            let n = matrix.words_per_row();
+            matrix.bits.resize(matrix.bits.len() + n, 0);
+            let row = &mut matrix.bits[matrix.num_records * n..];
+            self.check(record, &mut cp, row)
+                .chain_err(|| format!("record #{}", matrix.num_records))?;
+            matrix.num_records += 1;
+        }
+        Ok(matrix)
+    }
+
+    fn check(&self, record: &str, cp: &mut Vec<usize>, row: &mut [u64]) -> Result<()> {
+        let record = record.trim();
+        if !record.starts_with('{') {
+            return Err(Error::from(ErrorKind::InvalidRecord))
+                .chain_err(|| "KeyPresence supports only objects");
+        }
+        let index = self.index_builder.build(record)?;
+        index.colon_positions(0, record.len(), 0, cp);
+        for (i, &colon) in cp.iter().enumerate() {
+            let begin = if i == 0 { 0 } else { cp[i - 1] };
+            let (field, _) = index.find_object_field(begin, colon)?;
+            if let Some(&j) = self.keys.get(field.as_raw_str()) {
+                row[j / 64] |= 1 << (j % 64);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+
+    #[test]
+    fn presence_matrix() {
+        let checker = KeyPresence::new(FallbackBackend::default(), &["a", "b", "c"]).unwrap();
+        let matrix = checker
+            .batch(vec![
+                r#"{ "a": 1, "b": { "c": 2 } }"#,
+                r#"{ "c": "a:b", "d": [1, 2] }"#,
+                "{}",
+            ])
+            .unwrap();
+        assert_eq!(matrix.num_records(), 3);
+        assert_eq!(matrix.row(0), &[0b011]);
+        assert_eq!(matrix.row(1), &[0b100]);
+        assert_eq!(matrix.row(2), &[0]);
+        assert!(matrix.contains(0, 1));
+        assert!(!matrix.contains(0, 2));
+
+        assert!(checker.batch(vec!["[1]"]).is_err());
+        assert!(KeyPresence::new(FallbackBackend::default(), &["a", "a"]).is_err());
+    }
+
+    #[test]
+    fn many_keys() {
+        let keys: Vec<String> = (0..100).map(|i| format!("k{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let checker = KeyPresence::new(FallbackBackend::default(), &keys).unwrap();
+        let matrix = checker
+            .batch(vec![r#"{"k0": 0, "k99": 0}"#, r#"{"k64": 0}"#])
+            .unwrap();
+        assert_eq!(matrix.words_per_row(), 2);
+        assert_eq!(matrix.row(0), &[1, 1 << 35]);
+        assert_eq!(matrix.row(1), &[0, 1]);
+    }
+}
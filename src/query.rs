@@ -1,11 +1,15 @@
 //! Definition of pattern tree and query parsing
 
-use crate::errors::{ErrorKind, Result};
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::pattern_tree::escape_dot;
+use crate::validate;
+use crate::value::Value;
 use fnv::FnvHashMap;
 use std::cmp;
+use std::fmt::{self, Write};
 
 /// Child node in pattern tree
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct QueryNode<'a> {
     /// identifier of this node
@@ -46,23 +50,40 @@ impl<'a> QueryNode<'a> {
 
     #[allow(missing_docs)]
     pub fn field(&self, field: &str) -> Option<&'a str> {
-        self.children.keys().find(|&f| f == &field).map(|f| *f)
+        self.children.keys().find(|&f| f == &field).copied()
     }
 
     #[allow(missing_docs)]
     pub fn num_children(&self) -> usize {
         self.children.len()
     }
+
+    /// Iterate over the child nodes with their field names, in arbitrary order.
+    pub fn children(&self) -> impl Iterator<Item = (&'a str, &QueryNode<'a>)> {
+        self.children.iter().map(|(&field, node)| (field, node))
+    }
 }
 
 /// A pattern tree
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct QueryTree<'a> {
     /// root node
     root: QueryNode<'a>,
     /// query paths
     paths: Vec<&'a str>,
+    /// user-defined names of query paths
+    names: Vec<Option<&'a str>>,
+    /// default values of query paths, as JSON texts
+    defaults: Vec<Option<&'a str>>,
+    /// whether the strings of query paths are decoded from Base64, by `!base64`
+    base64: Vec<bool>,
+    /// the query paths of the alternatives following the first one of each path, in
+    /// the order of priority. The i-th alternative is stored at the slot
+    /// `paths.len() + i` of the results.
+    alternatives: Vec<usize>,
+    /// the paths queried within the strings of other paths, by their sources
+    embedded: Vec<EmbeddedPaths<'a>>,
     /// maximal level in this tree
     max_level: usize,
     /// number of nodes in this tree
@@ -74,6 +95,11 @@ impl<'a> Default for QueryTree<'a> {
         Self {
             root: QueryNode::default(),
             paths: vec![],
+            names: vec![],
+            defaults: vec![],
+            base64: vec![],
+            alternatives: vec![],
+            embedded: vec![],
             max_level: 0,
             num_nodes: 1,
         }
@@ -82,17 +108,257 @@ impl<'a> Default for QueryTree<'a> {
 
 impl<'a> QueryTree<'a> {
     /// Parse query path and append it to the pattern tree.
+    ///
+    /// A default value can follow the path as a JSON text, e.g. `$.retries ?? 0`, which
+    /// is substituted for the missing value only by `QueryParser::parse_cow`,
+    /// `parse_bytes` and `parse_to`. The other methods return slices (or ranges) of the
+    /// record, so the value is still missing there; use `default_value` to fill it in.
+    ///
+    /// Alternative paths can be separated by `|`, e.g. `$.user_id | $.uid | $.user.id`,
+    /// and the value of the first one found in each record is returned for the path.
+    /// A field can be queried only by one path or alternative.
+    ///
+    /// The path `$` queries the whole record, which is returned as it is (without the
+    /// surrounding whitespace).
+    ///
+    /// A field followed by `!json` is a string containing a JSON text (e.g.
+    /// `"payload": "{\"a\":1}"`), and the rest of the path is queried within the
+    /// unescaped text, e.g. `$.payload!json.a`. See `QueryParser::parse_cow` for the
+    /// values of such paths. They cannot have alternatives.
+    ///
+    /// A path ending with `!base64` queries a Base64 string, e.g. `$.data!base64`, which
+    /// is decoded by `QueryParser::parse_bytes` (with the feature `base64`). The path
+    /// keeps the suffix in `paths` and `path_id_of`.
     pub fn add_path(&mut self, path: &'a str) -> Result<()> {
-        if !path.starts_with("$.") {
+        let text = path;
+        let (path, default) = match path.find("??") {
+            Some(i) => (path[..i].trim_end(), Some(path[i + 2..].trim())),
+            None => (path, None),
+        };
+        // the path is stored with `!base64`, as it is looked up by `path_id_of`.
+        let stored = path;
+        let (path, base64) = match path.strip_suffix("!base64") {
+            Some(path) => (path.trim_end(), true),
+            None => (path, false),
+        };
+        let alternatives: Vec<&str> = path.split('|').map(str::trim).collect();
+        for alt in &alternatives {
+            let valid = match alt.strip_prefix("$.") {
+                Some(fields) => {
+                    let fields: Vec<&str> = fields.split('.').collect();
+                    fields
+                        .iter()
+                        .enumerate()
+                        .all(|(i, field)| match field.strip_suffix("!json") {
+                            Some(field) => {
+                                !field.is_empty() && i + 1 < fields.len() && alternatives.len() == 1
+                            }
+                            None => !field.is_empty() && !field.ends_with("!base64"),
+                        })
+                }
+                None => *alt == "$" && alternatives.len() == 1,
+            };
+            if !valid {
+                Err(ErrorKind::InvalidQuery)?;
+            }
+        }
+        if let Some(default) = default {
+            validate::validate(default)
+                .chain_err(|| ErrorKind::InvalidQuery)
+                .chain_err(|| format!("invalid default value {:?}", default))?;
+        }
+        for (i, alt) in alternatives.iter().enumerate().skip(1) {
+            match self.find_node(fields_of(alt)) {
+                _ if alternatives[..i].contains(alt) => {
+                    return Err(Error::from(ErrorKind::InvalidQuery))
+                        .chain_err(|| format!("duplicated alternative {:?}", alt));
+                }
+                Some(node) if node.query_id.is_some() => {
+                    return Err(Error::from(ErrorKind::InvalidQuery))
+                        .chain_err(|| format!("{:?} is already queried", alt));
+                }
+                _ => {}
+            }
+        }
+        // the names share the namespace of the paths in `path_id_of`.
+        if let Some(&name) = self
+            .names
+            .iter()
+            .flatten()
+            .find(|&&n| n == text || n == stored)
+        {
+            return Err(Error::from(ErrorKind::InvalidQuery))
+                .chain_err(|| format!("{:?} is already the name of a path", name));
+        }
+
+        let path_id = self.paths.len();
+        match alternatives[0][1..].split_once("!json.") {
+            Some((outer, inner)) => self.add_embedded(stored, &outer[1..], inner)?,
+            None => self.add_fields(stored, fields_of(alternatives[0]))?,
+        }
+        *self.defaults.last_mut().unwrap() = default;
+        *self.base64.last_mut().unwrap() = base64;
+        for alt in &alternatives[1..] {
+            let slot = self.num_slots();
+            let node = self.node_mut(fields_of(alt));
+            node.query_id = Some(slot);
+            let level = node.level;
+            self.max_level = cmp::max(self.max_level, level);
+            self.alternatives.push(path_id);
+        }
+        Ok(())
+    }
+
+    /// Append a query path whose fields are given separately, e.g. those following
+    /// an array of `path`.
+    pub(crate) fn add_fields<I>(&mut self, path: &'a str, fields: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let fields: Vec<&'a str> = fields.into_iter().collect();
+        if fields.iter().any(|field| field.is_empty()) {
             Err(ErrorKind::InvalidQuery)?;
         }
+        let path_id = self.paths.len();
+        if let Some(node) = self.find_node(fields.iter().cloned()) {
+            if node.query_id.is_some_and(|id| id >= path_id) {
+                return Err(Error::from(ErrorKind::InvalidQuery))
+                    .chain_err(|| format!("{:?} is already queried as an alternative", path));
+            }
+            if node
+                .query_id
+                .is_some_and(|id| self.embedded_path(id).is_some())
+            {
+                return Err(Error::from(ErrorKind::InvalidQuery))
+                    .chain_err(|| format!("{:?} is already queried by an embedded path", path));
+            }
+            if let Some(id) = node.query_id {
+                return Err(Error::from(ErrorKind::InvalidQuery)).chain_err(|| {
+                    format!("{:?} is already queried by {:?}", path, self.paths[id])
+                });
+            }
+        }
 
-        let mut cur = &mut self.root;
-        for field in path[2..].split('.') {
-            if field.is_empty() {
-                Err(ErrorKind::InvalidQuery)?;
+        self.push_path(path);
+        let cur = self.node_mut(fields);
+        cur.query_id = Some(path_id);
+        let level = cur.level;
+        self.max_level = cmp::max(self.max_level, level);
+
+        Ok(())
+    }
+
+    /// Append a query path without its node, and return its identifier.
+    fn push_path(&mut self, path: &'a str) -> usize {
+        let path_id = self.paths.len();
+        // the slots of the alternatives follow the paths.
+        fn shift(node: &mut QueryNode<'_>, path_id: usize) {
+            if let Some(ref mut id) = node.query_id {
+                if *id >= path_id {
+                    *id += 1;
+                }
             }
+            for child in node.children.values_mut() {
+                shift(child, path_id);
+            }
+        }
+        if !self.alternatives.is_empty() {
+            shift(&mut self.root, path_id);
+        }
+
+        self.paths.push(path);
+        self.names.push(None);
+        self.defaults.push(None);
+        self.base64.push(false);
+        path_id
+    }
+
+    /// Append a query path of `inner` within the string of the fields `outer`.
+    ///
+    /// The string is taken from the path already querying `outer` if any, so that
+    /// the paths within the same string are extracted together.
+    fn add_embedded(&mut self, path: &'a str, outer: &'a str, inner: &'a str) -> Result<()> {
+        let path_id = self.paths.len();
+        let source = match self
+            .find_node(outer.split('.'))
+            .and_then(|node| node.query_id)
+        {
+            Some(slot) if slot < path_id => {
+                self.push_path(path);
+                slot
+            }
+            Some(_) => {
+                return Err(Error::from(ErrorKind::InvalidQuery)).chain_err(|| {
+                    format!("the string of {:?} is queried as an alternative", path)
+                });
+            }
+            None => {
+                self.add_fields(path, outer.split('.'))?;
+                path_id
+            }
+        };
+        let embedded = match self.embedded.iter_mut().find(|e| e.source == source) {
+            Some(embedded) => embedded,
+            None => {
+                self.embedded.push(EmbeddedPaths {
+                    source,
+                    paths: vec![],
+                    tree: QueryTree::default(),
+                });
+                self.embedded.last_mut().unwrap()
+            }
+        };
+        match inner.split_once("!json.") {
+            Some((outer, inner)) => embedded.tree.add_embedded(path, outer, inner)?,
+            None => embedded.tree.add_fields(path, inner.split('.'))?,
+        }
+        embedded.paths.push(path_id);
+        Ok(())
+    }
 
+    /// Return the paths queried within the strings of other paths, by `!json`.
+    pub fn embedded(&self) -> &[EmbeddedPaths<'a>] {
+        &self.embedded
+    }
+
+    /// Return the paths within the string of the path `path_id`, if it is their source
+    /// and is queried by `!json` itself.
+    fn embedded_path(&self, path_id: usize) -> Option<&EmbeddedPaths<'a>> {
+        self.embedded
+            .iter()
+            .find(|e| e.source == path_id && e.paths.contains(&path_id))
+    }
+
+    /// Shift the levels of all nodes by `level`, so that the tree is matched within a
+    /// value at that level.
+    pub(crate) fn rebase(&mut self, level: usize) {
+        fn shift(node: &mut QueryNode<'_>, level: usize) {
+            node.level += level;
+            for child in node.children.values_mut() {
+                shift(child, level);
+            }
+        }
+        shift(&mut self.root, level);
+        self.max_level += level;
+    }
+
+    /// Return the node of the fields, if it exists.
+    fn find_node<'f, I>(&self, fields: I) -> Option<&QueryNode<'a>>
+    where
+        I: IntoIterator<Item = &'f str>,
+    {
+        fields
+            .into_iter()
+            .try_fold(&self.root, |node, field| node.children.get(field))
+    }
+
+    /// Return the node of the fields, which is created if missing.
+    fn node_mut<I>(&mut self, fields: I) -> &mut QueryNode<'a>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut cur = &mut self.root;
+        for field in fields {
             let level = cur.level + 1;
             let num_nodes = &mut self.num_nodes;
 
@@ -107,13 +373,45 @@ impl<'a> QueryTree<'a> {
                 node
             });
         }
+        cur
+    }
 
-        cur.query_id = Some(self.paths.len());
+    /// Append a query path with a user-defined name, which can be used to look up the
+    /// result instead of its position (see `QueryParser::parse_named`).
+    pub fn add_named(&mut self, name: &'a str, path: &'a str) -> Result<()> {
+        if self.path_id_of(name).is_some() {
+            return Err(Error::from(ErrorKind::InvalidQuery))
+                .chain_err(|| format!("duplicated name {:?}", name));
+        }
+        self.add_path(path)?;
+        *self.names.last_mut().unwrap() = Some(name);
+        Ok(())
+    }
 
-        self.max_level = cmp::max(self.max_level, cur.level);
-        self.paths.push(path);
+    /// Return the name of the query path associated with `path_id`, or the path itself
+    /// if it has no name.
+    pub fn name(&self, path_id: usize) -> Option<&'a str> {
+        self.names
+            .get(path_id)
+            .map(|&name| name.unwrap_or(self.paths[path_id]))
+    }
 
-        Ok(())
+    /// Return the default value of the query path associated with `path_id`, if any.
+    pub fn default_value(&self, path_id: usize) -> Option<&'a str> {
+        self.defaults.get(path_id).cloned().flatten()
+    }
+
+    /// Return whether the string of the query path `path_id` is decoded from Base64.
+    pub fn is_base64(&self, path_id: usize) -> bool {
+        self.base64.get(path_id).cloned().unwrap_or(false)
+    }
+
+    /// Return the identifier of the query path with `name`, or whose path is `name`.
+    pub fn path_id_of(&self, name: &str) -> Option<usize> {
+        self.names
+            .iter()
+            .position(|&n| n == Some(name))
+            .or_else(|| self.paths.iter().position(|&p| p == name))
     }
 
     #[allow(missing_docs)]
@@ -126,15 +424,244 @@ impl<'a> QueryTree<'a> {
         self.max_level
     }
 
+    /// Return the query paths, in the order of their identifiers.
+    pub fn paths(&self) -> &[&'a str] {
+        &self.paths
+    }
+
     #[allow(missing_docs)]
     pub fn num_paths(&self) -> usize {
         self.paths.len()
     }
 
+    /// Return the number of the results collected while parsing, i.e. the paths and
+    /// their alternatives. The path identifiers of nodes are less than this.
+    pub fn num_slots(&self) -> usize {
+        self.paths.len() + self.alternatives.len()
+    }
+
+    /// Return the query path whose value is stored at the slot, as the path itself or
+    /// one of its alternatives.
+    pub fn path_of_slot(&self, slot: usize) -> Option<usize> {
+        if slot < self.paths.len() {
+            Some(slot)
+        } else {
+            self.alternatives.get(slot - self.paths.len()).cloned()
+        }
+    }
+
+    /// Move the values of the alternatives in `slots` to their paths, in the order of
+    /// priority, and drop the slots of the alternatives. `missing` returns whether no
+    /// value has been found for a slot.
+    pub(crate) fn coalesce<T: Default>(&self, slots: &mut Vec<T>, missing: impl Fn(&T) -> bool) {
+        let num_paths = self.paths.len();
+        for (i, &path_id) in self.alternatives.iter().enumerate() {
+            if missing(&slots[path_id]) {
+                slots[path_id] = std::mem::take(&mut slots[num_paths + i]);
+            }
+        }
+        slots.truncate(num_paths);
+    }
+
+    /// Return the query path associated with `path_id`.
+    pub fn path(&self, path_id: usize) -> Option<&'a str> {
+        self.paths.get(path_id).copied()
+    }
+
     #[allow(missing_docs)]
     pub fn as_node(&self) -> &QueryNode<'_> {
         &self.root
     }
+
+    /// Return the node associated with `path_id`.
+    ///
+    /// The node of the first alternative is returned if the path has alternatives.
+    pub fn node_for_path(&self, path_id: usize) -> Option<&QueryNode<'a>> {
+        let path = self.path(path_id)?;
+        self.find_node(fields_of(first_alternative(path)))
+    }
+
+    /// Visit all nodes in depth-first order, passing the fields from the root to each node.
+    ///
+    /// The children of a node are visited in the order of their field names, so that
+    /// the order is stable across runs.
+    pub fn visit<F>(&self, mut f: F)
+    where
+        F: FnMut(&[&'a str], &QueryNode<'a>),
+    {
+        fn visit_node<'a, F>(fields: &mut Vec<&'a str>, node: &QueryNode<'a>, f: &mut F)
+        where
+            F: FnMut(&[&'a str], &QueryNode<'a>),
+        {
+            f(fields, node);
+            let mut children: Vec<_> = node.children().collect();
+            children.sort_by_key(|&(field, _)| field);
+            for (field, child) in children {
+                fields.push(field);
+                visit_node(fields, child, f);
+                fields.pop();
+            }
+        }
+        visit_node(&mut vec![], &self.root, &mut f);
+    }
+
+    /// Render the tree in the DOT language of Graphviz, e.g. to visualize the
+    /// configured queries by `dot -Tsvg`.
+    ///
+    /// Each node is labeled with its field name and the query path of its value.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph QueryTree {\n");
+        // the nodes from the root to the current one
+        let mut ancestors = vec![];
+        self.visit(|fields, node| {
+            let mut label = escape_dot(fields.last().map_or("$", |field| field));
+            if let Some(path_id) = node.path_id().and_then(|slot| self.path_of_slot(slot)) {
+                let _ = write!(label, "\\npath {}", path_id);
+            }
+            let _ = writeln!(out, "    n{} [label=\"{}\"];", node.node_id(), label);
+            ancestors.truncate(fields.len());
+            if let Some(parent) = ancestors.last() {
+                let _ = writeln!(out, "    n{} -> n{};", parent, node.node_id());
+            }
+            ancestors.push(node.node_id());
+        });
+        out.push_str("}\n");
+        out
+    }
+
+    /// Check the query paths against a sample record, and report those which do not
+    /// match it (e.g. typos in field names).
+    ///
+    /// A path reaching a raw value, which was not parsed beyond the level of the
+    /// parser, is assumed to match.
+    pub fn check_against(&self, sample: &Value<'_>) -> Vec<UnmatchedPath<'a>> {
+        let mut unmatched = vec![];
+        for (path_id, &path) in self.paths.iter().enumerate() {
+            // a path with alternatives is reported with its first unmatched alternative,
+            // if none of them matches.
+            let mut first = None;
+            for alt in path.split('|').map(str::trim) {
+                match unmatched_field(fields_of(alt), sample) {
+                    Some(found) => {
+                        first = first.or(Some(found));
+                    }
+                    None => {
+                        first = None;
+                        break;
+                    }
+                }
+            }
+            if let Some((field, found)) = first {
+                unmatched.push(UnmatchedPath {
+                    path_id,
+                    path,
+                    field,
+                    found,
+                });
+            }
+        }
+        unmatched
+    }
+}
+
+/// The paths queried within the JSON texts embedded in the strings of a path
+///
+/// The string is the value of the path `source`, which is either a path querying the
+/// string itself or the first of `paths`.
+#[derive(Debug, Clone)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct EmbeddedPaths<'a> {
+    source: usize,
+    paths: Vec<usize>,
+    tree: QueryTree<'a>,
+}
+
+impl<'a> EmbeddedPaths<'a> {
+    /// Return the identifier of the path whose value is the string.
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    /// Return the identifiers of the paths queried within the string, in the order of
+    /// the paths of `tree`.
+    pub fn paths(&self) -> &[usize] {
+        &self.paths
+    }
+
+    /// Return the tree of the paths within the string.
+    pub fn tree(&self) -> &QueryTree<'a> {
+        &self.tree
+    }
+}
+
+/// Return the first alternative of a query path.
+fn first_alternative(path: &str) -> &str {
+    path.split('|').next().unwrap().trim()
+}
+
+/// Return the fields of a single query path, which are empty for `$`. The fields in
+/// the string of `!json` are not included, nor is `!base64`.
+fn fields_of(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_suffix("!base64").map_or(path, str::trim_end);
+    path.strip_prefix("$.")
+        .map(|fields| {
+            fields
+                .split_once("!json.")
+                .map_or(fields, |(outer, _)| outer)
+        })
+        .into_iter()
+        .flat_map(|fields| fields.split('.'))
+}
+
+/// Find the first field of `fields` which is not found in `sample`, with the type of
+/// the value in which it was looked up.
+fn unmatched_field<'f, I>(fields: I, sample: &Value<'_>) -> Option<(&'f str, &'static str)>
+where
+    I: IntoIterator<Item = &'f str>,
+{
+    let mut value = sample;
+    for field in fields {
+        let next = match *value {
+            Value::Object(ref fields) => fields
+                .iter()
+                .find(|(key, _)| key.as_raw_str() == field)
+                .map(|(_, v)| v),
+            Value::Raw(..) => return None,
+            _ => None,
+        };
+        match next {
+            Some(next) => value = next,
+            None => return Some((field, value.type_name())),
+        }
+    }
+    None
+}
+
+/// A query path which does not match a sample record, reported by `QueryTree::check_against`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnmatchedPath<'a> {
+    /// identifier of the query path
+    pub path_id: usize,
+    /// the query path
+    pub path: &'a str,
+    /// the first field of the path which could not be found
+    pub field: &'a str,
+    /// the JSON type of the value in which `field` was looked up
+    pub found: &'static str,
+}
+
+impl<'a> fmt::Display for UnmatchedPath<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.found == "object" {
+            write!(f, "{}: no field {:?}", self.path, self.field)
+        } else {
+            write!(
+                f,
+                "{}: field {:?} is looked up in {}",
+                self.path, self.field, self.found
+            )
+        }
+    }
 }
 
 #[cfg(test)]
@@ -153,9 +680,236 @@ mod tests {
         }}
     }
 
+    #[test]
+    fn introspection() {
+        let mut tree = QueryTree::default();
+        for path in &["$.foo.bar", "$.baz", "$.foo"] {
+            tree.add_path(path).unwrap();
+        }
+        assert_eq!(tree.path(1), Some("$.baz"));
+        assert_eq!(tree.path(3), None);
+        assert_eq!(tree.node_for_path(0).unwrap().path_id(), Some(0));
+        assert_eq!(tree.node_for_path(2).unwrap().num_children(), 1);
+        assert!(tree.node_for_path(3).is_none());
+
+        let mut visited = vec![];
+        tree.visit(|fields, node| visited.push((fields.join("."), node.path_id())));
+        assert_eq!(
+            visited,
+            [
+                ("".to_owned(), None),
+                ("baz".to_owned(), Some(1)),
+                ("foo".to_owned(), Some(2)),
+                ("foo.bar".to_owned(), Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut tree = QueryTree::default();
+        for path in &["$.foo.bar", "$.baz | $.foo.qux", "$.\"q\""] {
+            tree.add_path(path).unwrap();
+        }
+        assert_eq!(
+            tree.to_dot(),
+            concat!(
+                "digraph QueryTree {\n",
+                "    n0 [label=\"$\"];\n",
+                "    n5 [label=\"\\\"q\\\"\\npath 2\"];\n",
+                "    n0 -> n5;\n",
+                "    n3 [label=\"baz\\npath 1\"];\n",
+                "    n0 -> n3;\n",
+                "    n1 [label=\"foo\"];\n",
+                "    n0 -> n1;\n",
+                "    n2 [label=\"bar\\npath 0\"];\n",
+                "    n1 -> n2;\n",
+                "    n4 [label=\"qux\\npath 1\"];\n",
+                "    n1 -> n4;\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn check_against() {
+        use crate::value::Value;
+
+        let mut tree = QueryTree::default();
+        for path in &["$.user.id", "$.user.nmae", "$.tags.a", "$.raw.x", "$.url"] {
+            tree.add_path(path).unwrap();
+        }
+        let sample = Value::Object(vec![
+            (
+                "user".into(),
+                Value::Object(vec![("id".into(), Value::Number(1.0))]),
+            ),
+            ("tags".into(), Value::Array(vec![])),
+            ("raw".into(), Value::raw("{\"x\": 1}")),
+        ]);
+        let unmatched = tree.check_against(&sample);
+        assert_eq!(
+            unmatched.iter().map(|u| u.path_id).collect::<Vec<_>>(),
+            [1, 2, 4]
+        );
+        assert_eq!(unmatched[0].to_string(), "$.user.nmae: no field \"nmae\"");
+        assert_eq!(
+            unmatched[1].to_string(),
+            "$.tags.a: field \"a\" is looked up in array"
+        );
+    }
+
+    #[test]
+    fn named_paths() {
+        let mut tree = QueryTree::default();
+        tree.add_named("user_id", "$.user.id").unwrap();
+        tree.add_path("$.url").unwrap();
+        assert!(tree.add_named("user_id", "$.id").is_err());
+        assert!(tree.add_named("$.url", "$.id").is_err());
+        assert_eq!(tree.num_paths(), 2);
+
+        assert_eq!(tree.name(0), Some("user_id"));
+        assert_eq!(tree.name(1), Some("$.url"));
+        assert_eq!(tree.name(2), None);
+        assert_eq!(tree.path_id_of("user_id"), Some(0));
+        assert_eq!(tree.path_id_of("$.url"), Some(1));
+        assert_eq!(tree.path_id_of("$.user.id"), Some(0));
+        assert_eq!(tree.path_id_of("id"), None);
+
+        // a path cannot be the name of another path either.
+        tree.add_named("$.a", "$.b").unwrap();
+        assert!(tree.add_path("$.a").is_err());
+        assert!(tree.add_path("$.a ?? 1").is_err());
+        assert!(tree.add_named("a", "$.a").is_err());
+        assert_eq!(tree.num_paths(), 3);
+        assert_eq!(tree.path_id_of("$.a"), Some(2));
+    }
+
+    #[test]
+    fn default_values() {
+        let mut tree = QueryTree::default();
+        tree.add_path("$.retries ?? 0").unwrap();
+        tree.add_named("name", r#"$.user.name??"?? none""#).unwrap();
+        tree.add_path("$.id").unwrap();
+        assert_eq!(tree.paths(), &["$.retries", "$.user.name", "$.id"]);
+        assert_eq!(tree.default_value(0), Some("0"));
+        assert_eq!(tree.default_value(1), Some(r#""?? none""#));
+        assert_eq!(tree.default_value(2), None);
+        assert!(tree.node_for_path(1).is_some());
+        assert!(tree.add_path("$.a ?? nil").is_err());
+        assert!(tree.add_path("$.a ??").is_err());
+    }
+
+    #[test]
+    fn alternative_paths() {
+        let mut tree = QueryTree::default();
+        tree.add_path("$.user_id | $.uid | $.user.id ?? 0").unwrap();
+        tree.add_path("$.name").unwrap();
+        tree.add_path("$.a|$.b").unwrap();
+        assert_eq!(
+            tree.paths(),
+            &["$.user_id | $.uid | $.user.id", "$.name", "$.a|$.b"]
+        );
+        assert_eq!(tree.num_paths(), 3);
+        assert_eq!(tree.num_slots(), 6);
+        assert_eq!(tree.default_value(0), Some("0"));
+        assert_eq!(tree.max_level(), 2);
+
+        let slots: Vec<_> = ["user_id", "uid", "name", "a", "b"]
+            .iter()
+            .map(|f| tree.as_node().find_child(f).unwrap().path_id().unwrap())
+            .collect();
+        assert_eq!(slots, [0, 3, 1, 2, 5]);
+        let id = tree.node_for_path(0).unwrap();
+        assert_eq!(id.path_id(), Some(0));
+        let user_id = tree.as_node().find_child("user").unwrap().find_child("id");
+        assert_eq!(user_id.unwrap().path_id(), Some(4));
+        assert_eq!(tree.path_of_slot(4), Some(0));
+        assert_eq!(tree.path_of_slot(5), Some(2));
+        assert_eq!(tree.path_of_slot(6), None);
+
+        let mut results = vec![None, Some("x"), None, Some("1"), Some("2"), Some("b")];
+        tree.coalesce(&mut results, Option::is_none);
+        assert_eq!(results, [Some("1"), Some("x"), Some("b")]);
+
+        assert!(tree.add_path("$.c | $.uid").is_err());
+        assert!(tree.add_path("$.uid").is_err());
+        assert!(tree.add_path("$.c | $.c").is_err());
+        assert!(tree.add_path("$.c | ").is_err());
+        assert!(tree.add_path("$.c | d").is_err());
+        assert_eq!(tree.num_slots(), 6);
+
+        let sample = Value::Object(vec![("uid".into(), Value::Null)]);
+        let unmatched = tree.check_against(&sample);
+        assert_eq!(
+            unmatched
+                .iter()
+                .map(|u| (u.path_id, u.field))
+                .collect::<Vec<_>>(),
+            [(1, "name"), (2, "a")]
+        );
+    }
+
+    #[test]
+    fn root_path() {
+        let mut tree = QueryTree::default();
+        for path in &["$.a", "$"] {
+            tree.add_path(path).unwrap();
+        }
+        assert_eq!(tree.as_node().path_id(), Some(1));
+        assert_eq!(tree.node_for_path(1).unwrap().node_id(), 0);
+        assert_eq!(tree.max_level(), 1);
+        assert!(tree.check_against(&Value::Object(vec![])).len() == 1);
+
+        assert!(tree.add_path("$ | $.b").is_err());
+        assert!(tree.add_path("$.b | $").is_err());
+        assert!(tree.add_path("$.").is_err());
+    }
+
+    #[test]
+    fn embedded_paths() {
+        let mut tree = QueryTree::default();
+        for path in &["$.a!json.b", "$.c", "$.c!json.d!json.e", "$.a!json.f"] {
+            tree.add_path(path).unwrap();
+        }
+        assert_eq!(tree.num_paths(), 4);
+        assert_eq!(tree.node_for_path(0).unwrap().path_id(), Some(0));
+        assert_eq!(tree.max_level(), 1);
+
+        let embedded = tree.embedded();
+        assert_eq!(embedded.len(), 2);
+        assert_eq!(
+            (embedded[0].source(), embedded[0].paths()),
+            (0, &[0, 3][..])
+        );
+        assert_eq!(embedded[0].tree().paths(), ["$.a!json.b", "$.a!json.f"]);
+        assert_eq!((embedded[1].source(), embedded[1].paths()), (1, &[2][..]));
+        assert_eq!(embedded[1].tree().embedded()[0].tree().max_level(), 1);
+
+        assert!(tree.add_path("$.a").is_err());
+        tree.add_path("$.g!base64").unwrap();
+        assert_eq!(tree.paths()[4], "$.g!base64");
+        assert_eq!(tree.path_id_of("$.g!base64"), Some(4));
+        assert_eq!(tree.node_for_path(4).unwrap().path_id(), Some(4));
+        assert!(tree.is_base64(4) && !tree.is_base64(0));
+        let err = tree.add_path("$.g").unwrap_err();
+        assert!(err
+            .iter()
+            .any(|e| e.to_string() == r#""$.g" is already queried by "$.g!base64""#));
+        for path in &[
+            "$.x!json",
+            "$.x!json.",
+            "$.!json.y",
+            "$.x!json.y | $.z",
+            "$.x!base64.y",
+        ] {
+            assert!(tree.add_path(path).is_err(), "{}", path);
+        }
+    }
+
     #[test]
     fn invalid_query() {
-        let cases: &[&str] = &["", "$", "$.."];
+        let cases: &[&str] = &["", "$a", "$.."];
         for c in cases {
             let mut tree = QueryTree::default();
             assert!(tree.add_path(c).is_err());
@@ -175,6 +929,11 @@ mod tests {
                     max_level: 1,
                     num_nodes: 2,
                     paths: vec!["$.foo"],
+                    names: vec![None],
+                    defaults: vec![None],
+                    base64: vec![false],
+                    alternatives: vec![],
+                    embedded: vec![],
                     root: QueryNode {
                         node_id: 0,
                         query_id: None,
@@ -196,6 +955,11 @@ mod tests {
                     max_level: 2,
                     num_nodes: 3,
                     paths: vec!["$.foo.bar"],
+                    names: vec![None],
+                    defaults: vec![None],
+                    base64: vec![false],
+                    alternatives: vec![],
+                    embedded: vec![],
                     root: QueryNode {
                         node_id: 0,
                         query_id: None,
@@ -224,6 +988,11 @@ mod tests {
                     max_level: 3,
                     num_nodes: 6,
                     paths: vec!["$.f1.e1", "$.f1.e1.c3", "$.f2.e1"],
+                    names: vec![None; 3],
+                    defaults: vec![None; 3],
+                    base64: vec![false; 3],
+                    alternatives: vec![],
+                    embedded: vec![],
                     root: QueryNode {
                         node_id: 0,
                         query_id: None,
@@ -1,288 +1,3990 @@
 #![allow(missing_docs)]
 
+#[cfg(feature = "base64")]
+use crate::base64;
 use crate::errors::{Error, ErrorKind, Result, ResultExt};
 use crate::index_builder::backend::Backend;
-use crate::index_builder::{IndexBuilder, StructuralIndex};
+use crate::index_builder::{
+    BufferPolicy, IndexBuffers, IndexBuilder, StructuralIndex, ValueTrimming,
+};
 use crate::pattern_tree::PatternTree;
 use crate::query::{QueryNode, QueryTree};
-use std::cell::RefCell;
-use std::collections::VecDeque;
+use crate::scan;
+use crate::typed::{self, JsonType};
+use crate::validate;
+use crate::value::{self, canonical, EscapedStr, ValueType};
+use itertools::Either;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error as StdError;
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, TryLockError};
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum QueryParserMode {
     Basic,
     Speculative,
 }
 
+/// The direction to scan the fields of objects in basic mode
+///
+/// Scanning stops once all fields of the query have been found (unless
+/// `QueryParser::scan_all_fields` is set), so the direction determines which value
+/// wins if a key appears more than once, and which part of the record is touched.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ScanDirection {
+    /// from the first field to the last; the first duplicate wins
+    Forward,
+    /// from the last field to the first; the last duplicate wins
+    ///
+    /// This is the default, and keeps the results of earlier versions, which always
+    /// scanned in reverse.
+    #[default]
+    Reverse,
+}
+
+/// Whether the string values returned from `QueryParser` include their quotes
+///
+/// The quotes are included by default, so that the results are valid JSON texts and
+/// strings are distinguishable from the other values. Note that `Parser` always
+/// returns the contents of strings without quotes, as `Value::String`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum StringQuotes {
+    /// `"foo"` is returned as `"\"foo\""`
+    #[default]
+    Include,
+    /// `"foo"` is returned as `"foo"`, still escaped
+    Exclude,
+}
+
+/// How `QueryParser` handles the raw control characters (`U+0000` to `U+001F`) within
+/// the strings of the extracted values, which are forbidden by JSON but often found
+/// in logs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ControlChars {
+    /// return the values as they are
+    #[default]
+    Accept,
+    /// fail with `ErrorKind::InvalidRecord`, checked with the control character bitmap
+    /// of the index
+    Reject,
+    /// escape them (e.g. as `\n` or `\u0001`) in the results of `parse_cow`, so that
+    /// the values are valid JSON texts. The other results are returned as they are.
+    Escape,
+}
+
+/// A parser which extracts the values of query paths from records.
+///
+/// The parser is `Sync` if the backend is, so it can be shared among threads. The
+/// trained patterns are guarded by locks, and `parse_with_workspace` should be used
+/// to give each thread its own buffers.
+///
+/// The parser may also be called again from within its own callbacks (e.g. a
+/// transform registered by `map`, or the failure sink). The nested parses use
+/// temporary buffers while the internal ones are in use, and no locks are held while
+/// the transforms and the prefilter are called.
+///
+/// The records must be objects, and only the parts needed to locate the values of the
+/// query paths are examined, so some malformed records are accepted (e.g. `{"id":0,}`
+/// or `{"a" b}`). Use `validate::validate` before parsing to reject them.
 #[derive(Debug)]
 pub struct QueryParser<'a, B: Backend> {
     index_builder: IndexBuilder<B>,
     query_tree: QueryTree<'a>,
-    colon_positions: Vec<RefCell<Vec<usize>>>,
-    pattern_trees: Vec<RefCell<PatternTree>>,
+    workspace: Mutex<Workspace>,
+    pattern_trees: PatternTrees,
     save_patterns: bool,
     allow_fallback: bool,
+    unescape_strings: bool,
+    unescape_fields: bool,
+    string_quotes: StringQuotes,
+    value_trimming: ValueTrimming,
+    scan_direction: ScanDirection,
+    scan_all_fields: bool,
+    cache_offsets: bool,
+    /// the quoted field names scanned by the pre-check, if enabled
+    precheck_keys: Option<Vec<String>>,
+    small_record_len: usize,
+    preserve_offsets: bool,
+    buffer_policy: BufferPolicy,
+    control_chars: ControlChars,
+    max_value_len: Option<usize>,
+    prefilter: Option<Prefilter>,
+    failure_sink: Option<FailureSink>,
+    /// the expected types of the values of each path
+    expected_types: Vec<Option<JsonType>>,
+    /// the transforms of the values of each path, applied by `parse_cow`
+    transforms: Vec<Option<Transform>>,
+    /// whether all values of each path are collected by `parse_all`
+    all_matches: Vec<bool>,
+    /// whether each node has a descendant path whose all values are collected
+    collects_all: Vec<bool>,
+    /// whether the first duplicate wins for each path, regardless of `scan_direction`
+    first_match: Vec<bool>,
+    /// whether each node has a descendant path whose first duplicate wins
+    first_matches: Vec<bool>,
+    /// the maximum number of the captured failures of speculation
+    capture_failures: usize,
+    failures: Mutex<Vec<SpeculationFailure>>,
+    /// the parsers of the paths within the strings, for `QueryTree::embedded`, which
+    /// follow the options of this parser
+    embedded: Vec<QueryParser<'a, B>>,
+}
+
+/// A clone starts with an empty workspace and a copy of the trained patterns, which
+/// are trained independently afterwards.
+impl<'a, B: Backend + Clone> Clone for QueryParser<'a, B> {
+    fn clone(&self) -> Self {
+        Self {
+            index_builder: self.index_builder.clone(),
+            query_tree: self.query_tree.clone(),
+            workspace: Mutex::new(Workspace::new()),
+            pattern_trees: self.pattern_trees.clone(),
+            save_patterns: self.save_patterns,
+            allow_fallback: self.allow_fallback,
+            unescape_strings: self.unescape_strings,
+            unescape_fields: self.unescape_fields,
+            string_quotes: self.string_quotes,
+            value_trimming: self.value_trimming,
+            scan_direction: self.scan_direction,
+            scan_all_fields: self.scan_all_fields,
+            cache_offsets: self.cache_offsets,
+            precheck_keys: self.precheck_keys.clone(),
+            small_record_len: self.small_record_len,
+            preserve_offsets: self.preserve_offsets,
+            buffer_policy: self.buffer_policy,
+            control_chars: self.control_chars,
+            max_value_len: self.max_value_len,
+            prefilter: self.prefilter.clone(),
+            failure_sink: self.failure_sink.clone(),
+            expected_types: self.expected_types.clone(),
+            transforms: self.transforms.clone(),
+            all_matches: self.all_matches.clone(),
+            collects_all: self.collects_all.clone(),
+            first_match: self.first_match.clone(),
+            first_matches: self.first_matches.clone(),
+            capture_failures: self.capture_failures,
+            failures: Mutex::new(vec![]),
+            embedded: self.embedded.clone(),
+        }
+    }
+}
+
+/// A transform of the values of a path, registered by `QueryParser::map`
+#[derive(Clone)]
+struct Transform(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Transform")
+    }
+}
+
+/// A predicate of records, registered by `QueryParser::with_prefilter`
+#[derive(Clone)]
+struct Prefilter(Arc<dyn Fn(&str) -> bool + Send + Sync>);
+
+impl fmt::Debug for Prefilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Prefilter")
+    }
+}
+
+/// A callback of invalid records, registered by `QueryParser::failure_sink`
+#[derive(Clone)]
+struct FailureSink {
+    f: Arc<dyn Fn(&ParseFailure<'_>) + Send + Sync>,
+    max_record_len: usize,
+}
+
+impl fmt::Debug for FailureSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FailureSink")
+    }
+}
+
+/// Pattern trees for each node of the query tree
+#[derive(Debug)]
+enum PatternTrees {
+    Training(Vec<RwLock<PatternTree>>),
+    Frozen(Vec<PatternTree>),
+}
+
+impl Clone for PatternTrees {
+    fn clone(&self) -> Self {
+        match *self {
+            PatternTrees::Training(ref trees) => PatternTrees::Training(
+                trees
+                    .iter()
+                    .map(|tree| {
+                        RwLock::new(tree.read().unwrap_or_else(PoisonError::into_inner).clone())
+                    })
+                    .collect(),
+            ),
+            PatternTrees::Frozen(ref trees) => PatternTrees::Frozen(trees.clone()),
+        }
+    }
 }
 
 impl<'a, B: Backend> QueryParser<'a, B> {
     pub fn new(index_builder: IndexBuilder<B>, query_tree: QueryTree<'a>) -> Self {
         let num_nodes = query_tree.num_nodes();
+        let num_paths = query_tree.num_paths();
+        let num_slots = query_tree.num_slots();
 
         let mut pattern_trees = Vec::with_capacity(num_nodes);
         for _ in 0..num_nodes {
-            pattern_trees.push(RefCell::new(Default::default()));
+            pattern_trees.push(RwLock::new(Default::default()));
         }
+        let embedded = query_tree
+            .embedded()
+            .iter()
+            .map(|embedded| {
+                let tree = embedded.tree().clone();
+                QueryParser::new(index_builder.with_level(tree.max_level()), tree)
+            })
+            .collect();
 
         Self {
             index_builder,
             query_tree,
-            colon_positions: vec![RefCell::new(vec![]); num_nodes],
-            pattern_trees,
+            workspace: Mutex::new(Workspace::new()),
+            pattern_trees: PatternTrees::Training(pattern_trees),
             save_patterns: false,
             allow_fallback: true,
+            unescape_strings: false,
+            unescape_fields: false,
+            string_quotes: StringQuotes::default(),
+            value_trimming: ValueTrimming::default(),
+            scan_direction: ScanDirection::default(),
+            scan_all_fields: false,
+            cache_offsets: false,
+            precheck_keys: None,
+            small_record_len: 0,
+            preserve_offsets: false,
+            buffer_policy: BufferPolicy::KeepPeak,
+            control_chars: ControlChars::default(),
+            max_value_len: None,
+            prefilter: None,
+            failure_sink: None,
+            expected_types: vec![None; num_paths],
+            transforms: vec![None; num_paths],
+            all_matches: vec![false; num_slots],
+            collects_all: vec![false; num_nodes],
+            first_match: vec![false; num_slots],
+            first_matches: vec![false; num_nodes],
+            capture_failures: 0,
+            failures: Mutex::new(vec![]),
+            embedded,
+        }
+    }
+
+    /// Apply an option of this parser to the parsers of the paths within the strings.
+    fn configure_embedded(&mut self, f: impl Fn(&mut QueryParser<'a, B>)) {
+        self.embedded.iter_mut().for_each(f);
+    }
+
+    /// Collect all values of the path `path_id` by `parse_all` if the key appears
+    /// more than once in an object, instead of only the first one. `parse` is not
+    /// affected by this option.
+    pub fn all_matches(&mut self, path_id: usize, v: bool) {
+        set_slots(&self.query_tree, path_id, v, &mut self.all_matches);
+        mark_nodes(
+            self.query_tree.as_node(),
+            &self.all_matches,
+            &mut self.collects_all,
+        );
+    }
+
+    /// Take the value of the path `path_id` from the first of the duplicates of each
+    /// key on the path, as if the fields were scanned forward, regardless of
+    /// `scan_direction`. The other paths are not affected.
+    ///
+    /// This applies to `parse` and `parse_all` in basic mode and to the small records.
+    /// The speculative mode takes the values at the trained positions.
+    pub fn first_match(&mut self, path_id: usize, v: bool) {
+        set_slots(&self.query_tree, path_id, v, &mut self.first_match);
+        mark_nodes(
+            self.query_tree.as_node(),
+            &self.first_match,
+            &mut self.first_matches,
+        );
+    }
+
+    /// Copy the values of the `first_match` paths within `node` from `found`, which
+    /// have been extracted from a duplicate of the key of `node` preceding the others.
+    fn override_first_matches<'s>(
+        &self,
+        node: &QueryNode<'_>,
+        found: &[Option<&'s str>],
+        results: &mut [Option<&'s str>],
+    ) {
+        if let Some(id) = node.path_id() {
+            if self.first_match[id] {
+                results[id] = found[id];
+            }
+        }
+        for (_, child) in node.children() {
+            if self.first_matches[child.node_id()] {
+                self.override_first_matches(child, found, results);
+            }
         }
     }
 
     pub fn save_patterns(&mut self, v: bool) {
         self.save_patterns = v;
+        self.configure_embedded(|parser| parser.save_patterns(v));
+    }
+
+    /// Halve the weights of the trained patterns every `interval` records, so that
+    /// the parser adapts to the changes of field order. See `PatternTree::decay`.
+    pub fn decay_patterns(&mut self, interval: Option<usize>) {
+        if let PatternTrees::Training(ref mut trees) = self.pattern_trees {
+            for tree in trees {
+                tree.get_mut()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .set_decay_interval(interval);
+            }
+        }
+        self.configure_embedded(|parser| parser.decay_patterns(interval));
+    }
+
+    /// Return the total bytes held by the internal workspace and by the internal buffers
+    /// of the index builder, which are retained among parses, or `None` if they are in
+    /// use (e.g. when called from a callback during a parse, or while another thread
+    /// parses a record).
+    pub fn memory_usage(&self) -> Option<usize> {
+        let workspace = match self.workspace.try_lock() {
+            Ok(workspace) => workspace,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        let embedded = self
+            .embedded
+            .iter()
+            .map(QueryParser::memory_usage)
+            .sum::<Option<usize>>()?;
+        Some(workspace.memory_usage() + self.index_builder.memory_usage()?.total() + embedded)
+    }
+
+    /// Return the query tree of this parser.
+    pub fn query_tree(&self) -> &QueryTree<'a> {
+        &self.query_tree
+    }
+
+    /// Return a snapshot of the patterns trained for the node `node_id` of the query
+    /// tree.
+    pub fn pattern_tree(&self, node_id: usize) -> PatternTree {
+        match self.pattern_trees {
+            PatternTrees::Training(ref trees) => trees[node_id]
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            PatternTrees::Frozen(ref trees) => trees[node_id].clone(),
+        }
     }
 
     pub fn allow_fallback(&mut self, v: bool) {
         self.allow_fallback = v;
+        self.configure_embedded(|parser| parser.allow_fallback(v));
     }
 
-    pub fn parse<'s>(
-        &self,
-        record: &'s str,
-        mode: QueryParserMode,
-    ) -> Result<Vec<Option<&'s str>>> {
-        let record = record.trim();
-        if !record.starts_with("{") {
-            return Err(Error::from(ErrorKind::InvalidRecord))
-                .chain_err(|| "QueryParser supports only object parsing");
-        }
+    /// Capture up to `limit` failures of speculation, which are taken by
+    /// `take_failures`. The further failures are not captured until they are taken.
+    pub fn capture_failures(&mut self, limit: usize) {
+        self.capture_failures = limit;
+    }
+
+    /// Take the failures of speculation captured so far.
+    pub fn take_failures(&self) -> Vec<SpeculationFailure> {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        std::mem::take(&mut *failures)
+    }
 
+    /// Parse the record of `failure` speculatively again with the current patterns,
+    /// and return the failure if the speculation still fails.
+    ///
+    /// The failure does not fall back to basic mode, and is not captured.
+    pub fn replay(&self, failure: &SpeculationFailure) -> Result<Option<SpeculationFailure>> {
+        let (record, begin, end) = self.bounds(&failure.record)?;
         let index = self.index_builder.build(record)?;
+        let mut results = vec![None; self.query_tree.num_slots()];
+        let mut trace = Trace::default();
+        let root = self.query_tree.as_node();
+        self.parse_speculative(&index, begin, end, root, &mut results, Some(&mut trace))?;
+        Ok(trace.failure.map(|replayed| SpeculationFailure {
+            record: record.to_owned(),
+            ..replayed
+        }))
+    }
 
-        let mut result = vec![None; self.query_tree.num_paths()];
-        match mode {
-            QueryParserMode::Basic => {
-                self.parse_basic(
-                    &index,
-                    0,
-                    record.len(),
-                    self.query_tree.as_node(),
-                    &mut result[..],
-                )?;
-            }
-            QueryParserMode::Speculative => {
-                let success = self.parse_speculative(
-                    &index,
-                    0,
-                    record.len(),
-                    self.query_tree.as_node(),
-                    &mut result[..],
-                )?;
-                if !success {
-                    if !self.allow_fallback {
-                        return Err(ErrorKind::FailedSpeculativeParse.into());
-                    }
-                    self.parse_basic(
-                        &index,
-                        0,
-                        record.len(),
-                        self.query_tree.as_node(),
-                        &mut result[..],
-                    )?;
-                }
+    /// Merge the patterns trained by `other`, e.g. on another thread or shard.
+    ///
+    /// Both parsers must be created from the same query paths, in the same order.
+    pub fn merge_patterns<B2: Backend>(&mut self, other: QueryParser<'_, B2>) -> Result<()> {
+        if self.query_tree.paths() != other.query_tree.paths() {
+            return Err(Error::from(ErrorKind::InvalidQuery))
+                .chain_err(|| "cannot merge the patterns of different query trees");
+        }
+        let others: Vec<PatternTree> = match other.pattern_trees {
+            PatternTrees::Training(trees) => trees
+                .into_iter()
+                .map(|tree| tree.into_inner().unwrap_or_else(PoisonError::into_inner))
+                .collect(),
+            PatternTrees::Frozen(trees) => trees,
+        };
+        for (i, other) in others.into_iter().enumerate() {
+            match self.pattern_trees {
+                PatternTrees::Training(ref mut trees) => trees[i]
+                    .get_mut()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .merge(other),
+                PatternTrees::Frozen(ref mut trees) => trees[i].merge(other),
             }
         }
-
-        Ok(result)
+        Ok(())
     }
 
-    #[inline]
-    fn parse_basic<'b, 's>(
-        &self,
-        index: &StructuralIndex<'b, 's>,
-        begin: usize,
-        mut end: usize,
-        node: &QueryNode<'_>,
-        results: &mut [Option<&'s str>],
-    ) -> Result<()> {
-        // TODO: avoid to calculate colon positions if it has already generated.
-        if !index.colon_positions(
-            begin,
-            end,
-            node.level(),
-            &mut *RefCell::borrow_mut(&self.colon_positions[node.node_id()]),
-        ) {
-            return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "mismatched level");
+    /// Consume the trained patterns into an immutable parser which can be cheaply
+    /// cloned and shared among threads.
+    pub fn freeze(mut self) -> FrozenQueryParser<'a, B> {
+        if let PatternTrees::Training(trees) = self.pattern_trees {
+            let trees = trees
+                .into_iter()
+                .map(|tree| {
+                    let mut tree = tree.into_inner().unwrap_or_else(PoisonError::into_inner);
+                    tree.sort_by_weight();
+                    tree
+                })
+                .collect();
+            self.pattern_trees = PatternTrees::Frozen(trees);
+        }
+        self.save_patterns = false;
+        FrozenQueryParser {
+            parser: Arc::new(self),
         }
-        let cp = self.colon_positions[node.node_id()].borrow();
+    }
 
-        let mut pattern = VecDeque::with_capacity(node.num_children());
+    /// Set the strategy to determine the boundaries of the extracted values.
+    pub fn value_trimming(&mut self, v: ValueTrimming) {
+        self.value_trimming = v;
+        self.configure_embedded(|parser| parser.value_trimming(v));
+    }
 
-        for i in (0..cp.len()).rev() {
-            let (field, fsi) =
-                index.find_object_field(if i == 0 { begin } else { cp[i - 1] }, cp[i])?;
-            if let Some(ch) = node.find_child(field.as_raw_str()) {
-                let (vsi, vei) = index.find_object_value(cp[i] + 1, end, i == cp.len() - 1);
+    /// Set the direction to scan the fields in basic mode, `ScanDirection::Reverse`
+    /// by default. See `ScanDirection`.
+    ///
+    /// `parse_all` scans in the same direction, so that the first value of each path is
+    /// the same as that of `parse`. Use `first_match` to override it for some paths.
+    pub fn scan_direction(&mut self, v: ScanDirection) {
+        self.scan_direction = v;
+        self.configure_embedded(|parser| parser.scan_direction(v));
+    }
 
-                if let Some(id) = ch.path_id() {
-                    results[id] = Some(index.substr(vsi, vei));
-                }
+    /// Scan all fields of objects in basic mode, even after all fields of the query
+    /// have been found, so that malformed field names are detected in the rest of
+    /// the object. The winner of duplicated keys is not changed.
+    pub fn scan_all_fields(&mut self, v: bool) {
+        self.scan_all_fields = v;
+        self.configure_embedded(|parser| parser.scan_all_fields(v));
+    }
 
-                if !ch.is_leaf() {
-                    self.parse_basic(index, vsi, vei, ch, results)?;
+    /// Strip the quotes and decode the escape sequences of string values returned
+    /// from `parse_cow`. Objects, arrays and other values are kept raw.
+    pub fn unescape_strings(&mut self, v: bool) {
+        self.unescape_strings = v;
+    }
+
+    /// Register a transform of the values of the path `path_id`, e.g. to normalize them
+    /// with `|s| s.trim_matches('"').to_lowercase()`.
+    ///
+    /// The transform is applied by `parse_cow` to the matched values, after they are
+    /// unescaped or stripped of their quotes, but not to the default values. The other
+    /// results are returned as they are. Registering another transform for the same
+    /// path replaces the previous one.
+    pub fn map<F>(&mut self, path_id: usize, f: F)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.transforms[path_id] = Some(Transform(Arc::new(f)));
+    }
+
+    /// Cache the byte offsets of the fields found in speculative mode, and try them
+    /// first on the next record of the same workspace.
+    ///
+    /// The cached offsets are verified by checking the bits of the quotes and colons
+    /// at those offsets, so that the colons are not enumerated at all when the records
+    /// have the same byte layout (e.g. produced by the same serializer with fixed-width
+    /// values). Otherwise, the trained patterns are used as usual.
+    pub fn cache_offsets(&mut self, v: bool) {
+        self.cache_offsets = v;
+        self.configure_embedded(|parser| parser.cache_offsets(v));
+    }
+
+    /// Scan the record for the queried field names before building the index, and
+    /// return no values at all if none of them appears.
+    ///
+    /// The longest field name of each path, which is likely the rarest one, is searched
+    /// as a substring with its quotes, so that sparse extraction from heterogeneous
+    /// records skips the records without the fields. The check is skipped if
+    /// `unescape_fields` is set, since the names may be escaped in the record.
+    pub fn key_precheck(&mut self, v: bool) {
+        self.precheck_keys = if v {
+            let mut keys = vec![];
+            self.query_tree.visit(|fields, node| {
+                if node.path_id().is_some() {
+                    match fields.iter().max_by_key(|field| field.len()) {
+                        Some(field) => keys.push(format!("\"{}\"", field)),
+                        None => keys.push(String::new()), // always present
+                    }
                 }
+            });
+            keys.sort();
+            keys.dedup();
+            Some(keys)
+        } else {
+            None
+        };
+    }
+
+    /// Set the length of records below which the bytes are scanned directly, without
+    /// building the structural index (e.g. `parser::DEFAULT_SMALL_RECORD_LEN`).
+    ///
+    /// This is disabled by default, since the small records are parsed in the same way
+    /// in both modes and the patterns are not trained from them. As with
+    /// `Parser::small_record_len`, some malformed strings are not rejected in the same
+    /// way by the index and the scan. `parse_all` always builds the index.
+    pub fn small_record_len(&mut self, len: usize) {
+        self.small_record_len = len;
+        self.configure_embedded(|parser| parser.small_record_len(len));
+    }
+
+    /// Parse the records without trimming their surrounding whitespace (e.g. the
+    /// trailing `\r\n`), so that the byte offsets reported while parsing (in the error
+    /// messages and in `SpeculationFailure`) refer to the record as it is given rather
+    /// than to the trimmed one. Disabled by default.
+    ///
+    /// The extracted values are the same in both modes.
+    pub fn preserve_offsets(&mut self, v: bool) {
+        self.preserve_offsets = v;
+    }
+
+    /// Set the retention policy of the buffers of the workspaces, including those of
+    /// the index builder (see `IndexBuilder::buffer_policy`).
+    pub fn buffer_policy(&mut self, policy: BufferPolicy) {
+        self.buffer_policy = policy;
+        self.index_builder.buffer_policy(policy);
+        self.configure_embedded(|parser| parser.buffer_policy(policy));
+    }
+
+    /// Check the record, and return the buffer to parse it in with the range of its
+    /// object, which is the whole buffer unless the offsets are preserved.
+    fn bounds<'s>(&self, record: &'s str) -> Result<(&'s str, usize, usize)> {
+        let trimmed = check_record(record)?;
+        if !self.preserve_offsets {
+            return Ok((trimmed, 0, trimmed.len()));
+        }
+        let begin = trimmed.as_ptr() as usize - record.as_ptr() as usize;
+        Ok((record, begin, begin + trimmed.len()))
+    }
+
+    /// Set a predicate of the raw records, which is called before anything else (even
+    /// before the record is checked to be an object), so that the irrelevant records in
+    /// mixed streams are cheaply skipped, e.g. with `|record| record.contains("\"GET\"")`.
+    ///
+    /// No values are returned for the records rejected by the predicate, as if none of
+    /// the paths were found.
+    pub fn with_prefilter<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.prefilter = Some(Prefilter(Arc::new(f)));
+    }
+
+    /// Call `f` with every record which fails to parse because it is invalid (i.e. the
+    /// errors of `ErrorKind::InvalidRecord`, `InvalidType`, `TypeMismatch` and
+    /// `DepthLimitExceeded`),
+    /// e.g. to send them into a quarantine queue by `ParseFailure::to_json`.
+    ///
+    /// The records are truncated to `max_record_len` bytes. The error is still returned
+    /// from the parse after `f` is called.
+    pub fn failure_sink<F>(&mut self, max_record_len: usize, f: F)
+    where
+        F: Fn(&ParseFailure<'_>) + Send + Sync + 'static,
+    {
+        self.failure_sink = Some(FailureSink {
+            f: Arc::new(f),
+            max_record_len,
+        });
+    }
 
-                pattern.push_front((field.as_raw_str().to_owned(), i));
-                if pattern.len() == node.num_children() {
-                    if self.save_patterns {
-                        self.pattern_trees[node.node_id()]
-                            .borrow_mut()
-                            .append(pattern);
+    /// Pass the failure of parsing `record` to the failure sink, if any.
+    fn report<T>(&self, record: &str, result: Result<T>) -> Result<T> {
+        let err = match (&self.failure_sink, result) {
+            (Some(sink), Err(err)) => {
+                if let Some(kind) = failure_kind(&err) {
+                    let mut len = cmp::min(record.len(), sink.max_record_len);
+                    while !record.is_char_boundary(len) {
+                        len -= 1;
                     }
-                    break;
+                    (sink.f)(&ParseFailure {
+                        record: &record[..len],
+                        truncated: len < record.len(),
+                        offset: validate::error_offset(record),
+                        kind,
+                        message: err
+                            .iter()
+                            .map(|e| e.to_string())
+                            .collect::<Vec<_>>()
+                            .join(": "),
+                    });
                 }
+                err
             }
+            (_, result) => return result,
+        };
+        Err(err)
+    }
 
-            end = fsi - 1;
+    /// Return whether the raw record is accepted by the prefilter, if any.
+    fn accepts(&self, record: &str) -> bool {
+        match self.prefilter {
+            Some(Prefilter(ref f)) => f(record),
+            None => true,
         }
-
-        Ok(())
     }
 
-    #[inline]
-    fn parse_speculative<'b, 's>(
-        &self,
-        index: &StructuralIndex<'b, 's>,
-        begin: usize,
-        end: usize,
-        node: &QueryNode<'_>,
-        results: &mut [Option<&'s str>],
-    ) -> Result<bool> {
-        if !index.colon_positions(
-            begin,
-            end,
-            node.level(),
-            &mut *RefCell::borrow_mut(&self.colon_positions[node.node_id()]),
-        ) {
-            return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "mismatched level");
+    /// Return whether the record may contain any value of the query.
+    fn keys_present(&self, record: &str) -> bool {
+        match self.precheck_keys {
+            Some(ref keys) if !self.unescape_fields => {
+                keys.iter().any(|key| record.contains(key.as_str()))
+            }
+            _ => true,
         }
-        let cp = self.colon_positions[node.node_id()].borrow();
+    }
 
-        let pattern_tree = self.pattern_trees[node.node_id()].borrow();
-        let mut pattern_node = pattern_tree.root_node();
+    /// Set whether the string values include their quotes. See `StringQuotes`.
+    ///
+    /// This applies to all results as raw slices, including `parse_all` and
+    /// `parse_named`, and to `parse_cow` unless `unescape_strings` is set (which always
+    /// strips the quotes). `parse_to` is not affected.
+    pub fn string_quotes(&mut self, v: StringQuotes) {
+        self.string_quotes = v;
+    }
 
-        while !pattern_node.is_leaf() {
-            let mut success = false;
-            for child in pattern_node.children() {
-                let i = child.position();
-                let (field, _) =
-                    index.find_object_field(if i == 0 { begin } else { cp[i - 1] }, cp[i])?;
-                success = field.as_raw_str() == child.field();
-                if success {
-                    let ch_node = node.find_child(field.as_raw_str()).unwrap();
+    /// Set how to handle the control characters within strings. See `ControlChars`.
+    ///
+    /// `Reject` enables `IndexBuilder::control_bitmap` of the index builder.
+    pub fn control_chars(&mut self, v: ControlChars) {
+        self.control_chars = v;
+        self.index_builder.control_bitmap(v == ControlChars::Reject);
+        self.configure_embedded(|parser| parser.control_chars(v));
+    }
 
-                    let fsi = if i == cp.len() - 1 {
-                        end
-                    } else {
-                        index.find_object_field(cp[i], cp[i + 1])?.1 - 1
-                    };
-                    let (vsi, vei) = index.find_object_value(cp[i] + 1, fsi, i == cp.len() - 1);
+    /// Fail if any value contains control characters and they are rejected.
+    fn check_control_chars<'s, I>(
+        &self,
+        index: Option<&StructuralIndex<'_, 's>>,
+        record: &str,
+        values: I,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = &'s str>,
+    {
+        if self.control_chars != ControlChars::Reject {
+            return Ok(());
+        }
+        for value in values {
+            let begin = value.as_ptr() as usize - record.as_ptr() as usize;
+            let found = index
+                .and_then(|index| index.contains_control(begin, begin + value.len()))
+                .unwrap_or_else(|| scan::contains_control(value));
+            if found {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| format!("control character in the value at {}", begin));
+            }
+        }
+        Ok(())
+    }
 
-                    if let Some(id) = ch_node.path_id() {
-                        results[id] = Some(index.substr(vsi, vei));
-                    }
+    /// Expect the values of the path `path_id` to be of type `ty`, or remove the
+    /// expectation by `None`.
+    ///
+    /// The type is checked only from the first byte of each value found, and the
+    /// parse fails with `ErrorKind::TypeMismatch` if it differs. The missing values
+    /// and the default values are not checked.
+    pub fn expect_type(&mut self, path_id: usize, ty: Option<JsonType>) {
+        self.expected_types[path_id] = ty;
+    }
 
-                    if !ch_node.is_leaf() {
-                        success &= self.parse_speculative(index, vsi, vei, ch_node, results)?;
-                    }
+    /// Fail if any value is not of the type expected for its path.
+    fn check_types<'s, I>(&self, values: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (usize, &'s str)>,
+    {
+        for (path_id, value) in values {
+            if let Some(expected) = self.expected_types[path_id] {
+                if JsonType::of(value) != Some(expected) {
+                    let path = self.query_tree.paths()[path_id].to_owned();
+                    return Err(ErrorKind::TypeMismatch(
+                        path,
+                        expected.name(),
+                        typed::found(value),
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
 
-                    pattern_node = child;
-                    break;
+    /// Cap the length of the returned values to `len` bytes, so that huge values (e.g.
+    /// embedded base64) are not copied accidentally when the results are owned later.
+    ///
+    /// The longer values are cut at the last character boundary within `len` bytes,
+    /// and are no longer valid JSON texts. This applies to the results of `parse`,
+    /// `parse_all`, `parse_named`, `parse_with_workspace` and `parse_cow` (in which the
+    /// strings are cut at the boundary of an escape sequence before being unescaped),
+    /// but not to `parse_to` and `parse_ranges`, which can be used to get the whole
+    /// extent of the values.
+    pub fn max_value_len(&mut self, len: Option<usize>) {
+        self.max_value_len = len;
+    }
+
+    /// Return the byte length of `value` capped by `max_value_len`.
+    fn capped_len(&self, value: &str) -> usize {
+        match self.max_value_len {
+            Some(mut len) if len < value.len() => {
+                while !value.is_char_boundary(len) {
+                    len -= 1;
                 }
+                len
             }
+            _ => value.len(),
+        }
+    }
 
-            if !success {
-                break;
+    /// Same as `parse`, but the byte ranges of the values in `record` are returned
+    /// instead of their slices, which are not capped by `max_value_len`.
+    pub fn parse_ranges(
+        &self,
+        record: &str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Range<usize>>>> {
+        let mut results = vec![];
+        self.with_workspace(|workspace| {
+            self.parse_raw_with_workspace(record, mode, workspace, &mut results, None)
+        })?;
+        self.clear_embedded(&mut results);
+        self.apply_string_quotes(&mut results);
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                result.map(|value| {
+                    let begin = value.as_ptr() as usize - record.as_ptr() as usize;
+                    begin..begin + value.len()
+                })
+            })
+            .collect())
+    }
+
+    /// Strip the quotes of the string values in `results` if excluded.
+    fn apply_string_quotes(&self, results: &mut [Option<&str>]) {
+        if self.string_quotes == StringQuotes::Exclude {
+            for result in results.iter_mut().flatten() {
+                *result = strip_quotes(result);
             }
         }
+    }
 
-        Ok(!pattern_node.is_root() && pattern_node.is_leaf())
+    /// Same as `parse`, but the quotes of strings are always included.
+    pub(crate) fn parse_raw<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<&'s str>>> {
+        let mut results = self.parse_sources(record, mode)?;
+        self.clear_embedded(&mut results);
+        Ok(results)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::super::index_builder::backend::FallbackBackend;
-    use super::*;
+    /// Same as `parse_raw`, but the strings of the paths within them are kept in the
+    /// results of the paths querying the strings (see `EmbeddedPaths::source`).
+    fn parse_sources<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<&'s str>>> {
+        let mut results = vec![];
+        self.with_workspace(|workspace| {
+            self.parse_raw_with_workspace(record, mode, workspace, &mut results, None)
+        })?;
+        Ok(results)
+    }
 
-    #[test]
-    fn basic_parsing() {
-        let paths = &["$.f1", "$.f2.e1", "$.f3"];
-        let record = r#"{
-            "f1": true,
-            "f2": {
-                "e2": "\"foo\\",
-                "e1": { "c1": null }
-            },
-            "f3": [ true, "10", null ]
-        }"#;
+    /// Match the names of fields with the query by `EscapedStr::eq_unescaped`, so that
+    /// the differences of escaping are ignored, e.g. `$.café` and `$.caf\u00e9` both
+    /// match the keys `"café"` and `"caf\u00e9"`.
+    ///
+    /// Only the field names containing backslashes are decoded, which is checked from
+    /// the bitmaps. The names are matched exactly by default.
+    pub fn unescape_fields(&mut self, v: bool) {
+        self.unescape_fields = v;
+        self.configure_embedded(|parser| parser.unescape_fields(v));
+    }
 
-        let mut query_tree = QueryTree::default();
-        for path in paths {
-            query_tree.add_path(path).unwrap();
+    /// Find the child of `node` matching the name of `field`, which contains backslashes
+    /// if `escaped`.
+    #[inline]
+    fn find_child<'n>(
+        &self,
+        node: &'n QueryNode<'_>,
+        field: &EscapedStr<'_>,
+        escaped: bool,
+    ) -> Result<Option<&'n QueryNode<'n>>> {
+        let ch = node.find_child(field.as_raw_str());
+        if ch.is_some() || !self.unescape_fields {
+            return Ok(ch);
+        }
+        if escaped {
+            if let Some(ch) = node.find_child(&field.unescape()?) {
+                return Ok(Some(ch));
+            }
         }
+        // the names in the query may be escaped as well.
+        Ok(node
+            .children()
+            .find(|&(name, _)| name.contains('\\') && field.eq_unescaped(&EscapedStr::from(name)))
+            .map(|(_, ch)| ch))
+    }
 
-        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
-        let parser = QueryParser::new(index_builder, query_tree);
+    /// Same as `parse`, but string values are unescaped if `unescape_strings` is set.
+    ///
+    /// The values of the paths within the strings (by `!json`) are extracted from the
+    /// unescaped strings here and by `parse_bytes` and `parse_to`, and are missing if
+    /// the value is not a string. The other methods return `None` for them.
+    pub fn parse_cow<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Cow<'s, str>>>> {
+        let results = self.parse_embedded(record, mode)?;
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(id, raw)| self.convert_result(id, raw))
+            .collect()
+    }
 
-        let result = parser.parse(record, QueryParserMode::Basic).unwrap();
-        assert_eq!(
-            result,
-            &[
-                Some("true"),
-                Some(r#"{ "c1": null }"#),
-                Some(r#"[ true, "10", null ]"#)
-            ]
-        );
+    /// Same as `parse_cow`, but the values are returned as bytes, and the strings of the
+    /// paths ending with `!base64` (and their default values) are decoded from Base64.
+    ///
+    /// The decoded values are neither transformed nor capped by `max_value_len`, and
+    /// are missing if the value is not a string.
+    #[cfg(feature = "base64")]
+    pub fn parse_bytes<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Cow<'s, [u8]>>>> {
+        let results = self.parse_embedded(record, mode)?;
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(id, raw)| {
+                if !self.query_tree.is_base64(id) {
+                    return Ok(self.convert_result(id, raw)?.map(|value| match value {
+                        Cow::Borrowed(value) => Cow::Borrowed(value.as_bytes()),
+                        Cow::Owned(value) => Cow::Owned(value.into_bytes()),
+                    }));
+                }
+                let raw = match raw.as_deref().or_else(|| self.query_tree.default_value(id)) {
+                    Some(raw) if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') => {
+                        raw
+                    }
+                    _ => return Ok(None),
+                };
+                let s = EscapedStr::from(&raw[1..raw.len() - 1]).into_unescaped()?;
+                base64::decode(&s)
+                    .map(|bytes| Some(Cow::Owned(bytes)))
+                    .chain_err(|| {
+                        let path = self.query_tree.path(id).unwrap();
+                        format!("invalid Base64 string of {:?}", path)
+                    })
+            })
+            .collect()
     }
 
-    #[test]
-    fn speculative_parsing() {
-        let paths = &["$.f1", "$.f2.e1", "$.f3"];
-        let record = r#"{
-            "f1": true,
-            "f2": {
-                "e2": "\"foo\\",
-                "e1": { "c1": null }
+    /// Convert a raw result of the path `id` for `parse_cow`.
+    fn convert_result<'s>(
+        &self,
+        id: usize,
+        raw: Option<Cow<'s, str>>,
+    ) -> Result<Option<Cow<'s, str>>> {
+        match raw {
+            Some(raw) => {
+                let value = match raw {
+                    Cow::Borrowed(raw) => self.convert_cow(raw, self.max_value_len)?,
+                    Cow::Owned(raw) => {
+                        Cow::Owned(self.convert_cow(&raw, self.max_value_len)?.into_owned())
+                    }
+                };
+                let value = match value {
+                    Cow::Borrowed(value) => Cow::Borrowed(&value[..self.capped_len(value)]),
+                    Cow::Owned(mut value) => {
+                        value.truncate(self.capped_len(&value));
+                        Cow::Owned(value)
+                    }
+                };
+                Ok(Some(match self.transforms[id] {
+                    Some(Transform(ref f)) => Cow::Owned(f(&value)),
+                    None => value,
+                }))
+            }
+            None => match self.query_tree.default_value(id) {
+                Some(default) => self
+                    .convert_cow(default, None)
+                    .map(|value| Some(Cow::Owned(value.into_owned()))),
+                None => Ok(None),
             },
-            "f3": [ true, "10", null ]
-        }"#;
+        }
+    }
 
-        let mut query_tree = QueryTree::default();
-        for path in paths {
-            query_tree.add_path(path).unwrap();
+    /// Same as `parse_raw`, but the raw values of the paths within the strings are
+    /// extracted from the unescaped strings.
+    fn parse_embedded<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Cow<'s, str>>>> {
+        let raw = self.parse_sources(record, mode)?;
+        let mut results: Vec<_> = raw.iter().map(|raw| raw.map(Cow::Borrowed)).collect();
+        for (embedded, parser) in self.query_tree.embedded().iter().zip(&self.embedded) {
+            for &id in embedded.paths() {
+                results[id] = None;
+            }
+            let text = match raw[embedded.source()] {
+                Some(s) if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') => {
+                    EscapedStr::from(&s[1..s.len() - 1]).into_unescaped()?
+                }
+                _ => continue,
+            };
+            let values = parser.parse_embedded(&text, mode).chain_err(|| {
+                let path = self.query_tree.path(embedded.source()).unwrap();
+                format!("invalid JSON text in the string of {:?}", path)
+            })?;
+            for (&id, value) in embedded.paths().iter().zip(values) {
+                results[id] = value.map(|value| Cow::Owned(value.into_owned()));
+            }
+            self.check_types(
+                embedded
+                    .paths()
+                    .iter()
+                    .filter_map(|&id| Some((id, results[id].as_deref()?))),
+            )?;
         }
+        Ok(results)
+    }
 
-        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+    /// Convert a raw value for `parse_cow`. A string to unescape is cut to `max_len`
+    /// bytes (see `value::escaped_prefix`) before it is unescaped.
+    fn convert_cow<'v>(&self, raw: &'v str, max_len: Option<usize>) -> Result<Cow<'v, str>> {
+        if self.control_chars == ControlChars::Escape && !self.unescape_strings {
+            // the strings are found with their quotes.
+            return Ok(match (scan::escape_control(raw), self.string_quotes) {
+                (value, StringQuotes::Include) => value,
+                (Cow::Borrowed(value), StringQuotes::Exclude) => Cow::Borrowed(strip_quotes(value)),
+                (Cow::Owned(value), StringQuotes::Exclude) => {
+                    Cow::Owned(strip_quotes(&value).to_owned())
+                }
+            });
+        }
+        if self.unescape_strings && raw.starts_with('"') {
+            if raw.len() < 2 || !raw.ends_with('"') {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| format!("unterminated string: {:?}", raw));
+            }
+            let s = &raw[1..raw.len() - 1];
+            let s = max_len.map_or(s, |len| value::escaped_prefix(s, len));
+            return EscapedStr::from(s).into_unescaped();
+        }
+        match self.string_quotes {
+            StringQuotes::Include => Ok(Cow::Borrowed(raw)),
+            StringQuotes::Exclude => Ok(Cow::Borrowed(strip_quotes(raw))),
+        }
+    }
+
+    /// Parse a record and return the value of each path, in the order of the paths.
+    ///
+    /// If a key appears more than once in an object, the last value is returned by
+    /// default (see `ScanDirection`, and `parse_all` to collect the others).
+    ///
+    /// The default values of the paths (see `QueryTree::add_path`) are not substituted
+    /// for the missing values, which are not in the record; use `parse_cow` for them.
+    pub fn parse<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<&'s str>>> {
+        let mut results = vec![];
+        self.with_workspace(|workspace| {
+            self.parse_with_workspace(record, mode, workspace, &mut results)
+        })?;
+        Ok(results)
+    }
+
+    /// Parse a record in basic mode, and return all values of each path in the order
+    /// of the record.
+    ///
+    /// Only the value which `parse` returns in basic mode (see `scan_direction` and
+    /// `first_match`) is returned for the paths unless `all_matches` is set.
+    pub fn parse_all<'s>(&self, record: &'s str) -> Result<Vec<Vec<&'s str>>> {
+        self.report(record, self.parse_all_unreported(record))
+    }
+
+    fn parse_all_unreported<'s>(&self, record: &'s str) -> Result<Vec<Vec<&'s str>>> {
+        if !self.accepts(record) {
+            return Ok(vec![vec![]; self.query_tree.num_paths()]);
+        }
+        let (record, begin, end) = self.bounds(record)?;
+        let mut results = vec![None; self.query_tree.num_slots()];
+        let mut all = vec![vec![]; self.query_tree.num_slots()];
+        if let Some(slot) = self.query_tree.as_node().path_id() {
+            results[slot] = Some(&record[begin..end]);
+        }
+        let skips = self.query_tree.as_node().is_leaf() || !self.keys_present(&record[begin..end]);
+        self.with_workspace(|workspace| {
+            let index = if skips {
+                None
+            } else {
+                let index = self
+                    .index_builder
+                    .build_with(record, &mut workspace.index)?;
+                let scratch = &mut workspace.scratch;
+                scratch.prepare(self.query_tree.num_nodes(), self.buffer_policy);
+                let root = self.query_tree.as_node();
+                self.parse_basic(&index, begin, end, root, scratch, &mut results, &mut all)?;
+                Some(index)
+            };
+            // the single values are the same as those of `parse`.
+            for (slot, values) in all.iter_mut().enumerate() {
+                if !self.all_matches[slot] {
+                    *values = results[slot].into_iter().collect();
+                } else if self.scan_direction == ScanDirection::Reverse {
+                    values.reverse();
+                }
+            }
+            self.query_tree.coalesce(&mut all, Vec::is_empty);
+            self.clear_embedded(&mut all);
+            self.check_types(
+                all.iter()
+                    .enumerate()
+                    .flat_map(|(i, values)| values.iter().map(move |&value| (i, value))),
+            )?;
+            self.check_control_chars(index.as_ref(), record, all.iter().flatten().cloned())
+        })?;
+        for value in all.iter_mut().flatten() {
+            if self.string_quotes == StringQuotes::Exclude {
+                *value = strip_quotes(value);
+            }
+            *value = &value[..self.capped_len(value)];
+        }
+        Ok(all)
+    }
+
+    /// Run `f` with the internal workspace, or a new one if another thread is using it.
+    fn with_workspace<T>(&self, f: impl FnOnce(&mut Workspace) -> Result<T>) -> Result<T> {
+        match self.workspace.try_lock() {
+            Ok(mut workspace) => f(&mut workspace),
+            Err(TryLockError::Poisoned(err)) => f(&mut err.into_inner()),
+            Err(TryLockError::WouldBlock) => f(&mut Workspace::new()),
+        }
+    }
+
+    /// Same as `parse`, but the results are keyed by the names of the query paths
+    /// (see `QueryTree::add_named`), or by the paths themselves if unnamed.
+    pub fn parse_named<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<HashMap<&'a str, Option<&'s str>>> {
+        let results = self.parse(record, mode)?;
+        Ok(results
+            .into_iter()
+            .enumerate()
+            .map(|(i, result)| (self.query_tree.name(i).unwrap(), result))
+            .collect())
+    }
+
+    /// Deserialize the results into `T`, as an object whose fields are the names of
+    /// the query paths (see `parse_named`). Missing values are omitted from the object.
+    #[cfg(feature = "serde_json")]
+    pub fn parse_to<T>(&self, record: &str, mode: QueryParserMode) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let results = self.parse_embedded(record, mode)?;
+        let mut object = String::with_capacity(record.len());
+        object.push('{');
+        for (i, result) in results.iter().enumerate() {
+            let value = result
+                .as_deref()
+                .or_else(|| self.query_tree.default_value(i));
+            if let Some(value) = value {
+                if object.len() > 1 {
+                    object.push(',');
+                }
+                let name = self.query_tree.name(i).unwrap();
+                object.push_str(&serde_json::to_string(name).unwrap());
+                object.push(':');
+                object.push_str(value);
+            }
+        }
+        object.push('}');
+        serde_json::from_str(&object).map_err(|e| {
+            Error::from(ErrorKind::InvalidRecord).chain_err(|| format!("parse_to: {}", e))
+        })
+    }
+
+    /// Same as `parse`, but all buffers used while parsing are taken from `workspace`,
+    /// and the extracted values are stored into `results`.
+    ///
+    /// Once the buffers have grown enough, parsing records of similar shapes does not
+    /// allocate any memory (except for recording the patterns, if enabled).
+    pub fn parse_with_workspace<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+        workspace: &mut Workspace,
+        results: &mut Vec<Option<&'s str>>,
+    ) -> Result<()> {
+        self.parse_raw_with_workspace(record, mode, workspace, results, None)?;
+        self.finish_results(results);
+        Ok(())
+    }
+
+    /// Same as `parse`, but also return how the results were produced.
+    pub fn parse_with_provenance<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<(Vec<Option<&'s str>>, Provenance)> {
+        let mut results = vec![];
+        let mut trace = Trace::default();
+        let parsed_by = self.with_workspace(|workspace| {
+            self.parse_raw_with_workspace(record, mode, workspace, &mut results, Some(&mut trace))
+        })?;
+        self.finish_results(&mut results);
+        let provenance = Provenance {
+            parsed_by,
+            pattern: trace.pattern,
+        };
+        Ok((results, provenance))
+    }
+
+    /// Clear the results of the paths within the strings, and strip the quotes and cap
+    /// the lengths of `results` if configured.
+    fn finish_results(&self, results: &mut [Option<&str>]) {
+        self.clear_embedded(results);
+        self.apply_string_quotes(results);
+        if self.max_value_len.is_some() {
+            for result in results.iter_mut().flatten() {
+                *result = &result[..self.capped_len(result)];
+            }
+        }
+    }
+
+    fn parse_raw_with_workspace<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+        workspace: &mut Workspace,
+        results: &mut Vec<Option<&'s str>>,
+        trace: Option<&mut Trace>,
+    ) -> Result<ParsedBy> {
+        let parsed_by = self.parse_raw_unreported(record, mode, workspace, results, trace, None);
+        self.report(record, parsed_by)
+    }
+
+    /// Create a subquery of the paths in `relative_tree` within the value of the path
+    /// `parent_path`, e.g. `$.id` within `$.payload`.
+    ///
+    /// The subquery is parsed along with the record by `parse_subquery`, walking from the
+    /// byte range and the level of the parent value in the same structural index, so
+    /// the record is neither indexed nor walked from the root again. The index builder
+    /// of this parser must have the levels of both trees, i.e. the level of the parent
+    /// path plus `relative_tree.max_level()`.
+    pub fn subquery<'q>(
+        &self,
+        parent_path: usize,
+        mut relative_tree: QueryTree<'q>,
+    ) -> Result<SubQuery<'q, B>>
+    where
+        B: Clone,
+    {
+        let path = match self.query_tree.path(parent_path) {
+            Some(path) if !path.contains('|') => path,
+            _ => {
+                return Err(Error::from(ErrorKind::InvalidQuery))
+                    .chain_err(|| format!("invalid parent path {}", parent_path));
+            }
+        };
+        let level = self.query_tree.node_for_path(parent_path).unwrap().level();
+        let levels = level + relative_tree.max_level();
+        if levels > self.index_builder.level() {
+            return Err(Error::from(ErrorKind::InvalidQuery)).chain_err(|| {
+                format!(
+                    "the subquery of {:?} needs {} levels of the index, but it has {}",
+                    path,
+                    levels,
+                    self.index_builder.level()
+                )
+            });
+        }
+        relative_tree.rebase(level);
+        Ok(SubQuery {
+            parent: parent_path,
+            parser: QueryParser::new(self.index_builder.clone(), relative_tree),
+        })
+    }
+
+    /// Same as `parse`, but also return the values of the paths of `subquery` within the
+    /// value of its parent path, which are all missing if the parent value is missing or
+    /// is not an object.
+    ///
+    /// `subquery` must have been created by `subquery` of this parser.
+    #[allow(clippy::type_complexity)]
+    pub fn parse_subquery<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+        subquery: &SubQuery<'_, B>,
+    ) -> Result<(Vec<Option<&'s str>>, Vec<Option<&'s str>>)> {
+        let mut results = vec![];
+        let mut sub_results = vec![None; subquery.parser.query_tree.num_paths()];
+        let parsed_by = self.with_workspace(|workspace| {
+            self.parse_raw_unreported(
+                record,
+                mode,
+                workspace,
+                &mut results,
+                None,
+                Some((subquery, &mut sub_results)),
+            )
+        });
+        self.report(record, parsed_by)?;
+        self.finish_results(&mut results);
+        subquery.parser.finish_results(&mut sub_results);
+        Ok((results, sub_results))
+    }
+
+    /// Parse the paths of `subquery` within the value of its parent path in `results`,
+    /// from `index` if the record has been indexed.
+    fn parse_within<'s>(
+        &self,
+        subquery: Option<(&SubQuery<'_, B>, &mut Vec<Option<&'s str>>)>,
+        record: &'s str,
+        index: Option<&StructuralIndex<'_, 's>>,
+        mode: QueryParserMode,
+        results: &[Option<&'s str>],
+    ) -> Result<()> {
+        let (subquery, sub_results) = match subquery {
+            Some(subquery) => subquery,
+            None => return Ok(()),
+        };
+        let parser = &subquery.parser;
+        let value = match results[subquery.parent] {
+            Some(value) if value.starts_with('{') => value,
+            _ => return Ok(()),
+        };
+        let begin = value.as_ptr() as usize - record.as_ptr() as usize;
+        let end = begin + value.len();
+        sub_results.resize(parser.query_tree.num_slots(), None);
+        match index {
+            Some(index) => {
+                parser.with_workspace(|workspace| {
+                    parser.parse_index(
+                        index,
+                        begin,
+                        end,
+                        mode,
+                        &mut workspace.scratch,
+                        sub_results,
+                        None,
+                    )
+                })?;
+            }
+            None => {
+                parser.parse_small(record, begin, end, parser.query_tree.as_node(), sub_results)?
+            }
+        }
+        parser.finish_parse(index, record, value, mode, sub_results, None)
+    }
+
+    /// Complete `results` parsed from the value `root` of `record`, by storing the root
+    /// and the embedded paths and checking the values, then parse `subquery` within it.
+    fn finish_parse<'s>(
+        &self,
+        index: Option<&StructuralIndex<'_, 's>>,
+        record: &'s str,
+        root: &'s str,
+        mode: QueryParserMode,
+        results: &mut Vec<Option<&'s str>>,
+        subquery: Option<(&SubQuery<'_, B>, &mut Vec<Option<&'s str>>)>,
+    ) -> Result<()> {
+        self.set_root(root, results);
+        self.query_tree.coalesce(results, Option::is_none);
+        // the values within the strings are checked once they are extracted.
+        self.check_types(found_values(results).filter(|&(id, _)| !self.is_embedded(id)))?;
+        self.check_control_chars(index, record, results.iter().flatten().cloned())?;
+        self.parse_within(subquery, record, index, mode, results)
+    }
+
+    fn parse_raw_unreported<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+        workspace: &mut Workspace,
+        results: &mut Vec<Option<&'s str>>,
+        trace: Option<&mut Trace>,
+        subquery: Option<(&SubQuery<'_, B>, &mut Vec<Option<&'s str>>)>,
+    ) -> Result<ParsedBy> {
+        results.clear();
+        if !self.accepts(record) {
+            results.resize(self.query_tree.num_paths(), None);
+            return Ok(ParsedBy::Skip);
+        }
+        let (record, begin, end) = self.bounds(record)?;
+        results.resize(self.query_tree.num_slots(), None);
+        if self.query_tree.as_node().is_leaf() || !self.keys_present(&record[begin..end]) {
+            self.finish_parse(None, record, &record[begin..end], mode, results, subquery)?;
+            return Ok(if self.query_tree.as_node().is_leaf() {
+                ParsedBy::Root
+            } else {
+                ParsedBy::Skip
+            });
+        }
+        if end - begin < self.small_record_len {
+            self.parse_small(record, begin, end, self.query_tree.as_node(), results)?;
+            self.finish_parse(None, record, &record[begin..end], mode, results, subquery)?;
+            return Ok(ParsedBy::Scan);
+        }
+        let index = self
+            .index_builder
+            .build_with(record, &mut workspace.index)?;
+
+        let parsed_by = self.parse_index(
+            &index,
+            begin,
+            end,
+            mode,
+            &mut workspace.scratch,
+            results,
+            trace,
+        )?;
+        let root = &record[begin..end];
+        self.finish_parse(Some(&index), record, root, mode, results, subquery)?;
+        Ok(parsed_by)
+    }
+
+    /// Clear the results of the paths within the strings, which are extracted only by
+    /// `parse_cow`, `parse_bytes` and `parse_to`.
+    fn clear_embedded<T: Default>(&self, slots: &mut [T]) {
+        for embedded in self.query_tree.embedded() {
+            for &id in embedded.paths() {
+                slots[id] = T::default();
+            }
+        }
+    }
+
+    /// Return whether the path `path_id` is queried within a string.
+    fn is_embedded(&self, path_id: usize) -> bool {
+        self.query_tree
+            .embedded()
+            .iter()
+            .any(|embedded| embedded.paths().contains(&path_id))
+    }
+
+    /// Store the whole record as the value of the root, if it is queried by `$`.
+    fn set_root<'s>(&self, record: &'s str, results: &mut [Option<&'s str>]) {
+        if let Some(slot) = self.query_tree.as_node().path_id() {
+            results[slot] = Some(record);
+        }
+    }
+
+    /// Parse the object `record[begin..end]` from its index.
+    #[allow(clippy::too_many_arguments)]
+    fn parse_index<'s>(
+        &self,
+        index: &StructuralIndex<'_, 's>,
+        begin: usize,
+        end: usize,
+        mode: QueryParserMode,
+        scratch: &mut Scratch,
+        results: &mut [Option<&'s str>],
+        trace: Option<&mut Trace>,
+    ) -> Result<ParsedBy> {
+        scratch.prepare(self.query_tree.num_nodes(), self.buffer_policy);
+        let root = self.query_tree.as_node();
+        match mode {
+            QueryParserMode::Basic => {
+                self.parse_basic(index, begin, end, root, scratch, results, &mut [])?;
+                Ok(ParsedBy::Basic)
+            }
+            QueryParserMode::Speculative => {
+                if self.cache_offsets && !scratch.offsets.is_empty() {
+                    let mut pos = 0;
+                    if self.parse_cached(
+                        index,
+                        begin,
+                        end,
+                        root,
+                        &scratch.offsets,
+                        &mut pos,
+                        results,
+                    )? {
+                        return Ok(ParsedBy::Cache);
+                    }
+                    results.iter_mut().for_each(|r| *r = None);
+                }
+                let capture = self.capture_failures > 0;
+                let mut captured = Trace::default();
+                let mut trace = match trace {
+                    Some(trace) => Some(trace),
+                    None if capture => Some(&mut captured),
+                    None => None,
+                };
+                let mut parsed_by = ParsedBy::Speculation;
+                if !self.parse_speculative(
+                    index,
+                    begin,
+                    end,
+                    root,
+                    results,
+                    trace.as_deref_mut(),
+                )? {
+                    let failure = trace.and_then(|trace| trace.failure.take());
+                    if let Some(failure) = failure.filter(|_| capture) {
+                        let mut failures =
+                            self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+                        if failures.len() < self.capture_failures {
+                            failures.push(SpeculationFailure {
+                                record: index.as_str().to_owned(),
+                                ..failure
+                            });
+                        }
+                    }
+                    if !self.allow_fallback {
+                        return Err(ErrorKind::FailedSpeculativeParse.into());
+                    }
+                    self.parse_basic(index, begin, end, root, scratch, results, &mut [])?;
+                    parsed_by = ParsedBy::Fallback;
+                }
+                if self.cache_offsets {
+                    scratch.offsets.clear();
+                    if !self.record_offsets(index, begin, end, root, &mut scratch.offsets)? {
+                        scratch.offsets.clear();
+                    }
+                }
+                Ok(parsed_by)
+            }
+        }
+    }
+
+    /// Parse the object `record[begin..end]` by scanning its bytes directly.
+    fn parse_small<'s>(
+        &self,
+        record: &'s str,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        results: &mut [Option<&'s str>],
+    ) -> Result<()> {
+        if !record[begin..end].starts_with('{') {
+            return Ok(());
+        }
+        let mut fields = vec![];
+        scan::members(record, begin, end, &mut fields)?;
+        if self.scan_direction == ScanDirection::Reverse {
+            fields.reverse();
+        }
+
+        // the preceding duplicates are scanned later in reverse.
+        let reverse = self.scan_direction == ScanDirection::Reverse;
+        let stops = !self.scan_all_fields && (!reverse || !self.first_matches[node.node_id()]);
+
+        let mut found = Vec::with_capacity(node.num_children());
+        for (fsi, fei) in fields {
+            let (field, vsi) = scan::split_field(record, fsi, fei)?;
+            let escaped = field.as_raw_str().contains('\\');
+            let ch = match self.find_child(node, &field, escaped)? {
+                Some(ch) => ch,
+                None => continue,
+            };
+            if found.contains(&ch.node_id()) {
+                if reverse && self.first_matches[ch.node_id()] {
+                    let mut preceding = vec![None; results.len()];
+                    if let Some(id) = ch.path_id() {
+                        preceding[id] = Some(&record[vsi..fei]);
+                    }
+                    if !ch.is_leaf() {
+                        self.parse_small(record, vsi, fei, ch, &mut preceding)?;
+                    }
+                    self.override_first_matches(ch, &preceding, results);
+                }
+                continue;
+            }
+            found.push(ch.node_id());
+
+            if let Some(id) = ch.path_id() {
+                results[id] = Some(&record[vsi..fei]);
+            }
+            if !ch.is_leaf() {
+                self.parse_small(record, vsi, fei, ch, results)?;
+            }
+            if found.len() == node.num_children() && stops {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse an object with the cached offsets of the fields, starting at `entries[*pos]`.
+    #[allow(clippy::too_many_arguments)]
+    fn parse_cached<'s>(
+        &self,
+        index: &StructuralIndex<'_, 's>,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        entries: &[CachedField],
+        pos: &mut usize,
+        results: &mut [Option<&'s str>],
+    ) -> Result<bool> {
+        let level = node.level();
+        let mut found = Vec::with_capacity(node.num_children());
+        for _ in 0..node.num_children() {
+            let entry = match entries.get(*pos) {
+                Some(entry) => entry,
+                None => return Ok(false),
+            };
+            *pos += 1;
+            if entry.fsi <= begin
+                || end <= entry.ci
+                || !index.is_field_at(entry.fsi, entry.fei, entry.ci, level)
+            {
+                return Ok(false);
+            }
+            let field = EscapedStr::from(index.substr(entry.fsi, entry.fei));
+            let escaped = index.contains_backslash(entry.fsi, entry.fei);
+            let ch = match self.find_child(node, &field, escaped)? {
+                Some(ch) => ch,
+                None => return Ok(false),
+            };
+            // a key cannot be matched twice, since the number of children is fixed.
+            if found.contains(&ch.node_id()) {
+                return Ok(false);
+            }
+            found.push(ch.node_id());
+
+            let vend = match entry.next {
+                Some((fsi, fei, ci)) => {
+                    if end <= ci
+                        || !index.is_field_at(fsi, fei, ci, level)
+                        || index.has_colon(entry.ci + 1, ci, level)
+                    {
+                        return Ok(false);
+                    }
+                    fsi - 1
+                }
+                None => {
+                    if index.has_colon(entry.ci + 1, end, level) {
+                        return Ok(false);
+                    }
+                    end
+                }
+            };
+            let (vsi, vei) = match index.find_object_value_with(
+                entry.ci + 1,
+                vend,
+                level,
+                entry.next.is_none(),
+                self.value_trimming,
+            ) {
+                Ok(range) => range,
+                Err(_) => return Ok(false),
+            };
+
+            if let Some(id) = ch.path_id() {
+                results[id] = Some(index.substr(vsi, vei));
+            }
+            if !ch.is_leaf() && !self.parse_cached(index, vsi, vei, ch, entries, pos, results)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Record the offsets of the fields of the query in the object, and return whether
+    /// all of them are found.
+    fn record_offsets(
+        &self,
+        index: &StructuralIndex<'_, '_>,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        entries: &mut Vec<CachedField>,
+    ) -> Result<bool> {
+        let mut cp = vec![];
+        if !index.colon_positions(begin, end, node.level(), &mut cp) {
+            return Ok(false);
+        }
+        let mut found = vec![];
+        // the entry of the preceding field, whose `next` is the current field.
+        let mut preceding: Option<usize> = None;
+        for field in index.object_fields_at(begin, end, &cp) {
+            let field = field?;
+            if let Some(j) = preceding.take() {
+                entries[j].next = Some((field.fsi, field.fei(), field.ci));
+            }
+
+            let escaped = index.contains_backslash(field.fsi, field.fei());
+            let ch = match self.find_child(node, &field.name, escaped)? {
+                Some(ch) if !found.contains(&ch.node_id()) => ch,
+                _ => continue,
+            };
+            found.push(ch.node_id());
+            preceding = Some(entries.len());
+            entries.push(CachedField {
+                fsi: field.fsi,
+                fei: field.fei(),
+                ci: field.ci,
+                next: None,
+            });
+
+            let (vsi, vei) = index.field_value_with(&field, node.level(), self.value_trimming)?;
+            if !ch.is_leaf() && !self.record_offsets(index, vsi, vei, ch, entries)? {
+                return Ok(false);
+            }
+        }
+        Ok(found.len() == node.num_children())
+    }
+
+    /// Parse an object by scanning its fields in the direction of `scan_direction`.
+    ///
+    /// The first value of each path found in that direction (or the first one in the
+    /// record for `first_match`) is stored into `results`, and all values of the paths
+    /// of `all_matches` into `all` if it is not empty, in the order of the scan.
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    fn parse_basic<'s>(
+        &self,
+        index: &StructuralIndex<'_, 's>,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        scratch: &mut Scratch,
+        results: &mut [Option<&'s str>],
+        all: &mut [Vec<&'s str>],
+    ) -> Result<()> {
+        // The buffers of this node are moved out while visiting the children.
+        let mut cp = std::mem::take(&mut scratch.colon_positions[node.node_id()]);
+        let mut pattern = std::mem::take(&mut scratch.patterns[node.node_id()]);
+        let result = self.parse_basic_with(
+            index,
+            begin,
+            end,
+            node,
+            &mut cp,
+            &mut pattern,
+            scratch,
+            results,
+            all,
+        );
+        scratch.colon_positions[node.node_id()] = cp;
+        scratch.patterns[node.node_id()] = pattern;
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    fn parse_basic_with<'s>(
+        &self,
+        index: &StructuralIndex<'_, 's>,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        cp: &mut Vec<usize>,
+        pattern: &mut VecDeque<(usize, usize, usize)>,
+        scratch: &mut Scratch,
+        results: &mut [Option<&'s str>],
+        all: &mut [Vec<&'s str>],
+    ) -> Result<()> {
+        scratch.colon_positions_for(index, begin, end, node, cp)?;
+
+        pattern.clear();
+        // Scanning can stop once all children are found, unless the remaining
+        // duplicates of the keys are collected.
+        let collects_all = !all.is_empty() && self.collects_all[node.node_id()];
+        let forward = self.scan_direction == ScanDirection::Forward;
+        // the preceding duplicates are scanned later in reverse.
+        let stops = !collects_all
+            && !self.scan_all_fields
+            && (forward || !self.first_matches[node.node_id()]);
+
+        let fields = index.object_fields_at(begin, end, cp).enumerate();
+        let fields = if forward {
+            Either::Left(fields)
+        } else {
+            Either::Right(fields.rev())
+        };
+        for (i, field) in fields {
+            let field = field?;
+            let escaped = index.contains_backslash(field.fsi, field.fei());
+            let ch = match self.find_child(node, &field.name, escaped)? {
+                Some(ch) => ch,
+                None => continue,
+            };
+            let mut is_first = true;
+            for &(fsi, fei, _) in pattern.iter() {
+                let other = EscapedStr::from(index.substr(fsi, fei));
+                let escaped = index.contains_backslash(fsi, fei);
+                if let Some(other) = self.find_child(node, &other, escaped)? {
+                    if other.node_id() == ch.node_id() {
+                        is_first = false;
+                        break;
+                    }
+                }
+            }
+            let collects = collects_all && self.collects_all[ch.node_id()];
+            let overrides = !forward && !is_first && self.first_matches[ch.node_id()];
+            if !(is_first || collects || overrides) {
+                continue;
+            }
+
+            let (vsi, vei) = index.field_value_with(&field, node.level(), self.value_trimming)?;
+
+            if let Some(id) = ch.path_id() {
+                let value = index.substr(vsi, vei);
+                if self.all_matches[id] {
+                    if let Some(values) = all.get_mut(id) {
+                        values.push(value);
+                    }
+                }
+                results[id].get_or_insert(value);
+            }
+
+            if overrides {
+                let mut preceding = vec![None; results.len()];
+                if let Some(id) = ch.path_id() {
+                    preceding[id] = Some(index.substr(vsi, vei));
+                }
+                if !ch.is_leaf() {
+                    // the values of `all_matches` are collected at the same time.
+                    let all = if collects { &mut *all } else { &mut [] };
+                    self.parse_basic(index, vsi, vei, ch, scratch, &mut preceding, all)?;
+                }
+                self.override_first_matches(ch, &preceding, results);
+            } else if !ch.is_leaf() && is_first {
+                self.parse_basic(index, vsi, vei, ch, scratch, results, all)?;
+            } else if !ch.is_leaf() {
+                // only the values of `all_matches` are collected from the duplicates.
+                let mut ignored = vec![None; results.len()];
+                self.parse_basic(index, vsi, vei, ch, scratch, &mut ignored, all)?;
+            }
+
+            if !is_first {
+                continue;
+            }
+            if forward {
+                pattern.push_back((field.fsi, field.fei(), i));
+            } else {
+                pattern.push_front((field.fsi, field.fei(), i));
+            }
+            if pattern.len() == node.num_children() {
+                if let (true, PatternTrees::Training(ref trees)) =
+                    (self.save_patterns, &self.pattern_trees)
+                {
+                    trees[node.node_id()]
+                        .write()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .append(
+                            pattern
+                                .iter()
+                                .map(|&(fsi, fei, i)| (index.substr(fsi, fei), i)),
+                        );
+                }
+                if stops {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse an object by probing the trained patterns.
+    ///
+    /// The colons of the predicted positions are located directly from the leveled
+    /// bitmaps, without enumerating all colons in the object.
+    #[inline]
+    fn parse_speculative<'s>(
+        &self,
+        index: &StructuralIndex<'_, 's>,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        results: &mut [Option<&'s str>],
+        mut trace: Option<&mut Trace>,
+    ) -> Result<bool> {
+        let colon = |n| index.nth_colon(begin, end, node.level(), n);
+
+        let guard;
+        let pattern_tree = match self.pattern_trees {
+            PatternTrees::Training(ref trees) => {
+                guard = trees[node.node_id()]
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner);
+                &*guard
+            }
+            PatternTrees::Frozen(ref trees) => &trees[node.node_id()],
+        };
+        let mut pattern_node = pattern_tree.root_node();
+        // the fields matched so far, recorded only when traced
+        let mut matched = vec![];
+
+        let mut success = false;
+        while !pattern_node.is_leaf() {
+            success = false;
+            for child in pattern_node.children() {
+                let i = child.position();
+                let ci = match colon(i)? {
+                    Some(ci) => ci,
+                    None => continue, // the object has fewer fields.
+                };
+                let prev = if i == 0 {
+                    begin
+                } else {
+                    colon(i - 1)?.unwrap()
+                };
+                let (field, fsi) = index.find_object_field(prev, ci)?;
+                success = field.as_raw_str() == child.field();
+                if success {
+                    // the patterns are trained with the raw names of the fields.
+                    let escaped = index.contains_backslash(fsi, fsi + field.as_raw_str().len());
+                    let ch_node = match self.find_child(node, &field, escaped)? {
+                        Some(ch_node) => ch_node,
+                        None => {
+                            success = false;
+                            break;
+                        }
+                    };
+
+                    let next = colon(i + 1)?;
+                    let fsi = match next {
+                        Some(next) => index.find_object_field(ci, next)?.1 - 1,
+                        None => end,
+                    };
+                    let (vsi, vei) = index.find_object_value_with(
+                        ci + 1,
+                        fsi,
+                        node.level(),
+                        next.is_none(),
+                        self.value_trimming,
+                    )?;
+
+                    if let Some(id) = ch_node.path_id() {
+                        results[id] = Some(index.substr(vsi, vei));
+                    }
+
+                    if !ch_node.is_leaf() {
+                        success &= self.parse_speculative(
+                            index,
+                            vsi,
+                            vei,
+                            ch_node,
+                            results,
+                            trace.as_deref_mut(),
+                        )?;
+                    }
+
+                    if trace.is_some() {
+                        matched.push((child.field().to_owned(), i));
+                    }
+                    pattern_node = child;
+                    break;
+                }
+            }
+
+            if !success {
+                break;
+            }
+        }
+
+        // a failure in the nested objects also invalidates the whole speculation.
+        let success = success && !pattern_node.is_root() && pattern_node.is_leaf();
+        let trace = match trace {
+            Some(trace) => trace,
+            None => return Ok(success),
+        };
+        if success {
+            if node.node_id() == self.query_tree.as_node().node_id() {
+                trace.pattern = matched;
+            }
+        } else if trace.failure.is_none() {
+            let mut colons = vec![];
+            if !index.colon_positions(begin, end, node.level(), &mut colons) {
+                return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "mismatched level");
+            }
+            // the record is filled by the caller.
+            trace.failure = Some(SpeculationFailure {
+                record: String::new(),
+                node_id: node.node_id(),
+                begin,
+                end,
+                matched,
+                tried: pattern_node
+                    .children()
+                    .iter()
+                    .map(|child| (child.field().to_owned(), child.position()))
+                    .collect(),
+                colons,
+            });
+        }
+        Ok(success)
+    }
+}
+
+/// An invalid record passed to the sink of `QueryParser::failure_sink`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFailure<'r> {
+    /// the record, truncated to the maximum length of the sink
+    pub record: &'r str,
+    pub truncated: bool,
+    /// the byte offset where the record stops being a JSON text, if found by
+    /// `validate::error_offset`
+    pub offset: Option<usize>,
+    /// the name of the error kind, e.g. `"InvalidRecord"`
+    pub kind: &'static str,
+    /// the messages of the error and its causes, separated by `": "`
+    pub message: String,
+}
+
+impl<'r> ParseFailure<'r> {
+    /// Serialize the failure as a JSON object, e.g.
+    /// `{"kind":"InvalidRecord","offset":3,"message":"...","record":"{\"a\"","truncated":true}`.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\"kind\":");
+        canonical::write_string(self.kind, &mut out);
+        out.push_str(",\"offset\":");
+        match self.offset {
+            Some(offset) => out.push_str(&offset.to_string()),
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"message\":");
+        canonical::write_string(&self.message, &mut out);
+        out.push_str(",\"record\":");
+        canonical::write_string(self.record, &mut out);
+        out.push_str(",\"truncated\":");
+        out.push_str(if self.truncated { "true" } else { "false" });
+        out.push('}');
+        out
+    }
+}
+
+/// Return the name of the kind of `err` (or of its causes) caused by an invalid record.
+fn failure_kind(err: &Error) -> Option<&'static str> {
+    let mut err = Some(err);
+    while let Some(e) = err {
+        match *e.kind() {
+            ErrorKind::InvalidRecord => return Some("InvalidRecord"),
+            ErrorKind::InvalidType(..) => return Some("InvalidType"),
+            ErrorKind::TypeMismatch(..) => return Some("TypeMismatch"),
+            ErrorKind::DepthLimitExceeded(..) => return Some("DepthLimitExceeded"),
+            _ => {}
+        }
+        err = StdError::source(e).and_then(|e| e.downcast_ref::<Error>());
+    }
+    None
+}
+
+/// How the results of a record were produced, returned by
+/// `QueryParser::parse_with_provenance`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub parsed_by: ParsedBy,
+    /// the raw names and the positions of the fields of the pattern leaf matched at
+    /// the root object, which is empty unless `parsed_by` is `ParsedBy::Speculation`
+    pub pattern: Vec<(String, usize)>,
+}
+
+/// The strategy which produced the results of a record
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParsedBy {
+    /// the record was rejected by the prefilter or the key pre-check
+    Skip,
+    /// only the root was queried, so the record was not indexed
+    Root,
+    /// the small record was scanned directly
+    Scan,
+    /// the offsets cached from the previous record matched
+    Cache,
+    /// the trained patterns matched
+    Speculation,
+    /// no pattern matched, and the record was parsed in basic mode
+    Fallback,
+    /// the record was parsed in basic mode
+    Basic,
+}
+
+/// The details of a speculation collected while parsing
+#[derive(Debug, Default)]
+struct Trace {
+    failure: Option<SpeculationFailure>,
+    /// the fields of the pattern leaf matched at the root object
+    pattern: Vec<(String, usize)>,
+}
+
+/// A failure of speculation captured by `QueryParser::capture_failures`
+///
+/// The failure is located at the innermost object whose fields did not match the
+/// trained patterns. The record is kept so that the failure can be reproduced by
+/// `QueryParser::replay`, e.g. in a test with the same training records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeculationFailure {
+    /// the record which failed
+    pub record: String,
+    /// the node of the query tree of the object
+    pub node_id: usize,
+    /// the byte offset of the opening brace of the object in the record
+    pub begin: usize,
+    /// the byte offset just past the closing brace of the object, so that
+    /// `record[begin..end]` is the object
+    pub end: usize,
+    /// the raw names and the positions of the fields matched before the failure
+    pub matched: Vec<(String, usize)>,
+    /// the raw names and the positions of the pattern branches tried at the failure
+    pub tried: Vec<(String, usize)>,
+    /// the positions of the colons in the object
+    pub colons: Vec<usize>,
+}
+
+/// The paths queried within the value of a path of a `QueryParser`, created by
+/// `QueryParser::subquery`
+#[derive(Debug)]
+pub struct SubQuery<'q, B: Backend> {
+    parent: usize,
+    parser: QueryParser<'q, B>,
+}
+
+impl<'q, B: Backend> SubQuery<'q, B> {
+    /// Return the identifier of the parent path.
+    pub fn parent_path(&self) -> usize {
+        self.parent
+    }
+
+    /// Return the parser of the relative paths, e.g. to enable the speculative parsing
+    /// or to transform their values.
+    ///
+    /// The settings about the whole record (e.g. `with_prefilter`) are not used.
+    pub fn parser_mut(&mut self) -> &mut QueryParser<'q, B> {
+        &mut self.parser
+    }
+}
+
+/// An immutable snapshot of a trained `QueryParser`, created by `QueryParser::freeze`
+///
+/// The children of the patterns are sorted by their weights, and no lock is taken
+/// while parsing in speculative mode. Cloning shares the same snapshot.
+#[derive(Debug)]
+pub struct FrozenQueryParser<'a, B: Backend> {
+    parser: Arc<QueryParser<'a, B>>,
+}
+
+impl<'a, B: Backend> Clone for FrozenQueryParser<'a, B> {
+    fn clone(&self) -> Self {
+        Self {
+            parser: self.parser.clone(),
+        }
+    }
+}
+
+impl<'a, B: Backend> FrozenQueryParser<'a, B> {
+    /// See `QueryParser::parse`.
+    pub fn parse<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<&'s str>>> {
+        self.parser.parse(record, mode)
+    }
+
+    /// See `QueryParser::parse_cow`.
+    pub fn parse_cow<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Cow<'s, str>>>> {
+        self.parser.parse_cow(record, mode)
+    }
+
+    /// See `QueryParser::parse_bytes`.
+    #[cfg(feature = "base64")]
+    pub fn parse_bytes<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Cow<'s, [u8]>>>> {
+        self.parser.parse_bytes(record, mode)
+    }
+
+    /// See `QueryParser::parse_ranges`.
+    pub fn parse_ranges(
+        &self,
+        record: &str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Option<Range<usize>>>> {
+        self.parser.parse_ranges(record, mode)
+    }
+
+    /// See `QueryParser::parse_all`.
+    pub fn parse_all<'s>(&self, record: &'s str) -> Result<Vec<Vec<&'s str>>> {
+        self.parser.parse_all(record)
+    }
+
+    /// See `QueryParser::parse_named`.
+    pub fn parse_named<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<HashMap<&'a str, Option<&'s str>>> {
+        self.parser.parse_named(record, mode)
+    }
+
+    /// See `QueryParser::parse_to`.
+    #[cfg(feature = "serde_json")]
+    pub fn parse_to<T>(&self, record: &str, mode: QueryParserMode) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.parser.parse_to(record, mode)
+    }
+
+    /// See `QueryParser::parse_with_provenance`.
+    pub fn parse_with_provenance<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<(Vec<Option<&'s str>>, Provenance)> {
+        self.parser.parse_with_provenance(record, mode)
+    }
+
+    /// See `QueryParser::parse_subquery`.
+    #[allow(clippy::type_complexity)]
+    pub fn parse_subquery<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+        subquery: &SubQuery<'_, B>,
+    ) -> Result<(Vec<Option<&'s str>>, Vec<Option<&'s str>>)> {
+        self.parser.parse_subquery(record, mode, subquery)
+    }
+
+    /// See `QueryParser::take_failures`.
+    pub fn take_failures(&self) -> Vec<SpeculationFailure> {
+        self.parser.take_failures()
+    }
+
+    /// See `QueryParser::replay`.
+    pub fn replay(&self, failure: &SpeculationFailure) -> Result<Option<SpeculationFailure>> {
+        self.parser.replay(failure)
+    }
+
+    /// See `QueryParser::parse_with_workspace`.
+    pub fn parse_with_workspace<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+        workspace: &mut Workspace,
+        results: &mut Vec<Option<&'s str>>,
+    ) -> Result<()> {
+        self.parser
+            .parse_with_workspace(record, mode, workspace, results)
+    }
+}
+
+/// Return the values found in `results` with their path identifiers.
+fn found_values<'r, 's>(
+    results: &'r [Option<&'s str>],
+) -> impl Iterator<Item = (usize, &'s str)> + 'r {
+    results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, value)| value.map(|value| (i, value)))
+}
+
+/// Return the contents of a string value, or `value` itself if it is not a string.
+#[inline]
+fn strip_quotes(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+/// Check that the record is an object, and fail with `ErrorKind::InvalidType` if it is
+/// another value.
+/// Set `v` to the flags of the slots of the path `path_id`.
+fn set_slots(query_tree: &QueryTree<'_>, path_id: usize, v: bool, flags: &mut [bool]) {
+    for (slot, flag) in flags.iter_mut().enumerate() {
+        if query_tree.path_of_slot(slot) == Some(path_id) {
+            *flag = v;
+        }
+    }
+}
+
+/// Mark the nodes which have a descendant slot (or are a slot) set in `flags`.
+fn mark_nodes(node: &QueryNode<'_>, flags: &[bool], marks: &mut [bool]) -> bool {
+    let mut marked = node.path_id().is_some_and(|id| flags[id]);
+    for (_, child) in node.children() {
+        marked |= mark_nodes(child, flags, marks);
+    }
+    marks[node.node_id()] = marked;
+    marked
+}
+
+fn check_record(record: &str) -> Result<&str> {
+    let record = record.trim();
+    if record.starts_with('{') {
+        return Ok(record);
+    }
+    let found = match value::parse_with(record, false) {
+        Ok(ValueType::Atomic(value)) => value.type_name(),
+        Ok(_) => "array",
+        Err(err) => {
+            return Err(err).chain_err(|| "QueryParser supports only object parsing");
+        }
+    };
+    Err(ErrorKind::InvalidType("object", found).into())
+}
+
+/// The offsets of a field found in the previous record, and of the next field
+#[derive(Debug, Copy, Clone)]
+struct CachedField {
+    fsi: usize,
+    fei: usize,
+    ci: usize,
+    next: Option<(usize, usize, usize)>,
+}
+
+/// Buffers of colon positions and patterns for each node of the query tree
+///
+/// The colon positions of a node are memoized with the range they were calculated
+/// for, so that they are not recalculated within a parse.
+#[derive(Debug, Default)]
+struct Scratch {
+    colon_positions: Vec<Vec<usize>>,
+    cp_ranges: Vec<Option<(u64, usize, usize)>>,
+    patterns: Vec<VecDeque<(usize, usize, usize)>>,
+    generation: u64,
+    /// the offsets of the fields found in the previous record, in depth-first order
+    offsets: Vec<CachedField>,
+    /// the number of parses so far
+    parses: usize,
+}
+
+impl Scratch {
+    /// Prepare the buffers for a new parse, invalidating the memoized positions.
+    ///
+    /// The buffers are shrunk before the parse if `policy` says so.
+    fn prepare(&mut self, num_nodes: usize, policy: BufferPolicy) {
+        let parses = self.parses;
+        self.parses = self.parses.wrapping_add(1);
+        if policy.shrinks(parses, || self.memory_usage()) {
+            for cp in &mut self.colon_positions {
+                *cp = Vec::new();
+            }
+            for pattern in &mut self.patterns {
+                *pattern = VecDeque::new();
+            }
+            self.offsets.shrink_to_fit();
+        }
+        if self.colon_positions.len() < num_nodes {
+            self.colon_positions.resize_with(num_nodes, Vec::new);
+            self.cp_ranges.resize(num_nodes, None);
+            self.patterns.resize_with(num_nodes, VecDeque::new);
+        }
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Return the bytes held by the buffers.
+    fn memory_usage(&self) -> usize {
+        let cp: usize = self.colon_positions.iter().map(Vec::capacity).sum();
+        let patterns: usize = self.patterns.iter().map(VecDeque::capacity).sum();
+        cp * mem::size_of::<usize>()
+            + patterns * mem::size_of::<(usize, usize, usize)>()
+            + self.offsets.capacity() * mem::size_of::<CachedField>()
+    }
+
+    /// Calculate the colon positions of `node` into `cp`, unless they have already
+    /// been calculated for the same range in the current parse.
+    ///
+    /// `cp` is the buffer moved out of `self.colon_positions[node.node_id()]`.
+    fn colon_positions_for(
+        &mut self,
+        index: &StructuralIndex<'_, '_>,
+        begin: usize,
+        end: usize,
+        node: &QueryNode<'_>,
+        cp: &mut Vec<usize>,
+    ) -> Result<()> {
+        let range = Some((self.generation, begin, end));
+        if self.cp_ranges[node.node_id()] == range {
+            return Ok(());
+        }
+        self.cp_ranges[node.node_id()] = None;
+        if !index.colon_positions(begin, end, node.level(), cp) {
+            return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "mismatched level");
+        }
+        self.cp_ranges[node.node_id()] = range;
+        Ok(())
+    }
+}
+
+/// Reusable buffers for `QueryParser::parse_with_workspace`
+///
+/// A workspace can be shared among parsers, but the buffers are sized for the
+/// largest records and query trees seen so far.
+#[derive(Debug, Default)]
+pub struct Workspace {
+    index: IndexBuffers,
+    scratch: Scratch,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the total bytes held by the buffers.
+    pub fn memory_usage(&self) -> usize {
+        self.index.memory_usage().total() + self.scratch.memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index_builder::backend::FallbackBackend;
+    use super::*;
+    use crate::parser::DEFAULT_SMALL_RECORD_LEN;
+
+    #[test]
+    fn basic_parsing() {
+        let paths = &["$.f1", "$.f2.e1", "$.f3"];
+        let record = r#"{
+            "f1": true,
+            "f2": {
+                "e2": "\"foo\\",
+                "e1": { "c1": null }
+            },
+            "f3": [ true, "10", null ]
+        }"#;
+
+        let mut query_tree = QueryTree::default();
+        for path in paths {
+            query_tree.add_path(path).unwrap();
+        }
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        let result = parser.parse(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(
+            result,
+            &[
+                Some("true"),
+                Some(r#"{ "c1": null }"#),
+                Some(r#"[ true, "10", null ]"#)
+            ]
+        );
+    }
+
+    #[test]
+    fn speculative_parsing() {
+        let paths = &["$.f1", "$.f2.e1", "$.f3"];
+        let record = r#"{
+            "f1": true,
+            "f2": {
+                "e2": "\"foo\\",
+                "e1": { "c1": null }
+            },
+            "f3": [ true, "10", null ]
+        }"#;
+
+        let mut query_tree = QueryTree::default();
+        for path in paths {
+            query_tree.add_path(path).unwrap();
+        }
+
+        let mut index_builder =
+            IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        index_builder.rank_select(true);
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.allow_fallback(false);
+
+        let _ = parser.parse(record, QueryParserMode::Basic).unwrap();
+
+        let result = parser.parse(record, QueryParserMode::Speculative).unwrap();
+        assert_eq!(
+            result,
+            &[
+                Some("true"),
+                Some(r#"{ "c1": null }"#),
+                Some(r#"[ true, "10", null ]"#)
+            ]
+        );
+    }
+
+    #[test]
+    fn unescaped_strings() {
+        let record = r#"{ "a": "foo", "b": "x\"\u00e9", "c": [ "d" ], "d": 1 }"#;
+
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b", "$.c", "$.d", "$.e"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+
+        let result = parser.parse_cow(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(result[1].as_deref(), Some(r#""x\"\u00e9""#));
+
+        parser.unescape_strings(true);
+        let result = parser.parse_cow(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(
+            result,
+            &[
+                Some(Cow::Borrowed("foo")),
+                Some(Cow::Owned("x\"\u{e9}".to_owned())),
+                Some(Cow::Borrowed(r#"[ "d" ]"#)),
+                Some(Cow::Borrowed("1")),
+                None,
+            ]
+        );
+        assert!(matches!(result[0], Some(Cow::Borrowed(..))));
+    }
+
+    #[test]
+    fn excluded_string_quotes() {
+        let record = r#"{ "a": "foo", "b": "x\"", "c": [ "d" ], "d": 1, "e": "" }"#;
+
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b", "$.c", "$.d", "$.e"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.string_quotes(StringQuotes::Exclude);
+
+        let expected = [
+            Some("foo"),
+            Some(r#"x\""#),
+            Some(r#"[ "d" ]"#),
+            Some("1"),
+            Some(""),
+        ];
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            expected
+                .iter()
+                .map(|v| vec![v.unwrap()])
+                .collect::<Vec<_>>()
+        );
+        let result = parser.parse_cow(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(result[0].as_deref(), Some("foo"));
+        parser.unescape_strings(true);
+        let result = parser.parse_cow(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(result[1].as_deref(), Some("x\""));
+    }
+
+    #[test]
+    fn strict_value_trimming() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c", "$.d"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.value_trimming(ValueTrimming::Strict);
+
+        let record = "{ \"a\" :\t[1, {\"x\": 2}]\r\n,\n\"b\": {\"c\": \"}, \" \t},\"d\":1}";
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some(r#"[1, {"x": 2}]"#), Some(r#""}, ""#), Some("1"),]
+        );
+
+        // the byte scanning leaves the trailing comma in the value.
+        let record = r#"{ "a": 1, "d": 2, }"#;
+        assert!(parser.parse(record, QueryParserMode::Basic).is_err());
+        parser.value_trimming(ValueTrimming::Scan);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap()[2],
+            Some("2,")
+        );
+    }
+
+    #[test]
+    fn parse_with_workspace() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.f1", "$.f2.e1"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+
+        let mut workspace = Workspace::new();
+        let mut results = vec![];
+        let records = [
+            r#"{ "f1": 1, "f2": { "e1": "a" } }"#,
+            r#"{ "f2": { "e0": null, "e1": [] }, "f1": true }"#,
+            r#"{ "f0": {} }"#,
+        ];
+        for record in &records {
+            parser
+                .parse_with_workspace(
+                    record,
+                    QueryParserMode::Speculative,
+                    &mut workspace,
+                    &mut results,
+                )
+                .unwrap();
+            assert_eq!(
+                results,
+                parser.parse(record, QueryParserMode::Speculative).unwrap()
+            );
+        }
+        assert_eq!(results, &[None, None]);
+        assert!(parser
+            .parse_with_workspace("[]", QueryParserMode::Basic, &mut workspace, &mut results)
+            .is_err());
+    }
+
+    #[test]
+    fn cached_offsets() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.cache_offsets(true);
+
+        let mut workspace = Workspace::new();
+        let mut results = vec![];
+        let records = [
+            r#"{ "a": 1, "x": "x", "b": { "c": 2 } }"#,
+            // the same layout, where the colons in strings are not structural.
+            r#"{ "a": 3, "x": "y", "b": { "c": 4 } }"#,
+            r#"{ "a": 5, "x": ":", "b": { "c": 6 } }"#,
+            // the offset of "b" is taken by another field.
+            r#"{ "a": 7, "y":0,"x": { "c": 8 } }"#,
+            r#"{ "b": { "c": 9 }, "a": 10 }"#,
+            r#"{ "b": { "c": 9 } }"#,
+        ];
+        for record in &records {
+            parser
+                .parse_with_workspace(
+                    record,
+                    QueryParserMode::Speculative,
+                    &mut workspace,
+                    &mut results,
+                )
+                .unwrap();
+            assert_eq!(
+                results,
+                parser.parse(record, QueryParserMode::Basic).unwrap()
+            );
+            assert_eq!(
+                workspace.scratch.offsets.len(),
+                if results.contains(&None) { 0 } else { 3 }
+            );
+        }
+        assert_eq!(results, &[None, Some("9")]);
+    }
+
+    #[test]
+    fn key_precheck() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a.long_name", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.key_precheck(true);
+
+        // the records without the keys are not indexed at all.
+        let record = r#"{ "a": { "x": 1 }, "c": 2 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[None, None]
+        );
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            &[vec![], vec![]] as &[Vec<&str>]
+        );
+        assert!(parser.parse("[]", QueryParserMode::Basic).is_err());
+
+        let record = r#"{ "a": { "long_name": 1 }, "c": 2 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("1"), None]
+        );
+        // "b" appears as a substring of a string value.
+        let record = r#"{ "a": "b", "b": 2 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[None, Some("2")]
+        );
+
+        parser.unescape_fields(true);
+        let record = r#"{ "\u0062": 3 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[None, Some("3")]
+        );
+
+        // the results of the skipped records are finished as usual.
+        let mut query_tree = QueryTree::default();
+        for path in &["$", "$.a | $.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.key_precheck(true);
+        parser.control_chars(ControlChars::Reject);
+        let record = r#"{ "c": 1 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some(record), None]
+        );
+        assert_eq!(parser.parse_all(record).unwrap(), &[vec![record], vec![]]);
+        assert!(parser
+            .parse("{ \"c\": \"\t\" }", QueryParserMode::Basic)
+            .is_err());
+        assert!(parser.parse_all("{ \"c\": \"\t\" }").is_err());
+    }
+
+    #[test]
+    fn small_records() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c", "$.b.d", "$.café"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.unescape_fields(true);
+        let mut small = parser.clone();
+        small.small_record_len(DEFAULT_SMALL_RECORD_LEN);
+
+        let records = [
+            r#"{ "a": 1, "b": { "d": [1, {"c": 0}], "c": "x,}" }, "a": 2 }"#,
+            r#"{ "b": 1, "caf\u00e9": {}, "a" : "\"" }"#,
+            r#"{}"#,
+        ];
+        for &direction in &[ScanDirection::Forward, ScanDirection::Reverse] {
+            parser.scan_direction(direction);
+            small.scan_direction(direction);
+            for record in &records {
+                for &mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+                    assert_eq!(
+                        small.parse(record, mode).unwrap(),
+                        parser.parse(record, QueryParserMode::Basic).unwrap()
+                    );
+                }
+            }
+        }
+        assert!(small
+            .parse(r#"{ "a": [1 }"#, QueryParserMode::Basic)
+            .is_err());
+    }
+
+    #[test]
+    fn control_chars() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b", "$.c"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        let record = "{\n\t\"a\": \"x\ty\",\n\t\"b\": [\n1, \"\u{1}\"],\n\t\"c\": 2\n}";
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("\"x\ty\""), Some("[\n1, \"\u{1}\"]"), Some("2")]
+        );
+
+        parser.control_chars(ControlChars::Escape);
+        parser.string_quotes(StringQuotes::Exclude);
+        assert_eq!(
+            parser.parse_cow(record, QueryParserMode::Basic).unwrap(),
+            &[
+                Some(Cow::from("x\\ty")),
+                Some(Cow::from("[\n1, \"\\u0001\"]")),
+                Some(Cow::from("2"))
+            ]
+        );
+
+        parser.control_chars(ControlChars::Reject);
+        let mut small = parser.clone();
+        small.small_record_len(DEFAULT_SMALL_RECORD_LEN);
+        for parser in &[&parser, &small] {
+            assert!(parser.parse(record, QueryParserMode::Basic).is_err());
+            assert!(parser.parse_all(record).is_err());
+            // the whitespace outside strings is allowed.
+            let record = "{\n\t\"a\": \"x\",\n\t\"b\": [\n1, 2]}";
+            assert_eq!(
+                parser.parse(record, QueryParserMode::Basic).unwrap(),
+                &[Some("x"), Some("[\n1, 2]"), None]
+            );
+        }
+    }
+
+    #[test]
+    fn default_values() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.retries ?? 0", r#"$.user.name ?? "a\u0062c""#, "$.id"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+
+        let record = r#"{ "user": {} }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[None, None, None]
+        );
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            &[vec![], vec![], vec![]] as &[Vec<&str>]
+        );
+        assert_eq!(parser.query_tree().default_value(0), Some("0"));
+        assert_eq!(
+            parser.parse_cow(record, QueryParserMode::Basic).unwrap(),
+            &[Some(Cow::from("0")), Some(Cow::from(r#""a\u0062c""#)), None]
+        );
+        let record = r#"{ "retries": 3, "user": { "name": "x" } }"#;
+        assert_eq!(
+            parser.parse_cow(record, QueryParserMode::Basic).unwrap(),
+            &[Some(Cow::from("3")), Some(Cow::from(r#""x""#)), None]
+        );
+
+        parser.unescape_strings(true);
+        assert_eq!(
+            parser.parse_cow("{}", QueryParserMode::Basic).unwrap(),
+            &[Some(Cow::from("0")), Some(Cow::from("abc")), None]
+        );
+    }
+
+    #[test]
+    fn prefilter() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.method", "$.path"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.with_prefilter(|record| record.contains(r#""GET""#));
+
+        let record = r#"{ "method": "GET", "path": "/" }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some(r#""GET""#), Some(r#""/""#)]
+        );
+        let record = r#"{ "method": "POST", "path": "/" }"#;
+        for mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+            assert_eq!(parser.parse(record, *mode).unwrap(), &[None, None]);
+        }
+        assert!(parser.parse_all(record).unwrap().iter().all(Vec::is_empty));
+        // the rejected records are not checked.
+        assert_eq!(
+            parser.parse("# comment", QueryParserMode::Basic).unwrap(),
+            &[None, None]
+        );
+        assert!(parser.parse("\"GET\"", QueryParserMode::Basic).is_err());
+    }
+
+    #[test]
+    fn max_value_len() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.id", "$.data", "$.name"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.max_value_len(Some(6));
+
+        let record = r#" { "id": 1, "data": "QUJDREVGR0g=", "name": "ééé" }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("1"), Some(r#""QUJDR"#), Some("\"éé")]
+        );
+        assert_eq!(parser.parse_all(record).unwrap()[1], &[r#""QUJDR"#]);
+        let ranges = parser.parse_ranges(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(ranges[1], Some(20..34));
+        assert_eq!(&record[ranges[2].clone().unwrap()], r#""ééé""#);
+
+        parser.unescape_strings(true);
+        assert_eq!(
+            parser.parse_cow(record, QueryParserMode::Basic).unwrap(),
+            &[
+                Some(Cow::from("1")),
+                Some(Cow::from("QUJDRE")),
+                Some(Cow::from("ééé"))
+            ]
+        );
+        parser.max_value_len(Some(5));
+        let results = parser.parse_cow(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(results[2], Some(Cow::from("éé")));
+        // only the part within the cap is unescaped.
+        let escaped = r#"{ "name": "\u00e9\u00e9\u00e9\x" }"#;
+        let results = parser.parse_cow(escaped, QueryParserMode::Basic).unwrap();
+        assert_eq!(results[2], Some(Cow::from("éé")));
+        parser.max_value_len(Some(7));
+        assert!(parser.parse_cow(escaped, QueryParserMode::Basic).is_err());
+
+        parser.string_quotes(StringQuotes::Exclude);
+        parser.max_value_len(None);
+        let ranges = parser.parse_ranges(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(ranges[1], Some(21..33));
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap()[1],
+            Some("QUJDREVGR0g=")
+        );
+    }
+
+    #[test]
+    fn transforms() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.level ?? \"INFO\"", "$.host", "$.msg"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.map(0, |s| s.trim_matches('"').to_lowercase());
+        parser.map(1, |_| unreachable!());
+        // the length of the string, which is unescaped if `unescape_strings` is set.
+        parser.map(1, |s| s.len().to_string());
+
+        let record = r#"{ "level": "WARN", "host": "a\u0062", "msg": "Hi" }"#;
+        assert_eq!(
+            parser.parse_cow(record, QueryParserMode::Basic).unwrap(),
+            &[
+                Some(Cow::from("warn")),
+                Some(Cow::from("9")),
+                Some(Cow::from(r#""Hi""#))
+            ]
+        );
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap()[0],
+            Some(r#""WARN""#)
+        );
+
+        parser.unescape_strings(true);
+        let parser = parser.freeze();
+        assert_eq!(
+            parser.parse_cow(record, QueryParserMode::Basic).unwrap(),
+            &[
+                Some(Cow::from("warn")),
+                Some(Cow::from("2")),
+                Some(Cow::from("Hi"))
+            ]
+        );
+        assert_eq!(
+            parser.parse_cow("{}", QueryParserMode::Basic).unwrap()[0],
+            Some(Cow::from("INFO"))
+        );
+    }
+
+    #[test]
+    fn scalar_root() {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.a").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        for &(record, found) in &[(" \"a\" ", "string"), ("42", "number"), ("[1]", "array")] {
+            let err = parser.parse(record, QueryParserMode::Basic).unwrap_err();
+            match *err.kind() {
+                ErrorKind::InvalidType("object", f) => assert_eq!(f, found),
+                ref kind => panic!("{:?}: {}", record, kind),
+            }
+            assert!(parser.parse_all(record).is_err());
+        }
+        assert_eq!(
+            parser
+                .parse("42", QueryParserMode::Basic)
+                .unwrap_err()
+                .to_string(),
+            "invalid type: expected object, found number"
+        );
+        assert!(parser.parse("", QueryParserMode::Basic).is_err());
+    }
+
+    #[test]
+    fn alternative_paths() {
+        let mut query_tree = QueryTree::default();
+        for path in &[
+            "$.user_id | $.uid | $.user.id ?? 0",
+            "$.name",
+            "$.tags | $.labels",
+        ] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.all_matches(2, true);
+
+        let records = [
+            (
+                r#"{ "user": { "id": 3 }, "uid": 2, "name": "x" }"#,
+                Some("2"),
+            ),
+            (
+                r#"{ "user": { "id": 3 }, "user_id": 1, "uid": 2 }"#,
+                Some("1"),
+            ),
+            (r#"{ "user": { "id": 3 } }"#, Some("3")),
+            (r#"{ "name": "y" }"#, None),
+        ];
+        for mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+            for &(record, expected) in &records {
+                let results = parser.parse(record, *mode).unwrap();
+                assert_eq!(results.len(), 3);
+                assert_eq!(results[0], expected, "{}", record);
+            }
+        }
+        assert_eq!(
+            parser
+                .parse_cow(records[3].0, QueryParserMode::Basic)
+                .unwrap()[0],
+            Some(Cow::from("0"))
+        );
+
+        let record = r#"{ "labels": [1], "tags": 1, "labels": [2], "tags": 2 }"#;
+        assert_eq!(parser.parse_all(record).unwrap()[2], &["1", "2"]);
+        let record = r#"{ "labels": [1], "labels": [2] }"#;
+        assert_eq!(parser.parse_all(record).unwrap()[2], &["[1]", "[2]"]);
+    }
+
+    #[test]
+    fn speculative_fallback() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c", "$.b.d"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+
+        let record = r#"{ "a": 1, "b": { "c": 2, "d": 3 } }"#;
+        parser.parse(record, QueryParserMode::Basic).unwrap();
+
+        // the pattern of the nested object differs, so the speculation fails in the
+        // middle and the basic parsing reuses the colon positions of the root.
+        let record = r#"{ "a": 1, "b": { "x": 0, "d": 3, "c": 2 } }"#;
+        parser.allow_fallback(false);
+        assert!(parser.parse(record, QueryParserMode::Speculative).is_err());
+        parser.allow_fallback(true);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Speculative).unwrap(),
+            &[Some("1"), Some("2"), Some("3")]
+        );
+
+        // the predicted positions beyond the number of fields.
+        assert_eq!(
+            parser
+                .parse(r#"{ "a": 1 }"#, QueryParserMode::Speculative)
+                .unwrap(),
+            &[Some("1"), None, None]
+        );
+    }
+
+    #[test]
+    fn subqueries() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.kind", "$.payload"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let mut relative_tree = QueryTree::default();
+        for path in &["$.id", "$.user.name"] {
+            relative_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 3);
+        let parser = QueryParser::new(index_builder, query_tree.clone());
+        let mut subquery = parser.subquery(1, relative_tree.clone()).unwrap();
+        subquery.parser_mut().save_patterns(true);
+        subquery.parser_mut().string_quotes(StringQuotes::Exclude);
+
+        let record = r#"{"kind": "a", "id": 0, "payload": {"user": {"name": "x"}, "id": 1}}"#;
+        for &mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+            let (results, sub_results) = parser.parse_subquery(record, mode, &subquery).unwrap();
+            assert_eq!(
+                results,
+                [Some(r#""a""#), Some(r#"{"user": {"name": "x"}, "id": 1}"#)]
+            );
+            assert_eq!(sub_results, [Some("1"), Some("x")]);
+        }
+        assert_eq!(subquery.parser_mut().pattern_tree(0).patterns().count(), 1);
+
+        let (_, sub_results) = parser
+            .parse_subquery(r#"{"payload": [1]}"#, QueryParserMode::Basic, &subquery)
+            .unwrap();
+        assert_eq!(sub_results, [None, None]);
+
+        let mut parser = parser;
+        parser.small_record_len(1024);
+        let (_, sub_results) = parser
+            .parse_subquery(record, QueryParserMode::Basic, &subquery)
+            .unwrap();
+        assert_eq!(sub_results, [Some("1"), Some("x")]);
+
+        assert!(parser.subquery(0, relative_tree.clone()).is_ok());
+        assert!(parser.subquery(2, relative_tree.clone()).is_err());
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let parser = QueryParser::new(index_builder, query_tree);
+        assert!(parser.subquery(1, relative_tree).is_err());
+    }
+
+    #[test]
+    fn embedded_json() {
+        let mut query_tree = QueryTree::default();
+        let paths = [
+            "$.id",
+            "$.payload!json.a",
+            "$.payload!json.b.c",
+            "$.payload!json.d!json.e",
+            "$.meta",
+            "$.meta!json.x ?? 0",
+        ];
+        for path in &paths {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.unescape_strings(true);
+
+        let record = r#"{"id": 1, "payload": "{\"a\": \"\\u00e9\", \"b\": {\"c\": [1]}, \"d\": \"{\\\"e\\\": 2}\"}", "meta": "{}"}"#;
+        // the paths within the strings are extracted only by `parse_cow` and `parse_to`.
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            [Some("1"), None, None, None, Some(r#""{}""#), None]
+        );
+        let all = parser.parse_all(record).unwrap();
+        assert!(all[1..4].iter().chain(&all[5..]).all(Vec::is_empty));
+        for mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+            let values = parser.parse_cow(record, *mode).unwrap();
+            assert_eq!(
+                values.iter().map(|v| v.as_deref()).collect::<Vec<_>>(),
+                [
+                    Some("1"),
+                    Some("\u{e9}"),
+                    Some("[1]"),
+                    Some("2"),
+                    Some("{}"),
+                    Some("0")
+                ]
+            );
+        }
+
+        let values = parser.parse_cow(r#"{"payload": 1, "meta": "[1]"}"#, QueryParserMode::Basic);
+        assert!(values.is_err());
+        let values = parser
+            .parse_cow(r#"{"payload": 1}"#, QueryParserMode::Basic)
+            .unwrap();
+        assert!(values[1..4].iter().all(Option::is_none));
+
+        // the strings are parsed with the options of the outer parser.
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.payload!json.a.b").unwrap();
+        let mut index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        index_builder.max_depth(2);
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.unescape_fields(true);
+        let record = r#"{"payload": "{\"\\u0061\": {\"b\": 1}}"}"#;
+        let values = parser.parse_cow(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(values[0].as_deref(), Some("1"));
+        let record = r#"{"payload": "{\"a\": {\"b\": [1]}}"}"#;
+        let err = parser
+            .parse_cow(record, QueryParserMode::Basic)
+            .unwrap_err();
+        assert!(err
+            .iter()
+            .any(|e| e.to_string() == "nesting depth limit exceeded: 2"));
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    fn base64_strings() {
+        let mut query_tree = QueryTree::default();
+        for path in &[
+            "$.id",
+            "$.data!base64",
+            r#"$.missing!base64 ?? "Zm9v""#,
+            "$.payload!json.data !base64",
+        ] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        let record = r#"{"id": "x", "data": "AAEC\/w==", "payload": "{\"data\": \"YmFy\"}"}"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap()[1],
+            Some(r#""AAEC\/w==""#)
+        );
+        let values = parser.parse_bytes(record, QueryParserMode::Basic).unwrap();
+        assert_eq!(
+            values.iter().map(|v| v.as_deref()).collect::<Vec<_>>(),
+            [
+                Some(&b"\"x\""[..]),
+                Some(&[0, 1, 2, 0xff][..]),
+                Some(&b"foo"[..]),
+                Some(&b"bar"[..])
+            ]
+        );
+        let values = parser
+            .parse_bytes(r#"{"data": 1}"#, QueryParserMode::Basic)
+            .unwrap();
+        assert_eq!(values[1], None);
+        assert!(parser
+            .parse_bytes(r#"{"data": "A"}"#, QueryParserMode::Basic)
+            .is_err());
+    }
+
+    #[test]
+    fn buffer_policy() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.buffer_policy(BufferPolicy::HardCap(4096));
+
+        let fields: Vec<String> = (0..1000).map(|i| format!("\"f{}\": {}", i, i)).collect();
+        let large = format!(
+            "{{{}, \"b\": {{{}, \"c\": 1}}}}",
+            fields.join(","),
+            fields.join(",")
+        );
+        let small = r#"{"a": 1, "b": {"c": 2}}"#;
+        let mut workspace = Workspace::new();
+        let mut results = vec![];
+        for &mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+            parser
+                .parse_with_workspace(&large, mode, &mut workspace, &mut results)
+                .unwrap();
+            assert_eq!(results, [None, Some("1")]);
+            assert!(workspace.memory_usage() > 4096);
+            parser
+                .parse_with_workspace(small, mode, &mut workspace, &mut results)
+                .unwrap();
+            assert_eq!(results, [Some("1"), Some("2")]);
+            assert!(
+                workspace.memory_usage() <= 4096,
+                "{}",
+                workspace.memory_usage()
+            );
+        }
+
+        parser.buffer_policy(BufferPolicy::KeepPeak);
+        parser
+            .parse_with_workspace(&large, QueryParserMode::Basic, &mut workspace, &mut results)
+            .unwrap();
+        let peak = workspace.memory_usage();
+        parser
+            .parse_with_workspace(small, QueryParserMode::Basic, &mut workspace, &mut results)
+            .unwrap();
+        assert_eq!(workspace.memory_usage(), peak);
+
+        // the internal workspace follows the same policy.
+        parser.buffer_policy(BufferPolicy::HardCap(4096));
+        parser.parse(&large, QueryParserMode::Basic).unwrap();
+        assert!(parser.memory_usage().unwrap() > 4096);
+        parser.parse(small, QueryParserMode::Basic).unwrap();
+        let usage = parser.memory_usage().unwrap();
+        assert!(usage <= 4096, "{}", usage);
+    }
+
+    #[test]
+    fn root_path() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$", "$.a"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.cache_offsets(true);
+
+        let record = r#" { "a": 1, "b": 2 }
+"#;
+        for &mode in &[
+            QueryParserMode::Basic,
+            QueryParserMode::Speculative,
+            QueryParserMode::Speculative,
+        ] {
+            assert_eq!(
+                parser.parse(record, mode).unwrap(),
+                [Some(record.trim()), Some("1")]
+            );
+        }
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            [vec![record.trim()], vec!["1"]]
+        );
+        parser.small_record_len(64);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            [Some(record.trim()), Some("1")]
+        );
+
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+        let (results, provenance) = parser
+            .parse_with_provenance(record, QueryParserMode::Speculative)
+            .unwrap();
+        assert_eq!(results, [Some(record.trim())]);
+        assert_eq!(provenance.parsed_by, ParsedBy::Root);
+        assert!(parser.parse("[1]", QueryParserMode::Basic).is_err());
+    }
+
+    #[test]
+    fn expected_types() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b", "$.c"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.expect_type(0, Some(JsonType::Number));
+        parser.expect_type(1, Some(JsonType::Object));
+
+        let record = r#"{ "a": -1, "b": {}, "c": "x" }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            [Some("-1"), Some("{}"), Some("\"x\"")]
+        );
+        parser
+            .parse(r#"{ "c": 1 }"#, QueryParserMode::Basic)
+            .unwrap();
+
+        let record = r#"{ "a": 1, "b": [1] }"#;
+        for len in &[0, 64] {
+            parser.small_record_len(*len);
+            let err = parser.parse(record, QueryParserMode::Basic).unwrap_err();
+            match *err.kind() {
+                ErrorKind::TypeMismatch(ref path, "object", "array") => assert_eq!(path, "$.b"),
+                ref kind => panic!("{}", kind),
+            }
+        }
+        assert!(parser.parse_all(r#"{ "a": "1" }"#).is_err());
+        parser.expect_type(0, None);
+        parser.parse_all(r#"{ "a": "1" }"#).unwrap();
+    }
+
+    #[test]
+    fn failure_sink() {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.a").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        let failures = Arc::new(Mutex::new(vec![]));
+        let sink = failures.clone();
+        parser.failure_sink(8, move |failure| {
+            sink.lock().unwrap().push(failure.to_json());
+        });
+
+        parser.parse(r#"{"a": 1}"#, QueryParserMode::Basic).unwrap();
+        assert!(parser
+            .parse(r#"{"a": 1} }"#, QueryParserMode::Basic)
+            .is_err());
+        assert!(parser.parse_all("[\"\u{e9}\u{e9}\u{e9}\"]").is_err());
+        parser.allow_fallback(false);
+        assert!(parser
+            .parse(r#"{"a": 1}"#, QueryParserMode::Speculative)
+            .is_err());
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 2);
+        assert!(failures[0].starts_with(r#"{"kind":"InvalidRecord","offset":9,"message":""#));
+        assert!(failures[0].ends_with(r#"","record":"{\"a\": 1}","truncated":true}"#));
+        assert_eq!(
+            failures[1],
+            concat!(
+                r#"{"kind":"InvalidType","offset":null,"#,
+                r#""message":"invalid type: expected object, found array","#,
+                "\"record\":\"[\\\"\u{e9}\u{e9}\u{e9}\",\"truncated\":true}",
+            )
+        );
+    }
+
+    #[test]
+    fn reentrant_parsing() {
+        use std::sync::{OnceLock, Weak};
+
+        type Parser = QueryParser<'static, FallbackBackend>;
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.a").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+
+        // the nested objects are parsed recursively by the transform, and the invalid
+        // records are replaced by the sink.
+        let this: Arc<OnceLock<Weak<Parser>>> = Arc::new(OnceLock::new());
+        let nested = this.clone();
+        parser.map(0, move |value| {
+            if !value.starts_with('{') {
+                return value.to_owned();
+            }
+            let parser = nested.get().and_then(Weak::upgrade).unwrap();
+            let results = parser
+                .parse_cow(value, QueryParserMode::Speculative)
+                .unwrap();
+            format!("<{}>", results[0].as_deref().unwrap_or(""))
+        });
+        let replaced = Arc::new(Mutex::new(vec![]));
+        let sink = (this.clone(), replaced.clone());
+        parser.failure_sink(64, move |_| {
+            let parser = sink.0.get().and_then(Weak::upgrade).unwrap();
+            let results = parser.parse(r#"{"a": 0}"#, QueryParserMode::Basic).unwrap();
+            sink.1.lock().unwrap().push(results[0].unwrap().to_owned());
+        });
+        let parser = Arc::new(parser);
+        this.set(Arc::downgrade(&parser)).unwrap();
+
+        let record = r#"{"a": {"a": {"a": [1]}}}"#;
+        for _ in 0..3 {
+            let results = parser
+                .parse_cow(record, QueryParserMode::Speculative)
+                .unwrap();
+            assert_eq!(results[0].as_deref(), Some("<<[1]>>"));
+        }
+        assert!(parser
+            .parse(r#"{"a": 1} }"#, QueryParserMode::Basic)
+            .is_err());
+        assert_eq!(*replaced.lock().unwrap(), ["0"]);
+    }
+
+    #[test]
+    fn provenance() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+
+        let record = r#"{ "x": 0, "a": 1, "b": 2 }"#;
+        let (results, provenance) = parser
+            .parse_with_provenance(record, QueryParserMode::Speculative)
+            .unwrap();
+        assert_eq!(results, [Some("1"), Some("2")]);
+        assert_eq!(provenance.parsed_by, ParsedBy::Fallback);
+        assert!(provenance.pattern.is_empty());
+
+        let (results, provenance) = parser
+            .parse_with_provenance(record, QueryParserMode::Speculative)
+            .unwrap();
+        assert_eq!(results, [Some("1"), Some("2")]);
+        assert_eq!(
+            provenance,
+            Provenance {
+                parsed_by: ParsedBy::Speculation,
+                pattern: vec![("a".to_owned(), 1), ("b".to_owned(), 2)],
+            }
+        );
+
+        let (_, provenance) = parser
+            .parse_with_provenance(record, QueryParserMode::Basic)
+            .unwrap();
+        assert_eq!(provenance.parsed_by, ParsedBy::Basic);
+        parser.small_record_len(64);
+        let (_, provenance) = parser
+            .parse_with_provenance(record, QueryParserMode::Speculative)
+            .unwrap();
+        assert_eq!(provenance.parsed_by, ParsedBy::Scan);
+    }
+
+    #[test]
+    fn preserved_offsets() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c", "$.b.d"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.capture_failures(1);
+
+        parser
+            .parse(
+                "{ \"a\": 1, \"b\": { \"c\": 2, \"d\": 3 } }\r\n",
+                QueryParserMode::Basic,
+            )
+            .unwrap();
+        parser.save_patterns(false);
+
+        let record = "\r\n{ \"a\": 1, \"b\": { \"d\": 3, \"c\": \"x\" } }\r\n";
+        for &(preserve, begin) in &[(false, 15), (true, 17)] {
+            parser.preserve_offsets(preserve);
+            for &mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+                assert_eq!(
+                    parser.parse_ranges(record, mode).unwrap(),
+                    [Some(9..10), Some(32..35), Some(24..25)]
+                );
+            }
+            assert_eq!(
+                parser.parse_all(record).unwrap(),
+                [vec!["1"], vec!["\"x\""], vec!["3"]]
+            );
+
+            // the offsets of the failures refer to the record with its line terminators
+            // only if preserved.
+            let failures = parser.take_failures();
+            assert_eq!(failures.len(), 1);
+            assert_eq!(
+                failures[0].record,
+                if preserve { record } else { record.trim() }
+            );
+            assert_eq!((failures[0].begin, failures[0].end), (begin, begin + 20));
+            assert_eq!(
+                parser.replay(&failures[0]).unwrap().as_ref(),
+                Some(&failures[0])
+            );
+        }
+
+        let message = |parser: &QueryParser<'_, _>| {
+            let err = parser
+                .parse("\r\n{\"a\" 1}\r\n", QueryParserMode::Basic)
+                .unwrap_err();
+            err.iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(": ")
+        };
+        parser.small_record_len(1024);
+        assert!(message(&parser).contains("at 3"), "{}", message(&parser));
+        parser.preserve_offsets(false);
+        assert!(message(&parser).contains("at 1"), "{}", message(&parser));
+    }
+
+    #[test]
+    fn captured_failures() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b.c", "$.b.d"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.capture_failures(1);
+
+        parser
+            .parse(
+                r#"{ "a": 1, "b": { "c": 2, "d": 3 } }"#,
+                QueryParserMode::Basic,
+            )
+            .unwrap();
+        parser.save_patterns(false);
+        let records = [
+            r#"{ "a": 1, "b": { "d": 3, "c": 2 } }"#,
+            r#"{ "b": {}, "a": 1 }"#,
+        ];
+        for record in &records {
+            parser.parse(record, QueryParserMode::Speculative).unwrap();
+        }
+
+        let failures = parser.take_failures();
+        assert_eq!(
+            failures,
+            [SpeculationFailure {
+                record: records[0].to_owned(),
+                node_id: 2,
+                begin: 15,
+                end: 33,
+                matched: vec![],
+                tried: vec![("c".to_owned(), 0)],
+                colons: vec![20, 28],
+            }]
+        );
+        assert!(parser.take_failures().is_empty());
+
+        // the replay is deterministic, until the patterns of the record are trained.
+        assert_eq!(
+            parser.replay(&failures[0]).unwrap().as_ref(),
+            Some(&failures[0])
+        );
+        parser.save_patterns(true);
+        parser.parse(records[0], QueryParserMode::Basic).unwrap();
+        assert_eq!(parser.replay(&failures[0]).unwrap(), None);
+    }
+
+    #[test]
+    fn shared_among_threads() {
+        fn assert_sync<T: Send + Sync>() {}
+        assert_sync::<QueryParser<'static, FallbackBackend>>();
+
+        let mut query_tree = QueryTree::default();
+        for path in &["$.id", "$.user.name"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+
+        let parser = &parser;
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                scope.spawn(move || {
+                    let mut workspace = Workspace::new();
+                    for i in 0..100 {
+                        let record = format!(r#"{{ "id": {}, "user": {{ "name": "{}" }} }}"#, i, t);
+                        let mut results = vec![];
+                        let mode = if i % 2 == 0 {
+                            QueryParserMode::Basic
+                        } else {
+                            QueryParserMode::Speculative
+                        };
+                        parser
+                            .parse_with_workspace(&record, mode, &mut workspace, &mut results)
+                            .unwrap();
+                        assert_eq!(results[0], Some(&*i.to_string()));
+                        assert_eq!(
+                            parser.parse(&record, mode).unwrap()[1],
+                            Some(&*format!(r#""{}""#, t))
+                        );
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn frozen_parser() {
+        fn assert_sync<T: Send + Sync + Clone>() {}
+        assert_sync::<FrozenQueryParser<'static, FallbackBackend>>();
+
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
         let mut parser = QueryParser::new(index_builder, query_tree);
         parser.save_patterns(true);
         parser.allow_fallback(false);
+        parser
+            .parse(r#"{ "a": 1, "b": 2 }"#, QueryParserMode::Basic)
+            .unwrap();
 
-        let _ = parser.parse(record, QueryParserMode::Basic).unwrap();
+        let frozen = parser.freeze();
+        let cloned = frozen.clone();
+        assert_eq!(
+            cloned
+                .parse(r#"{ "a": 3, "b": 4 }"#, QueryParserMode::Speculative)
+                .unwrap(),
+            &[Some("3"), Some("4")]
+        );
+        assert!(frozen
+            .parse(r#"{ "b": 4, "a": 3 }"#, QueryParserMode::Speculative)
+            .is_err());
+        assert_eq!(
+            frozen
+                .parse(r#"{ "b": 4, "a": 3 }"#, QueryParserMode::Basic)
+                .unwrap(),
+            &[Some("3"), Some("4")]
+        );
+    }
 
-        let result = parser.parse(record, QueryParserMode::Speculative).unwrap();
+    #[test]
+    fn cloned_parser() {
+        fn assert_clone_send<T: Clone + Send>() {}
+        assert_clone_send::<crate::parser::Parser<FallbackBackend>>();
+        assert_clone_send::<QueryParser<'static, FallbackBackend>>();
+        assert_clone_send::<FrozenQueryParser<'static, FallbackBackend>>();
+
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.allow_fallback(false);
+        parser
+            .parse(r#"{ "a": 1, "b": 2 }"#, QueryParserMode::Basic)
+            .unwrap();
+
+        let cloned = parser.clone();
+        let handle = std::thread::spawn(move || {
+            cloned
+                .parse(r#"{ "a": 3, "b": 4 }"#, QueryParserMode::Speculative)
+                .map(|results| {
+                    results
+                        .into_iter()
+                        .map(|r| r.map(str::to_owned))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap()
+        });
         assert_eq!(
-            result,
-            &[
-                Some("true"),
-                Some(r#"{ "c1": null }"#),
-                Some(r#"[ true, "10", null ]"#)
-            ]
+            handle.join().unwrap(),
+            [Some("3".to_owned()), Some("4".to_owned())]
+        );
+        assert_eq!(
+            parser
+                .parse(r#"{ "a": 5, "b": 6 }"#, QueryParserMode::Speculative)
+                .unwrap(),
+            &[Some("5"), Some("6")]
+        );
+    }
+
+    #[test]
+    fn named_results() {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_named("user_id", "$.user.id").unwrap();
+        query_tree.add_path("$.url").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        let results = parser
+            .parse_named(r#"{ "user": { "id": 42 } }"#, QueryParserMode::Basic)
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results["user_id"], Some("42"));
+        assert_eq!(results["$.url"], None);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn parse_to_struct() {
+        #[derive(Debug, PartialEq, serde_derive::Deserialize)]
+        struct Click {
+            user_id: u64,
+            #[serde(rename = "$.url")]
+            url: Option<String>,
+            tags: Vec<String>,
+        }
+
+        let mut query_tree = QueryTree::default();
+        query_tree.add_named("user_id", "$.user.id").unwrap();
+        query_tree.add_path("$.url").unwrap();
+        query_tree.add_named("tags", "$.tags ?? []").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        let click: Click = parser
+            .parse_to(r#"{ "user": { "id": 1 } }"#, QueryParserMode::Basic)
+            .unwrap();
+        assert_eq!(click.tags, Vec::<String>::new());
+        let click: Click = parser
+            .parse_to(
+                r#"{ "user": { "id": 42 }, "tags": ["a", "b\"c"] }"#,
+                QueryParserMode::Basic,
+            )
+            .unwrap();
+        assert_eq!(
+            click,
+            Click {
+                user_id: 42,
+                url: None,
+                tags: vec!["a".into(), "b\"c".into()],
+            }
+        );
+        assert!(parser
+            .parse_to::<Click>(
+                r#"{ "user": { "id": "42" }, "tags": [] }"#,
+                QueryParserMode::Basic
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn unescaped_fields() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.café", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+
+        let record = r#"{ "café": 3, "b": 2, "caf\u00e9": 1 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("3"), Some("2")]
+        );
+
+        parser.unescape_fields(true);
+        parser.save_patterns(true);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("1"), Some("2")]
+        );
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Speculative).unwrap(),
+            &[Some("1"), Some("2")]
+        );
+
+        assert!(parser
+            .parse(r#"{ "caf\u00": 1 }"#, QueryParserMode::Basic)
+            .is_err());
+    }
+
+    #[test]
+    fn escaped_query_fields() {
+        let mut query_tree = QueryTree::default();
+        for path in &[r"$.caf\u00e9", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+
+        let record = r#"{ "café": 3, "b": 2 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[None, Some("2")]
+        );
+
+        parser.unescape_fields(true);
+        parser.save_patterns(true);
+        parser.cache_offsets(true);
+        let mut workspace = Workspace::new();
+        let mut results = vec![];
+        for &mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+            for _ in 0..2 {
+                parser
+                    .parse_with_workspace(record, mode, &mut workspace, &mut results)
+                    .unwrap();
+                assert_eq!(results, &[Some("3"), Some("2")]);
+            }
+        }
+
+        parser.small_record_len(DEFAULT_SMALL_RECORD_LEN);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("3"), Some("2")]
+        );
+    }
+
+    #[test]
+    fn duplicated_keys() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b", "$.c.d", "$.c.e"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+
+        // the last duplicate wins by default.
+        assert_eq!(
+            parser
+                .parse(r#"{"a":1,"a":2}"#, QueryParserMode::Basic)
+                .unwrap(),
+            &[Some("2"), None, None, None]
+        );
+
+        let record = r#"{
+            "a": 1, "b": 2, "a": 3,
+            "c": { "d": 4, "e": 5 },
+            "c": { "d": 6, "d": 7 },
+            "b": 8
+        }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("3"), Some("8"), Some("7"), None]
+        );
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            [vec!["3"], vec!["8"], vec!["7"], vec![]]
+        );
+
+        parser.all_matches(0, true);
+        parser.all_matches(2, true);
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            [vec!["1", "3"], vec!["8"], vec!["4", "6", "7"], vec![]]
+        );
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("3"), Some("8"), Some("7"), None]
+        );
+
+        // the first duplicates win for some paths.
+        parser.all_matches(0, false);
+        parser.first_match(0, true);
+        parser.first_match(2, true);
+        parser.first_match(3, true);
+        let expected = [Some("1"), Some("8"), Some("4"), Some("5")];
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &expected
+        );
+        assert_eq!(
+            parser.parse_all(record).unwrap(),
+            [vec!["1"], vec!["8"], vec!["4", "6", "7"], vec!["5"]]
+        );
+        let mut small = parser.clone();
+        small.small_record_len(DEFAULT_SMALL_RECORD_LEN);
+        assert_eq!(
+            small.parse(record, QueryParserMode::Basic).unwrap(),
+            &expected
+        );
+        parser.scan_direction(ScanDirection::Forward);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("1"), Some("2"), Some("4"), Some("5")]
+        );
+        parser.scan_direction(ScanDirection::Reverse);
+
+        // the values within the first duplicate are taken even if missing.
+        let record = r#"{ "c": { "e": 1 }, "c": { "d": 2, "e": 3 } }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[None, None, None, Some("1")]
+        );
+        parser.first_match(2, false);
+        parser.first_match(3, false);
+
+        // all keys are found even if a key follows its duplicate.
+        let record = r#"{ "a": 3, "b": 1, "b": 2 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("3"), Some("2"), None, None]
+        );
+    }
+
+    #[test]
+    fn scan_direction() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        assert_eq!(ScanDirection::default(), ScanDirection::Reverse);
+        parser.scan_direction(ScanDirection::Forward);
+
+        let record = r#"{ "a": 1, "b": 2, "a": 3, 4: 5 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("1"), Some("2")]
+        );
+        parser.scan_all_fields(true);
+        assert!(parser.parse(record, QueryParserMode::Basic).is_err());
+
+        parser.scan_all_fields(false);
+        parser.scan_direction(ScanDirection::Reverse);
+        let record = r#"{ "a": 1, "b": 2, "a": 3 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("3"), Some("2")]
+        );
+        assert_eq!(parser.parse_all(record).unwrap(), [vec!["3"], vec!["2"]]);
+
+        let record = r#"{ 0: 1, "a": 1, "b": 2 }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            &[Some("1"), Some("2")]
+        );
+        parser.scan_all_fields(true);
+        assert!(parser.parse(record, QueryParserMode::Basic).is_err());
+
+        // the patterns are recorded in the order of the positions in both directions.
+        parser.allow_fallback(false);
+        assert_eq!(
+            parser
+                .parse(r#"{ "a": 5, "b": 6 }"#, QueryParserMode::Speculative)
+                .unwrap(),
+            &[Some("5"), Some("6")]
         );
     }
+
+    #[test]
+    fn decayed_patterns() {
+        let mut query_tree = QueryTree::default();
+        for path in &["$.a", "$.b"] {
+            query_tree.add_path(path).unwrap();
+        }
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let mut parser = QueryParser::new(index_builder, query_tree);
+        parser.save_patterns(true);
+        parser.allow_fallback(false);
+        parser.decay_patterns(Some(4));
+
+        let old = r#"{ "a": 1, "b": 2 }"#;
+        let new = r#"{ "b": 2, "x": 0, "a": 1 }"#;
+        for _ in 0..10 {
+            parser.parse(old, QueryParserMode::Basic).unwrap();
+        }
+        for _ in 0..10 {
+            parser.parse(new, QueryParserMode::Basic).unwrap();
+        }
+        assert!(parser.parse(new, QueryParserMode::Speculative).is_ok());
+        assert!(parser.parse(old, QueryParserMode::Speculative).is_err());
+    }
+
+    #[test]
+    fn merge_patterns() {
+        let new_parser = |paths: &[&'static str]| {
+            let mut query_tree = QueryTree::default();
+            for path in paths {
+                query_tree.add_path(path).unwrap();
+            }
+            let index_builder =
+                IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+            let mut parser = QueryParser::new(index_builder, query_tree);
+            parser.save_patterns(true);
+            parser.allow_fallback(false);
+            parser
+        };
+        let record1 = r#"{ "a": 1, "b": { "c": 2 } }"#;
+        let record2 = r#"{ "b": { "x": 0, "c": 2 }, "a": 1 }"#;
+
+        let mut parser1 = new_parser(&["$.a", "$.b.c"]);
+        parser1.parse(record1, QueryParserMode::Basic).unwrap();
+        let parser2 = new_parser(&["$.a", "$.b.c"]);
+        parser2.parse(record2, QueryParserMode::Basic).unwrap();
+        parser1.merge_patterns(parser2).unwrap();
+
+        let frozen = parser1.freeze();
+        for record in &[record1, record2] {
+            assert_eq!(
+                frozen.parse(record, QueryParserMode::Speculative).unwrap(),
+                &[Some("1"), Some("2")]
+            );
+        }
+
+        let mut parser1 = new_parser(&["$.a", "$.b.c"]);
+        assert!(parser1
+            .merge_patterns(new_parser(&["$.b.c", "$.a"]))
+            .is_err());
+    }
 }
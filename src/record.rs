@@ -0,0 +1,387 @@
+//! Mapping of query results into user-defined types and typed columns
+//!
+//! With the `derive` feature, `FromRecord` can be derived for structs whose fields
+//! are annotated with their query paths:
+//!
+//! ```ignore
+//! #[derive(FromRecord)]
+//! struct Click<'s> {
+//!     #[record(path = "$.user.id")]
+//!     user_id: u64,
+//!     #[record(path = "$.url")]
+//!     url: Option<&'s str>,
+//! }
+//! ```
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+use crate::typed::{found, FieldType, TypedValue};
+use crate::value;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+#[cfg(feature = "derive")]
+pub use misosoup_derive::FromRecord;
+
+/// Types which can be constructed from the results of `QueryParser`
+pub trait FromRecord<'s>: Sized {
+    /// Query paths whose results are passed to `from_results`, in this order.
+    fn paths() -> &'static [&'static str];
+
+    /// Construct a value from the slices extracted for `paths()`.
+    fn from_results(results: &[Option<&'s str>]) -> Result<Self>;
+}
+
+/// Types which can be converted from an extracted slice
+///
+/// `None` means that the field is missing in the record.
+pub trait FromSlice<'s>: Sized {
+    #[allow(missing_docs)]
+    fn from_slice(raw: Option<&'s str>) -> Result<Self>;
+}
+
+fn required(raw: Option<&str>) -> Result<&str> {
+    raw.ok_or_else(|| Error::from(ErrorKind::InvalidRecord))
+        .chain_err(|| "missing field")
+}
+
+impl<'s, T: FromSlice<'s>> FromSlice<'s> for Option<T> {
+    fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+        match raw {
+            Some(raw) => T::from_slice(Some(raw)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'s> FromSlice<'s> for &'s str {
+    /// The raw slice of the value.
+    fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+        required(raw)
+    }
+}
+
+impl<'s> FromSlice<'s> for Cow<'s, str> {
+    fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+        match FieldType::String.convert(required(raw)?)? {
+            TypedValue::String(s) => Ok(s),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<'s> FromSlice<'s> for String {
+    fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+        Cow::from_slice(raw).map(Cow::into_owned)
+    }
+}
+
+impl<'s> FromSlice<'s> for bool {
+    fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+        match FieldType::Bool.convert(required(raw)?)? {
+            TypedValue::Bool(b) => Ok(b),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<'s> FromSlice<'s> for f64 {
+    fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+        match FieldType::F64.convert(required(raw)?)? {
+            TypedValue::F64(n) => Ok(n),
+            _ => unreachable!(),
+        }
+    }
+}
+
+macro_rules! impl_from_slice_for_integers {
+    ($($t:ty,)*) => {$(
+        impl<'s> FromSlice<'s> for $t {
+            fn from_slice(raw: Option<&'s str>) -> Result<Self> {
+                let raw = required(raw)?;
+                if !value::is_json_number(raw) {
+                    return Err(ErrorKind::InvalidType(stringify!($t), found(raw)).into());
+                }
+                raw.parse()
+                    .map_err(|_| ErrorKind::InvalidType(stringify!($t), "non-integer value").into())
+            }
+        }
+    )*};
+}
+
+impl_from_slice_for_integers!(i8, i16, i32, i64, u8, u16, u32, u64, isize, usize,);
+
+/// A parser which extracts records into values of `T`
+#[derive(Debug)]
+pub struct RecordParser<T, B: Backend> {
+    parser: QueryParser<'static, B>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, B: Backend> RecordParser<T, B> {
+    /// Create a new parser. The level of `index_builder` should cover all paths of `T`.
+    pub fn new<'s>(index_builder: IndexBuilder<B>) -> Result<Self>
+    where
+        T: FromRecord<'s>,
+    {
+        let mut query_tree = QueryTree::default();
+        for path in T::paths() {
+            query_tree.add_path(path)?;
+        }
+        Ok(Self {
+            parser: QueryParser::new(index_builder, query_tree),
+            _marker: PhantomData,
+        })
+    }
+
+    #[allow(missing_docs)]
+    pub fn query_parser(&mut self) -> &mut QueryParser<'static, B> {
+        &mut self.parser
+    }
+
+    /// Parse a record and convert it into `T`.
+    pub fn parse<'s>(&self, record: &'s str, mode: QueryParserMode) -> Result<T>
+    where
+        T: FromRecord<'s>,
+    {
+        let results = self.parser.parse(record, mode)?;
+        T::from_results(&results)
+    }
+}
+
+/// Tuples of column types which can be collected by `Collector`
+///
+/// Each element `T` is collected into a buffer of `Vec<Option<T>>`, where `None`
+/// means that the field is missing in the record.
+pub trait Columns {
+    /// The tuple of column buffers
+    type Buffers: Default;
+
+    #[allow(missing_docs)]
+    fn num_columns() -> usize;
+
+    /// Convert a row of extracted slices and append it to `buffers`.
+    ///
+    /// If any of the slices could not be converted, `buffers` is left unchanged.
+    fn append(buffers: &mut Self::Buffers, results: &[Option<&str>]) -> Result<()>;
+}
+
+macro_rules! impl_columns {
+    ($($n:expr => ($($T:ident $i:tt),*);)*) => {$(
+        impl<$($T),*> Columns for ($($T,)*)
+        where
+            $($T: for<'s> FromSlice<'s>,)*
+        {
+            type Buffers = ($(Vec<Option<$T>>,)*);
+
+            fn num_columns() -> usize {
+                $n
+            }
+
+            fn append(buffers: &mut Self::Buffers, results: &[Option<&str>]) -> Result<()> {
+                let row = ($(
+                    Option::<$T>::from_slice(results[$i]).chain_err(|| format!("column {}", $i))?,
+                )*);
+                $(buffers.$i.push(row.$i);)*
+                Ok(())
+            }
+        }
+    )*};
+}
+
+impl_columns! {
+    1 => (A 0);
+    2 => (A 0, B 1);
+    3 => (A 0, B 1, C 2);
+    4 => (A 0, B 1, C 2, D 3);
+    5 => (A 0, B 1, C 2, D 3, E 4);
+    6 => (A 0, B 1, C 2, D 3, E 4, F 5);
+    7 => (A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+    8 => (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+}
+
+/// A collector which appends the values of each path into a typed column.
+///
+/// ```ignore
+/// let mut collector = Collector::<(u64, String), _>::new(index_builder, &["$.id", "$.name"])?;
+/// for record in records {
+///     collector.parse_append(record)?;
+/// }
+/// let (ids, names) = collector.take_columns();
+/// ```
+#[derive(Debug)]
+pub struct Collector<'q, T: Columns, B: Backend> {
+    parser: QueryParser<'q, B>,
+    mode: QueryParserMode,
+    buffers: T::Buffers,
+    len: usize,
+}
+
+impl<'q, T: Columns, B: Backend> Collector<'q, T, B> {
+    /// Create a new collector binding `paths` to the columns of `T`, in order.
+    ///
+    /// The level of `index_builder` should cover all paths.
+    pub fn new(index_builder: IndexBuilder<B>, paths: &[&'q str]) -> Result<Self> {
+        if paths.len() != T::num_columns() {
+            return Err(Error::from(ErrorKind::InvalidQuery)).chain_err(|| {
+                format!("expected {} paths, found {}", T::num_columns(), paths.len())
+            });
+        }
+        let mut query_tree = QueryTree::default();
+        for path in paths {
+            query_tree.add_path(path)?;
+        }
+        Ok(Self {
+            parser: QueryParser::new(index_builder, query_tree),
+            mode: QueryParserMode::Basic,
+            buffers: T::Buffers::default(),
+            len: 0,
+        })
+    }
+
+    /// Set the parsing mode used to extract the values.
+    pub fn mode(mut self, mode: QueryParserMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    #[allow(missing_docs)]
+    pub fn query_parser(&mut self) -> &mut QueryParser<'q, B> {
+        &mut self.parser
+    }
+
+    /// Parse a record and append its values as a row.
+    ///
+    /// On error, no value is appended to any column.
+    pub fn parse_append(&mut self, record: &str) -> Result<()> {
+        let results = self.parser.parse(record, self.mode)?;
+        T::append(&mut self.buffers, &results)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Return the number of collected rows.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(missing_docs)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[allow(missing_docs)]
+    pub fn columns(&self) -> &T::Buffers {
+        &self.buffers
+    }
+
+    /// Take the collected columns, leaving the collector empty for the next batch.
+    pub fn take_columns(&mut self) -> T::Buffers {
+        self.len = 0;
+        std::mem::take(&mut self.buffers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+
+    #[derive(Debug, PartialEq)]
+    struct Click<'s> {
+        user_id: u64,
+        url: Option<&'s str>,
+        name: String,
+    }
+
+    impl<'s> FromRecord<'s> for Click<'s> {
+        fn paths() -> &'static [&'static str] {
+            &["$.user.id", "$.url", "$.user.name"]
+        }
+
+        fn from_results(results: &[Option<&'s str>]) -> Result<Self> {
+            Ok(Click {
+                user_id: FromSlice::from_slice(results[0])?,
+                url: FromSlice::from_slice(results[1])?,
+                name: FromSlice::from_slice(results[2])?,
+            })
+        }
+    }
+
+    #[test]
+    fn parse_records() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let parser = RecordParser::<Click<'_>, _>::new(index_builder).unwrap();
+
+        let record = r#"{ "user": { "id": 42, "name": "A" }, "url": "/" }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            Click {
+                user_id: 42,
+                url: Some(r#""/""#),
+                name: "A".into(),
+            }
+        );
+
+        let record = r#"{ "user": { "id": 42, "name": "a" } }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap().url,
+            None
+        );
+
+        assert!(parser
+            .parse(
+                r#"{ "user": { "id": -1, "name": "a" } }"#,
+                QueryParserMode::Basic
+            )
+            .is_err());
+        assert!(parser
+            .parse(r#"{ "user": { "id": 1 } }"#, QueryParserMode::Basic)
+            .is_err());
+    }
+
+    #[test]
+    fn non_json_numbers() {
+        assert_eq!(u64::from_slice(Some("42")).unwrap(), 42);
+        assert!(u64::from_slice(Some("+42")).is_err());
+        assert!(i32::from_slice(Some("-042")).is_err());
+        assert!(i32::from_slice(Some("1.0")).is_err());
+        assert_eq!(f64::from_slice(Some("-1e3")).unwrap(), -1000.0);
+        assert!(f64::from_slice(Some("-inf")).is_err());
+        assert!(f64::from_slice(Some("+1")).is_err());
+    }
+
+    #[test]
+    fn collect_columns() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let mut collector = Collector::<(u64, String, bool), _>::new(
+            index_builder,
+            &["$.id", "$.user.name", "$.ok"],
+        )
+        .unwrap();
+
+        collector
+            .parse_append(r#"{ "id": 1, "user": { "name": "a" }, "ok": true }"#)
+            .unwrap();
+        collector
+            .parse_append(r#"{ "id": 2, "ok": false }"#)
+            .unwrap();
+        assert!(collector
+            .parse_append(r#"{ "id": 3, "user": { "name": "c" }, "ok": 0 }"#)
+            .is_err());
+        assert_eq!(collector.len(), 2);
+        assert_eq!(collector.columns().1.len(), 2);
+
+        let (ids, names, oks) = collector.take_columns();
+        assert_eq!(ids, [Some(1), Some(2)]);
+        assert_eq!(names, [Some("a".to_owned()), None]);
+        assert_eq!(oks, [Some(true), Some(false)]);
+        assert!(collector.is_empty());
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        assert!(Collector::<(u64,), _>::new(index_builder, &["$.a", "$.b"]).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Fan-out of records into sinks by an extracted value
+
+use crate::errors::{Error, Result};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+use fnv::{FnvHashMap, FnvHasher};
+use std::hash::Hasher;
+use std::io::Write;
+
+/// A router which writes each record into one of several sinks, chosen by the value
+/// of a key path.
+///
+/// Keys are compared by their raw slices (e.g. `"error"` including quotes), as in
+/// `aggregate::Aggregator`. A sink is chosen in this order:
+///
+/// 1. the sink registered for the key by `route`,
+/// 2. one of the hash buckets given to `by_hash`, by the FNV hash of the key,
+/// 3. the fallback sink, which also receives the records without the key path.
+///
+/// Otherwise the record is dropped. The bytes of each record are written as they are,
+/// followed by a newline.
+#[derive(Debug)]
+pub struct Router<'q, B: Backend, W> {
+    parser: QueryParser<'q, B>,
+    sinks: Vec<W>,
+    routes: FnvHashMap<String, usize>,
+    /// the number of the leading sinks used as the hash buckets
+    buckets: usize,
+    fallback: Option<usize>,
+}
+
+impl<'q, B: Backend, W: Write> Router<'q, B, W> {
+    /// Create a new router by the exact values of `path`, which are registered by
+    /// `route`.
+    pub fn by_value(index_builder: IndexBuilder<B>, path: &'q str) -> Result<Self> {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path(path)?;
+        Ok(Self {
+            parser: QueryParser::new(index_builder, query_tree),
+            sinks: vec![],
+            routes: FnvHashMap::default(),
+            buckets: 0,
+            fallback: None,
+        })
+    }
+
+    /// Create a new router which distributes the records into `buckets` by the hash
+    /// of the values of `path`, so that the records sharing a key go to the same sink.
+    pub fn by_hash(index_builder: IndexBuilder<B>, path: &'q str, buckets: Vec<W>) -> Result<Self> {
+        assert!(!buckets.is_empty(), "buckets must not be empty");
+        let mut router = Self::by_value(index_builder, path)?;
+        router.buckets = buckets.len();
+        router.sinks = buckets;
+        Ok(router)
+    }
+
+    /// Route the records whose raw key is `key` into `sink`, and return the index of
+    /// the sink.
+    pub fn route(&mut self, key: &str, sink: W) -> usize {
+        self.sinks.push(sink);
+        self.routes.insert(key.to_owned(), self.sinks.len() - 1);
+        self.sinks.len() - 1
+    }
+
+    /// Route the records not routed otherwise into `sink`, and return the index of the
+    /// sink. The previous fallback sink, if any, is no longer used.
+    pub fn fallback(&mut self, sink: W) -> usize {
+        self.sinks.push(sink);
+        self.fallback = Some(self.sinks.len() - 1);
+        self.sinks.len() - 1
+    }
+
+    /// Write a record into its sink, and return the index of the sink, or `None` if
+    /// the record is dropped.
+    pub fn write(&mut self, record: &str) -> Result<Option<usize>> {
+        let results = self.parser.parse(record, QueryParserMode::Basic)?;
+        let sink = match results[0] {
+            Some(key) => match self.routes.get(key) {
+                Some(&sink) => Some(sink),
+                None if self.buckets > 0 => {
+                    let mut hasher = FnvHasher::default();
+                    hasher.write(key.as_bytes());
+                    Some((hasher.finish() % self.buckets as u64) as usize)
+                }
+                None => self.fallback,
+            },
+            None => self.fallback,
+        };
+        if let Some(sink) = sink {
+            let out = &mut self.sinks[sink];
+            out.write_all(record.as_bytes())
+                .and_then(|_| out.write_all(b"\n"))
+                .map_err(|e| Error::with_chain(e, "failed to write a record"))?;
+        }
+        Ok(sink)
+    }
+
+    /// Write all records in `records` into their sinks.
+    pub fn write_all<'s, I>(&mut self, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'s str>,
+    {
+        for record in records {
+            self.write(record)?;
+        }
+        Ok(())
+    }
+
+    /// Flush all sinks.
+    pub fn flush(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()
+                .map_err(|e| Error::with_chain(e, "failed to flush a sink"))?;
+        }
+        Ok(())
+    }
+
+    /// Return the sinks, in the order of their indices.
+    pub fn sinks(&self) -> &[W] {
+        &self.sinks
+    }
+
+    /// Consume the router into its sinks, in the order of their indices.
+    pub fn into_sinks(self) -> Vec<W> {
+        self.sinks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::stream::StreamParser;
+
+    const INPUT: &str = "{\"level\": \"error\", \"user\": \"a\"}\n\
+                         {\"level\": \"info\", \"user\": \"b\"}\n\
+                         {\"user\": \"c\"}\n\
+                         {\"level\": \"warn\",  \"user\": \"a\"}\n";
+
+    #[test]
+    fn route_by_value() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let mut router = Router::by_value(index_builder, "$.level").unwrap();
+        assert_eq!(router.route("\"error\"", vec![]), 0);
+        assert_eq!(router.route("\"warn\"", vec![]), 1);
+        assert_eq!(router.write(r#"{"level": "info"}"#).unwrap(), None);
+        assert_eq!(router.fallback(vec![]), 2);
+
+        router.write_all(StreamParser::new(INPUT)).unwrap();
+        let sinks: Vec<String> = router
+            .into_sinks()
+            .into_iter()
+            .map(|sink| String::from_utf8(sink).unwrap())
+            .collect();
+        let lines: Vec<&str> = INPUT.lines().collect();
+        assert_eq!(sinks[0], format!("{}\n", lines[0]));
+        assert_eq!(sinks[1], format!("{}\n", lines[3]));
+        assert_eq!(sinks[2], format!("{}\n{}\n", lines[1], lines[2]));
+    }
+
+    #[test]
+    fn route_by_hash() {
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let mut router = Router::by_hash(index_builder, "$.user", vec![vec![]; 4]).unwrap();
+        router.route("\"b\"", vec![]);
+        let sinks: Vec<_> = StreamParser::new(INPUT)
+            .map(|record| router.write(record).unwrap())
+            .collect();
+        assert_eq!(sinks[0], sinks[3]);
+        assert!(sinks[0].unwrap() < 4);
+        assert_eq!(sinks[1], Some(4));
+        assert!(sinks[2].unwrap() < 4);
+        let total: usize = router.sinks().iter().map(|sink| sink.len()).sum();
+        assert_eq!(total, INPUT.len());
+    }
+}
@@ -0,0 +1,219 @@
+//! Direct byte scanning of small records and values
+//!
+//! Building the structural bitmaps costs more than scanning the bytes once for the
+//! records of a few hundred bytes, so `Parser` and `QueryParser` can split such records
+//! at their commas and colons directly while tracking the strings and the nesting
+//! (see their `small_record_len`).
+//! The control characters in the extracted values are also found by scanning them
+//! when the index is not available.
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::value::EscapedStr;
+use std::borrow::Cow;
+use std::cmp;
+use std::fmt::Write;
+
+/// Split the array or object `s[begin..end]` at the commas of its top level, and push
+/// the ranges of its elements or fields, without the surrounding whitespace.
+///
+/// Return the maximal nesting depth of the brackets and braces within its members, so
+/// that too deep values are rejected before scanning them again.
+pub(crate) fn members(
+    s: &str,
+    begin: usize,
+    end: usize,
+    out: &mut Vec<(usize, usize)>,
+) -> Result<usize> {
+    let bytes = s.as_bytes();
+    let close = match bytes[begin] {
+        b'[' => b']',
+        _ => b'}',
+    };
+    if end < begin + 2 || bytes[end - 1] != close {
+        return Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("unterminated value at {}", begin));
+    }
+
+    out.clear();
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    let mut max_depth = 0;
+    let mut start = begin + 1;
+    for (i, &c) in bytes.iter().enumerate().take(end - 1).skip(begin + 1) {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                max_depth = cmp::max(max_depth, depth);
+            }
+            b'}' | b']' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| format!("unexpected {:?} at {}", c as char, i))?;
+            }
+            b',' if depth == 0 => {
+                out.push(trim(bytes, start, i));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_string || depth != 0 {
+        return Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("unterminated value at {}", begin));
+    }
+
+    let last = trim(bytes, start, end - 1);
+    if out.is_empty() && last.0 == last.1 {
+        return Ok(max_depth); // an empty array or object
+    }
+    out.push(last);
+    Ok(max_depth)
+}
+
+/// Split a field `s[begin..end]` into its (escaped) name and the beginning of its value.
+pub(crate) fn split_field(s: &str, begin: usize, end: usize) -> Result<(EscapedStr<'_>, usize)> {
+    let bytes = s.as_bytes();
+    let missing = || {
+        Error::from(ErrorKind::InvalidRecord)
+            .chain_err(|| format!("missing field name at {}", begin))
+    };
+    if bytes[begin] != b'"' {
+        return Err(missing());
+    }
+    let mut escaped = false;
+    let mut fei = None;
+    for (i, &c) in bytes.iter().enumerate().take(end).skip(begin + 1) {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => {
+                fei = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let fei = fei.ok_or_else(missing)?;
+    let (ci, _) = trim(bytes, fei + 1, end);
+    if ci >= end || bytes[ci] != b':' {
+        return Err(missing());
+    }
+    let (vsi, _) = trim(bytes, ci + 1, end);
+    Ok((EscapedStr::from(&s[begin + 1..fei]), vsi))
+}
+
+/// Call `f` with the positions of the control characters within the strings of `s`.
+fn controls_in_strings<F: FnMut(usize) -> bool>(s: &str, mut f: F) {
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, &c) in s.as_bytes().iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            c if in_string && c < 0x20 && !f(i) => return,
+            _ => {}
+        }
+    }
+}
+
+/// Return whether any control character is within the strings of the value `s`.
+pub(crate) fn contains_control(s: &str) -> bool {
+    let mut found = false;
+    controls_in_strings(s, |_| {
+        found = true;
+        false
+    });
+    found
+}
+
+/// Escape the control characters within the strings of the value `s`.
+pub(crate) fn escape_control(s: &str) -> Cow<'_, str> {
+    let mut out = String::new();
+    let mut pos = 0;
+    controls_in_strings(s, |i| {
+        out.push_str(&s[pos..i]);
+        match s.as_bytes()[i] {
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x08 => out.push_str("\\b"),
+            0x0c => out.push_str("\\f"),
+            c => {
+                let _ = write!(out, "\\u{:04x}", c);
+            }
+        }
+        pos = i + 1;
+        true
+    });
+    if pos == 0 {
+        return Cow::Borrowed(s);
+    }
+    out.push_str(&s[pos..]);
+    Cow::Owned(out)
+}
+
+fn trim(s: &[u8], mut begin: usize, mut end: usize) -> (usize, usize) {
+    while begin < end && is_whitespace(s[begin]) {
+        begin += 1;
+    }
+    while end > begin && is_whitespace(s[end - 1]) {
+        end -= 1;
+    }
+    (begin, end)
+}
+
+#[inline]
+fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_members() {
+        let s = r#"{ "a" : [1, "x,]"], "b\"": {} , "c":"\\" }"#;
+        let mut out = vec![];
+        members(s, 0, s.len(), &mut out).unwrap();
+        let fields: Vec<_> = out.iter().map(|&(b, e)| &s[b..e]).collect();
+        assert_eq!(
+            fields,
+            [r#""a" : [1, "x,]"]"#, r#""b\"": {}"#, r#""c":"\\""#]
+        );
+
+        let (name, vsi) = split_field(s, out[0].0, out[0].1).unwrap();
+        assert_eq!(name.as_raw_str(), "a");
+        assert_eq!(&s[vsi..out[0].1], r#"[1, "x,]"]"#);
+        let (name, _) = split_field(s, out[1].0, out[1].1).unwrap();
+        assert_eq!(name.as_raw_str(), r#"b\""#);
+
+        assert_eq!(members("[[1], [[]]]", 0, 11, &mut out).unwrap(), 2);
+        assert_eq!(members("[ ]", 0, 3, &mut out).unwrap(), 0);
+        assert!(out.is_empty());
+        members("[1,]", 0, 4, &mut out).unwrap();
+        assert_eq!(out, [(1, 2), (3, 3)]);
+        assert!(members("[1, [2]", 0, 7, &mut out).is_err());
+        assert!(members(r#"["]"#, 0, 3, &mut out).is_err());
+        assert!(split_field(r#"{"a" 1}"#, 1, 6).is_err());
+    }
+
+    #[test]
+    fn control_characters() {
+        let s = "{\"a\tb\": [\n\"x\\\"\u{1}\"]}";
+        assert!(contains_control(s));
+        assert_eq!(escape_control(s), "{\"a\\tb\": [\n\"x\\\"\\u0001\"]}");
+        assert!(!contains_control("[\n\"x\"\t]"));
+        assert!(matches!(escape_control("[\n1]"), Cow::Borrowed(_)));
+    }
+}
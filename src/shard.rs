@@ -0,0 +1,159 @@
+//! Per-core sharding of newline-delimited records
+//!
+//! `shard` splits a buffer of newline-delimited records into a contiguous range per
+//! thread, cut at the newlines nearest to equal sizes, and parses each range on its
+//! own thread with a clone of a frozen parser. Unlike `pipeline::Pipeline`, the whole
+//! input must be in memory, but the records are neither copied nor sent through
+//! channels, and the results are returned in the order of the records.
+
+use crate::errors::Result;
+use crate::index_builder::backend::{Backend, DefaultBackend};
+use crate::query_parser::{FrozenQueryParser, QueryParserMode, Workspace};
+use crate::stream::StreamParser;
+use std::cmp;
+use std::ops::Range;
+use std::thread;
+
+/// Split `input` into at most `n` ranges of similar lengths, each of which ends at a
+/// newline (inclusive) or at the end of `input`.
+///
+/// The newlines are located from the bitmaps created by `backend`, starting at the
+/// ideal boundaries, so only the bytes between them and the next newlines are read.
+/// No range is empty, and none is returned for an empty input.
+pub fn ranges<B: Backend>(backend: &B, input: &str, n: usize) -> Vec<Range<usize>> {
+    assert!(n > 0, "the number of shards must be positive");
+    let s = input.as_bytes();
+    let mut ranges = vec![];
+    let mut begin = 0;
+    for i in 1..=n {
+        if begin >= s.len() {
+            break;
+        }
+        let end = if i == n {
+            s.len()
+        } else {
+            next_newline(backend, s, cmp::max(begin, s.len() * i / n)).map_or(s.len(), |p| p + 1)
+        };
+        if end > begin {
+            ranges.push(begin..end);
+            begin = end;
+        }
+    }
+    ranges
+}
+
+/// Return the position of the first newline at or after `pos`.
+fn next_newline<B: Backend>(backend: &B, s: &[u8], pos: usize) -> Option<usize> {
+    let mut block = pos - pos % 64;
+    // ignore the newlines before `pos` in the first block.
+    let mut mask = !0u64 << (pos % 64);
+    while block < s.len() {
+        let newlines = backend.create_newline_bitmap(s, block) & mask;
+        if newlines != 0 {
+            return Some(block + newlines.trailing_zeros() as usize);
+        }
+        block += 64;
+        mask = !0;
+    }
+    None
+}
+
+/// Parse the newline-delimited records in `input` on `shards` threads, and return the
+/// values of each record converted by `f`, or the error of parsing it.
+///
+/// Each thread runs a clone of `parser` with its own workspace over one of the
+/// `ranges` of `input`. Blank lines are skipped as in `StreamParser`, and the results
+/// are in the order of the records.
+pub fn shard<B, T, F>(
+    parser: &FrozenQueryParser<'_, B>,
+    input: &str,
+    mode: QueryParserMode,
+    shards: usize,
+    f: F,
+) -> Vec<Result<T>>
+where
+    B: Backend + Send + Sync,
+    T: Send,
+    F: Fn(&str, &[Option<&str>]) -> T + Sync,
+{
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = ranges(&DefaultBackend::default(), input, shards)
+            .into_iter()
+            .map(|range| {
+                let parser = parser.clone();
+                let records = &input[range];
+                scope.spawn(move || {
+                    let mut workspace = Workspace::new();
+                    StreamParser::new(records)
+                        .map(|record| {
+                            let mut results = vec![];
+                            parser
+                                .parse_with_workspace(record, mode, &mut workspace, &mut results)
+                                .map(|_| f(record, &results))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::query::QueryTree;
+    use crate::query_parser::QueryParser;
+
+    #[test]
+    fn shard_ranges() {
+        let backend = FallbackBackend::default();
+        let input: String = (0..100).map(|n| format!("{{\"n\": {}}}\n", n)).collect();
+        for n in 1..8 {
+            let ranges = ranges(&backend, &input, n);
+            assert_eq!(ranges.len(), n);
+            assert_eq!(ranges[0].start, 0);
+            assert_eq!(ranges[n - 1].end, input.len());
+            for w in ranges.windows(2) {
+                assert_eq!(w[0].end, w[1].start);
+                assert_eq!(input.as_bytes()[w[0].end - 1], b'\n');
+            }
+        }
+
+        // a long record is not split, and the empty ranges are dropped.
+        let input = format!("{}\n[1]", "1".repeat(200));
+        assert_eq!(ranges(&backend, &input, 4), [0..201, 201..204]);
+        let single = ranges(&backend, "[1]", 4);
+        assert_eq!((single.len(), single[0].clone()), (1, 0..3));
+        assert!(ranges(&backend, "", 4).is_empty());
+    }
+
+    #[test]
+    fn sharded_parsing() {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.n").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree).freeze();
+
+        let mut input: String = (0..1000).map(|n| format!("{{\"n\": {}}}\n\n", n)).collect();
+        input.push_str("[1]");
+        let results = shard(&parser, &input, QueryParserMode::Basic, 4, |_, results| {
+            results[0].unwrap().parse::<u64>().unwrap()
+        });
+        assert_eq!(results.len(), 1001);
+        for (n, result) in results[..1000].iter().enumerate() {
+            assert_eq!(*result.as_ref().unwrap(), n as u64);
+        }
+        assert!(results[1000].is_err());
+    }
+}
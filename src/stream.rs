@@ -0,0 +1,553 @@
+//! Adapters over streams of records
+
+use crate::bit;
+use crate::errors::Result;
+use crate::index_builder::backend::{Backend, DefaultBackend};
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+use crate::value::ValueHasher;
+use fnv::FnvHashMap;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How `StreamParser` finds the boundaries of records
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// one record per line
+    #[default]
+    Lines,
+    /// a record ends where the brackets and braces opened at the top level are
+    /// balanced, so that pretty-printed records spanning multiple lines are
+    /// reassembled. Scalars at the top level are not supported.
+    Balanced,
+}
+
+/// A parser which splits newline-delimited JSON into records.
+///
+/// Newlines are located from the bitmaps created by the backend, 64 bytes at a time.
+/// Blank lines are skipped, and the surrounding whitespace of each record is kept
+/// as is (parsers trim it).
+#[derive(Debug, Clone)]
+pub struct StreamParser<'s, B: Backend = DefaultBackend> {
+    input: &'s str,
+    backend: B,
+    framing: Framing,
+    pos: usize,
+    block: usize,
+    newlines: u64,
+    /// the unconsumed brackets and braces outside strings in the current block
+    brackets: u64,
+    /// the opening ones of `brackets`
+    opens: u64,
+    /// whether the current block begins within a string
+    in_string: bool,
+    depth: usize,
+}
+
+impl<'s> StreamParser<'s> {
+    #[allow(missing_docs)]
+    pub fn new(input: &'s str) -> Self {
+        Self::with_backend(input, DefaultBackend::default())
+    }
+}
+
+impl<'s, B: Backend> StreamParser<'s, B> {
+    #[allow(missing_docs)]
+    pub fn with_backend(input: &'s str, backend: B) -> Self {
+        let newlines = if input.is_empty() {
+            0
+        } else {
+            backend.create_newline_bitmap(input.as_bytes(), 0)
+        };
+        Self {
+            input,
+            backend,
+            framing: Framing::default(),
+            pos: 0,
+            block: 0,
+            newlines,
+            brackets: 0,
+            opens: 0,
+            in_string: false,
+            depth: 0,
+        }
+    }
+
+    /// Set how to find the boundaries of records, before iterating. See `Framing`.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        if framing == Framing::Balanced && !self.input.is_empty() {
+            self.load_brackets();
+        }
+        self
+    }
+
+    /// Locate the brackets and braces outside strings in the current block.
+    fn load_brackets(&mut self) {
+        let s = self.input.as_bytes();
+        let b = if s.len() - self.block >= 64 {
+            self.backend.create_full_bitmap(s, self.block)
+        } else {
+            self.backend.create_partial_bitmap(s, self.block)
+        };
+
+        let mut m_quote = b.quote;
+        let mut m_string = 0u64;
+        let mut in_string = self.in_string;
+        while m_quote != 0 {
+            let pos = self.block + m_quote.trailing_zeros() as usize;
+            // the quotes preceded by an odd number of backslashes are escaped.
+            let backslashes = s[..pos].iter().rev().take_while(|&&c| c == b'\\').count();
+            if backslashes % 2 == 0 {
+                m_string ^= bit::S(m_quote);
+                in_string = !in_string;
+            }
+            m_quote = bit::R(m_quote);
+        }
+        if in_string {
+            m_string ^= !0u64;
+        }
+        self.in_string = in_string;
+
+        self.opens = (b.left_brace | b.left_bracket) & !m_string;
+        self.brackets = self.opens | (b.right_brace | b.right_bracket) & !m_string;
+    }
+
+    fn next_balanced(&mut self) -> Option<&'s str> {
+        let s = self.input.as_bytes();
+        while self.pos < s.len() && matches!(s[self.pos], b' ' | b'\t' | b'\r' | b'\n') {
+            self.pos += 1;
+        }
+        if self.pos >= s.len() {
+            return None;
+        }
+        let start = self.pos;
+        loop {
+            if self.brackets == 0 {
+                self.block += 64;
+                if self.block >= s.len() {
+                    // an incomplete record, which the parsers reject
+                    self.pos = s.len();
+                    return Some(&self.input[start..]);
+                }
+                self.load_brackets();
+                continue;
+            }
+            let bit = bit::E(self.brackets);
+            let i = self.block + bit.trailing_zeros() as usize;
+            self.brackets ^= bit;
+            if self.opens & bit != 0 {
+                self.depth += 1;
+            } else {
+                self.depth = self.depth.saturating_sub(1);
+                if self.depth == 0 {
+                    self.pos = i + 1;
+                    return Some(&self.input[start..=i]);
+                }
+            }
+        }
+    }
+
+    fn next_newline(&mut self) -> Option<usize> {
+        loop {
+            if self.newlines != 0 {
+                let i = self.block + self.newlines.trailing_zeros() as usize;
+                self.newlines &= self.newlines - 1;
+                return Some(i);
+            }
+            self.block += 64;
+            if self.block >= self.input.len() {
+                return None;
+            }
+            self.newlines = self
+                .backend
+                .create_newline_bitmap(self.input.as_bytes(), self.block);
+        }
+    }
+}
+
+impl<'s, B: Backend> Iterator for StreamParser<'s, B> {
+    type Item = &'s str;
+
+    fn next(&mut self) -> Option<&'s str> {
+        if self.framing == Framing::Balanced {
+            return self.next_balanced();
+        }
+        while self.pos < self.input.len() {
+            let end = self.next_newline().unwrap_or(self.input.len());
+            let record = &self.input[self.pos..end];
+            self.pos = end + 1;
+            if !record.trim().is_empty() {
+                return Some(record);
+            }
+        }
+        None
+    }
+}
+
+/// Composable adapters over streams of records
+pub trait RecordStreamExt<'s>: Iterator<Item = &'s str> + Sized {
+    /// Keep one of every `n` records, starting from the first one.
+    fn sample(self, n: usize) -> Sample<Self> {
+        assert!(n > 0, "sampling interval must be positive");
+        Sample {
+            records: self,
+            n,
+            count: 0,
+        }
+    }
+
+    /// Drop the records exceeding `n_per_sec` records per second.
+    fn rate_limit(self, n_per_sec: usize) -> RateLimit<Self> {
+        RateLimit {
+            records: self,
+            n_per_sec,
+            window_start: None,
+            count: 0,
+            clock: Instant::now,
+        }
+    }
+
+    /// Yield records while the value at `path` satisfies `pred`.
+    ///
+    /// `pred` receives the raw slice of the value, or `None` if the field is missing.
+    /// The iteration also stops at the first record which cannot be parsed.
+    fn take_while_path<'q, F>(self, path: &'q str, pred: F) -> Result<TakeWhilePath<'q, Self, F>>
+    where
+        F: FnMut(Option<&str>) -> bool,
+    {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path(path)?;
+        let index_builder = IndexBuilder::with_best_backend(query_tree.max_level());
+        Ok(TakeWhilePath {
+            records: self,
+            parser: QueryParser::new(index_builder, query_tree),
+            pred,
+            done: false,
+        })
+    }
+}
+
+impl<'s, I: Iterator<Item = &'s str>> RecordStreamExt<'s> for I {}
+
+/// An adapter created by `RecordStreamExt::sample`
+#[derive(Debug)]
+pub struct Sample<I> {
+    records: I,
+    n: usize,
+    count: usize,
+}
+
+impl<'s, I: Iterator<Item = &'s str>> Iterator for Sample<I> {
+    type Item = &'s str;
+
+    fn next(&mut self) -> Option<&'s str> {
+        loop {
+            let record = self.records.next()?;
+            self.count += 1;
+            if (self.count - 1).is_multiple_of(self.n) {
+                return Some(record);
+            }
+        }
+    }
+}
+
+/// An adapter created by `RecordStreamExt::rate_limit`
+#[derive(Debug)]
+pub struct RateLimit<I, C = fn() -> Instant> {
+    records: I,
+    n_per_sec: usize,
+    window_start: Option<Instant>,
+    count: usize,
+    clock: C,
+}
+
+impl<I, C> RateLimit<I, C> {
+    /// Replace the source of the current time, `Instant::now` by default.
+    pub fn clock<C2>(self, clock: C2) -> RateLimit<I, C2>
+    where
+        C2: FnMut() -> Instant,
+    {
+        RateLimit {
+            records: self.records,
+            n_per_sec: self.n_per_sec,
+            window_start: self.window_start,
+            count: self.count,
+            clock,
+        }
+    }
+}
+
+impl<'s, I, C> Iterator for RateLimit<I, C>
+where
+    I: Iterator<Item = &'s str>,
+    C: FnMut() -> Instant,
+{
+    type Item = &'s str;
+
+    fn next(&mut self) -> Option<&'s str> {
+        loop {
+            let record = self.records.next()?;
+            let now = (self.clock)();
+            match self.window_start {
+                Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+                _ => {
+                    self.window_start = Some(now);
+                    self.count = 0;
+                }
+            }
+            if self.count < self.n_per_sec {
+                self.count += 1;
+                return Some(record);
+            }
+        }
+    }
+}
+
+/// An adapter created by `RecordStreamExt::take_while_path`
+#[derive(Debug)]
+pub struct TakeWhilePath<'q, I, F> {
+    records: I,
+    parser: QueryParser<'q, DefaultBackend>,
+    pred: F,
+    done: bool,
+}
+
+impl<'q, 's, I, F> Iterator for TakeWhilePath<'q, I, F>
+where
+    I: Iterator<Item = &'s str>,
+    F: FnMut(Option<&str>) -> bool,
+{
+    type Item = &'s str;
+
+    fn next(&mut self) -> Option<&'s str> {
+        if self.done {
+            return None;
+        }
+        let record = self.records.next()?;
+        match self.parser.parse(record, QueryParserMode::Basic) {
+            Ok(ref results) if (self.pred)(results[0]) => Some(record),
+            _ => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+/// An iterator adapter which drops records whose queried values are identical to
+/// one of the recently seen records.
+///
+/// Records are compared by the hash of the slices extracted by `parser`, and the
+/// last `window` distinct hashes are remembered in LRU order.
+#[derive(Debug)]
+pub struct DedupStream<'p, 'q, B: Backend, I> {
+    parser: &'p QueryParser<'q, B>,
+    mode: QueryParserMode,
+    records: I,
+    window: usize,
+    seq: u64,
+    seen: FnvHashMap<u64, u64>,
+    order: VecDeque<(u64, u64)>,
+}
+
+impl<'p, 'q, B: Backend, I> DedupStream<'p, 'q, B, I> {
+    #[allow(missing_docs)]
+    pub fn new(parser: &'p QueryParser<'q, B>, records: I, window: usize) -> Self {
+        Self {
+            parser,
+            mode: QueryParserMode::Basic,
+            records,
+            window,
+            seq: 0,
+            seen: FnvHashMap::default(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Set the parsing mode used to extract the queried values.
+    pub fn mode(mut self, mode: QueryParserMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Record `hash` as the most recently seen one, and return whether it was
+    /// already in the window.
+    fn check(&mut self, hash: u64) -> bool {
+        self.seq += 1;
+        let duplicated = self.seen.insert(hash, self.seq).is_some();
+        self.order.push_back((hash, self.seq));
+
+        while self.seen.len() > self.window {
+            let (h, seq) = self.order.pop_front().unwrap();
+            if self.seen.get(&h) == Some(&seq) {
+                self.seen.remove(&h);
+            }
+        }
+        if self.order.len() > 2 * self.window.max(1) {
+            let seen = &self.seen;
+            self.order.retain(|(h, seq)| seen.get(h) == Some(seq));
+        }
+
+        duplicated
+    }
+}
+
+impl<'p, 'q, 's, B: Backend, I> Iterator for DedupStream<'p, 'q, B, I>
+where
+    I: Iterator<Item = &'s str>,
+{
+    type Item = Result<&'s str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = self.records.next()?;
+            let results = match self.parser.parse(record, self.mode) {
+                Ok(results) => results,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let mut hasher = ValueHasher::default();
+            for result in results {
+                hasher.write_slice(result);
+            }
+            if !self.check(hasher.finish()) {
+                return Some(Ok(record));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::query::QueryTree;
+
+    #[test]
+    fn stream_parser() {
+        let input = "{\"a\": 1}\n\n  \r\n{\"a\": 2}\r\n{\"a\": 3}";
+        let records: Vec<_> = StreamParser::new(input).collect();
+        assert_eq!(records, vec!["{\"a\": 1}", "{\"a\": 2}\r", "{\"a\": 3}"]);
+
+        // records across the boundaries of 64-byte blocks
+        let records: Vec<_> = (0..20)
+            .map(|i| format!(r#"{{"a": "{}"}}"#, "x".repeat(i * 7)))
+            .collect();
+        let input = records.join("\n") + "\n";
+        assert_eq!(StreamParser::new(&input).collect::<Vec<_>>(), records);
+        assert_eq!(StreamParser::new("").count(), 0);
+    }
+
+    #[test]
+    fn balanced_framing() {
+        let records = [
+            "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": \"}{\\\"\\\\\"\n}",
+            "[{}, [\"]\"]]",
+            "{}",
+            &format!("{{\n  \"c\": \"{}\\\\\", \"d\": {{}}\n}}", "x".repeat(100)),
+        ];
+        let input = format!(
+            "{}{}\n\n  {}\r\n{}\n",
+            records[0], records[1], records[2], records[3]
+        );
+        let framed: Vec<_> = StreamParser::new(&input)
+            .framing(Framing::Balanced)
+            .collect();
+        assert_eq!(framed, records);
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let parser = crate::parser::Parser::new(index_builder);
+        for record in &framed {
+            parser.parse(record).unwrap();
+        }
+
+        // an incomplete record at the end
+        let framed: Vec<_> = StreamParser::new("{} \n{\"a\": [1,\n")
+            .framing(Framing::Balanced)
+            .collect();
+        assert_eq!(framed, ["{}", "{\"a\": [1,\n"]);
+        assert_eq!(
+            StreamParser::new(" \n").framing(Framing::Balanced).count(),
+            0
+        );
+        assert_eq!(StreamParser::new("").framing(Framing::Balanced).count(), 0);
+    }
+
+    #[test]
+    fn stream_adapters() {
+        let input = (0..10)
+            .map(|i| format!(r#"{{ "n": {} }}"#, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let sampled: Vec<_> = StreamParser::new(&input).sample(4).collect();
+        assert_eq!(
+            sampled,
+            vec![r#"{ "n": 0 }"#, r#"{ "n": 4 }"#, r#"{ "n": 8 }"#]
+        );
+
+        // the clock advances by 250ms for each record.
+        let start = Instant::now();
+        let mut elapsed = Duration::from_millis(0);
+        let limited: Vec<_> = StreamParser::new(&input)
+            .rate_limit(3)
+            .clock(|| {
+                let now = start + elapsed;
+                elapsed += Duration::from_millis(250);
+                now
+            })
+            .collect();
+        assert_eq!(
+            limited,
+            vec![
+                r#"{ "n": 0 }"#,
+                r#"{ "n": 1 }"#,
+                r#"{ "n": 2 }"#,
+                r#"{ "n": 4 }"#,
+                r#"{ "n": 5 }"#,
+                r#"{ "n": 6 }"#,
+                r#"{ "n": 8 }"#,
+                r#"{ "n": 9 }"#,
+            ]
+        );
+
+        let taken: Vec<_> = StreamParser::new(&input)
+            .take_while_path("$.n", |n| n.is_some_and(|n| n != "2"))
+            .unwrap()
+            .collect();
+        assert_eq!(taken, vec![r#"{ "n": 0 }"#, r#"{ "n": 1 }"#]);
+    }
+
+    #[test]
+    fn dedup_stream() {
+        let mut query_tree = QueryTree::default();
+        query_tree.add_path("$.id").unwrap();
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), query_tree.max_level());
+        let parser = QueryParser::new(index_builder, query_tree);
+
+        let records = vec![
+            r#"{ "id": 1, "n": 0 }"#,
+            r#"{ "id": 2, "n": 1 }"#,
+            r#"{ "id": 1, "n": 2 }"#,
+            r#"{ "id": 3, "n": 3 }"#,
+            r#"{ "id": 2, "n": 4 }"#,
+            r#"{ "n": 5 }"#,
+            r#"{ "n": 6 }"#,
+        ];
+        let result: Vec<_> = DedupStream::new(&parser, records.into_iter(), 2)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                r#"{ "id": 1, "n": 0 }"#,
+                r#"{ "id": 2, "n": 1 }"#,
+                r#"{ "id": 3, "n": 3 }"#,
+                r#"{ "id": 2, "n": 4 }"#,
+                r#"{ "n": 5 }"#,
+            ]
+        );
+    }
+}
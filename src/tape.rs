@@ -0,0 +1,619 @@
+//! Compact representation of parsed records
+//!
+//! A `CompactValue` stores the nodes of a record in a flat vector, in the order of the
+//! record (like the tape of simdjson). Arrays and objects record the position after
+//! their descendants, so that skipping a subtree does not visit its nodes, and no
+//! allocation is made per array or object.
+//!
+//! # Binary layout
+//!
+//! `CompactValue::to_bytes` exports the nodes so that they can be consumed without
+//! this crate (e.g. over FFI), along with the record. The layout is stable within a
+//! version:
+//!
+//! * an 8-byte header: the magic `b"MSTAPE"` and the version as a little-endian `u16`
+//!   (currently `1`),
+//! * followed by 16 bytes per node, in the order of `CompactValue::nodes`: two
+//!   little-endian `u64` words `w0` and `w1`, where the tag is the upper 8 bits of `w0`
+//!   and `a` is the lower 56 bits.
+//!
+//! | tag   | node               | `a`                   | `w1`                       |
+//! |-------|--------------------|-----------------------|----------------------------|
+//! | `'n'` | `null`             | 0                     | 0                          |
+//! | `'t'` | `true`             | 0                     | 0                          |
+//! | `'f'` | `false`            | 0                     | 0                          |
+//! | `'d'` | number             | 0                     | the bits of the `f64`      |
+//! | `'"'` | string             | offset of the content | end offset of the content  |
+//! | `':'` | field name         | offset of the content | end offset of the content  |
+//! | `'r'` | raw array / object | offset of the value   | end offset of the value    |
+//! | `'['` | array              | number of elements    | index of the node after it |
+//! | `'{'` | object             | number of fields      | index of the node after it |
+//!
+//! The offsets are in bytes of the record, and the contents of strings and field names
+//! are escaped and exclude the quotes.
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::{IndexBuffers, IndexBuilder, StructuralIndex, DEFAULT_MAX_DEPTH};
+use crate::parser::RawPolicy;
+use crate::value::{self, EscapedStr, Value, ValueType};
+use std::borrow::Cow;
+
+/// A node of `CompactValue`
+///
+/// The spans are the byte offsets in the record. Each field of an object is stored
+/// as a `Key` followed by the nodes of its value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Node {
+    #[allow(missing_docs)]
+    Null,
+    #[allow(missing_docs)]
+    Boolean(bool),
+    #[allow(missing_docs)]
+    Number(f64),
+    /// an escaped string, without the quotes
+    String(usize, usize),
+    /// an array or object nested deeper than the level of the index
+    Raw(usize, usize),
+    /// an array of `len` elements, whose descendants end before the node at `next`
+    Array { len: usize, next: usize },
+    /// an object of `len` fields, whose descendants end before the node at `next`
+    Object { len: usize, next: usize },
+    /// an escaped field name, without the quotes
+    Key(usize, usize),
+}
+
+/// A parsed record stored as a flat vector of nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactValue<'s> {
+    record: &'s str,
+    nodes: Vec<Node>,
+}
+
+impl<'s> CompactValue<'s> {
+    /// Return the record from which the value is parsed.
+    pub fn record(&self) -> &'s str {
+        self.record
+    }
+
+    /// Return all nodes, in the order of the record.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Export the nodes in the binary layout described in the module documentation.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.nodes.len() * NODE_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        for node in &self.nodes {
+            let (tag, a, w1) = match *node {
+                Node::Null => (b'n', 0, 0),
+                Node::Boolean(true) => (b't', 0, 0),
+                Node::Boolean(false) => (b'f', 0, 0),
+                Node::Number(n) => (b'd', 0, n.to_bits()),
+                Node::String(si, ei) => (b'"', si, ei as u64),
+                Node::Key(si, ei) => (b':', si, ei as u64),
+                Node::Raw(si, ei) => (b'r', si, ei as u64),
+                Node::Array { len, next } => (b'[', len, next as u64),
+                Node::Object { len, next } => (b'{', len, next as u64),
+            };
+            debug_assert!((a as u64) < 1 << 56);
+            let w0 = u64::from(tag) << 56 | a as u64;
+            bytes.extend_from_slice(&w0.to_le_bytes());
+            bytes.extend_from_slice(&w1.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Import the nodes exported by `to_bytes` from `record`.
+    ///
+    /// The spans and the structure of the nodes are validated, so that navigating the
+    /// result does not panic. Arrays and objects nested deeper than `DEFAULT_MAX_DEPTH`
+    /// are rejected, since the nodes are validated and converted recursively.
+    pub fn from_bytes(record: &'s str, bytes: &[u8]) -> Result<Self> {
+        let nodes = read_nodes(bytes)?.collect::<Result<Vec<_>>>()?;
+        let mut pos = 0;
+        validate(record, &nodes, &mut pos, 0)?;
+        if pos != nodes.len() {
+            return Err(Error::from(ErrorKind::InvalidRecord))
+                .chain_err(|| format!("invalid tape: trailing node #{}", pos));
+        }
+        Ok(Self { record, nodes })
+    }
+
+    /// Return the cursor at the root value.
+    pub fn root(&self) -> Cursor<'_, 's> {
+        Cursor { tape: self, pos: 0 }
+    }
+}
+
+const MAGIC: &[u8; 6] = b"MSTAPE";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 8;
+const NODE_LEN: usize = 16;
+
+/// Iterate over the nodes in the binary layout, after checking its header.
+pub fn read_nodes(bytes: &[u8]) -> Result<TapeNodes<'_>> {
+    if bytes.len() < HEADER_LEN || &bytes[..6] != MAGIC {
+        return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "invalid tape header");
+    }
+    let version = u16::from_le_bytes([bytes[6], bytes[7]]);
+    if version != VERSION {
+        return Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("unsupported tape version {}", version));
+    }
+    if !(bytes.len() - HEADER_LEN).is_multiple_of(NODE_LEN) {
+        return Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| "truncated tape");
+    }
+    Ok(TapeNodes {
+        chunks: bytes[HEADER_LEN..].chunks_exact(NODE_LEN),
+    })
+}
+
+/// An iterator over the nodes in the binary layout, created by `read_nodes`
+#[derive(Debug, Clone)]
+pub struct TapeNodes<'b> {
+    chunks: std::slice::ChunksExact<'b, u8>,
+}
+
+impl<'b> Iterator for TapeNodes<'b> {
+    type Item = Result<Node>;
+
+    fn next(&mut self) -> Option<Result<Node>> {
+        let chunk = self.chunks.next()?;
+        let word = |i: usize| {
+            let mut w = [0; 8];
+            w.copy_from_slice(&chunk[i * 8..i * 8 + 8]);
+            u64::from_le_bytes(w)
+        };
+        let (w0, w1) = (word(0), word(1));
+        let (a, b) = ((w0 & ((1 << 56) - 1)) as usize, w1 as usize);
+        Some(match (w0 >> 56) as u8 {
+            b'n' => Ok(Node::Null),
+            b't' => Ok(Node::Boolean(true)),
+            b'f' => Ok(Node::Boolean(false)),
+            b'd' => Ok(Node::Number(f64::from_bits(w1))),
+            b'"' => Ok(Node::String(a, b)),
+            b':' => Ok(Node::Key(a, b)),
+            b'r' => Ok(Node::Raw(a, b)),
+            b'[' => Ok(Node::Array { len: a, next: b }),
+            b'{' => Ok(Node::Object { len: a, next: b }),
+            tag => Err(Error::from(ErrorKind::InvalidRecord))
+                .chain_err(|| format!("invalid tape: unknown tag {:#x}", tag)),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+/// Check the value starting at `*pos` nested in `depth` arrays or objects, and move
+/// `*pos` after it.
+fn validate(record: &str, nodes: &[Node], pos: &mut usize, depth: usize) -> Result<()> {
+    let invalid = |pos: usize| {
+        Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("invalid tape: node #{}", pos))
+    };
+    let span = |si: usize, ei: usize| si <= ei && record.get(si..ei).is_some();
+
+    let node = match nodes.get(*pos) {
+        Some(&node) => node,
+        None => return invalid(*pos),
+    };
+    let start = *pos;
+    *pos += 1;
+    if let Node::Array { .. } | Node::Object { .. } = node {
+        if depth == DEFAULT_MAX_DEPTH {
+            return Err(Error::from(ErrorKind::InvalidRecord))
+                .chain_err(|| format!("invalid tape: node #{} nested too deeply", start));
+        }
+    }
+    match node {
+        Node::Null | Node::Boolean(..) | Node::Number(..) => {}
+        Node::String(si, ei) | Node::Raw(si, ei) if span(si, ei) => {}
+        Node::Array { len, next } => {
+            for _ in 0..len {
+                validate(record, nodes, pos, depth + 1)?;
+            }
+            if *pos != next {
+                return invalid(start);
+            }
+        }
+        Node::Object { len, next } => {
+            for _ in 0..len {
+                match nodes.get(*pos) {
+                    Some(&Node::Key(si, ei)) if span(si, ei) => *pos += 1,
+                    _ => return invalid(*pos),
+                }
+                validate(record, nodes, pos, depth + 1)?;
+            }
+            if *pos != next {
+                return invalid(start);
+            }
+        }
+        _ => return invalid(start),
+    }
+    Ok(())
+}
+
+/// A reference to a value in `CompactValue`
+#[derive(Debug, Copy, Clone)]
+pub struct Cursor<'t, 's> {
+    tape: &'t CompactValue<'s>,
+    pos: usize,
+}
+
+impl<'t, 's> Cursor<'t, 's> {
+    #[allow(missing_docs)]
+    pub fn node(&self) -> Node {
+        self.tape.nodes[self.pos]
+    }
+
+    /// Return the name of the JSON type of this value, like `Value::type_name`.
+    pub fn type_name(&self) -> &'static str {
+        match self.node() {
+            Node::Null => "null",
+            Node::Boolean(..) => "boolean",
+            Node::Number(..) => "number",
+            Node::String(..) => "string",
+            Node::Array { .. } => "array",
+            Node::Object { .. } => "object",
+            Node::Raw(..) | Node::Key(..) => "raw",
+        }
+    }
+
+    #[allow(missing_docs)]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.node() {
+            Node::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    #[allow(missing_docs)]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.node() {
+            Node::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    #[allow(missing_docs)]
+    pub fn as_str(&self) -> Option<EscapedStr<'s>> {
+        match self.node() {
+            Node::String(si, ei) => Some(EscapedStr::from(&self.tape.record[si..ei])),
+            _ => None,
+        }
+    }
+
+    /// Return the number of elements or fields of an array or object.
+    pub fn len(&self) -> Option<usize> {
+        match self.node() {
+            Node::Array { len, .. } | Node::Object { len, .. } => Some(len),
+            _ => None,
+        }
+    }
+
+    /// Return whether this value is an empty array or object.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Return the `i`-th element of an array.
+    pub fn at(&self, i: usize) -> Option<Cursor<'t, 's>> {
+        self.elements().nth(i)
+    }
+
+    /// Return the value of the first field named `field` (compared with the raw name).
+    pub fn get(&self, field: &str) -> Option<Cursor<'t, 's>> {
+        self.fields()
+            .find(|(key, _)| key.as_raw_str() == field)
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over the elements of an array, or nothing if this is not an array.
+    pub fn elements(&self) -> impl Iterator<Item = Cursor<'t, 's>> {
+        let len = match self.node() {
+            Node::Array { len, .. } => len,
+            _ => 0,
+        };
+        let tape = self.tape;
+        let mut pos = self.pos + 1;
+        (0..len).map(move |_| {
+            let elem = Cursor { tape, pos };
+            pos = elem.next();
+            elem
+        })
+    }
+
+    /// Iterate over the fields of an object, or nothing if this is not an object.
+    pub fn fields(&self) -> impl Iterator<Item = (EscapedStr<'s>, Cursor<'t, 's>)> {
+        let len = match self.node() {
+            Node::Object { len, .. } => len,
+            _ => 0,
+        };
+        let tape = self.tape;
+        let mut pos = self.pos + 1;
+        (0..len).map(move |_| {
+            let key = match tape.nodes[pos] {
+                Node::Key(si, ei) => EscapedStr::from(&tape.record[si..ei]),
+                _ => unreachable!("a field must start with a key"),
+            };
+            let value = Cursor { tape, pos: pos + 1 };
+            pos = value.next();
+            (key, value)
+        })
+    }
+
+    /// Convert this value into `Value`.
+    pub fn to_value(&self) -> Value<'s> {
+        let record = self.tape.record;
+        match self.node() {
+            Node::Null => Value::Null,
+            Node::Boolean(b) => Value::Boolean(b),
+            Node::Number(n) => Value::Number(n),
+            Node::String(si, ei) => Value::String(EscapedStr::from(&record[si..ei])),
+            Node::Raw(si, ei) | Node::Key(si, ei) => Value::Raw(Cow::Borrowed(&record[si..ei])),
+            Node::Array { .. } => Value::Array(self.elements().map(|v| v.to_value()).collect()),
+            Node::Object { .. } => Value::Object(
+                self.fields()
+                    .map(|(key, value)| (key, value.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Return the position of the node after this value.
+    fn next(&self) -> usize {
+        match self.node() {
+            Node::Array { next, .. } | Node::Object { next, .. } => next,
+            _ => self.pos + 1,
+        }
+    }
+}
+
+/// Build the tape of a record with `index_builder`.
+pub(crate) fn build<'s, B: Backend>(
+    index_builder: &IndexBuilder<B>,
+    record: &'s str,
+    policy: RawPolicy,
+    lenient: bool,
+) -> Result<CompactValue<'s>> {
+    let mut buffers = IndexBuffers::default();
+    let index = match policy {
+        RawPolicy::ParseRecursively => index_builder.build_all_levels(record, &mut buffers)?,
+        _ => index_builder.build(record)?,
+    };
+    let mut builder = TapeBuilder {
+        index_builder,
+        policy,
+        lenient,
+        nodes: Vec::with_capacity(record.len() / 8),
+    };
+    builder.build(&index, 0, record.len(), 0, 0)?;
+    Ok(CompactValue {
+        record,
+        nodes: builder.nodes,
+    })
+}
+
+struct TapeBuilder<'p, B: Backend> {
+    index_builder: &'p IndexBuilder<B>,
+    policy: RawPolicy,
+    lenient: bool,
+    nodes: Vec<Node>,
+}
+
+impl<'p, B: Backend> TapeBuilder<'p, B> {
+    /// Push the nodes of the value between `begin` and `end` of `index`, whose record
+    /// starts at `base` in the whole record.
+    fn build(
+        &mut self,
+        index: &StructuralIndex<'_, '_>,
+        begin: usize,
+        end: usize,
+        level: usize,
+        base: usize,
+    ) -> Result<()> {
+        match value::parse_with(index.substr(begin, end), self.lenient)? {
+            ValueType::Atomic(Value::Null) => self.nodes.push(Node::Null),
+            ValueType::Atomic(Value::Boolean(b)) => self.nodes.push(Node::Boolean(b)),
+            ValueType::Atomic(Value::Number(n)) => self.nodes.push(Node::Number(n)),
+            ValueType::Atomic(_) => self
+                .nodes
+                .push(Node::String(base + begin + 1, base + end - 1)),
+            ValueType::Array => self.build_array(index, begin, end, level, base)?,
+            ValueType::Object => self.build_object(index, begin, end, level, base)?,
+        }
+        Ok(())
+    }
+
+    fn build_array(
+        &mut self,
+        index: &StructuralIndex<'_, '_>,
+        begin: usize,
+        end: usize,
+        level: usize,
+        base: usize,
+    ) -> Result<()> {
+        let mut cp = Vec::new();
+        if !index.comma_positions(begin, end, level, &mut cp) {
+            return self.build_beyond_levels(index.substr(begin, end), base + begin);
+        }
+        cp.push(end - 1); // dummy
+
+        let pos = self.nodes.len();
+        self.nodes.push(Node::Array { len: 0, next: 0 });
+        let mut len = 0;
+        for i in 0..cp.len() {
+            let (vsi, vei) =
+                index.find_array_value(if i == 0 { begin + 1 } else { cp[i - 1] + 1 }, cp[i]);
+            if i == 0 && vsi == vei && cp.len() == 1 {
+                break; // an empty array
+            }
+            self.build(index, vsi, vei, level + 1, base)?;
+            len += 1;
+        }
+        self.nodes[pos] = Node::Array {
+            len,
+            next: self.nodes.len(),
+        };
+        Ok(())
+    }
+
+    fn build_object(
+        &mut self,
+        index: &StructuralIndex<'_, '_>,
+        begin: usize,
+        end: usize,
+        level: usize,
+        base: usize,
+    ) -> Result<()> {
+        let fields = match index.object_fields_strict(begin, end, level) {
+            Some(fields) => fields,
+            None => return self.build_beyond_levels(index.substr(begin, end), base + begin),
+        };
+
+        let pos = self.nodes.len();
+        self.nodes.push(Node::Object { len: 0, next: 0 });
+        let mut len = 0;
+        for field in fields {
+            let field = field?;
+            self.nodes
+                .push(Node::Key(base + field.fsi, base + field.fei()));
+            self.build(index, field.vsi, field.vei, level + 1, base)?;
+            len += 1;
+        }
+        self.nodes[pos] = Node::Object {
+            len,
+            next: self.nodes.len(),
+        };
+        Ok(())
+    }
+
+    /// Push an array or object which is not covered by the levels of the index.
+    fn build_beyond_levels(&mut self, value: &str, base: usize) -> Result<()> {
+        match self.policy {
+            RawPolicy::ReturnRaw => {
+                self.nodes.push(Node::Raw(base, base + value.len()));
+                return Ok(());
+            }
+            RawPolicy::Error => {
+                return Err(ErrorKind::DepthLimitExceeded(self.index_builder.level()).into())
+            }
+            RawPolicy::ParseRecursively => {}
+        }
+        // Not reached from the index built by `build`, which covers all levels.
+        let mut buffers = IndexBuffers::default();
+        let index = self.index_builder.build_all_levels(value, &mut buffers)?;
+        self.build(&index, 0, value.len(), 0, base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::parser::{Parser, RawPolicy};
+
+    const RECORD: &str = r#"{
+        "a": [1, "x\"y", { "b": null }, []],
+        "c": { "d": true, "e": { "f": [2, 3] } },
+        "g": {}
+    }"#;
+
+    #[test]
+    fn navigation() {
+        let mut parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        let tape = parser.parse_compact(RECORD).unwrap();
+        let root = tape.root();
+        assert_eq!(root.type_name(), "object");
+        assert_eq!(root.len(), Some(3));
+
+        let a = root.get("a").unwrap();
+        assert_eq!(a.len(), Some(4));
+        assert_eq!(a.at(0).unwrap().as_f64(), Some(1.0));
+        assert_eq!(a.at(1).unwrap().as_str().unwrap().as_raw_str(), r#"x\"y"#);
+        assert_eq!(a.at(2).unwrap().type_name(), "raw");
+        assert_eq!(a.at(3).unwrap().to_value(), Value::raw("[]"));
+        assert!(a.at(4).is_none());
+        assert_eq!(
+            root.get("c").unwrap().get("d").unwrap().as_bool(),
+            Some(true)
+        );
+        assert!(root.get("g").unwrap().is_empty());
+        assert!(root.get("h").is_none());
+
+        assert_eq!(root.to_value(), parser.parse(RECORD).unwrap());
+
+        parser.raw_policy(RawPolicy::ParseRecursively);
+        let tape = parser.parse_compact(RECORD).unwrap();
+        let f = tape
+            .root()
+            .get("c")
+            .unwrap()
+            .get("e")
+            .unwrap()
+            .get("f")
+            .unwrap();
+        assert_eq!(
+            f.elements()
+                .map(|v| v.as_f64().unwrap())
+                .collect::<Vec<_>>(),
+            [2.0, 3.0]
+        );
+        assert_eq!(tape.root().to_value(), parser.parse(RECORD).unwrap());
+
+        parser.raw_policy(RawPolicy::Error);
+        assert!(parser.parse_compact(RECORD).is_err());
+    }
+
+    #[test]
+    fn binary_layout() {
+        let parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        let tape = parser.parse_compact(RECORD).unwrap();
+        let bytes = tape.to_bytes();
+        assert_eq!(&bytes[..8], b"MSTAPE\x01\x00");
+        assert_eq!(bytes.len(), 8 + 16 * tape.nodes().len());
+        // the root object
+        assert_eq!(bytes[15], b'{');
+        assert_eq!(bytes[8], 3);
+        assert_eq!(bytes[16] as usize, tape.nodes().len());
+
+        let nodes = read_nodes(&bytes).unwrap();
+        assert_eq!(nodes.collect::<Result<Vec<_>>>().unwrap(), tape.nodes());
+        assert_eq!(CompactValue::from_bytes(RECORD, &bytes).unwrap(), tape);
+
+        assert!(read_nodes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read_nodes(b"MSTAPE\x02\x00").is_err());
+        assert!(CompactValue::from_bytes(RECORD, b"MSTAPE\x01\x00").is_err());
+        assert!(CompactValue::from_bytes(&RECORD[..10], &bytes).is_err());
+        assert!(CompactValue::from_bytes(RECORD, &bytes[..bytes.len() - 16]).is_err());
+        let mut broken = bytes.clone();
+        broken[16] += 1;
+        assert!(CompactValue::from_bytes(RECORD, &broken).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_tape() {
+        let tape = |depth: usize| {
+            let mut nodes = vec![
+                Node::Array {
+                    len: 1,
+                    next: depth + 1
+                };
+                depth
+            ];
+            nodes.push(Node::Null);
+            CompactValue { record: "", nodes }.to_bytes()
+        };
+        let value = CompactValue::from_bytes("", &tape(DEFAULT_MAX_DEPTH)).unwrap();
+        assert_eq!(value.root().len(), Some(1));
+
+        assert!(CompactValue::from_bytes("", &tape(DEFAULT_MAX_DEPTH + 1)).is_err());
+        assert!(CompactValue::from_bytes("", &tape(1_000_000)).is_err());
+    }
+}
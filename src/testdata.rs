@@ -0,0 +1,454 @@
+//! Deterministic generators of benchmark corpora
+//!
+//! Each `Corpus` generates records of a typical shape from a seed, so that the
+//! benchmarks do not depend on external files and their results are reproducible.
+//! The records are generated with a small xorshift generator and are identical
+//! across platforms for the same seed.
+//!
+//! The corpora can also be written as JSON lines with the `testdata` example:
+//!
+//! ```text
+//! cargo run --example testdata -- tweets 1000 42 > tweets.json
+//! ```
+
+use crate::value::canonical::write_string;
+use std::fmt::Write;
+
+/// The seed used by the benchmarks
+pub const DEFAULT_SEED: u64 = 0x6d69_736f_736f_7570;
+
+/// The kinds of generated records
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corpus {
+    /// company profiles with nested offices and funding rounds (like Crunchbase dumps)
+    Companies,
+    /// tweets with user objects and entities
+    Tweets,
+    /// deeply nested configurations with many small objects
+    NestedConfigs,
+    /// records whose strings contain many escape sequences, quotes and colons
+    EscapedStrings,
+    /// records with arrays of thousands of numbers and pairs
+    HugeArrays,
+}
+
+impl Corpus {
+    /// All kinds of corpora
+    pub const ALL: [Corpus; 5] = [
+        Corpus::Companies,
+        Corpus::Tweets,
+        Corpus::NestedConfigs,
+        Corpus::EscapedStrings,
+        Corpus::HugeArrays,
+    ];
+
+    /// Return the name of the corpus, e.g. `"tweets"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Corpus::Companies => "companies",
+            Corpus::Tweets => "tweets",
+            Corpus::NestedConfigs => "nested_configs",
+            Corpus::EscapedStrings => "escaped_strings",
+            Corpus::HugeArrays => "huge_arrays",
+        }
+    }
+
+    #[allow(missing_docs)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|c| c.name() == name)
+    }
+
+    /// Generate `count` records from `seed`.
+    pub fn records(self, seed: u64, count: usize) -> Vec<String> {
+        let mut rng = Rng::new(seed);
+        (0..count).map(|_| self.record(&mut rng)).collect()
+    }
+
+    /// Generate a record with `rng`.
+    pub fn record(self, rng: &mut Rng) -> String {
+        let mut out = String::new();
+        match self {
+            Corpus::Companies => company(rng, &mut out),
+            Corpus::Tweets => tweet(rng, &mut out),
+            Corpus::NestedConfigs => config(rng, 0, &mut out),
+            Corpus::EscapedStrings => escaped(rng, &mut out),
+            Corpus::HugeArrays => huge_arrays(rng, &mut out),
+        }
+        out
+    }
+}
+
+/// A xorshift64* pseudo random number generator
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    #[allow(missing_docs)]
+    pub fn new(seed: u64) -> Self {
+        // scramble the seed with splitmix64, since the state must not be zero.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)).max(1))
+    }
+
+    /// Return the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Return a random number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    fn choose<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len() as u64) as usize]
+    }
+
+    fn word(&mut self) -> String {
+        const SYLLABLES: &[&str] = &[
+            "ka", "to", "mi", "so", "ru", "ne", "ha", "li", "po", "zen", "qua", "bit", "lo",
+        ];
+        let n = 1 + self.below(4);
+        (0..n).map(|_| self.choose(SYLLABLES)).collect()
+    }
+
+    fn sentence(&mut self, words: u64) -> String {
+        let n = 1 + self.below(words);
+        let words: Vec<String> = (0..n).map(|_| self.word()).collect();
+        words.join(" ")
+    }
+}
+
+fn push_field(out: &mut String, first: &mut bool, name: &str) {
+    if !*first {
+        out.push_str(", ");
+    }
+    *first = false;
+    write_string(name, out);
+    out.push_str(": ");
+}
+
+fn company(rng: &mut Rng, out: &mut String) {
+    let name = rng.word();
+    let mut first = true;
+    out.push('{');
+    push_field(out, &mut first, "_id");
+    let _ = write!(
+        out,
+        r#"{{"$oid": "{:016x}{:08x}"}}"#,
+        rng.next_u64(),
+        rng.below(1 << 32)
+    );
+    push_field(out, &mut first, "name");
+    write_string(&name, out);
+    push_field(out, &mut first, "permalink");
+    write_string(&name.to_lowercase(), out);
+    push_field(out, &mut first, "twitter_username");
+    if rng.chance(70) {
+        write_string(&rng.word(), out);
+    } else {
+        out.push_str("null");
+    }
+    push_field(out, &mut first, "category_code");
+    write_string(rng.choose(&["web", "software", "mobile", "games"]), out);
+    push_field(out, &mut first, "number_of_employees");
+    let _ = write!(out, "{}", rng.below(5000));
+    push_field(out, &mut first, "founded_year");
+    let _ = write!(out, "{}", 1980 + rng.below(40));
+    push_field(out, &mut first, "total_money_raised");
+    let _ = write!(out, r#""${}.{}M""#, rng.below(500), rng.below(10));
+    push_field(out, &mut first, "tag_list");
+    write_string(&rng.sentence(5).replace(' ', ", "), out);
+    push_field(out, &mut first, "description");
+    write_string(&rng.sentence(30), out);
+
+    push_field(out, &mut first, "offices");
+    out.push('[');
+    for i in 0..rng.below(4) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(
+            out,
+            r#"{{"description": "{}", "city": "{}", "zip_code": "{:05}", "latitude": {:.6}, "longitude": {:.6}}}"#,
+            rng.word(),
+            rng.word(),
+            rng.below(100_000),
+            rng.below(180_000_000) as f64 / 1e6 - 90.0,
+            rng.below(360_000_000) as f64 / 1e6 - 180.0,
+        );
+    }
+    out.push(']');
+
+    push_field(out, &mut first, "funding_rounds");
+    out.push('[');
+    for i in 0..rng.below(5) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(
+            out,
+            r#"{{"round_code": "{}", "raised_amount": {}, "raised_currency_code": "USD", "investments": ["#,
+            rng.choose(&["seed", "angel", "a", "b", "c"]),
+            rng.below(100) * 100_000,
+        );
+        for j in 0..rng.below(3) {
+            if j > 0 {
+                out.push_str(", ");
+            }
+            let org = rng.word();
+            let _ = write!(
+                out,
+                r#"{{"company": null, "financial_org": {{"name": "{}", "permalink": "{}"}}, "person": null}}"#,
+                org,
+                org.to_lowercase(),
+            );
+        }
+        out.push_str("]}");
+    }
+    out.push(']');
+
+    push_field(out, &mut first, "partners");
+    out.push_str("[]");
+    out.push('}');
+}
+
+fn tweet(rng: &mut Rng, out: &mut String) {
+    let id = rng.next_u64() >> 11;
+    let text = rng.sentence(20);
+    let mut first = true;
+    out.push('{');
+    push_field(out, &mut first, "created_at");
+    let _ = write!(
+        out,
+        r#""{} Oct {:02} {:02}:{:02}:{:02} +0000 2017""#,
+        rng.choose(&["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]),
+        1 + rng.below(31),
+        rng.below(24),
+        rng.below(60),
+        rng.below(60),
+    );
+    push_field(out, &mut first, "id");
+    let _ = write!(out, "{}", id);
+    push_field(out, &mut first, "id_str");
+    let _ = write!(out, r#""{}""#, id);
+    push_field(out, &mut first, "text");
+    write_string(&text, out);
+    push_field(out, &mut first, "truncated");
+    out.push_str("false");
+
+    push_field(out, &mut first, "user");
+    let screen_name = rng.word();
+    let _ = write!(
+        out,
+        r#"{{"id": {}, "name": "{}", "screen_name": "{}", "location": "{}", "followers_count": {}, "friends_count": {}, "verified": {}, "lang": "{}"}}"#,
+        rng.next_u64() >> 20,
+        rng.sentence(2),
+        screen_name,
+        rng.word(),
+        rng.below(1_000_000),
+        rng.below(5000),
+        rng.chance(5),
+        rng.choose(&["en", "ja", "es", "fr"]),
+    );
+
+    push_field(out, &mut first, "geo");
+    if rng.chance(10) {
+        let _ = write!(
+            out,
+            r#"{{"type": "Point", "coordinates": [{:.4}, {:.4}]}}"#,
+            rng.below(1_800_000) as f64 / 1e4 - 90.0,
+            rng.below(3_600_000) as f64 / 1e4 - 180.0,
+        );
+    } else {
+        out.push_str("null");
+    }
+
+    push_field(out, &mut first, "entities");
+    out.push_str(r#"{"hashtags": ["#);
+    for i in 0..rng.below(3) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let begin = rng.below(text.len() as u64);
+        let _ = write!(
+            out,
+            r#"{{"text": "{}", "indices": [{}, {}]}}"#,
+            rng.word(),
+            begin,
+            begin + 4,
+        );
+    }
+    out.push_str(r#"], "user_mentions": ["#);
+    for i in 0..rng.below(2) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(
+            out,
+            r#"{{"screen_name": "{}", "id": {}}}"#,
+            rng.word(),
+            rng.next_u64() >> 20,
+        );
+    }
+    out.push_str("]}");
+
+    push_field(out, &mut first, "retweet_count");
+    let _ = write!(out, "{}", rng.below(10_000));
+    push_field(out, &mut first, "favorite_count");
+    let _ = write!(out, "{}", rng.below(10_000));
+    push_field(out, &mut first, "lang");
+    write_string(rng.choose(&["en", "ja", "es", "fr"]), out);
+    out.push('}');
+}
+
+/// The maximum depth of the nested configurations
+const CONFIG_DEPTH: usize = 8;
+
+fn config(rng: &mut Rng, depth: usize, out: &mut String) {
+    let mut first = true;
+    out.push('{');
+    for i in 0..2 + rng.below(4) {
+        push_field(out, &mut first, &format!("{}_{}", rng.word(), i));
+        match rng.below(6) {
+            0 | 1 if depth < CONFIG_DEPTH => config(rng, depth + 1, out),
+            2 => {
+                let _ = write!(
+                    out,
+                    "[{}, {}, {}]",
+                    rng.below(10),
+                    rng.below(100),
+                    rng.below(1000)
+                );
+            }
+            3 => out.push_str(if rng.chance(50) { "true" } else { "false" }),
+            4 => {
+                let _ = write!(out, "{}.{}", rng.below(1000), rng.below(100));
+            }
+            _ => write_string(&rng.word(), out),
+        }
+    }
+    out.push('}');
+}
+
+fn escaped_string(rng: &mut Rng, out: &mut String) {
+    const PIECES: &[&str] = &[
+        r#"\""#,
+        r"\\",
+        r"\/",
+        r"\n",
+        r"\t",
+        r"\r",
+        r"\b",
+        r"\f",
+        r"é",
+        r"あ",
+        r"😀",
+        ":",
+        ",",
+        "{",
+        "}",
+        "[",
+        "]",
+        " ",
+        r"\u00e9",
+        r"\ud83d\ude00",
+    ];
+    out.push('"');
+    for _ in 0..8 + rng.below(32) {
+        if rng.chance(40) {
+            out.push_str(&rng.word());
+        } else {
+            out.push_str(rng.choose(PIECES));
+        }
+    }
+    out.push('"');
+}
+
+fn escaped(rng: &mut Rng, out: &mut String) {
+    let mut first = true;
+    out.push('{');
+    push_field(out, &mut first, "id");
+    let _ = write!(out, "{}", rng.below(1_000_000));
+    for name in &["message", "path", "quote"] {
+        push_field(out, &mut first, name);
+        escaped_string(rng, out);
+    }
+    push_field(out, &mut first, "key\\\"with\\\\escapes");
+    escaped_string(rng, out);
+    push_field(out, &mut first, "lines");
+    out.push('[');
+    for i in 0..rng.below(8) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        escaped_string(rng, out);
+    }
+    out.push_str("]}");
+}
+
+/// The maximum number of elements of the huge arrays
+const ARRAY_LEN: u64 = 4096;
+
+fn huge_arrays(rng: &mut Rng, out: &mut String) {
+    let mut first = true;
+    out.push('{');
+    push_field(out, &mut first, "id");
+    let _ = write!(out, "{}", rng.below(1_000_000));
+    push_field(out, &mut first, "values");
+    out.push('[');
+    for i in 0..ARRAY_LEN / 2 + rng.below(ARRAY_LEN / 2) {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}", rng.below(1 << 20) as i64 - (1 << 19));
+    }
+    out.push(']');
+    push_field(out, &mut first, "points");
+    out.push('[');
+    for i in 0..rng.below(ARRAY_LEN / 4) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(
+            out,
+            "[{:.3}, {:.3}]",
+            rng.below(1_000_000) as f64 / 1e3,
+            rng.below(1_000_000) as f64 / 1e3,
+        );
+    }
+    out.push_str("]}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::parser::Parser;
+    use crate::value::Value;
+
+    #[test]
+    fn generated_corpora() {
+        let parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 10));
+        for &corpus in &Corpus::ALL {
+            assert_eq!(Corpus::from_name(corpus.name()), Some(corpus));
+            let records = corpus.records(DEFAULT_SEED, 20);
+            assert_eq!(records, corpus.records(DEFAULT_SEED, 20));
+            assert_ne!(records, corpus.records(DEFAULT_SEED + 1, 20));
+            for record in &records {
+                let _: serde_json::Value = serde_json::from_str(record).unwrap();
+                assert!(matches!(parser.parse(record).unwrap(), Value::Object(..)));
+            }
+        }
+    }
+}
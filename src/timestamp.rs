@@ -0,0 +1,100 @@
+//! Helpers for parsing timestamps from query results
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use chrono::{DateTime, Utc};
+
+/// Check cheaply whether a raw slice has the shape of a supported timestamp.
+///
+/// This only inspects the positions of digits and separators, so that records whose
+/// event time is obviously malformed can be rejected before calling `parse_timestamp`.
+pub fn is_timestamp_like(raw: &str) -> bool {
+    let raw = raw.trim().as_bytes();
+    if raw.len() >= 2 && raw[0] == b'"' && raw[raw.len() - 1] == b'"' {
+        // "YYYY-MM-DDTHH:MM:SS" followed by fractions and the offset ("Z" at least).
+        let s = &raw[1..raw.len() - 1];
+        let digit = |i: usize| s[i].is_ascii_digit();
+        s.len() >= 20
+            && (0..4).all(digit)
+            && s[4] == b'-'
+            && (5..7).all(digit)
+            && s[7] == b'-'
+            && (8..10).all(digit)
+            && matches!(s[10], b'T' | b't' | b' ')
+            && (11..13).all(digit)
+            && s[13] == b':'
+            && (14..16).all(digit)
+            && s[16] == b':'
+            && (17..19).all(digit)
+    } else {
+        let digits = if raw.first() == Some(&b'-') {
+            &raw[1..]
+        } else {
+            raw
+        };
+        !digits.is_empty() && digits.len() <= 18 && digits.iter().all(u8::is_ascii_digit)
+    }
+}
+
+/// Parse a raw slice as a timestamp.
+///
+/// A string is parsed as an RFC 3339 date-time, and an integer is interpreted as the
+/// number of milliseconds since the Unix epoch.
+pub fn parse_timestamp(raw: &str) -> Result<DateTime<Utc>> {
+    let raw = raw.trim();
+    if !is_timestamp_like(raw) {
+        return Err(Error::from(ErrorKind::InvalidType(
+            "timestamp",
+            "malformed value",
+        )))
+        .chain_err(|| format!("parse_timestamp({:?})", raw));
+    }
+
+    if raw.starts_with('"') {
+        DateTime::parse_from_rfc3339(&raw[1..raw.len() - 1])
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| {
+                Error::from(ErrorKind::InvalidType("timestamp", "string"))
+                    .chain_err(|| e.to_string())
+            })
+    } else {
+        raw.parse::<i64>()
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidType("timestamp", "number")))
+            .chain_err(|| format!("{} is out of range", raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_like() {
+        assert!(is_timestamp_like(r#""2018-05-01T12:34:56Z""#));
+        assert!(is_timestamp_like(r#""2018-05-01 12:34:56.789+09:00""#));
+        assert!(is_timestamp_like("1525178096000"));
+        assert!(is_timestamp_like("-1000"));
+        assert!(!is_timestamp_like(r#""2018/05/01T12:34:56Z""#));
+        assert!(!is_timestamp_like(r#""2018-05-01""#));
+        assert!(!is_timestamp_like("1.5"));
+        assert!(!is_timestamp_like("null"));
+        assert!(!is_timestamp_like("-"));
+    }
+
+    #[test]
+    fn parse() {
+        let expected = DateTime::from_timestamp_millis(1_525_178_096_789).unwrap();
+        assert_eq!(
+            parse_timestamp(r#""2018-05-01T12:34:56.789Z""#).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_timestamp(r#""2018-05-01T21:34:56.789+09:00""#).unwrap(),
+            expected
+        );
+        assert_eq!(parse_timestamp(" 1525178096789 ").unwrap(), expected);
+        assert!(parse_timestamp(r#""2018-13-01T12:34:56Z""#).is_err());
+        assert!(parse_timestamp("true").is_err());
+    }
+}
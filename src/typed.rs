@@ -0,0 +1,352 @@
+//! Typed extraction of query results
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+use crate::value::{self, EscapedStr};
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// Expected type of an extracted field
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldType {
+    Bool,
+    I64,
+    F64,
+    /// a JSON string, returned with its escape sequences decoded
+    String,
+    /// any JSON value, returned as its raw slice
+    Raw,
+}
+
+impl FromStr for FieldType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bool" => Ok(FieldType::Bool),
+            "i64" => Ok(FieldType::I64),
+            "f64" => Ok(FieldType::F64),
+            "string" | "String" => Ok(FieldType::String),
+            "raw" => Ok(FieldType::Raw),
+            s => Err(Error::from(ErrorKind::InvalidQuery))
+                .chain_err(|| format!("unknown field type {:?}", s)),
+        }
+    }
+}
+
+/// The type of a JSON value
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JsonType {
+    Object,
+    Array,
+    String,
+    Number,
+    Boolean,
+    Null,
+}
+
+impl JsonType {
+    /// Guess the type of a raw slice of a JSON value from its first non-whitespace byte,
+    /// without validating the rest.
+    pub fn of(raw: &str) -> Option<JsonType> {
+        let first = raw
+            .bytes()
+            .find(|c| !matches!(c, b' ' | b'\t' | b'\r' | b'\n'));
+        Self::of_byte(first?)
+    }
+
+    /// Same as `of`, but with the first byte of the value.
+    #[inline]
+    pub fn of_byte(first: u8) -> Option<JsonType> {
+        match first {
+            b'"' => Some(JsonType::String),
+            b'{' => Some(JsonType::Object),
+            b'[' => Some(JsonType::Array),
+            b't' | b'f' => Some(JsonType::Boolean),
+            b'n' => Some(JsonType::Null),
+            b'-' | b'0'..=b'9' => Some(JsonType::Number),
+            _ => None,
+        }
+    }
+
+    /// Return the name of the type used in `ErrorKind::InvalidType`, e.g. `"boolean"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            JsonType::Object => "object",
+            JsonType::Array => "array",
+            JsonType::String => "string",
+            JsonType::Number => "number",
+            JsonType::Boolean => "boolean",
+            JsonType::Null => "null",
+        }
+    }
+}
+
+/// A field converted into its expected type
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedValue<'s> {
+    Bool(bool),
+    I64(i64),
+    F64(f64),
+    String(Cow<'s, str>),
+    Raw(&'s str),
+}
+
+impl FieldType {
+    #[allow(missing_docs)]
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldType::Bool => "bool",
+            FieldType::I64 => "i64",
+            FieldType::F64 => "f64",
+            FieldType::String => "string",
+            FieldType::Raw => "raw",
+        }
+    }
+
+    /// Convert a raw slice of a JSON value into this type.
+    pub fn convert(self, raw: &str) -> Result<TypedValue<'_>> {
+        self.convert_with(raw, false)
+    }
+
+    /// Same as `convert`, but the numbers are parsed leniently if `lenient_numbers`
+    /// (see `value::parse_number`).
+    pub fn convert_with(self, raw: &str, lenient_numbers: bool) -> Result<TypedValue<'_>> {
+        let mismatch = || Err(ErrorKind::InvalidType(self.name(), found(raw)).into());
+        match self {
+            FieldType::Bool => match raw {
+                "true" => Ok(TypedValue::Bool(true)),
+                "false" => Ok(TypedValue::Bool(false)),
+                _ => mismatch(),
+            },
+            FieldType::I64 => match value::parse_integer(raw, lenient_numbers) {
+                Some(n) => Ok(TypedValue::I64(n)),
+                None => mismatch(),
+            },
+            FieldType::F64 => match value::parse_number(raw, lenient_numbers) {
+                Some(n) => Ok(TypedValue::F64(n)),
+                None => mismatch(),
+            },
+            FieldType::String if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') => {
+                EscapedStr::from(&raw[1..raw.len() - 1])
+                    .into_unescaped()
+                    .map(TypedValue::String)
+            }
+            FieldType::String => mismatch(),
+            FieldType::Raw => Ok(TypedValue::Raw(raw)),
+        }
+    }
+}
+
+/// Guess the JSON type of a raw slice from its first byte.
+pub(crate) fn found(raw: &str) -> &'static str {
+    JsonType::of(raw).map_or("unknown", JsonType::name)
+}
+
+/// A list of query paths with their expected types
+#[derive(Debug, Default, Clone)]
+pub struct Schema<'a> {
+    fields: Vec<(&'a str, FieldType)>,
+}
+
+impl<'a> Schema<'a> {
+    /// Append a path with its expected type.
+    pub fn add(&mut self, path: &'a str, ty: FieldType) -> &mut Self {
+        self.fields.push((path, ty));
+        self
+    }
+
+    /// Append a path from a specification like `"$.age: i64"`.
+    pub fn add_spec(&mut self, spec: &'a str) -> Result<&mut Self> {
+        let i = spec
+            .rfind(':')
+            .ok_or_else(|| Error::from(ErrorKind::InvalidQuery))
+            .chain_err(|| format!("missing type in {:?}", spec))?;
+        let ty = spec[i + 1..].trim().parse()?;
+        Ok(self.add(spec[..i].trim(), ty))
+    }
+
+    #[allow(missing_docs)]
+    pub fn fields(&self) -> &[(&'a str, FieldType)] {
+        &self.fields
+    }
+}
+
+/// A record whose fields are converted into the types declared in the schema
+///
+/// Each field is `Ok(None)` if missing in the record, or `Err(..)` if the value
+/// could not be converted.
+#[derive(Debug)]
+pub struct TypedRecord<'s> {
+    fields: Vec<Result<Option<TypedValue<'s>>>>,
+}
+
+impl<'s> TypedRecord<'s> {
+    /// Return the field at `i`, in the order of the schema.
+    pub fn get(&self, i: usize) -> Option<&Result<Option<TypedValue<'s>>>> {
+        self.fields.get(i)
+    }
+
+    #[allow(missing_docs)]
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    #[allow(missing_docs)]
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    #[allow(missing_docs)]
+    pub fn into_fields(self) -> Vec<Result<Option<TypedValue<'s>>>> {
+        self.fields
+    }
+}
+
+/// A query parser which converts the extracted values according to a schema
+#[derive(Debug)]
+pub struct TypedQueryParser<'a, B: Backend> {
+    parser: QueryParser<'a, B>,
+    types: Vec<FieldType>,
+    lenient_numbers: bool,
+}
+
+impl<'a, B: Backend> TypedQueryParser<'a, B> {
+    /// Create a new parser from a schema.
+    ///
+    /// The level of `index_builder` should be at least the maximal level of the paths.
+    pub fn new(index_builder: IndexBuilder<B>, schema: &Schema<'a>) -> Result<Self> {
+        let mut query_tree = QueryTree::default();
+        for &(path, _) in &schema.fields {
+            query_tree.add_path(path)?;
+        }
+        Ok(Self {
+            parser: QueryParser::new(index_builder, query_tree),
+            types: schema.fields.iter().map(|&(_, ty)| ty).collect(),
+            lenient_numbers: false,
+        })
+    }
+
+    #[allow(missing_docs)]
+    pub fn query_parser(&mut self) -> &mut QueryParser<'a, B> {
+        &mut self.parser
+    }
+
+    /// Accept `NaN`, `Infinity` and hexadecimal integers for the numeric fields.
+    /// Disabled by default.
+    pub fn lenient_numbers(&mut self, v: bool) {
+        self.lenient_numbers = v;
+    }
+
+    /// Parse a record and convert the extracted values.
+    pub fn parse<'s>(&self, record: &'s str, mode: QueryParserMode) -> Result<TypedRecord<'s>> {
+        // the conversions expect the quotes of strings.
+        let results = self.parser.parse_raw(record, mode)?;
+        let fields = results
+            .into_iter()
+            .zip(&self.types)
+            .map(|(raw, ty)| {
+                raw.map(|raw| ty.convert_with(raw, self.lenient_numbers))
+                    .transpose()
+            })
+            .collect();
+        Ok(TypedRecord { fields })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+
+    #[test]
+    fn typed_parsing() {
+        let mut schema = Schema::default();
+        schema
+            .add("$.name", FieldType::String)
+            .add("$.ok", FieldType::Bool);
+        schema.add_spec("$.user.age: i64").unwrap();
+        schema.add_spec("$.score: f64").unwrap();
+        schema.add_spec("$.tags: raw").unwrap();
+        schema.add_spec("$.missing: bool").unwrap();
+        assert!(schema.add_spec("$.x: u8").is_err());
+
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 2);
+        let parser = TypedQueryParser::new(index_builder, &schema).unwrap();
+
+        let record = r#"{"name": "a\"b", "ok": 1, "user": {"age": 42}, "score": 0.5, "tags": [1]}"#;
+        let result = parser
+            .parse(record, QueryParserMode::Basic)
+            .unwrap()
+            .into_fields();
+        assert_eq!(result.len(), 6);
+        assert_eq!(
+            result[0].as_ref().unwrap(),
+            &Some(TypedValue::String("a\"b".into()))
+        );
+        match result[1] {
+            Err(Error(ErrorKind::InvalidType("bool", "number"), _)) => {}
+            ref e => panic!("unexpected result: {:?}", e),
+        }
+        assert_eq!(result[2].as_ref().unwrap(), &Some(TypedValue::I64(42)));
+        assert_eq!(result[3].as_ref().unwrap(), &Some(TypedValue::F64(0.5)));
+        assert_eq!(result[4].as_ref().unwrap(), &Some(TypedValue::Raw("[1]")));
+        assert_eq!(result[5].as_ref().unwrap(), &None);
+    }
+
+    #[test]
+    fn json_types() {
+        assert_eq!(JsonType::of(" \n\"a\""), Some(JsonType::String));
+        assert_eq!(JsonType::of("-1"), Some(JsonType::Number));
+        assert_eq!(JsonType::of("false"), Some(JsonType::Boolean));
+        assert_eq!(JsonType::of("\tnull"), Some(JsonType::Null));
+        assert_eq!(JsonType::of("{}"), Some(JsonType::Object));
+        assert_eq!(JsonType::of("x"), None);
+        assert_eq!(JsonType::of("  "), None);
+    }
+
+    #[test]
+    fn lenient_numbers() {
+        let mut schema = Schema::default();
+        schema.add("$.a", FieldType::I64).add("$.b", FieldType::F64);
+        let index_builder = IndexBuilder::new(FallbackBackend::default(), 1);
+        let mut parser = TypedQueryParser::new(index_builder, &schema).unwrap();
+
+        let record = r#"{"a": 0x1F, "b": -Infinity}"#;
+        let result = parser.parse(record, QueryParserMode::Basic).unwrap();
+        assert!(result.get(0).unwrap().is_err());
+        assert!(result.get(1).unwrap().is_err());
+
+        parser.lenient_numbers(true);
+        let result = parser
+            .parse(record, QueryParserMode::Basic)
+            .unwrap()
+            .into_fields();
+        assert_eq!(result[0].as_ref().unwrap(), &Some(TypedValue::I64(31)));
+        assert_eq!(
+            result[1].as_ref().unwrap(),
+            &Some(TypedValue::F64(f64::NEG_INFINITY))
+        );
+    }
+
+    #[test]
+    fn non_json_numbers() {
+        assert!(FieldType::I64.convert("+1").is_err());
+        assert!(FieldType::I64.convert("01").is_err());
+        assert!(FieldType::F64.convert("-inf").is_err());
+        assert!(FieldType::F64.convert("+1.5").is_err());
+
+        assert_eq!(
+            FieldType::I64.convert_with("+1", true).unwrap(),
+            TypedValue::I64(1)
+        );
+        assert_eq!(
+            FieldType::F64.convert_with("+1.5", true).unwrap(),
+            TypedValue::F64(1.5)
+        );
+        assert!(FieldType::F64.convert_with("-inf", true).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Extraction of arrays of objects into one row per element
+//!
+//! The paths following an array are written with `[*]`, like `$.order.items[*].sku`,
+//! and the values of the other paths are repeated for each element of the array:
+//!
+//! ```ignore
+//! let parser = Unnest::new(backend, &["$.order.id", "$.order.items[*].sku"])?;
+//! // [[Some("1"), Some("\"a\"")], [Some("1"), Some("\"b\"")]]
+//! let rows = parser.parse(r#"{"order": {"id": 1, "items": [{"sku": "a"}, {"sku": "b"}]}}"#, mode)?;
+//! ```
+
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::index_builder::backend::Backend;
+use crate::index_builder::IndexBuilder;
+use crate::query::QueryTree;
+use crate::query_parser::{QueryParser, QueryParserMode};
+
+/// Where the value of a path is taken from
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    /// a path outside of the array
+    Outer(usize),
+    /// the element itself (`$.items[*]`)
+    Element,
+    /// a path within the element
+    Inner(usize),
+}
+
+/// A parser which emits one row per element of an array
+#[derive(Debug)]
+pub struct Unnest<'q, B: Backend> {
+    outer: QueryParser<'q, B>,
+    inner: QueryParser<'q, B>,
+    elements: IndexBuilder<B>,
+    array_id: usize,
+    num_inner: usize,
+    columns: Vec<Column>,
+    keep_empty: bool,
+}
+
+impl<'q, B: Backend + Clone> Unnest<'q, B> {
+    /// Create a new parser of `paths`, in order.
+    ///
+    /// All paths containing `[*]` must refer to the same array, and `[*]` may appear
+    /// only once in each path.
+    pub fn new(backend: B, paths: &[&'q str]) -> Result<Self> {
+        let mut outer = QueryTree::default();
+        let mut inner = QueryTree::default();
+        let mut array_path = None;
+        let mut columns = Vec::with_capacity(paths.len());
+        for &path in paths {
+            let i = match path.find("[*]") {
+                Some(i) => i,
+                None => {
+                    columns.push(Column::Outer(add_path(&mut outer, path)?));
+                    continue;
+                }
+            };
+            let (prefix, rest) = (&path[..i], &path[i + 3..]);
+            match array_path {
+                Some(array_path) if array_path != prefix => {
+                    return Err(Error::from(ErrorKind::InvalidQuery))
+                        .chain_err(|| format!("{:?} refers to another array", path));
+                }
+                _ => array_path = Some(prefix),
+            }
+            if rest.is_empty() {
+                columns.push(Column::Element);
+            } else if rest.starts_with('.') && !rest.contains("[*]") {
+                inner
+                    .add_fields(path, rest[1..].split('.'))
+                    .chain_err(|| format!("invalid path {:?}", path))?;
+                columns.push(Column::Inner(inner.num_paths() - 1));
+            } else {
+                return Err(Error::from(ErrorKind::InvalidQuery))
+                    .chain_err(|| format!("invalid path {:?}", path));
+            }
+        }
+
+        let array_path = array_path
+            .ok_or_else(|| Error::from(ErrorKind::InvalidQuery))
+            .chain_err(|| "no path to unnest")?;
+        let array_id = add_path(&mut outer, array_path)?;
+
+        let outer_level = outer.max_level();
+        let inner_level = inner.max_level();
+        let num_inner = inner.num_paths();
+        Ok(Self {
+            outer: QueryParser::new(IndexBuilder::new(backend.clone(), outer_level), outer),
+            inner: QueryParser::new(IndexBuilder::new(backend.clone(), inner_level), inner),
+            elements: IndexBuilder::new(backend, 1),
+            array_id,
+            num_inner,
+            columns,
+            keep_empty: false,
+        })
+    }
+}
+
+/// Add `path` unless it has already been added, and return its identifier.
+fn add_path<'q>(tree: &mut QueryTree<'q>, path: &'q str) -> Result<usize> {
+    match tree.paths().iter().position(|&p| p == path) {
+        Some(id) => Ok(id),
+        None => {
+            tree.add_path(path)?;
+            Ok(tree.num_paths() - 1)
+        }
+    }
+}
+
+impl<'q, B: Backend> Unnest<'q, B> {
+    /// Emit a row whose array values are all `None` if the array is missing or empty,
+    /// instead of emitting no row (like an outer join).
+    pub fn keep_empty(&mut self, v: bool) {
+        self.keep_empty = v;
+    }
+
+    /// Parse a record and return the rows, in the order of the elements.
+    ///
+    /// If the value of the array is not an array, it is treated as missing. The
+    /// paths within an element are `None` unless the element is an object.
+    pub fn parse<'s>(
+        &self,
+        record: &'s str,
+        mode: QueryParserMode,
+    ) -> Result<Vec<Vec<Option<&'s str>>>> {
+        let outer = self.outer.parse(record, mode)?;
+
+        let mut rows = vec![];
+        if let Some(array) = outer[self.array_id].filter(|v| v.starts_with('[')) {
+            let index = self.elements.build(array)?;
+            let mut cp = vec![];
+            index.comma_positions(0, array.len(), 0, &mut cp);
+            cp.push(array.len() - 1);
+
+            let mut begin = 1;
+            for (i, &end) in cp.iter().enumerate() {
+                let (vsi, vei) = index.find_array_value(begin, end);
+                begin = end + 1;
+                if vsi == vei && cp.len() == 1 {
+                    break; // an empty array
+                }
+                let element = index.substr(vsi, vei);
+                let inner = if element.starts_with('{') && self.num_inner > 0 {
+                    self.inner
+                        .parse(element, mode)
+                        .chain_err(|| format!("element #{}", i))?
+                } else {
+                    vec![None; self.num_inner]
+                };
+                rows.push(self.row(&outer, Some(element), &inner));
+            }
+        }
+
+        if rows.is_empty() && self.keep_empty {
+            rows.push(self.row(&outer, None, &vec![None; self.num_inner]));
+        }
+        Ok(rows)
+    }
+
+    fn row<'s>(
+        &self,
+        outer: &[Option<&'s str>],
+        element: Option<&'s str>,
+        inner: &[Option<&'s str>],
+    ) -> Vec<Option<&'s str>> {
+        self.columns
+            .iter()
+            .map(|&column| match column {
+                Column::Outer(id) => outer[id],
+                Column::Element => element,
+                Column::Inner(id) => inner[id],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+
+    #[test]
+    fn unnest_objects() {
+        let parser = Unnest::new(
+            FallbackBackend::default(),
+            &[
+                "$.order.id",
+                "$.order.items[*].sku",
+                "$.order.items[*].qty.n",
+            ],
+        )
+        .unwrap();
+        let record = r#"{
+            "order": {
+                "id": 1,
+                "items": [
+                    { "sku": "a", "qty": { "n": 2 } },
+                    { "sku": "b,c" },
+                    "unknown"
+                ]
+            }
+        }"#;
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            [
+                vec![Some("1"), Some(r#""a""#), Some("2")],
+                vec![Some("1"), Some(r#""b,c""#), None],
+                vec![Some("1"), None, None],
+            ]
+        );
+    }
+
+    #[test]
+    fn unnest_elements() {
+        let mut parser =
+            Unnest::new(FallbackBackend::default(), &["$.tags[*]", "$.id", "$.tags"]).unwrap();
+        assert_eq!(
+            parser
+                .parse(
+                    r#"{ "id": 1, "tags": [ "x", [1, 2] ] }"#,
+                    QueryParserMode::Basic
+                )
+                .unwrap(),
+            [
+                vec![Some(r#""x""#), Some("1"), Some(r#"[ "x", [1, 2] ]"#)],
+                vec![Some("[1, 2]"), Some("1"), Some(r#"[ "x", [1, 2] ]"#)],
+            ]
+        );
+
+        let record = r#"{ "id": 1, "tags": [] }"#;
+        assert!(parser
+            .parse(record, QueryParserMode::Basic)
+            .unwrap()
+            .is_empty());
+        parser.keep_empty(true);
+        assert_eq!(
+            parser.parse(record, QueryParserMode::Basic).unwrap(),
+            [vec![None, Some("1"), Some("[]")]]
+        );
+        assert_eq!(
+            parser
+                .parse(r#"{ "id": 2 }"#, QueryParserMode::Basic)
+                .unwrap(),
+            [vec![None, Some("2"), None]]
+        );
+    }
+
+    #[test]
+    fn invalid_paths() {
+        let backend = FallbackBackend::default();
+        assert!(Unnest::new(backend.clone(), &["$.a"]).is_err());
+        assert!(Unnest::new(backend.clone(), &["$.a[*].b", "$.c[*].d"]).is_err());
+        assert!(Unnest::new(backend.clone(), &["$.a[*].b[*].c"]).is_err());
+        assert!(Unnest::new(backend.clone(), &["$.a[*]b"]).is_err());
+        assert!(Unnest::new(backend, &["$.a[*]..b"]).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+//! Strict validation of the JSON grammar (RFC 8259)
+//!
+//! The structural index locates the values without looking at most bytes of a record,
+//! so malformed records are often accepted by the parsers (e.g. unescaped control
+//! characters in strings, or trailing commas outside the queried values of
+//! `QueryParser`). `validate` checks the whole record instead, and is
+//! run before parsing by `Parser` in strict mode.
+//!
+//! The escaped lone surrogates (e.g. `"\uD800"`), which are allowed by the grammar but
+//! cannot be represented in Rust strings, are rejected as well as by
+//! `EscapedStr::unescape`.
+
+use crate::errors::{Error, ErrorKind, Result};
+
+/// The maximum depth of the nested arrays and objects accepted by `validate`
+pub const MAX_DEPTH: usize = 1024;
+
+/// Check that the record is a JSON text.
+///
+/// Only the whitespace defined by JSON is allowed around the value. The validation
+/// does not recurse, so any deep nesting is rejected without overflowing the stack.
+pub fn validate(record: &str) -> Result<()> {
+    validate_with_depth(record, MAX_DEPTH)
+}
+
+/// Same as `validate`, but with the maximum depth of nesting.
+pub fn validate_with_depth(record: &str, max_depth: usize) -> Result<()> {
+    let mut v = Validator {
+        s: record.as_bytes(),
+        pos: 0,
+    };
+    run(&mut v, max_depth)
+}
+
+/// Return the byte offset where the record stops being a JSON text, or `None` if it
+/// is valid.
+pub fn error_offset(record: &str) -> Option<usize> {
+    let mut v = Validator {
+        s: record.as_bytes(),
+        pos: 0,
+    };
+    run(&mut v, MAX_DEPTH).err().map(|_| v.pos)
+}
+
+fn run(v: &mut Validator<'_>, max_depth: usize) -> Result<()> {
+    // the kinds of the open arrays and objects
+    let mut stack = Vec::new();
+
+    'value: loop {
+        v.skip_whitespace();
+        match v.peek() {
+            Some(b'[') | Some(b'{') => {
+                let open = v.s[v.pos];
+                let close = if open == b'[' { b']' } else { b'}' };
+                if stack.len() >= max_depth {
+                    return Err(ErrorKind::DepthLimitExceeded(max_depth).into());
+                }
+                v.pos += 1;
+                v.skip_whitespace();
+                if v.peek() == Some(close) {
+                    v.pos += 1;
+                } else {
+                    stack.push(open);
+                    if open == b'{' {
+                        v.field_name()?;
+                    }
+                    continue 'value;
+                }
+            }
+            Some(b'"') => v.string()?,
+            Some(b'-') | Some(b'0'..=b'9') => v.number()?,
+            Some(b't') => v.literal("true")?,
+            Some(b'f') => v.literal("false")?,
+            Some(b'n') => v.literal("null")?,
+            _ => return Err(v.error("expected a value")),
+        }
+
+        // the end of a value
+        loop {
+            v.skip_whitespace();
+            let open = match stack.last() {
+                Some(&open) => open,
+                None if v.pos == v.s.len() => return Ok(()),
+                None => return Err(v.error("unexpected trailing characters")),
+            };
+            match (open, v.peek()) {
+                (_, Some(b',')) => {
+                    v.pos += 1;
+                    if open == b'{' {
+                        v.skip_whitespace();
+                        v.field_name()?;
+                    }
+                    continue 'value;
+                }
+                (b'[', Some(b']')) | (b'{', Some(b'}')) => {
+                    v.pos += 1;
+                    stack.pop();
+                }
+                (b'[', _) => return Err(v.error("expected ',' or ']'")),
+                _ => return Err(v.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+struct Validator<'s> {
+    s: &'s [u8],
+    pos: usize,
+}
+
+impl<'s> Validator<'s> {
+    fn error(&self, msg: &str) -> Error {
+        Error::from(ErrorKind::InvalidRecord).chain_err(|| format!("{} at {}", msg, self.pos))
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).cloned()
+    }
+
+    #[inline]
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let begin = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - begin
+    }
+
+    fn number(&mut self) -> Result<()> {
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {
+                self.digits();
+            }
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(self.error("invalid fraction"));
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error("invalid exponent"));
+            }
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, lit: &str) -> Result<()> {
+        if !self.s[self.pos..].starts_with(lit.as_bytes()) {
+            return Err(self.error("invalid literal"));
+        }
+        self.pos += lit.len();
+        Ok(())
+    }
+
+    /// Consume the name of a field and its colon.
+    fn field_name(&mut self) -> Result<()> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("expected a field name"));
+        }
+        self.string()?;
+        self.skip_whitespace();
+        if self.peek() != Some(b':') {
+            return Err(self.error("expected ':'"));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let hex = self
+            .s
+            .get(self.pos..self.pos + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .filter(|hex| hex.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(hex, 16).unwrap())
+    }
+
+    fn string(&mut self) -> Result<()> {
+        self.pos += 1; // the opening quote
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(c) if c < 0x20 => return Err(self.error("unescaped control character")),
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"') | Some(b'\\') | Some(b'/') | Some(b'b') | Some(b'f')
+                        | Some(b'n') | Some(b'r') | Some(b't') => self.pos += 1,
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let code = self.hex4()?;
+                            if (0xDC00..0xE000).contains(&code) {
+                                return Err(self.error("lone low surrogate"));
+                            }
+                            if (0xD800..0xDC00).contains(&code) {
+                                if !self.s[self.pos..].starts_with(b"\\u") {
+                                    return Err(self.error("lone high surrogate"));
+                                }
+                                self.pos += 2;
+                                if !(0xDC00..0xE000).contains(&self.hex4()?) {
+                                    return Err(self.error("lone high surrogate"));
+                                }
+                            }
+                        }
+                        _ => return Err(self.error("invalid escape sequence")),
+                    }
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    /// Some cases of JSONTestSuite (https://github.com/nst/JSONTestSuite), which must be
+    /// accepted (`y_`) or rejected (`n_`)
+    const CASES: &[(&str, &str)] = &[
+        ("y_array_arraysWithSpaces", "[[]   ]"),
+        ("y_array_empty-string", r#"[""]"#),
+        ("y_array_heterogeneous", r#"[null, 1, "1", {}]"#),
+        ("y_number_minus_zero", "[-0]"),
+        ("y_number_real_capital_e_neg_exp", "[1E-2]"),
+        ("y_object_duplicated_key", r#"{"a":"b","a":"c"}"#),
+        ("y_object_escaped_null_in_key", r#"{"foo\u0000bar": 42}"#),
+        ("y_string_accepted_surrogate_pair", r#"["\uD801\udc37"]"#),
+        ("y_string_allowed_escapes", r#"["\"\\\/\b\f\n\r\t"]"#),
+        ("y_string_utf8", "[\"\u{20ac}\u{1d11e}\"]"),
+        ("y_structure_lonely_int", "42"),
+        ("y_structure_lonely_string", r#""asd""#),
+        ("y_structure_whitespace_array", " [] "),
+        ("y_structure_trailing_newline", "[\"a\"]\n"),
+        ("n_array_1_true_without_comma", "[1 true]"),
+        ("n_array_extra_comma", r#"["",]"#),
+        ("n_array_unclosed", r#"["""#),
+        ("n_number_with_leading_zero", "[012]"),
+        ("n_number_hex_1_digit", "[0x1]"),
+        ("n_number_minus_infinity", "[-Infinity]"),
+        ("n_number_NaN", "[NaN]"),
+        ("n_number_real_without_fractional_part", "[1.]"),
+        ("n_object_missing_colon", r#"{"a" b}"#),
+        ("n_object_trailing_comma", r#"{"id":0,}"#),
+        ("n_object_single_quote", "{'a':0}"),
+        ("n_string_escape_x", r#"["\x00"]"#),
+        ("n_string_unescaped_ctrl_char", "[\"a\u{0}a\"]"),
+        ("n_string_unescaped_tab", "[\"\t\"]"),
+        (
+            "n_string_incomplete_surrogate_escape_invalid",
+            r#"["\uD800\uD800\x"]"#,
+        ),
+        ("n_structure_double_array", "[][]"),
+        ("n_structure_whitespace_formfeed", "[\u{c}]"),
+        ("n_structure_UTF8_BOM_no_data", "\u{feff}"),
+        ("n_structure_no_data", ""),
+        ("i_string_1st_surrogate_but_2nd_missing", r#"["\uDADA"]"#),
+        ("i_string_incomplete_surrogate_pair", r#"["\uDd1ea"]"#),
+    ];
+
+    fn check(name: &str, record: &str) {
+        let result = validate(record);
+        if name.starts_with("y_") {
+            assert!(result.is_ok(), "{}: {}", name, result.unwrap_err());
+        } else {
+            assert!(result.is_err(), "{} is accepted", name);
+        }
+    }
+
+    #[test]
+    fn json_test_suite() {
+        for &(name, record) in CASES {
+            check(name, record);
+        }
+
+        assert_eq!(error_offset(r#"{"a": [1 2]}"#), Some(9));
+        assert_eq!(error_offset("[1, "), Some(4));
+        assert_eq!(error_offset("[1]"), None);
+
+        let deep = "[".repeat(100_000);
+        assert!(validate(&deep).is_err());
+        let deep = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        validate(&deep).unwrap();
+        let deep = format!("[{}]", deep);
+        let err = validate(&deep).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::DepthLimitExceeded(MAX_DEPTH)
+        ));
+    }
+
+    /// The `n_` cases accepted by `Parser` unless it is strict, since the contents of
+    /// strings are not validated until they are unescaped
+    const ACCEPTED_BY_PARSER: &[&str] = &[
+        "n_string_escape_x",
+        "n_string_unescaped_ctrl_char",
+        "n_string_unescaped_tab",
+        "n_string_incomplete_surrogate_escape_invalid",
+    ];
+
+    /// The `n_` cases accepted by `QueryParser`, which looks only at the values of the
+    /// query paths
+    const ACCEPTED_BY_QUERY_PARSER: &[&str] =
+        &["n_object_missing_colon", "n_object_trailing_comma"];
+
+    #[test]
+    fn json_test_suite_parsers() {
+        use crate::index_builder::{backend::FallbackBackend, IndexBuilder};
+        use crate::parser::{Parser, DEFAULT_SMALL_RECORD_LEN};
+        use crate::query::QueryTree;
+        use crate::query_parser::{QueryParser, QueryParserMode};
+
+        let parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 1));
+        let mut small = parser.clone();
+        small.small_record_len(DEFAULT_SMALL_RECORD_LEN);
+        let mut strict = parser.clone();
+        strict.strict(true);
+        let mut tree = QueryTree::default();
+        tree.add_path("$").unwrap();
+        tree.add_path("$.a").unwrap();
+        let query_parser = QueryParser::new(IndexBuilder::new(FallbackBackend::default(), 1), tree);
+
+        for &(name, record) in CASES {
+            if name.starts_with("i_") {
+                continue;
+            }
+            let valid = name.starts_with("y_");
+            let accepted = valid || ACCEPTED_BY_PARSER.contains(&name);
+            assert_eq!(parser.parse(record).is_ok(), accepted, "{}", name);
+            assert_eq!(small.parse(record).is_ok(), accepted, "{}", name);
+            assert_eq!(strict.parse(record).is_ok(), valid, "{}", name);
+
+            // only objects are accepted at the root.
+            let accepted = (valid && record.trim().starts_with('{'))
+                || ACCEPTED_BY_QUERY_PARSER.contains(&name);
+            for &mode in &[QueryParserMode::Basic, QueryParserMode::Speculative] {
+                let result = query_parser.parse(record, mode);
+                assert_eq!(result.is_ok(), accepted, "{}", name);
+            }
+        }
+    }
+
+    /// Run all `y_` and `n_` cases in the directory `test_parsing` of JSONTestSuite,
+    /// if it is given by `JSON_TEST_SUITE_DIR`.
+    #[test]
+    fn json_test_suite_dir() {
+        let dir = match env::var_os("JSON_TEST_SUITE_DIR") {
+            Some(dir) => dir,
+            None => return,
+        };
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if !(name.starts_with("y_") || name.starts_with("n_")) {
+                continue;
+            }
+            match String::from_utf8(fs::read(&path).unwrap()) {
+                Ok(record) => check(&name, &record),
+                // the records must be valid UTF-8 to be parsed.
+                Err(_) => assert!(name.starts_with("n_"), "{} is not UTF-8", name),
+            }
+        }
+    }
+}
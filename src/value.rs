@@ -2,50 +2,278 @@
 #![allow(missing_docs)]
 
 use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use crate::typed::JsonType;
 use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct EscapedStr<'a>(Cow<'a, str>);
+pub(crate) mod canonical;
+mod diff;
+mod hash;
+mod intern;
+mod json;
+mod merge;
+pub(crate) mod pointer;
+
+pub use self::diff::{diff, DiffEntry};
+pub use self::hash::ValueHasher;
+pub use self::intern::{KeyInterner, DEFAULT_INTERN_LIMIT};
+pub use self::merge::MergeStrategy;
+
+/// A string of a JSON value or field name
+///
+/// The text is either escaped (e.g. `a\"b`, as sliced from records) or unescaped
+/// (e.g. `a"b`, as given by `EscapedStr::unescaped`). The strings converted by `From`
+/// are escaped, and `escape` and `unescape` convert between the states as needed.
+///
+/// Two strings are equal if they have the same text and the same state, or if the
+/// text does not depend on the state (i.e. it has no backslashes, quotes nor control
+/// characters).
+#[derive(Clone)]
+pub struct EscapedStr<'a> {
+    s: Cow<'a, str>,
+    escaped: bool,
+}
 
 impl<'a> EscapedStr<'a> {
+    /// Create a string from its unescaped text.
+    pub fn unescaped<S: Into<Cow<'a, str>>>(s: S) -> Self {
+        EscapedStr {
+            s: s.into(),
+            escaped: false,
+        }
+    }
+
+    /// Return whether the text is escaped.
+    pub fn is_escaped(&self) -> bool {
+        self.escaped
+    }
+
+    /// Return the text as it is stored, which is escaped only if `is_escaped`.
     pub fn as_raw_str(&self) -> &str {
-        &self.0
+        &self.s
+    }
+
+    /// Decode the escape sequences and return the resulting string.
+    ///
+    /// The original slice is returned as is if it is unescaped or contains no
+    /// backslash.
+    pub fn unescape(&self) -> Result<Cow<'_, str>> {
+        if self.escaped && self.s.contains('\\') {
+            unescape(&self.s).map(Cow::Owned)
+        } else {
+            Ok(Cow::Borrowed(&*self.s))
+        }
+    }
+
+    /// Consume `self` and return the unescaped string.
+    pub fn into_unescaped(self) -> Result<Cow<'a, str>> {
+        if self.escaped && self.s.contains('\\') {
+            unescape(&self.s).map(Cow::Owned)
+        } else {
+            Ok(self.s)
+        }
+    }
+
+    /// Return the escaped text, which can be written between quotes in JSON.
+    ///
+    /// The original slice is returned as is if it is escaped or needs no escaping.
+    pub fn escape(&self) -> Cow<'_, str> {
+        if self.escaped || !needs_escaping(&self.s) {
+            return Cow::Borrowed(&*self.s);
+        }
+        let mut out = String::with_capacity(self.s.len() + 2);
+        canonical::write_escaped(&self.s, &mut out);
+        Cow::Owned(out)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn as_cow(&self) -> &Cow<'a, str> {
+        &self.s
     }
+
+    /// Return whether both strings are equal after decoding their escape sequences,
+    /// e.g. `a\u0041` and `aA`.
+    ///
+    /// The texts are compared as they are if neither contains a backslash, and the
+    /// invalid escape sequences are compared as they are otherwise.
+    pub fn eq_unescaped(&self, other: &EscapedStr<'_>) -> bool {
+        let has_escape = |s: &EscapedStr<'_>| s.escaped && s.s.contains('\\');
+        if !has_escape(self) && !has_escape(other) {
+            return self.s == other.s;
+        }
+        match (self.unescape(), other.unescape()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => self == other,
+        }
+    }
+
+    /// Replace the text with `f` applied to it, keeping the state.
+    pub(crate) fn map_text<'b, F>(self, f: F) -> EscapedStr<'b>
+    where
+        F: FnOnce(Cow<'a, str>) -> Cow<'b, str>,
+    {
+        EscapedStr {
+            s: f(self.s),
+            escaped: self.escaped,
+        }
+    }
+}
+
+/// Return whether the text differs between the escaped and unescaped states.
+fn needs_escaping(s: &str) -> bool {
+    s.bytes().any(|c| c == b'\\' || c == b'"' || c < 0x20)
+}
+
+impl<'a, 'b> PartialEq<EscapedStr<'b>> for EscapedStr<'a> {
+    fn eq(&self, other: &EscapedStr<'b>) -> bool {
+        self.s == other.s && (self.escaped == other.escaped || !needs_escaping(&self.s))
+    }
+}
+
+impl<'a> Eq for EscapedStr<'a> {}
+
+/// Only the text is hashed, since the equal strings have the same text.
+impl<'a> Hash for EscapedStr<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.s.hash(state);
+    }
+}
+
+fn unescape(s: &str) -> Result<String> {
+    fn invalid(s: &str) -> Result<String> {
+        Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("invalid escape sequence in {:?}", s))
+    }
+
+    fn hex4(chars: &mut std::str::Chars<'_>) -> Option<u32> {
+        let mut n = 0;
+        for _ in 0..4 {
+            n = n * 16 + chars.next()?.to_digit(16)?;
+        }
+        Some(n)
+    }
+
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let c = match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let hi = match hex4(&mut chars) {
+                    Some(n) => n,
+                    None => return invalid(s),
+                };
+                let code = if (0xD800..0xDC00).contains(&hi) {
+                    // a high surrogate must be followed by an escaped low surrogate.
+                    let lo = match (chars.next(), chars.next(), hex4(&mut chars)) {
+                        (Some('\\'), Some('u'), Some(lo)) if (0xDC00..0xE000).contains(&lo) => lo,
+                        _ => return invalid(s),
+                    };
+                    0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+                } else {
+                    hi
+                };
+                match std::char::from_u32(code) {
+                    Some(c) => c,
+                    None => return invalid(s),
+                }
+            }
+            _ => return invalid(s),
+        };
+        result.push(c);
+    }
+    Ok(result)
+}
+
+/// Return the longest prefix of the escaped text `s` which is unescaped into at most
+/// `len` bytes, cut at the boundary of a character or an escape sequence.
+///
+/// If an invalid escape sequence is found within `len` bytes, the rest of the text is
+/// returned as well, so that `unescape` reports it.
+pub(crate) fn escaped_prefix(s: &str, len: usize) -> &str {
+    let hex4 = |i: usize| {
+        s.get(i..i + 4)
+            .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+            .map(|h| u32::from_str_radix(h, 16).unwrap())
+    };
+    let bytes = s.as_bytes();
+    let (mut i, mut n) = (0, 0);
+    while i < bytes.len() && n < len {
+        // the numbers of bytes before and after unescaping
+        let (width, unescaped) = match bytes[i] {
+            b'\\' if bytes.get(i + 1) == Some(&b'u') => match hex4(i + 2) {
+                Some(hi) if (0xD800..0xDC00).contains(&hi) => {
+                    if s.get(i + 6..i + 8) != Some("\\u") || hex4(i + 8).is_none() {
+                        return s;
+                    }
+                    (12, 4)
+                }
+                Some(code) => (6, std::char::from_u32(code).map_or(3, char::len_utf8)),
+                None => return s,
+            },
+            b'\\' if bytes.get(i + 1).is_some_and(u8::is_ascii) => (2, 1),
+            b'\\' => return s,
+            c if c < 0x80 => (1, 1),
+            c if c < 0xE0 => (2, 2),
+            c if c < 0xF0 => (3, 3),
+            _ => (4, 4),
+        };
+        if n + unescaped > len {
+            break;
+        }
+        i += width;
+        n += unescaped;
+    }
+    &s[..i.min(s.len())]
 }
 
 impl<'a> fmt::Debug for EscapedStr<'a> {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&self.0, f)
+        fmt::Debug::fmt(&self.s, f)
     }
 }
 
 impl<'a> From<&'a str> for EscapedStr<'a> {
     #[inline]
     fn from(val: &'a str) -> Self {
-        EscapedStr(val.into())
+        EscapedStr::from(Cow::Borrowed(val))
     }
 }
 
 impl<'a> From<String> for EscapedStr<'a> {
     #[inline]
     fn from(val: String) -> Self {
-        EscapedStr(val.into())
+        EscapedStr::from(Cow::Owned(val))
     }
 }
 
 impl<'a> From<Cow<'a, str>> for EscapedStr<'a> {
     #[inline]
     fn from(val: Cow<'a, str>) -> Self {
-        EscapedStr(val.into())
+        EscapedStr {
+            s: val,
+            escaped: true,
+        }
     }
 }
 
 pub type LinearMap<K, V> = Vec<(K, V)>;
 
-#[derive(Clone)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Clone, PartialEq)]
 pub enum Value<'a> {
     Null,
     Boolean(bool),
@@ -66,7 +294,7 @@ impl<'a> fmt::Debug for Value<'a> {
             Value::Array(ref arr) => fmt::Debug::fmt(arr, f),
             Value::Object(ref obj) => f
                 .debug_map()
-                .entries(obj.iter().map(|&(ref k, ref v)| (k, v)))
+                .entries(obj.iter().map(|(k, v)| (k, v)))
                 .finish(),
             Value::Raw(ref s) => write!(f, "Raw({:?})", s),
         }
@@ -78,6 +306,79 @@ impl<'a> Value<'a> {
     pub fn raw<S: Into<Cow<'a, str>>>(val: S) -> Self {
         Value::Raw(val.into())
     }
+
+    /// Return the name of the JSON type of this value.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Null => "null",
+            Value::Boolean(..) => "boolean",
+            Value::Number(..) => "number",
+            Value::String(..) => "string",
+            Value::Array(..) => "array",
+            Value::Object(..) => "object",
+            Value::Raw(..) => "raw",
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for bool {
+    type Error = Error;
+
+    fn try_from(val: Value<'a>) -> Result<Self> {
+        match val {
+            Value::Boolean(b) => Ok(b),
+            val => Err(ErrorKind::InvalidType("boolean", val.type_name()).into()),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for f64 {
+    type Error = Error;
+
+    fn try_from(val: Value<'a>) -> Result<Self> {
+        match val {
+            Value::Number(n) => Ok(n),
+            val => Err(ErrorKind::InvalidType("number", val.type_name()).into()),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for i64 {
+    type Error = Error;
+
+    fn try_from(val: Value<'a>) -> Result<Self> {
+        match val {
+            Value::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                Ok(n as i64)
+            }
+            Value::Number(n) => Err(Error::from(ErrorKind::InvalidType("integer", "number")))
+                .chain_err(|| format!("{} is not representable as i64", n)),
+            val => Err(ErrorKind::InvalidType("integer", val.type_name()).into()),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for String {
+    type Error = Error;
+
+    /// Extract the unescaped content of a string value.
+    fn try_from(val: Value<'a>) -> Result<Self> {
+        match val {
+            Value::String(s) => s.into_unescaped().map(Cow::into_owned),
+            val => Err(ErrorKind::InvalidType("string", val.type_name()).into()),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for Vec<Value<'a>> {
+    type Error = Error;
+
+    fn try_from(val: Value<'a>) -> Result<Self> {
+        match val {
+            Value::Array(arr) => Ok(arr),
+            val => Err(ErrorKind::InvalidType("array", val.type_name()).into()),
+        }
+    }
 }
 
 impl<'a> From<bool> for Value<'a> {
@@ -87,6 +388,13 @@ impl<'a> From<bool> for Value<'a> {
     }
 }
 
+impl<'a> From<f64> for Value<'a> {
+    #[inline]
+    fn from(val: f64) -> Value<'a> {
+        Value::Number(val)
+    }
+}
+
 impl<'a> From<&'a str> for Value<'a> {
     #[inline]
     fn from(val: &'a str) -> Value<'a> {
@@ -108,6 +416,161 @@ impl<'a> From<Cow<'a, str>> for Value<'a> {
     }
 }
 
+impl<'a> PartialEq<bool> for Value<'a> {
+    #[inline]
+    fn eq(&self, other: &bool) -> bool {
+        match *self {
+            Value::Boolean(b) => b == *other,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> PartialEq<f64> for Value<'a> {
+    #[inline]
+    fn eq(&self, other: &f64) -> bool {
+        match *self {
+            Value::Number(n) => n == *other,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> PartialEq<str> for Value<'a> {
+    /// Compares the raw (escaped) content of a string value with `other`.
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        match *self {
+            Value::String(ref s) => s.as_raw_str() == other,
+            _ => false,
+        }
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for Value<'a> {
+    #[inline]
+    fn eq(&self, other: &&'b str) -> bool {
+        *self == **other
+    }
+}
+
+impl<'a> Value<'a> {
+    /// Same as `==`, but the strings and the names of fields are compared by
+    /// `EscapedStr::eq_unescaped`, so that the differences of escaping are ignored.
+    pub fn eq_unescaped(&self, other: &Value<'_>) -> bool {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => a.eq_unescaped(b),
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_unescaped(b))
+            }
+            (Value::Object(a), Value::Object(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|((ka, va), (kb, vb))| ka.eq_unescaped(kb) && va.eq_unescaped(vb))
+            }
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Raw(a), Value::Raw(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Null-safe navigation into nested values.
+pub trait ValueExt<'a> {
+    /// Walk nested objects and arrays by a path like `"a.b[2].c"` (the leading `"$."`
+    /// used by query paths is optional).
+    ///
+    /// Returns `None` if any step of the path is missing or the path is malformed.
+    fn path(&self, path: &str) -> Option<&Value<'a>>;
+}
+
+impl<'a> ValueExt<'a> for Value<'a> {
+    fn path(&self, path: &str) -> Option<&Value<'a>> {
+        let path = match path {
+            "$" => "",
+            p if p.starts_with("$.") => &p[2..],
+            p => p,
+        };
+        if path.is_empty() {
+            return Some(self);
+        }
+
+        let mut cur = self;
+        for segment in path.split('.') {
+            let (field, mut indices) = match segment.find('[') {
+                Some(i) => (&segment[..i], &segment[i..]),
+                None => (segment, ""),
+            };
+            if !field.is_empty() {
+                cur = match *cur {
+                    Value::Object(ref obj) => &obj.iter().find(|(k, _)| k.as_raw_str() == field)?.1,
+                    _ => return None,
+                };
+            } else if indices.is_empty() {
+                return None;
+            }
+            while !indices.is_empty() {
+                if !indices.starts_with('[') {
+                    return None;
+                }
+                let close = indices.find(']')?;
+                let i: usize = indices[1..close].parse().ok()?;
+                cur = match *cur {
+                    Value::Array(ref arr) => arr.get(i)?,
+                    _ => return None,
+                };
+                indices = &indices[close + 1..];
+            }
+        }
+        Some(cur)
+    }
+}
+
+impl<'a> ValueExt<'a> for Option<&Value<'a>> {
+    fn path(&self, path: &str) -> Option<&Value<'a>> {
+        self.and_then(|v| v.path(path))
+    }
+}
+
+/// Strings are serialized with their escape sequences decoded, and unparsed `Raw`
+/// subtrees are serialized as strings containing their original JSON text.
+#[cfg(feature = "serde")]
+impl<'a> serde::Serialize for Value<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::{Error as _, SerializeMap, SerializeSeq};
+
+        match *self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Boolean(b) => serializer.serialize_bool(b),
+            Value::Number(n) => serializer.serialize_f64(n),
+            Value::String(ref s) => {
+                serializer.serialize_str(&s.unescape().map_err(S::Error::custom)?)
+            }
+            Value::Array(ref arr) => {
+                let mut seq = serializer.serialize_seq(Some(arr.len()))?;
+                for v in arr {
+                    seq.serialize_element(v)?;
+                }
+                seq.end()
+            }
+            Value::Object(ref obj) => {
+                let mut map = serializer.serialize_map(Some(obj.len()))?;
+                for (k, v) in obj {
+                    map.serialize_entry(&*k.unescape().map_err(S::Error::custom)?, v)?;
+                }
+                map.end()
+            }
+            Value::Raw(ref s) => serializer.serialize_str(s),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ValueType<'a> {
     Atomic(Value<'a>),
@@ -118,35 +581,147 @@ pub enum ValueType<'a> {
 /// Parse the input string and returns the instance of `Value`.
 #[inline]
 pub fn parse<'a>(s: &'a str) -> Result<ValueType<'a>> {
-    match s {
-        "null" => Ok(ValueType::Atomic(Value::Null)),
-        "true" => Ok(ValueType::Atomic(Value::Boolean(true))),
-        "false" => Ok(ValueType::Atomic(Value::Boolean(false))),
-        s if s.starts_with("\"") && s.ends_with("\"") && s.len() > 1 => {
+    parse_with(s, false)
+}
+
+/// Same as `parse`, but the numbers are parsed by `parse_number` with `lenient`.
+#[inline]
+pub fn parse_with<'a>(s: &'a str, lenient: bool) -> Result<ValueType<'a>> {
+    let ty = s.as_bytes().first().and_then(|&c| JsonType::of_byte(c));
+    parse_typed(s, ty, lenient)
+}
+
+/// Same as `parse_with`, but with the type of `s` guessed from its first byte by
+/// `JsonType::of_byte` (e.g. by `StructuralIndex::find_object_value_typed`).
+#[inline]
+pub(crate) fn parse_typed<'a>(
+    s: &'a str,
+    ty: Option<JsonType>,
+    lenient: bool,
+) -> Result<ValueType<'a>> {
+    // dispatch on the type, so that only the literals are compared as a whole.
+    let value = match (ty, s.as_bytes().last()) {
+        (Some(JsonType::Null), _) if s == "null" => Value::Null,
+        (Some(JsonType::Boolean), _) if s == "true" => Value::Boolean(true),
+        (Some(JsonType::Boolean), _) if s == "false" => Value::Boolean(false),
+        (Some(JsonType::String), Some(&b'"')) if s.len() > 1 => {
             // FIXME: check if s is a valid UTF-8 string
-            Ok(ValueType::Atomic(Value::String(s[1..s.len() - 1].into())))
-        }
-        s if s.starts_with("[") && s.ends_with("]") => Ok(ValueType::Array),
-        s if s.starts_with("{") && s.ends_with("}") => Ok(ValueType::Object),
-        s => {
-            if let Ok(n) = s.parse::<f64>() {
-                Ok(ValueType::Atomic(Value::Number(n)))
-            } else {
-                Err(Error::from(ErrorKind::InvalidRecord))
-                    .chain_err(|| format!("Value::from_str({:?})", s))
-            }
+            Value::String(s[1..s.len() - 1].into())
+        }
+        (Some(JsonType::Array), Some(&b']')) => return Ok(ValueType::Array),
+        (Some(JsonType::Object), Some(&b'}')) => return Ok(ValueType::Object),
+        (Some(JsonType::Number), _) | (None, _) => match parse_number(s, lenient) {
+            Some(n) => Value::Number(n),
+            None => return invalid_value(s),
+        },
+        _ => return invalid_value(s),
+    };
+    Ok(ValueType::Atomic(value))
+}
+
+fn invalid_value<'a>(s: &str) -> Result<ValueType<'a>> {
+    Err(Error::from(ErrorKind::InvalidRecord)).chain_err(|| format!("Value::from_str({:?})", s))
+}
+
+/// Parse a number as defined by JSON (RFC 8259).
+///
+/// If `lenient`, `NaN`, `Infinity` and hexadecimal integers like `0x1F` are also
+/// accepted, and any number may have an optional sign (`+1`). Large hexadecimal
+/// integers may lose precision.
+pub fn parse_number(s: &str, lenient: bool) -> Option<f64> {
+    if is_json_number(s) {
+        return s.parse().ok();
+    }
+    if !lenient {
+        return None;
+    }
+    let (negative, body) = split_sign(s);
+    let n = match body {
+        "NaN" => f64::NAN,
+        "Infinity" => f64::INFINITY,
+        body if is_unsigned_json_number(body) => body.parse().ok()?,
+        body => parse_hex(body)? as f64,
+    };
+    Some(if negative { -n } else { n })
+}
+
+/// Same as `parse_number`, but only integers are accepted.
+pub fn parse_integer(s: &str, lenient: bool) -> Option<i64> {
+    if let Ok(n) = s.parse() {
+        if is_json_number(s) {
+            return Some(n);
         }
     }
+    if !lenient {
+        return None;
+    }
+    let (negative, body) = split_sign(s);
+    let n = match body {
+        body if is_unsigned_json_number(body) => body.parse().ok()?,
+        body => i128::from(parse_hex(body)?),
+    };
+    i64::try_from(if negative { -n } else { n }).ok()
+}
+
+fn split_sign(s: &str) -> (bool, &str) {
+    match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(digits, 16).ok()
+}
+
+fn is_unsigned_json_number(s: &str) -> bool {
+    !s.starts_with('-') && is_json_number(s)
+}
+
+/// Check the grammar `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`.
+pub(crate) fn is_json_number(s: &str) -> bool {
+    fn digits(s: &[u8]) -> usize {
+        s.iter().take_while(|b| b.is_ascii_digit()).count()
+    }
+    let s = s.as_bytes();
+    let mut i = usize::from(s.first() == Some(&b'-'));
+    match digits(&s[i..]) {
+        0 => return false,
+        n if n > 1 && s[i] == b'0' => return false,
+        n => i += n,
+    }
+    if s.get(i) == Some(&b'.') {
+        match digits(&s[i + 1..]) {
+            0 => return false,
+            n => i += 1 + n,
+        }
+    }
+    if let Some(b'e') | Some(b'E') = s.get(i) {
+        i += 1;
+        if let Some(b'+') | Some(b'-') = s.get(i) {
+            i += 1;
+        }
+        match digits(&s[i..]) {
+            0 => return false,
+            n => i += n,
+        }
+    }
+    i == s.len()
 }
 
 #[macro_export]
 macro_rules! object {
     ($( $f:expr => $v:expr,)+ ) => {{
-        let mut h = Vec::new();
-        $(
-            h.push((From::from($f), From::from($v)));
-        )*
-        Value::Object(h)
+        Value::Object(vec![
+            $(
+                (From::from($f), From::from($v)),
+            )*
+        ])
     }}
 }
 
@@ -160,3 +735,197 @@ macro_rules! array {
         ])
     }}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eq_native_types() {
+        assert!(Value::Boolean(true) == true);
+        assert!(Value::Number(1.5) == 1.5);
+        let foo = Value::from("foo");
+        assert!(foo == "foo");
+        assert!(foo != true);
+        assert!(Value::Number(1.5) != "1.5");
+        assert!(Value::Null != false);
+    }
+
+    #[test]
+    fn escaped_state() {
+        let escaped = EscapedStr::from(r#"a\"b\\"#);
+        let unescaped = EscapedStr::unescaped(r#"a"b\"#);
+        assert!(escaped.is_escaped());
+        assert!(!unescaped.is_escaped());
+        assert_eq!(escaped.unescape().unwrap(), r#"a"b\"#);
+        assert_eq!(unescaped.unescape().unwrap(), r#"a"b\"#);
+        assert!(matches!(escaped.escape(), Cow::Borrowed(r#"a\"b\\"#)));
+        assert_eq!(unescaped.escape(), r#"a\"b\\"#);
+        // an unescaped text is never decoded.
+        let literal = EscapedStr::unescaped(r#"\u0041"#);
+        assert_eq!(literal.unescape().unwrap(), r#"\u0041"#);
+        assert_eq!(literal.escape(), r#"\\u0041"#);
+
+        assert_ne!(escaped, unescaped);
+        assert_ne!(literal, EscapedStr::from(r#"\u0041"#));
+        assert_eq!(EscapedStr::from("ab"), EscapedStr::unescaped("ab"));
+
+        for s in [escaped, unescaped] {
+            let value = Value::String(s);
+            assert_eq!(value.to_json_string().unwrap(), r#""a\"b\\""#);
+            assert_eq!(value.to_canonical_string().unwrap(), r#""a\"b\\""#);
+        }
+        let value = Value::String(EscapedStr::unescaped("\n\u{1}"));
+        assert_eq!(value.to_json_string().unwrap(), r#""\n\u0001""#);
+    }
+
+    #[test]
+    fn escaped_prefixes() {
+        let s = r#"a\"\u00e9\ud83d\ude00b"#;
+        let prefixes = [
+            (0, ""),
+            (1, "a"),
+            (2, r#"a\""#),
+            (3, r#"a\""#),
+            (4, r#"a\"\u00e9"#),
+            (7, r#"a\"\u00e9"#),
+            (8, r#"a\"\u00e9\ud83d\ude00"#),
+            (100, s),
+        ];
+        for &(len, prefix) in &prefixes {
+            assert_eq!(escaped_prefix(s, len), prefix, "{}", len);
+            let unescaped = EscapedStr::from(prefix).into_unescaped().unwrap();
+            assert!(unescaped.len() <= len);
+        }
+        assert_eq!(escaped_prefix("ééé", 5), "éé");
+        assert_eq!(escaped_prefix(r#"a\u12"#, 1), "a");
+        assert_eq!(escaped_prefix(r#"a\u12"#, 2), r#"a\u12"#);
+        assert_eq!(escaped_prefix(r#"\ud83d\n"#, 0), "");
+        assert_eq!(escaped_prefix(r#"\ud83d\n"#, 4), r#"\ud83d\n"#);
+        assert_eq!(escaped_prefix("a\\é", 2), "a\\é");
+    }
+
+    #[test]
+    fn unescaped_equality() {
+        let a = EscapedStr::from(r#"a\u0041"#);
+        assert!(a.eq_unescaped(&EscapedStr::from("aA")));
+        assert!(a.eq_unescaped(&EscapedStr::unescaped("aA")));
+        assert!(a.eq_unescaped(&EscapedStr::from(r#"\u0061A"#)));
+        assert!(!a.eq_unescaped(&EscapedStr::unescaped(r#"a\u0041"#)));
+        assert!(!a.eq_unescaped(&EscapedStr::from("aa")));
+        assert!(EscapedStr::from(r#"\x"#).eq_unescaped(&EscapedStr::from(r#"\x"#)));
+        assert!(!EscapedStr::from(r#"\x"#).eq_unescaped(&EscapedStr::unescaped("x")));
+        assert_ne!(a, EscapedStr::from("aA"));
+
+        let a = object! { "\\u0066" => array![r#"\u0041"#,], "g" => 1.0, };
+        let b = object! { "f" => array!["A",], "g" => 1.0, };
+        assert_ne!(a, b);
+        assert!(a.eq_unescaped(&b));
+        assert!(!a.eq_unescaped(&object! { "f" => array!["A",], "g" => 2.0, }));
+        assert!(!a.eq_unescaped(&object! { "f" => array!["A",], }));
+    }
+
+    #[test]
+    fn path_access() {
+        let value = object! {
+            "a" => object! {
+                "b" => array![1.0, 2.0, object! { "c" => "foo", },],
+            },
+            "d" => array![array![true,],],
+        };
+        assert_eq!(value.path("a.b[2].c"), Some(&Value::from("foo")));
+        assert_eq!(value.path("$.a.b[1]"), Some(&Value::Number(2.0)));
+        assert_eq!(value.path("d[0][0]"), Some(&Value::Boolean(true)));
+        assert_eq!(value.path("$"), Some(&value));
+        assert_eq!(value.path("a.x.c"), None);
+        assert_eq!(value.path("a.b[3]"), None);
+        assert_eq!(value.path("a.b[x]"), None);
+        assert_eq!(value.path("a..b"), None);
+        assert_eq!(
+            value.path("a").path("b[2]").path("c"),
+            Some(&Value::from("foo"))
+        );
+        assert_eq!(value.path("x").path("b"), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let value = object! {
+            "f1" => true,
+            "f\\u0032" => array![Value::Number(1.5), Value::Null,],
+            "f3" => r#"\"foo\\"#,
+            "f4" => Value::raw(r#"{ "c1": null }"#),
+        };
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            r#"{"f1":true,"f2":[1.5,null],"f3":"\"foo\\","f4":"{ \"c1\": null }"}"#
+        );
+    }
+
+    #[test]
+    fn try_from_value() {
+        assert!(bool::try_from(Value::Boolean(true)).unwrap());
+        assert_eq!(f64::try_from(Value::Number(1.5)).unwrap(), 1.5);
+        assert_eq!(i64::try_from(Value::Number(-3.0)).unwrap(), -3);
+        assert!(i64::try_from(Value::Number(1.5)).is_err());
+        assert_eq!(
+            String::try_from(Value::from(r#"\"foo\\\u00e9\ud83d\ude00"#)).unwrap(),
+            "\"foo\\\u{e9}\u{1f600}"
+        );
+        assert!(String::try_from(Value::from(r#"\ud83d"#)).is_err());
+        assert_eq!(
+            Vec::<Value<'_>>::try_from(array![true, "a",]).unwrap(),
+            vec![Value::Boolean(true), Value::from("a")]
+        );
+
+        match bool::try_from(Value::Null) {
+            Err(Error(ErrorKind::InvalidType("boolean", "null"), _)) => {}
+            e => panic!("unexpected result: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        for s in &["0", "-0", "1.5", "-12e3", "1E+2", "0.0e-1"] {
+            assert_eq!(parse_number(s, false), Some(s.parse().unwrap()), "{}", s);
+        }
+        for s in &[
+            "NaN", "Infinity", "inf", "+1", "01", "1.", ".5", "1e", "0x1F", "-", "",
+        ] {
+            assert_eq!(parse_number(s, false), None, "{}", s);
+        }
+
+        assert!(parse_number("NaN", true).unwrap().is_nan());
+        assert_eq!(parse_number("-Infinity", true), Some(f64::NEG_INFINITY));
+        assert_eq!(parse_number("+Infinity", true), Some(f64::INFINITY));
+        assert_eq!(parse_number("0x1F", true), Some(31.0));
+        assert_eq!(parse_number("-0X1f", true), Some(-31.0));
+        assert_eq!(parse_number("0x", true), None);
+        assert_eq!(parse_number("inf", true), None);
+        assert_eq!(parse_number("-inf", true), None);
+        assert_eq!(parse_number("+1.5", true), Some(1.5));
+        assert_eq!(parse_number("+-1", true), None);
+
+        assert_eq!(parse_integer("-42", false), Some(-42));
+        assert_eq!(parse_integer("1.0", false), None);
+        assert_eq!(parse_integer("0x1F", false), None);
+        assert_eq!(parse_integer("-0x8000000000000000", true), Some(i64::MIN));
+        assert_eq!(parse_integer("0x8000000000000000", true), None);
+        assert_eq!(parse_integer("NaN", true), None);
+        assert_eq!(parse_integer("+42", false), None);
+        assert_eq!(parse_integer("+42", true), Some(42));
+        assert_eq!(parse_integer("+1.0", true), None);
+
+        assert!(parse("NaN").is_err());
+        assert!(parse("nul").is_err());
+        assert!(parse("truex").is_err());
+        assert!(parse("\"").is_err());
+        assert!(parse("[1").is_err());
+        assert!(matches!(parse("[1]").unwrap(), ValueType::Array));
+        assert!(matches!(
+            parse_with("Infinity", true).unwrap(),
+            ValueType::Atomic(Value::Number(n)) if n == f64::INFINITY
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+use super::Value;
+use crate::errors::{Error, ErrorKind, Result, ResultExt};
+use std::fmt::Write;
+
+impl<'a> Value<'a> {
+    /// Serialize this value in the canonical form defined by RFC 8785 (JCS).
+    ///
+    /// Object fields are sorted by the UTF-16 code units of their unescaped names,
+    /// numbers are formatted as ECMAScript does and strings use the minimal escaping.
+    /// Non-finite numbers and unparsed `Raw` subtrees cannot be canonicalized.
+    pub fn to_canonical_string(&self) -> Result<String> {
+        let mut out = String::new();
+        self.write_canonical(&mut out)?;
+        Ok(out)
+    }
+
+    /// Append the canonical form of this value to `out`.
+    pub fn write_canonical(&self, out: &mut String) -> Result<()> {
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Boolean(b) => out.push_str(if b { "true" } else { "false" }),
+            Value::Number(n) => write_number(n, out)?,
+            Value::String(ref s) => write_string(&s.unescape()?, out),
+            Value::Array(ref arr) => {
+                out.push('[');
+                for (i, v) in arr.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    v.write_canonical(out)?;
+                }
+                out.push(']');
+            }
+            Value::Object(ref obj) => {
+                let mut fields = obj
+                    .iter()
+                    .map(|(k, v)| Ok((k.unescape()?, v)))
+                    .collect::<Result<Vec<_>>>()?;
+                fields.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+                out.push('{');
+                for (i, (k, v)) in fields.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(&k, out);
+                    out.push(':');
+                    v.write_canonical(out)?;
+                }
+                out.push('}');
+            }
+            Value::Raw(..) => {
+                return Err(Error::from(ErrorKind::InvalidRecord))
+                    .chain_err(|| "cannot canonicalize an unparsed raw value")
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    write_escaped(s, out);
+    out.push('"');
+}
+
+/// Append `s` with the minimal escaping, without the quotes.
+pub(crate) fn write_escaped(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+/// Format a number as ECMAScript's `Number.prototype.toString` does.
+pub(crate) fn write_number(n: f64, out: &mut String) -> Result<()> {
+    if !n.is_finite() {
+        return Err(Error::from(ErrorKind::InvalidRecord))
+            .chain_err(|| format!("{} is not representable in JSON", n));
+    }
+    if n == 0.0 {
+        out.push('0');
+        return Ok(());
+    }
+    if n < 0.0 {
+        out.push('-');
+    }
+
+    // `{:e}` yields the shortest digits which round-trip, e.g. "1.2345e-7".
+    let repr = format!("{:e}", n.abs());
+    let (mantissa, exp) = repr.split_at(repr.find('e').unwrap());
+    let digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exp[1..].parse::<i32>().unwrap() + 1;
+
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.extend((0..n - k).map(|_| '0'));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.extend((0..-n).map(|_| '0'));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let _ = write!(out, "e{}{}", if n > 0 { "+" } else { "-" }, (n - 1).abs());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{array, object};
+
+    #[test]
+    fn canonical_numbers() {
+        let cases: &[(f64, &str)] = &[
+            (0.0, "0"),
+            (-0.0, "0"),
+            (1.0, "1"),
+            (-1.5, "-1.5"),
+            (100.0, "100"),
+            (1e21, "1e+21"),
+            (1e20, "100000000000000000000"),
+            (123.456, "123.456"),
+            (0.000001, "0.000001"),
+            (1e-7, "1e-7"),
+            (1.2345e-7, "1.2345e-7"),
+            (5e-324, "5e-324"),
+            (1.7976931348623157e308, "1.7976931348623157e+308"),
+            (9007199254740992.0, "9007199254740992"),
+            (333333333.3333333, "333333333.3333333"),
+        ];
+        for &(n, expected) in cases {
+            let mut out = String::new();
+            write_number(n, &mut out).unwrap();
+            assert_eq!(out, expected);
+        }
+        assert!(Value::Number(f64::NAN).to_canonical_string().is_err());
+    }
+
+    #[test]
+    fn canonical_value() {
+        let value = object! {
+            "b" => array![Value::Null, true, 1e30,],
+            "a" => r#"\u0041\/\"x"#,
+            "\u{fb33}" => "dalet",
+            "\u{20ac}" => "euro",
+            "\u{1f600}" => "smile",
+            "\\u000a" => object! { "z" => 1.0, "y" => 2.0, },
+        };
+        assert_eq!(
+            value.to_canonical_string().unwrap(),
+            "{\"\\n\":{\"y\":2,\"z\":1},\"a\":\"A/\\\"x\",\"b\":[null,true,1e+30],\
+             \"\u{20ac}\":\"euro\",\"\u{1f600}\":\"smile\",\"\u{fb33}\":\"dalet\"}"
+        );
+        assert!(Value::raw("{}").to_canonical_string().is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use super::Value;
+
+/// A difference between two values, located by a query-style path (e.g. `"$.a.b[2]"`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffEntry<'v, 'a> {
+    /// The path exists only in the second value.
+    Added { path: String, value: &'v Value<'a> },
+    /// The path exists only in the first value.
+    Removed { path: String, value: &'v Value<'a> },
+    /// The path exists in both values, with different contents.
+    Changed {
+        path: String,
+        old: &'v Value<'a>,
+        new: &'v Value<'a>,
+    },
+}
+
+impl<'v, 'a> DiffEntry<'v, 'a> {
+    #[allow(missing_docs)]
+    pub fn path(&self) -> &str {
+        match *self {
+            DiffEntry::Added { ref path, .. }
+            | DiffEntry::Removed { ref path, .. }
+            | DiffEntry::Changed { ref path, .. } => path,
+        }
+    }
+}
+
+/// Compute the list of differences from `a` to `b`.
+///
+/// Objects are compared field by field and arrays element by element, so that
+/// each entry points at the deepest path where the two values diverge. Fields of
+/// objects are reported in the order of `a`, followed by fields added in `b`.
+pub fn diff<'v, 'a>(a: &'v Value<'a>, b: &'v Value<'a>) -> Vec<DiffEntry<'v, 'a>> {
+    let mut entries = vec![];
+    diff_impl(a, b, &mut "$".to_owned(), &mut entries);
+    entries
+}
+
+fn diff_impl<'v, 'a>(
+    a: &'v Value<'a>,
+    b: &'v Value<'a>,
+    path: &mut String,
+    entries: &mut Vec<DiffEntry<'v, 'a>>,
+) {
+    let len = path.len();
+    match (a, b) {
+        (Value::Object(ref x), Value::Object(ref y)) => {
+            for (k, v) in x {
+                path.push('.');
+                path.push_str(k.as_raw_str());
+                match y.iter().find(|(k2, _)| k2 == k) {
+                    Some((_, v2)) => diff_impl(v, v2, path, entries),
+                    None => entries.push(DiffEntry::Removed {
+                        path: path.clone(),
+                        value: v,
+                    }),
+                }
+                path.truncate(len);
+            }
+            for (k, v) in y {
+                if !x.iter().any(|(k2, _)| k2 == k) {
+                    entries.push(DiffEntry::Added {
+                        path: format!("{}.{}", path, k.as_raw_str()),
+                        value: v,
+                    });
+                }
+            }
+        }
+        (Value::Array(ref x), Value::Array(ref y)) => {
+            for i in 0..x.len().max(y.len()) {
+                path.push_str(&format!("[{}]", i));
+                match (x.get(i), y.get(i)) {
+                    (Some(v), Some(v2)) => diff_impl(v, v2, path, entries),
+                    (Some(v), None) => entries.push(DiffEntry::Removed {
+                        path: path.clone(),
+                        value: v,
+                    }),
+                    (None, Some(v)) => entries.push(DiffEntry::Added {
+                        path: path.clone(),
+                        value: v,
+                    }),
+                    (None, None) => unreachable!(),
+                }
+                path.truncate(len);
+            }
+        }
+        (a, b) if a != b => entries.push(DiffEntry::Changed {
+            path: path.clone(),
+            old: a,
+            new: b,
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{array, object};
+
+    #[test]
+    fn diff_values() {
+        let a = object! {
+            "f1" => true,
+            "f2" => object! { "e1" => "foo", "e2" => Value::Null, },
+            "f3" => array![1.0, 2.0, 3.0,],
+        };
+        let b = object! {
+            "f2" => object! { "e1" => "bar", "e2" => Value::Null, },
+            "f3" => array![1.0, 5.0,],
+            "f4" => false,
+        };
+
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                DiffEntry::Removed {
+                    path: "$.f1".into(),
+                    value: &Value::Boolean(true),
+                },
+                DiffEntry::Changed {
+                    path: "$.f2.e1".into(),
+                    old: &Value::from("foo"),
+                    new: &Value::from("bar"),
+                },
+                DiffEntry::Changed {
+                    path: "$.f3[1]".into(),
+                    old: &Value::Number(2.0),
+                    new: &Value::Number(5.0),
+                },
+                DiffEntry::Removed {
+                    path: "$.f3[2]".into(),
+                    value: &Value::Number(3.0),
+                },
+                DiffEntry::Added {
+                    path: "$.f4".into(),
+                    value: &Value::Boolean(false),
+                },
+            ]
+        );
+        assert!(diff(&a, &a).is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use super::Value;
+use crate::errors::Result;
+use fnv::FnvHasher;
+use std::fmt;
+use std::hash::Hasher;
+
+impl<'a> Value<'a> {
+    /// Compute a stable 64-bit hash of this value.
+    ///
+    /// The hash is taken over the canonical form (see `to_canonical_string`), so that
+    /// values which differ only in formatting, field order or escaping hash identically.
+    pub fn hash_canonical(&self) -> Result<u64> {
+        let mut hasher = ValueHasher::default();
+        hasher.write_value(self)?;
+        Ok(hasher.finish())
+    }
+}
+
+/// A streaming hasher over values and raw slices of records.
+///
+/// The result is stable across platforms and runs (FNV-1a over the written bytes).
+#[derive(Default)]
+pub struct ValueHasher {
+    inner: FnvHasher,
+    buf: String,
+}
+
+impl fmt::Debug for ValueHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueHasher")
+            .field("hash", &self.inner.finish())
+            .finish()
+    }
+}
+
+impl ValueHasher {
+    /// Feed the canonical form of `value` to the hasher.
+    pub fn write_value(&mut self, value: &Value<'_>) -> Result<()> {
+        self.buf.clear();
+        value.write_canonical(&mut self.buf)?;
+        self.inner.write(self.buf.as_bytes());
+        self.inner.write_u8(0xff);
+        Ok(())
+    }
+
+    /// Feed a slice of a record (e.g. a result of `QueryParser`) to the hasher.
+    ///
+    /// The slice is hashed byte-wise without parsing, so equivalent values with
+    /// different formatting produce different hashes. `None` is distinguished from
+    /// any slice.
+    pub fn write_slice(&mut self, slice: Option<&str>) {
+        match slice {
+            Some(s) => {
+                self.inner.write(s.as_bytes());
+                self.inner.write_u8(0xff);
+            }
+            None => self.inner.write_u8(0xfe),
+        }
+    }
+
+    /// Return the hash value of the data written so far.
+    pub fn finish(&self) -> u64 {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{array, object};
+
+    #[test]
+    fn hash_canonical() {
+        let a = object! { "a" => 1.0, "b" => array!["x", true,], };
+        let b = object! { "b" => array![r#"x"#, true,], "a" => 1.0, };
+        let c = object! { "a" => 2.0, "b" => array!["x", true,], };
+        assert_eq!(a.hash_canonical().unwrap(), b.hash_canonical().unwrap());
+        assert_ne!(a.hash_canonical().unwrap(), c.hash_canonical().unwrap());
+    }
+
+    #[test]
+    fn hash_slices() {
+        let hash = |slices: &[Option<&str>]| {
+            let mut hasher = ValueHasher::default();
+            for &s in slices {
+                hasher.write_slice(s);
+            }
+            hasher.finish()
+        };
+        assert_eq!(hash(&[Some("1"), None]), hash(&[Some("1"), None]));
+        assert_ne!(hash(&[Some("1"), None]), hash(&[None, Some("1")]));
+        assert_ne!(
+            hash(&[Some("ab"), Some("c")]),
+            hash(&[Some("a"), Some("bc")])
+        );
+    }
+}
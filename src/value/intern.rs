@@ -0,0 +1,137 @@
+use super::Value;
+use fnv::FnvHashSet;
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+/// The default number of distinct keys interned by `KeyInterner`
+pub const DEFAULT_INTERN_LIMIT: usize = 65536;
+
+/// An interner of field names shared among the owned values of many records
+///
+/// Interned names are allocated once and kept until the interner is dropped, like a
+/// symbol table, so that the repeated keys of millions of records share the same
+/// allocations. The values borrow the names from the interner, and cannot outlive it.
+/// Once `limit` distinct names have been interned, the others are copied as usual,
+/// which bounds the memory kept by records with arbitrary keys.
+#[derive(Debug)]
+pub struct KeyInterner {
+    keys: RefCell<FnvHashSet<Box<str>>>,
+    limit: usize,
+}
+
+impl Default for KeyInterner {
+    fn default() -> Self {
+        Self::with_limit(DEFAULT_INTERN_LIMIT)
+    }
+}
+
+impl KeyInterner {
+    /// Create an interner which interns at most `limit` distinct names.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            keys: RefCell::new(FnvHashSet::default()),
+            limit,
+        }
+    }
+
+    /// Return the interned string equal to `s`, or a copy of it if the limit is reached.
+    ///
+    /// Any string can be interned, e.g. flattened key paths like `"user.id"`.
+    #[allow(unsafe_code)]
+    pub fn intern(&self, s: &str) -> Cow<'_, str> {
+        let mut keys = self.keys.borrow_mut();
+        let key: &str = match keys.get(s) {
+            Some(key) => key,
+            None if keys.len() >= self.limit => return Cow::Owned(s.to_owned()),
+            None => {
+                keys.insert(s.into());
+                keys.get(s).unwrap()
+            }
+        };
+        // the boxed names are neither mutated nor dropped until the interner is, and
+        // they stay at the same addresses while the set is growing.
+        Cow::Borrowed(unsafe { &*(key as *const str) })
+    }
+
+    /// Return the number of interned names.
+    pub fn len(&self) -> usize {
+        self.keys.borrow().len()
+    }
+
+    #[allow(missing_docs)]
+    pub fn is_empty(&self) -> bool {
+        self.keys.borrow().is_empty()
+    }
+}
+
+impl<'a> Value<'a> {
+    /// Convert into a value which owns all of its strings.
+    pub fn into_owned(self) -> Value<'static> {
+        self.into_owned_impl(&mut |k| Cow::Owned(k.into_owned()))
+    }
+
+    /// Same as `into_owned`, but the (escaped) field names are taken from `interner`.
+    pub fn into_owned_with(self, interner: &KeyInterner) -> Value<'_> {
+        self.into_owned_impl(&mut |k| match k {
+            Cow::Borrowed(k) => interner.intern(k),
+            Cow::Owned(k) => interner.intern(&k),
+        })
+    }
+
+    fn into_owned_impl<'b, F>(self, key: &mut F) -> Value<'b>
+    where
+        F: FnMut(Cow<'a, str>) -> Cow<'b, str>,
+    {
+        match self {
+            Value::Null => Value::Null,
+            Value::Boolean(b) => Value::Boolean(b),
+            Value::Number(n) => Value::Number(n),
+            Value::String(s) => Value::String(s.map_text(|s| Cow::Owned(s.into_owned()))),
+            Value::Array(arr) => {
+                Value::Array(arr.into_iter().map(|v| v.into_owned_impl(key)).collect())
+            }
+            Value::Object(obj) => Value::Object(
+                obj.into_iter()
+                    .map(|(k, v)| (k.map_text(&mut *key), v.into_owned_impl(key)))
+                    .collect(),
+            ),
+            Value::Raw(s) => Value::Raw(Cow::Owned(s.into_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::backend::FallbackBackend;
+    use crate::index_builder::IndexBuilder;
+    use crate::parser::Parser;
+    use crate::value::ValueExt;
+
+    #[test]
+    fn interned_keys() {
+        let parser = Parser::new(IndexBuilder::new(FallbackBackend::default(), 2));
+        let interner = KeyInterner::with_limit(2);
+        let records: Vec<Value<'_>> = (0..3)
+            .map(|i| {
+                let record = format!(r#"{{"a": {}, "b": {{"a": "x"}}, "c{}": null}}"#, i, i);
+                let value = parser.parse(&record).unwrap();
+                value.into_owned_with(&interner)
+            })
+            .collect();
+        assert_eq!(interner.len(), 2);
+
+        let key = |v: &Value<'_>, i: usize| match *v {
+            Value::Object(ref obj) => match *obj[i].0.as_cow() {
+                Cow::Borrowed(k) => Some(k.as_ptr()),
+                Cow::Owned(_) => None,
+            },
+            _ => unreachable!(),
+        };
+        assert!(key(&records[0], 0).is_some());
+        assert_eq!(key(&records[0], 0), key(&records[2], 0));
+        assert_eq!(key(&records[0], 0), key(records[1].path("b").unwrap(), 0));
+        assert_eq!(key(&records[1], 2), None);
+        assert_eq!(records[2].path("c2"), Some(&Value::Null));
+    }
+}
@@ -0,0 +1,124 @@
+use super::canonical::{write_number, write_string};
+use super::Value;
+use crate::errors::Result;
+
+impl<'a> Value<'a> {
+    /// Serialize this value without whitespace, in the order of its fields.
+    ///
+    /// The escape sequences of escaped strings are kept as they are, the unescaped
+    /// strings are escaped minimally, and unparsed `Raw` subtrees are written as their
+    /// original JSON text. Numbers are formatted by their shortest representations
+    /// which parse back into the same `f64` (e.g. `0.30000000000000004` and
+    /// `1e+300`), so serializing parsed values is lossless. Non-finite numbers cannot
+    /// be serialized.
+    pub fn to_json_string(&self) -> Result<String> {
+        let mut out = String::new();
+        self.write_json(&mut out)?;
+        Ok(out)
+    }
+
+    /// Append the serialized value to `out`. See `to_json_string`.
+    pub fn write_json(&self, out: &mut String) -> Result<()> {
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Boolean(b) => out.push_str(if b { "true" } else { "false" }),
+            Value::Number(n) => write_number(n, out)?,
+            Value::String(ref s) => {
+                out.push('"');
+                out.push_str(&s.escape());
+                out.push('"');
+            }
+            Value::Array(ref arr) => {
+                out.push('[');
+                for (i, v) in arr.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    v.write_json(out)?;
+                }
+                out.push(']');
+            }
+            Value::Object(ref obj) => {
+                out.push('{');
+                for (i, (k, v)) in obj.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(&k.unescape()?, out);
+                    out.push(':');
+                    v.write_json(out)?;
+                }
+                out.push('}');
+            }
+            Value::Raw(ref s) => out.push_str(s),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::Rng;
+    use crate::value::{parse, parse_number, ValueType};
+    use crate::{array, object};
+
+    fn round_trip(n: f64) {
+        let s = Value::Number(n).to_json_string().unwrap();
+        assert_eq!(
+            parse_number(&s, false).map(f64::to_bits),
+            Some(n.to_bits()),
+            "{}",
+            s
+        );
+        match parse(&s).unwrap() {
+            ValueType::Atomic(value) => assert_eq!(value.to_json_string().unwrap(), s),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn round_trip_numbers() {
+        for &n in &[
+            0.1,
+            0.1 + 0.2,
+            -1.5,
+            1e23,
+            1e300,
+            5e-324,
+            2.2250738585072014e-308,
+            f64::MAX,
+            f64::MIN,
+            9007199254740993.0,
+            123456789.98765433,
+        ] {
+            round_trip(n);
+        }
+        let mut rng = Rng::new(0);
+        for _ in 0..10_000 {
+            let n = f64::from_bits(rng.next_u64());
+            if n.is_finite() && n != 0.0 {
+                round_trip(n);
+            }
+        }
+        assert_eq!(
+            Value::Number(0.1 + 0.2).to_json_string().unwrap(),
+            "0.30000000000000004"
+        );
+        assert_eq!(Value::Number(1e300).to_json_string().unwrap(), "1e+300");
+        assert!(Value::Number(f64::INFINITY).to_json_string().is_err());
+    }
+
+    #[test]
+    fn json_string() {
+        let value = object! {
+            "b" => array![Value::Null, true, 1e30,],
+            "a\\u0041" => r#"\"x\\"#,
+            "c" => Value::raw(r#"{ "d": [1.50] }"#),
+        };
+        assert_eq!(
+            value.to_json_string().unwrap(),
+            r#"{"b":[null,true,1e+30],"aA":"\"x\\","c":{ "d": [1.50] }}"#
+        );
+    }
+}
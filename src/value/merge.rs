@@ -0,0 +1,103 @@
+use super::Value;
+
+/// Strategy used by `Value::merge`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// JSON Merge Patch (RFC 7386): objects are merged recursively, `null` removes
+    /// the field and any other value (including arrays) replaces the original.
+    MergePatch,
+    /// Same as `MergePatch`, except that arrays on both sides are concatenated.
+    ConcatArrays,
+}
+
+impl<'a> Value<'a> {
+    /// Merge `other` into `self` with the specified strategy.
+    pub fn merge(&mut self, other: Value<'a>, strategy: MergeStrategy) {
+        match (self, other) {
+            (Value::Array(ref mut arr), Value::Array(other))
+                if strategy == MergeStrategy::ConcatArrays =>
+            {
+                arr.extend(other);
+            }
+            (this, Value::Object(patch)) => {
+                if !matches!(*this, Value::Object(..)) {
+                    *this = Value::Object(vec![]);
+                }
+                let obj = match *this {
+                    Value::Object(ref mut obj) => obj,
+                    _ => unreachable!(),
+                };
+                for (k, v) in patch {
+                    let pos = obj.iter().position(|(k2, _)| *k2 == k);
+                    match (pos, v) {
+                        (Some(i), Value::Null) => {
+                            obj.remove(i);
+                        }
+                        (None, Value::Null) => {}
+                        (Some(i), v) => obj[i].1.merge(v, strategy),
+                        (None, v) => {
+                            let mut value = Value::Null;
+                            value.merge(v, strategy);
+                            obj.push((k, value));
+                        }
+                    }
+                }
+            }
+            (this, other) => *this = other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{array, object};
+
+    #[test]
+    fn merge_patch() {
+        let mut value = object! {
+            "title" => "Goodbye!",
+            "author" => object! { "givenName" => "John", "familyName" => "Doe", },
+            "tags" => array!["example", "sample",],
+            "content" => "This will be unchanged",
+        };
+        value.merge(
+            object! {
+                "title" => "Hello!",
+                "phoneNumber" => "+01-123-456-7890",
+                "author" => object! { "familyName" => Value::Null, },
+                "tags" => array!["example",],
+                "nested" => object! { "a" => Value::Null, "b" => 1.0, },
+            },
+            MergeStrategy::MergePatch,
+        );
+        assert_eq!(
+            value,
+            object! {
+                "title" => "Hello!",
+                "author" => object! { "givenName" => "John", },
+                "tags" => array!["example",],
+                "content" => "This will be unchanged",
+                "phoneNumber" => "+01-123-456-7890",
+                "nested" => object! { "b" => 1.0, },
+            }
+        );
+
+        let mut value = array![1.0,];
+        value.merge(object! { "a" => true, }, MergeStrategy::MergePatch);
+        assert_eq!(value, object! { "a" => true, });
+    }
+
+    #[test]
+    fn merge_concat_arrays() {
+        let mut value = object! { "tags" => array!["a",], "n" => 1.0, };
+        value.merge(
+            object! { "tags" => array!["b", "c",], "n" => array![2.0,], },
+            MergeStrategy::ConcatArrays,
+        );
+        assert_eq!(
+            value,
+            object! { "tags" => array!["a", "b", "c",], "n" => array![2.0,], }
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use super::{EscapedStr, Value};
+
+impl<'a> Value<'a> {
+    /// Look up a value by a JSON Pointer (RFC 6901), e.g. `"/f2/e1/0"`.
+    ///
+    /// Object fields are matched against their names with the escape sequences decoded,
+    /// as the reference tokens are.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value<'a>> {
+        let mut cur = self;
+        for token in tokens(pointer)? {
+            cur = match *cur {
+                Value::Object(ref obj) => &obj.iter().find(|(k, _)| is_named(k, &token))?.1,
+                Value::Array(ref arr) => arr.get(parse_index(&token)?)?,
+                _ => return None,
+            };
+        }
+        Some(cur)
+    }
+
+    /// Mutable version of `pointer`.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value<'a>> {
+        let mut cur = self;
+        for token in tokens(pointer)? {
+            cur = match *cur {
+                Value::Object(ref mut obj) => {
+                    &mut obj.iter_mut().find(|(k, _)| is_named(k, &token))?.1
+                }
+                Value::Array(ref mut arr) => arr.get_mut(parse_index(&token)?)?,
+                _ => return None,
+            };
+        }
+        Some(cur)
+    }
+}
+
+/// Return whether the field `key` is named by the decoded reference `token`.
+pub(crate) fn is_named(key: &EscapedStr<'_>, token: &str) -> bool {
+    key.eq_unescaped(&EscapedStr::unescaped(token))
+}
+
+/// Split a JSON Pointer into its reference tokens, decoding `~1` and `~0`.
+pub(crate) fn tokens(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(vec![]);
+    }
+    if !pointer.starts_with('/') {
+        return None;
+    }
+    Some(
+        pointer[1..]
+            .split('/')
+            .map(|t| t.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+    )
+}
+
+/// Parse an array index, rejecting leading zeros as required by RFC 6901.
+pub(crate) fn parse_index(token: &str) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    if !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{array, object};
+
+    #[test]
+    fn pointer() {
+        let mut value = object! {
+            "f1" => array![true, object! { "a/b" => 1.0, "m~n" => 2.0, },],
+            "" => "empty",
+            r#"q\"t"# => 3.0,
+        };
+        assert_eq!(value.pointer(""), Some(&value.clone()));
+        assert_eq!(value.pointer("/f1/0"), Some(&Value::Boolean(true)));
+        assert_eq!(value.pointer("/f1/1/a~1b"), Some(&Value::Number(1.0)));
+        assert_eq!(value.pointer("/f1/1/m~0n"), Some(&Value::Number(2.0)));
+        assert_eq!(value.pointer("/"), Some(&Value::from("empty")));
+        assert_eq!(value.pointer(r#"/q"t"#), Some(&Value::Number(3.0)));
+        assert_eq!(value.pointer("/f1/01"), None);
+        assert_eq!(value.pointer("/f1/2"), None);
+        assert_eq!(value.pointer("f1"), None);
+
+        *value.pointer_mut("/f1/0").unwrap() = Value::Boolean(false);
+        assert_eq!(value.pointer("/f1/0"), Some(&Value::Boolean(false)));
+    }
+}
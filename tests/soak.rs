@@ -0,0 +1,18 @@
+//! A shorter run of `examples/soak.rs`, which takes a while even so:
+//!
+//! ```text
+//! cargo test --release --test soak -- --ignored
+//! ```
+
+#[path = "../examples/soak.rs"]
+#[allow(dead_code)]
+mod soak;
+
+use misosoup::testdata::DEFAULT_SEED;
+
+#[test]
+#[ignore]
+fn soak() {
+    let failures = soak::soak(20_000, DEFAULT_SEED, 4);
+    assert!(failures.is_empty(), "{:?}", failures);
+}